@@ -0,0 +1,177 @@
+use criterion::{BatchSize, Criterion, Throughput, criterion_group, criterion_main};
+use sourcier_core::{Fixture, SourceFilesMap};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const SMALL_FILES: usize = 10_000;
+const LARGE_FILE: usize = 100 << 20;
+const DEEP_FILES: usize = 10_000;
+const DEPTH: usize = 32;
+
+/// System allocator counting live heap bytes, for the memory figures of
+/// [`path_storage`]
+struct CountingAlloc;
+
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        LIVE_BYTES.fetch_add(new_size, Ordering::Relaxed);
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc;
+
+fn fixtures() -> [(&'static str, Fixture); 3] {
+    [
+        ("small_files", Fixture::small_files(SMALL_FILES)),
+        ("large_file", Fixture::large_file(LARGE_FILE)),
+        ("deep_paths", Fixture::deep_paths(DEEP_FILES, DEPTH)),
+    ]
+}
+
+fn finalize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("finalize");
+    group.sample_size(10);
+    for (name, fixture) in fixtures() {
+        group.throughput(Throughput::Bytes(fixture.total_bytes() as u64));
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || fixture.builder::<u16>().expect("fixture fits u16 IDs"),
+                |builder| builder.finalize().expect("fixture paths are unique"),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Byte ranges of every line holding the needle, across all files
+fn needle_ranges(files: &SourceFilesMap<u16>) -> Vec<(u16, std::ops::Range<usize>)> {
+    let finder = memchr::memmem::Finder::new(Fixture::NEEDLE);
+    files
+        .iter()
+        .flat_map(|(id, _, content)| {
+            finder
+                .find_iter(content)
+                .map(move |at| (id, at..at + Fixture::NEEDLE.len()))
+        })
+        .collect()
+}
+
+fn position_and_view(c: &mut Criterion) {
+    for (name, fixture) in fixtures() {
+        let files = fixture.load::<u16>().expect("fixture loads");
+        // Positions hold 16-bit lines, so stay within the first lines of large files
+        let ranges: Vec<_> = needle_ranges(&files)
+            .into_iter()
+            .filter(|(_, range)| range.end < 1 << 20)
+            .take(10_000)
+            .collect();
+        let positions: Vec<_> = ranges
+            .iter()
+            .filter_map(|(id, range)| Some((*id, files.position(*id, range.clone())?)))
+            .collect();
+
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Elements(ranges.len() as u64));
+        group.bench_function("position_at", |b| {
+            b.iter(|| {
+                for (id, range) in &ranges {
+                    black_box(files.position(*id, range.clone()));
+                }
+            })
+        });
+        group.bench_function("view", |b| {
+            b.iter(|| {
+                for (id, pos) in &positions {
+                    black_box(files.view(*id, pos));
+                }
+            })
+        });
+        group.finish();
+    }
+}
+
+fn search(c: &mut Criterion) {
+    for (name, fixture) in fixtures() {
+        let files = fixture.load::<u16>().expect("fixture loads");
+        let paths: Vec<String> = files.iter().map(|(_, path, _)| path.to_string()).collect();
+        let phf = fixture
+            .builder::<u16>()
+            .expect("fixture fits u16 IDs")
+            .with_phf_lookup(true)
+            .finalize()
+            .expect("fixture paths are unique");
+
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Bytes(fixture.total_bytes() as u64));
+        group.bench_function("search", |b| b.iter(|| needle_ranges(black_box(&files))));
+        group.throughput(Throughput::Elements(paths.len() as u64));
+        group.bench_function("get_id", |b| {
+            b.iter(|| {
+                for path in &paths {
+                    black_box(files.get_id(path));
+                }
+            })
+        });
+        group.bench_function("get_id_phf", |b| {
+            b.iter(|| {
+                for path in &paths {
+                    black_box(phf.get_id(path));
+                }
+            })
+        });
+        group.finish();
+    }
+}
+
+/// Heap held by a finalized map beyond its contents, and path lookups by ID
+///
+/// Contents are loaded the same way whatever the path storage, so the bytes
+/// retained beyond them are mostly paths and the path to ID index. Lookups
+/// the other way are in [`search`].
+fn path_storage(c: &mut Criterion) {
+    let fixtures = [
+        ("small_files", Fixture::small_files(SMALL_FILES)),
+        ("deep_paths", Fixture::deep_paths(DEEP_FILES, DEPTH)),
+    ];
+    for (name, fixture) in fixtures {
+        let before = LIVE_BYTES.load(Ordering::Relaxed);
+        let files = fixture.load::<u16>().expect("fixture loads");
+        let retained = LIVE_BYTES.load(Ordering::Relaxed) - before - fixture.total_bytes();
+        println!(
+            "{name}/path_storage: {retained} bytes beyond content, {} per file",
+            retained / files.len()
+        );
+        let ids: Vec<u16> = files.iter().map(|(id, _, _)| id).collect();
+
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Elements(ids.len() as u64));
+        group.bench_function("get_path", |b| {
+            b.iter(|| {
+                for &id in &ids {
+                    black_box(files.get_path(id));
+                }
+            })
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, finalize, position_and_view, search, path_storage);
+criterion_main!(benches);
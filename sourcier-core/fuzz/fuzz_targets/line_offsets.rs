@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sourcier_core::clo::CompactLineOffsets;
+
+fuzz_target!(|content: &[u8]| {
+    let offsets = CompactLineOffsets::compute(content);
+    let lines: Vec<&[u8]> = content.split(|&byte| byte == b'\n').collect();
+    assert_eq!(offsets.line_count(), lines.len());
+
+    let mut start = 0;
+    for (index, line) in lines.iter().enumerate() {
+        let range = offsets.get_line_range(index + 1);
+        assert_eq!(range, Some((start, start + line.len())));
+        assert_eq!(offsets.locate(start), Some((index + 1, 0)));
+        start += line.len() + 1;
+    }
+    assert_eq!(offsets.get_line_range(0), None);
+    assert_eq!(offsets.get_line_range(lines.len() + 1), None);
+    assert_eq!(offsets.locate(content.len() + 1), None);
+});
@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sourcier_core::{ChunkManifest, ChunkStore};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(manifest) = ChunkManifest::read_from(&mut &data[..]) else {
+        return;
+    };
+    let mut out = Vec::new();
+    manifest.write_to(&mut out).expect("manifests write to memory");
+    assert_eq!(out, data[..out.len()]);
+    // No chunk exists there, so reading fails, but only once it opens one
+    let store = ChunkStore::new("/nonexistent/sourcier-fuzz");
+    assert!(store.read(&manifest).is_err() || manifest.chunks.is_empty());
+});
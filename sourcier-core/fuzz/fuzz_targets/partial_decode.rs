@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sourcier_core::SourceFilesMap;
+use std::io::Cursor;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(partial) = SourceFilesMap::<u16>::load_subset(Cursor::new(data), ["*.rs"]) else {
+        return;
+    };
+    // Loading everything under a small budget evicts as it goes
+    let mut partial = partial.with_memory_budget(64);
+    for raw in 1..=partial.len() as u16 {
+        if partial.load(raw).is_err() {
+            return;
+        }
+        assert!(partial.resident_bytes() <= 64 || partial.loaded_ids().count() == 1);
+    }
+    let _ = partial.into_map();
+});
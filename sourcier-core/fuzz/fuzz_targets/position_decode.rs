@@ -0,0 +1,32 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sourcier_core::{AbsolutePosition, RelativePosition, SourceFilePosition};
+
+fuzz_target!(|raw: u64| {
+    if let Some(pos) = AbsolutePosition::<u8>::from_raw(raw) {
+        assert_eq!(pos.as_raw(), raw);
+        let rebuilt = AbsolutePosition::new(
+            pos.file_id(),
+            pos.start_line(),
+            pos.start_column(),
+            pos.end_line(),
+            pos.end_column(),
+        );
+        assert_eq!(rebuilt, pos);
+        assert_eq!(pos.to_relative().start_line(), pos.start_line());
+    }
+    if let Some(pos) = AbsolutePosition::<u16>::from_raw(raw) {
+        assert_eq!(pos.as_raw(), raw);
+        assert_eq!(pos.source_file_id(), Some(pos.file_id().into()));
+    }
+    if let Some(pos) = RelativePosition::from_raw(raw) {
+        let rebuilt = RelativePosition::new(
+            pos.start_line(),
+            pos.start_column(),
+            pos.end_line(),
+            pos.end_column(),
+        );
+        assert_eq!(rebuilt.as_raw(), raw);
+    }
+});
@@ -0,0 +1,37 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sourcier_core::{RelativePosition, SourceFilesMap};
+
+fuzz_target!(|input: (Vec<u8>, u64, usize, usize)| {
+    let (content, raw, start, end) = input;
+    let mut files = SourceFilesMap::<u8>::new();
+    if files
+        .add_file("fuzz.rs".to_string(), content.clone())
+        .is_err()
+    {
+        return;
+    }
+    files.finalize().expect("one file always finalizes");
+    let id = files.get_id("fuzz.rs").expect("the file was added");
+
+    if let Some(pos) = RelativePosition::from_raw(raw) {
+        if let Ok(view) = files.try_view(id, &pos) {
+            // A view is always a subslice of the content
+            let at = view.as_ptr() as usize - content_ptr(&files, id);
+            assert!(at + view.len() <= content.len());
+        }
+        assert_eq!(files.view(id, &pos), files.try_view(id, &pos).ok());
+    }
+
+    if let Some(pos) = files.position(id, start..end) {
+        let view = files.view(id, &pos);
+        if let Some(view) = view {
+            assert!(view.len() <= content.len());
+        }
+    }
+});
+
+fn content_ptr(files: &SourceFilesMap<u8>, id: u8) -> usize {
+    files.get_content(id).expect("the file exists").as_ptr() as usize
+}
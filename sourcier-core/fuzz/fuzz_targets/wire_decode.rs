@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sourcier_core::SourceFilesMap;
+
+fuzz_target!(|data: &[u8]| {
+    // Corrupt caches fail with an error, never a panic or an abort
+    let _ = SourceFilesMap::<u16>::read_cache(&mut &data[..]);
+    let _ = SourceFilesMap::<u32>::read_cache(&mut &data[..]);
+    if let Ok(map) = SourceFilesMap::<u8>::read_cache(&mut &data[..]) {
+        let mut out = Vec::new();
+        map.write_cache(&mut out).expect("loaded maps write back");
+        let reread = SourceFilesMap::<u8>::read_cache(&mut out.as_slice())
+            .expect("written caches read back");
+        assert!(reread.iter().eq(map.iter()));
+    }
+});
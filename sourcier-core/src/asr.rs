@@ -0,0 +1,133 @@
+//! Span assertions with readable failures
+//!
+//! Comparing positions with `assert_eq!` prints their encoded `u64`s, which
+//! say nothing about where the spans are. [`assert_span_eq!`] takes the
+//! expected span as a path and `line:col..line:col`, as
+//! [`SourceFilesMap::display`] writes them, and on failure shows both
+//! locations with the lines under them underlined:
+//!
+//! ```
+//! use sourcier_core::{SourceFilesMap, assert_span_eq};
+//!
+//! let mut files = SourceFilesMap::<u8>::new();
+//! files.add_file("src/lib.rs".into(), b"fn main() {}\n".to_vec()).unwrap();
+//! files.finalize().unwrap();
+//! let id = files.get_id("src/lib.rs").unwrap();
+//! let name = files.position(id, 3..7).unwrap();
+//! assert_span_eq!(files, name, "src/lib.rs", 1:4..1:7);
+//! ```
+
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+use crate::snp::{DEFAULT_TAB_WIDTH, gutter_width, underline_segment};
+use std::fmt::Write as _;
+
+/// Expected span of [`check_span`]: start line and column, end line and column
+pub type ExpectedSpan = (u16, u8, u16, u8);
+
+/// Assert that a position is the span of a path, e.g.
+/// `assert_span_eq!(files, pos, "src/lib.rs", 3:5..3:18)`
+///
+/// Lines and columns are those of the position as stored, the way
+/// [`SourceFilesMap::display`](crate::SourceFilesMap::display) writes them.
+/// Panics with both spans rendered as snippets when they differ, see
+/// [`check_span`]; an optional format string after the span is prepended to
+/// the message.
+#[macro_export]
+macro_rules! assert_span_eq {
+    ($files:expr, $actual:expr, $path:expr, $sl:literal : $sc:literal .. $el:literal : $ec:literal $(,)?) => {
+        if let ::std::result::Result::Err(message) =
+            $crate::check_span(&$files, &$actual, $path, ($sl, $sc, $el, $ec))
+        {
+            ::std::panic!("{}", message);
+        }
+    };
+    ($files:expr, $actual:expr, $path:expr, $sl:literal : $sc:literal .. $el:literal : $ec:literal, $($arg:tt)+) => {
+        if let ::std::result::Result::Err(message) =
+            $crate::check_span(&$files, &$actual, $path, ($sl, $sc, $el, $ec))
+        {
+            ::std::panic!("{}\n{}", ::std::format_args!($($arg)+), message);
+        }
+    };
+}
+
+/// Compare a position with the expected span of a path
+///
+/// The error describes the mismatch and renders the lines of both spans
+/// with the span underlined, or says which file is missing from `files`.
+pub fn check_span<Id: FileId>(
+    files: &SourceFilesMap<Id>,
+    actual: &AbsolutePosition<Id>,
+    path: &str,
+    expected: ExpectedSpan,
+) -> Result<(), String> {
+    let (start_line, start_col, end_line, end_col) = expected;
+    let expected_pos = files
+        .get_id(path)
+        .map(|id| AbsolutePosition::new(id, start_line, start_col, end_line, end_col));
+    if expected_pos == Some(*actual) {
+        return Ok(());
+    }
+    let mut message = String::from("spans differ\n");
+    let _ = writeln!(
+        message,
+        "expected: {path}:{start_line}:{start_col}-{end_line}:{end_col}"
+    );
+    match &expected_pos {
+        Some(pos) => render_snippet(&mut message, files, pos),
+        None => message.push_str("  (file not in the map)\n"),
+    }
+    let _ = writeln!(message, "  actual: {}", files.display(actual));
+    if files.get_path(actual.file_id()).is_some() {
+        render_snippet(&mut message, files, actual);
+    } else {
+        message.push_str("  (file not in the map)\n");
+    }
+    Err(message)
+}
+
+/// Lines of `pos` with the span underlined, or a note when they are missing
+pub(crate) fn render_snippet<Id: FileId>(
+    out: &mut String,
+    files: &SourceFilesMap<Id>,
+    pos: &AbsolutePosition<Id>,
+) {
+    let Some(file) = files.file(pos.file_id()) else {
+        return;
+    };
+    let width = gutter_width(pos.end_line() as usize);
+    for line in pos.start_line() as usize..=pos.end_line() as usize {
+        let Some(text) = file.line(line) else {
+            let _ = writeln!(out, "  {line:>width$} │ (past the end of the file)");
+            break;
+        };
+        let text = text.strip_suffix(b"\r").unwrap_or(text);
+        let _ = writeln!(out, "  {line:>width$} │ {}", expand_tabs(text));
+        if let Some(segment) = underline_segment(text, line, pos, DEFAULT_TAB_WIDTH) {
+            let _ = writeln!(
+                out,
+                "  {:width$} │ {}{}",
+                "",
+                " ".repeat(segment.start),
+                "^".repeat(segment.carets())
+            );
+        }
+    }
+}
+
+/// Text of a line with tabs expanded, so carets line up under it
+fn expand_tabs(text: &[u8]) -> String {
+    let mut expanded = String::new();
+    let mut width = 0;
+    for c in String::from_utf8_lossy(text).chars() {
+        if c == '\t' {
+            let spaces = DEFAULT_TAB_WIDTH - width % DEFAULT_TAB_WIDTH;
+            expanded.extend(std::iter::repeat_n(' ', spaces));
+            width += spaces;
+        } else {
+            expanded.push(c);
+            width += 1;
+        }
+    }
+    expanded
+}
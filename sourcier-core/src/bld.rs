@@ -0,0 +1,126 @@
+use crate::err::SourceFilesError;
+use crate::fid::FileId;
+use crate::lod::LoadOptions;
+use crate::obs::MapObserver;
+#[cfg(feature = "rt-feedback")]
+use crate::rtf::RuntimeFeedback;
+use crate::sfm::{DuplicatePolicy, FileOrder, SourceFilesMap};
+use crate::wrn::WarningSink;
+use std::sync::Arc;
+
+/// Collects files before any ID exists
+///
+/// The builder only adds, replaces and removes files, and
+/// [`SourceFilesMapBuilder::finalize`] consumes it into a map, so querying IDs
+/// before finalizing does not compile instead of returning empty results. The
+/// finalized map is a plain [`SourceFilesMap`]: files added to it afterwards
+/// still wait for another `finalize`.
+///
+/// ```compile_fail
+/// let builder = sourcier_core::SourceFilesMapBuilder::<u8>::new();
+/// builder.get_id("src/lib.rs");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SourceFilesMapBuilder<Id: FileId> {
+    map: SourceFilesMap<Id>,
+}
+
+impl<Id: FileId> SourceFilesMapBuilder<Id> {
+    /// Create a builder with conservative defaults for small projects
+    pub fn new() -> Self {
+        Self {
+            map: SourceFilesMap::new(),
+        }
+    }
+
+    /// Create a builder sized and tracked by a feedback context
+    #[cfg(feature = "rt-feedback")]
+    pub fn with_feedback(feedback: Option<Arc<RuntimeFeedback>>) -> Self {
+        Self {
+            map: SourceFilesMap::with_feedback(feedback),
+        }
+    }
+
+    /// Register an observer notified of lifecycle events
+    pub fn with_observer(mut self, observer: Arc<dyn MapObserver<Id>>) -> Self {
+        self.map.add_observer(observer);
+        self
+    }
+
+    /// Set the order in which `finalize` assigns IDs
+    pub fn with_order(mut self, order: FileOrder) -> Self {
+        self.map.set_order(order);
+        self
+    }
+
+    /// Set how paths submitted more than once are resolved
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.map.set_duplicate_policy(policy);
+        self
+    }
+
+    /// Look paths up through a perfect hash after `finalize`
+    ///
+    /// See [`SourceFilesMap::set_phf_lookup`].
+    pub fn with_phf_lookup(mut self, enabled: bool) -> Self {
+        self.map.set_phf_lookup(enabled);
+        self
+    }
+
+    /// Send warnings to `sink`, see [`SourceFilesMap::set_warning_sink`]
+    pub fn with_warning_sink(mut self, sink: Arc<dyn WarningSink>) -> Self {
+        self.map.set_warning_sink(sink);
+        self
+    }
+
+    /// Set the filters applied to added files
+    pub fn with_load_options(mut self, options: LoadOptions) -> Self {
+        self.map.set_load_options(options);
+        self
+    }
+
+    /// Add a file with content
+    pub fn add_file(&mut self, path: String, content: Vec<u8>) -> Result<(), SourceFilesError> {
+        self.map.add_file(path, content)
+    }
+
+    /// Builder-style variant of [`SourceFilesMapBuilder::add_file`]
+    pub fn file(mut self, path: String, content: Vec<u8>) -> Result<Self, SourceFilesError> {
+        self.add_file(path, content)?;
+        Ok(self)
+    }
+
+    /// Replace the content of every pending submission of `path`
+    ///
+    /// Returns false, leaving the builder untouched, when `path` was never added.
+    pub fn update_file(&mut self, path: &str, content: Vec<u8>) -> bool {
+        self.map.replace_pending(path, content)
+    }
+
+    /// Withdraw every pending submission of `path`, returning whether there was one
+    pub fn remove_file(&mut self, path: &str) -> bool {
+        self.map.remove_pending(path)
+    }
+
+    /// Number of pending submissions, duplicates included
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Assign IDs and get the queryable map
+    pub fn finalize(mut self) -> Result<SourceFilesMap<Id>, SourceFilesError> {
+        self.map.finalize()?;
+        Ok(self.map)
+    }
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Start a type-checked two-phase construction
+    pub fn builder() -> SourceFilesMapBuilder<Id> {
+        SourceFilesMapBuilder::new()
+    }
+}
@@ -0,0 +1,156 @@
+use crate::fid::{FileId, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Hash git reports for lines not committed yet
+const UNCOMMITTED: &str = "0000000000000000000000000000000000000000";
+
+/// Commit that last touched one line of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlameEntry {
+    /// 1-based line in the map's content
+    pub line: usize,
+    /// Full commit hash
+    pub commit: String,
+    pub author: String,
+    pub author_email: String,
+    /// Author time, in seconds since the Unix epoch
+    pub author_time: i64,
+    /// First line of the commit message
+    pub summary: String,
+}
+
+impl BlameEntry {
+    /// Whether the line differs from every commit, e.g. an unsaved edit
+    pub fn is_uncommitted(&self) -> bool {
+        self.commit == UNCOMMITTED
+    }
+}
+
+/// Commit fields git prints once per commit in porcelain output
+#[derive(Debug, Clone, Default)]
+struct CommitInfo {
+    author: String,
+    author_email: String,
+    author_time: i64,
+    summary: String,
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Blame every line of a file, one entry per line in order
+    ///
+    /// Runs `git blame` in `repo`, the checkout the map's paths are relative
+    /// to, over the content held by the map rather than the file on disk, so
+    /// lines match the map's line offsets even after edits; edited lines are
+    /// [uncommitted](BlameEntry::is_uncommitted). Fails for unknown IDs, when
+    /// git is missing or the file is not tracked.
+    pub fn blame(&self, id: Id, repo: impl AsRef<Path>) -> io::Result<Vec<BlameEntry>> {
+        self.run_blame(id, repo.as_ref(), None)
+    }
+
+    /// Blame the first line of a span, None when the line is past the end
+    pub fn blame_at(
+        &self,
+        id: Id,
+        pos: &impl SourceFilePosition,
+        repo: impl AsRef<Path>,
+    ) -> io::Result<Option<BlameEntry>> {
+        let line = pos.start_line() as usize;
+        // git counts lines by their newlines, without the empty last line
+        let content = self.get_content(id).unwrap_or_default();
+        let lines = memchr::memchr_iter(b'\n', content).count()
+            + usize::from(!content.is_empty() && !content.ends_with(b"\n"));
+        if line == 0 || line > lines {
+            return Ok(None);
+        }
+        Ok(self.run_blame(id, repo.as_ref(), Some(line))?.pop())
+    }
+
+    fn run_blame(&self, id: Id, repo: &Path, line: Option<usize>) -> io::Result<Vec<BlameEntry>> {
+        let not_found = || io::Error::new(io::ErrorKind::NotFound, "unknown file ID");
+        let path = self.get_path(id).ok_or_else(not_found)?;
+        let content = self.get_content(id).ok_or_else(not_found)?;
+
+        let mut command = Command::new("git");
+        command
+            .current_dir(repo)
+            .args(["blame", "--porcelain", "--contents", "-"]);
+        if let Some(line) = line {
+            command.arg(format!("-L{line},{line}"));
+        }
+        let mut child = command
+            .args(["--", path])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // Written from another thread so a full stdout pipe cannot stall git
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let content = content.to_vec();
+        let writer = std::thread::spawn(move || stdin.write_all(&content));
+        let output = child.wait_with_output()?;
+        // git may stop reading early when it fails; its stderr says why
+        let written = writer.join().expect("blame writer panicked");
+        if !output.status.success() {
+            let message = String::from_utf8_lossy(&output.stderr);
+            return Err(io::Error::other(format!(
+                "git blame {path}: {}",
+                message.trim()
+            )));
+        }
+        written?;
+        parse_porcelain(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+/// Entries of `git blame --porcelain` output, sorted by line
+fn parse_porcelain(output: &str) -> io::Result<Vec<BlameEntry>> {
+    let invalid = |line: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected git blame line: {line}"),
+        )
+    };
+    let mut commits: HashMap<&str, CommitInfo> = HashMap::new();
+    let mut entries = Vec::new();
+    let mut lines = output.lines();
+    while let Some(header) = lines.next() {
+        let mut fields = header.split(' ');
+        let (Some(commit), Some(_), Some(final_line)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid(header));
+        };
+        let final_line: usize = final_line.parse().map_err(|_| invalid(header))?;
+        let info = commits.entry(commit).or_default();
+        // Commit fields until the tab-prefixed content line
+        for line in lines.by_ref() {
+            if line.starts_with('\t') {
+                break;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "author" => info.author = value.to_string(),
+                "author-mail" => {
+                    info.author_email = value.trim_matches(['<', '>']).to_string();
+                }
+                "author-time" => info.author_time = value.parse().map_err(|_| invalid(line))?,
+                "summary" => info.summary = value.to_string(),
+                _ => {}
+            }
+        }
+        entries.push(BlameEntry {
+            line: final_line,
+            commit: commit.to_string(),
+            author: info.author.clone(),
+            author_email: info.author_email.clone(),
+            author_time: info.author_time,
+            summary: info.summary.clone(),
+        });
+    }
+    entries.sort_by_key(|entry| entry.line);
+    Ok(entries)
+}
@@ -0,0 +1,243 @@
+//! Content-defined chunking of persisted maps
+//!
+//! [`SourceFilesMap::write_chunked`] cuts the [`write_cache`](SourceFilesMap::write_cache)
+//! bytes where their content says so (FastCDC) rather than at fixed offsets,
+//! and stores each chunk once under its hash. Editing a file only changes
+//! the chunks around it, so consecutive snapshots of a workspace share most
+//! of their chunks and keeping many of them costs little more than one.
+
+use crate::dsk::write_atomic;
+use crate::fid::FileId;
+use crate::sfm::SourceFilesMap;
+use crate::wire::{WireError, read_bytes, read_u32, read_u64};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use xxhash_rust::xxh3::xxh3_128;
+
+/// Magic number opening every persisted [`ChunkManifest`]
+pub const MANIFEST_MAGIC: [u8; 8] = *b"SRCCHUNK";
+
+/// Chunk sizes in bytes, clamped to what FastCDC accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chunking {
+    pub min_size: u32,
+    pub avg_size: u32,
+    pub max_size: u32,
+}
+
+impl Default for Chunking {
+    fn default() -> Self {
+        Self {
+            min_size: 16 << 10,
+            avg_size: 64 << 10,
+            max_size: 256 << 10,
+        }
+    }
+}
+
+impl Chunking {
+    pub fn new(min_size: u32, avg_size: u32, max_size: u32) -> Self {
+        use fastcdc::v2020::{
+            AVERAGE_MAX, AVERAGE_MIN, MAXIMUM_MAX, MAXIMUM_MIN, MINIMUM_MAX, MINIMUM_MIN,
+        };
+        let min_size = min_size.clamp(MINIMUM_MIN, MINIMUM_MAX);
+        let avg_size = avg_size.clamp(AVERAGE_MIN, AVERAGE_MAX).max(min_size);
+        Self {
+            min_size,
+            avg_size,
+            max_size: max_size.clamp(MAXIMUM_MIN, MAXIMUM_MAX).max(avg_size),
+        }
+    }
+
+    /// Chunks of `bytes`, in order
+    fn cut<'a>(&self, bytes: &'a [u8]) -> impl Iterator<Item = &'a [u8]> {
+        fastcdc::v2020::FastCDC::new(bytes, self.min_size, self.avg_size, self.max_size)
+            .map(move |chunk| &bytes[chunk.offset..chunk.offset + chunk.length])
+    }
+}
+
+/// Stored chunk, named by the XXH3-128 of its bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ChunkRef {
+    pub hash: u128,
+    pub len: u32,
+}
+
+/// Chunks making up one snapshot, in order
+///
+/// Layout (little endian): 8-byte magic, `u64` chunk count, then a `u128`
+/// hash and a `u32` length per chunk.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChunkManifest {
+    pub chunks: Vec<ChunkRef>,
+}
+
+impl ChunkManifest {
+    /// Bytes of the snapshot once reassembled
+    pub fn total_len(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.len as u64).sum()
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(&MANIFEST_MAGIC)?;
+        out.write_all(&(self.chunks.len() as u64).to_le_bytes())?;
+        for chunk in &self.chunks {
+            out.write_all(&chunk.hash.to_le_bytes())?;
+            out.write_all(&chunk.len.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(input: &mut impl Read) -> Result<Self, WireError> {
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if magic != MANIFEST_MAGIC {
+            return Err(WireError::BadMagic);
+        }
+        let count = read_u64(input)?;
+        let mut chunks = Vec::new();
+        for _ in 0..count {
+            let mut hash = [0u8; 16];
+            input.read_exact(&mut hash)?;
+            chunks.push(ChunkRef {
+                hash: u128::from_le_bytes(hash),
+                len: read_u32(input)?,
+            });
+        }
+        Ok(Self { chunks })
+    }
+}
+
+/// Directory of chunks stored once each, under their hash
+///
+/// Chunk `h` lives at `root/hh/h` with `h` in hex, its first two digits
+/// naming the subdirectory. Chunks are written through a temporary file, so
+/// readers never see a partial one.
+#[derive(Debug, Clone)]
+pub struct ChunkStore {
+    root: PathBuf,
+    chunking: Chunking,
+}
+
+impl ChunkStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            chunking: Chunking::default(),
+        }
+    }
+
+    pub fn with_chunking(mut self, chunking: Chunking) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Location of a chunk in the store
+    pub fn path(&self, chunk: &ChunkRef) -> PathBuf {
+        let name = format!("{:032x}", chunk.hash);
+        self.root.join(&name[..2]).join(name)
+    }
+
+    /// Whether a chunk is stored
+    pub fn contains(&self, chunk: &ChunkRef) -> bool {
+        self.path(chunk).is_file()
+    }
+
+    /// Store `bytes` in chunks, skipping the ones already stored
+    pub fn write(&self, bytes: &[u8]) -> io::Result<ChunkManifest> {
+        let mut chunks = Vec::new();
+        for data in self.chunking.cut(bytes) {
+            let chunk = ChunkRef {
+                hash: xxh3_128(data),
+                len: data.len() as u32,
+            };
+            if !self.contains(&chunk) {
+                let path = self.path(&chunk);
+                if let Some(dir) = path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                write_atomic(&path, data)?;
+            }
+            chunks.push(chunk);
+        }
+        Ok(ChunkManifest { chunks })
+    }
+
+    /// Reassemble the bytes of a manifest, checking every chunk's hash
+    ///
+    /// The buffer grows with the chunks actually read, not with the lengths
+    /// the manifest claims.
+    pub fn read(&self, manifest: &ChunkManifest) -> Result<Vec<u8>, WireError> {
+        let mut bytes = Vec::new();
+        for chunk in &manifest.chunks {
+            let mut file = fs::File::open(self.path(chunk))?;
+            let data = read_bytes(&mut file, chunk.len as u64)?;
+            if xxh3_128(&data) != chunk.hash {
+                return Err(WireError::Corrupt(format!(
+                    "chunk {:032x} does not match its hash",
+                    chunk.hash
+                )));
+            }
+            bytes.extend_from_slice(&data);
+        }
+        Ok(bytes)
+    }
+
+    /// Delete the chunks no manifest in `keep` refers to, returning how many
+    /// were deleted
+    pub fn retain<'a>(
+        &self,
+        keep: impl IntoIterator<Item = &'a ChunkManifest>,
+    ) -> io::Result<usize> {
+        let kept: HashSet<String> = keep
+            .into_iter()
+            .flat_map(|manifest| &manifest.chunks)
+            .map(|chunk| format!("{:032x}", chunk.hash))
+            .collect();
+        let mut deleted = 0;
+        let dirs = match fs::read_dir(&self.root) {
+            Ok(dirs) => dirs,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(error) => return Err(error),
+        };
+        for dir in dirs {
+            let dir = dir?;
+            if !dir.file_type()?.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(dir.path())? {
+                let entry = entry?;
+                let name = entry.file_name();
+                let is_chunk = name.len() == 32;
+                if is_chunk && !name.to_str().is_some_and(|name| kept.contains(name)) {
+                    fs::remove_file(entry.path())?;
+                    deleted += 1;
+                }
+            }
+        }
+        Ok(deleted)
+    }
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Persist the finalized map as chunks of its cache bytes, see [`ChunkStore`]
+    ///
+    /// Keep the returned manifest, e.g. with [`ChunkManifest::write_to`],
+    /// to load the snapshot back with [`SourceFilesMap::read_chunked`].
+    pub fn write_chunked(&self, store: &ChunkStore) -> Result<ChunkManifest, WireError> {
+        let mut bytes = Vec::new();
+        self.write_cache(&mut bytes)?;
+        Ok(store.write(&bytes)?)
+    }
+
+    /// Load a map written by [`SourceFilesMap::write_chunked`]
+    pub fn read_chunked(store: &ChunkStore, manifest: &ChunkManifest) -> Result<Self, WireError> {
+        Self::read_cache(&mut store.read(manifest)?.as_slice())
+    }
+}
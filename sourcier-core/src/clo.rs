@@ -13,7 +13,13 @@ pub struct CompactLineOffsets {
 impl CompactLineOffsets {
     // Precompute line offsets more efficiently
     pub fn compute(content: &[u8]) -> Self {
-        let mut offsets = vec![0u32];
+        Self::compute_with_capacity(content, 1)
+    }
+
+    // Same as `compute`, reserving room for `expected_lines` up front
+    pub fn compute_with_capacity(content: &[u8], expected_lines: usize) -> Self {
+        let mut offsets = Vec::with_capacity(expected_lines.max(1));
+        offsets.push(0u32);
         let content_length = content.len();
 
         // Use memchr for faster line break detection
@@ -44,4 +50,20 @@ impl CompactLineOffsets {
 
         Some((start, end))
     }
+
+    // 1-based line and 0-based byte column of `offset` (the end offset is valid too)
+    pub fn locate(&self, offset: usize) -> Option<(usize, usize)> {
+        if offset > self.content_length {
+            return None;
+        }
+        let line = self
+            .offsets
+            .partition_point(|&start| start as usize <= offset);
+        Some((line, offset - self.offsets[line - 1] as usize))
+    }
+
+    // Number of lines (a trailing newline starts an empty last line)
+    pub fn line_count(&self) -> usize {
+        self.offsets.len()
+    }
 }
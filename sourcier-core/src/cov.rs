@@ -0,0 +1,272 @@
+//! Coverage of spans, aggregated per line
+//!
+//! Tools that know which spans ran, like test coverage collectors or
+//! mutation testers, record them in a [`SpanCoverage`] with their hit counts.
+//! The counts are folded into lines with the line offsets of the map and
+//! written as lcov tracefiles or Cobertura XML, the formats CI services and
+//! editors display.
+//!
+//! A line counts as many hits as the most hit span over it, so a line is
+//! covered as soon as some of its code ran. Lines a span only reaches through
+//! an empty end, or that hold nothing but whitespace, are not counted.
+
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+
+/// Hit counts of spans, by file
+#[derive(Debug, Clone)]
+pub struct SpanCoverage<Id: FileId> {
+    spans: BTreeMap<Id, Vec<(AbsolutePosition<Id>, u64)>>,
+    // Index of each span among the spans of its file
+    index: HashMap<AbsolutePosition<Id>, usize>,
+}
+
+impl<Id: FileId> Default for SpanCoverage<Id> {
+    fn default() -> Self {
+        Self {
+            spans: BTreeMap::new(),
+            index: HashMap::new(),
+        }
+    }
+}
+
+/// Lines found and hit in a file, or in all of them
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CoverageSummary {
+    pub lines_found: usize,
+    pub lines_hit: usize,
+}
+
+impl CoverageSummary {
+    /// Summary of the hits of a file, by line
+    fn of(lines: &BTreeMap<usize, u64>) -> Self {
+        Self {
+            lines_found: lines.len(),
+            lines_hit: lines.values().filter(|hits| **hits > 0).count(),
+        }
+    }
+
+    /// Share of the lines hit, 1 when there are none
+    pub fn line_rate(&self) -> f64 {
+        match self.lines_found {
+            0 => 1.0,
+            found => self.lines_hit as f64 / found as f64,
+        }
+    }
+
+    fn add(&mut self, other: Self) {
+        self.lines_found += other.lines_found;
+        self.lines_hit += other.lines_hit;
+    }
+}
+
+/// Escape text for an XML attribute value
+fn xml_attr(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl<Id: FileId> SpanCoverage<Id> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `hits` to a span; the hits of a span marked again add up
+    pub fn mark(&mut self, pos: AbsolutePosition<Id>, hits: u64) {
+        let spans = self.spans.entry(pos.file_id()).or_default();
+        match self.index.entry(pos) {
+            Entry::Occupied(at) => {
+                let total = &mut spans[*at.get()].1;
+                *total = total.saturating_add(hits);
+            }
+            Entry::Vacant(at) => {
+                at.insert(spans.len());
+                spans.push((pos, hits));
+            }
+        }
+    }
+
+    /// Record that a span ran once
+    pub fn covered(&mut self, pos: AbsolutePosition<Id>) {
+        self.mark(pos, 1);
+    }
+
+    /// Record a span that never ran
+    pub fn uncovered(&mut self, pos: AbsolutePosition<Id>) {
+        self.mark(pos, 0);
+    }
+
+    /// Add the hits of another coverage, e.g. of another test run
+    pub fn merge(&mut self, other: &Self) {
+        for (pos, hits) in other.spans.values().flatten() {
+            self.mark(*pos, *hits);
+        }
+    }
+
+    /// Spans of a file with their hits, in marking order
+    pub fn spans(&self, id: Id) -> &[(AbsolutePosition<Id>, u64)] {
+        self.spans.get(&id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Files with marked spans, by ID
+    pub fn files(&self) -> impl Iterator<Item = Id> + '_ {
+        self.spans.keys().copied()
+    }
+
+    /// Hits of each counted line of a file, by 1-based line
+    ///
+    /// Empty when the file is not in `map` or has no line offsets.
+    pub fn line_hits(&self, map: &SourceFilesMap<Id>, id: Id) -> BTreeMap<usize, u64> {
+        let mut lines = BTreeMap::new();
+        let (Some(offsets), Some(content)) = (map.line_offsets(id), map.get_content(id)) else {
+            return lines;
+        };
+        for (pos, hits) in self.spans(id) {
+            let start = pos.start_line() as usize;
+            let mut end = pos.end_line() as usize;
+            if end > start && pos.end_column() == 0 {
+                end -= 1;
+            }
+            for line in start..=end {
+                let Some((line_start, line_end)) = offsets.get_line_range(line) else {
+                    continue;
+                };
+                if content[line_start..line_end].trim_ascii().is_empty() {
+                    continue;
+                }
+                let total = lines.entry(line).or_insert(0);
+                *total = (*total).max(*hits);
+            }
+        }
+        lines
+    }
+
+    /// Lines found and hit in a file
+    pub fn summary(&self, map: &SourceFilesMap<Id>, id: Id) -> CoverageSummary {
+        CoverageSummary::of(&self.line_hits(map, id))
+    }
+
+    /// Lines found and hit in every file
+    pub fn total(&self, map: &SourceFilesMap<Id>) -> CoverageSummary {
+        let mut total = CoverageSummary::default();
+        for id in self.files() {
+            total.add(self.summary(map, id));
+        }
+        total
+    }
+
+    /// Write an lcov tracefile, one record per file with its path in `map`
+    ///
+    /// `test_name` fills the `TN:` line, left empty when None. Files missing
+    /// from `map` are left out.
+    pub fn write_lcov(
+        &self,
+        map: &SourceFilesMap<Id>,
+        test_name: Option<&str>,
+        mut out: impl Write,
+    ) -> io::Result<()> {
+        for id in self.files() {
+            let Some(path) = map.get_path(id) else {
+                continue;
+            };
+            writeln!(out, "TN:{}", test_name.unwrap_or_default())?;
+            writeln!(out, "SF:{path}")?;
+            let lines = self.line_hits(map, id);
+            for (line, hits) in &lines {
+                writeln!(out, "DA:{line},{hits}")?;
+            }
+            let summary = CoverageSummary::of(&lines);
+            writeln!(out, "LF:{}", summary.lines_found)?;
+            writeln!(out, "LH:{}", summary.lines_hit)?;
+            writeln!(out, "end_of_record")?;
+        }
+        out.flush()
+    }
+
+    /// Write a Cobertura XML report, one package per directory
+    ///
+    /// `timestamp` is in milliseconds since the Unix epoch, as Cobertura
+    /// writes it; branches are not tracked, so their rates are 0. Files
+    /// missing from `map` are left out.
+    pub fn write_cobertura(
+        &self,
+        map: &SourceFilesMap<Id>,
+        timestamp: u64,
+        mut out: impl Write,
+    ) -> io::Result<()> {
+        type Class<'a> = (&'a str, BTreeMap<usize, u64>, CoverageSummary);
+        let mut packages: BTreeMap<&str, Vec<Class>> = BTreeMap::new();
+        let mut total = CoverageSummary::default();
+        for id in self.files() {
+            if let Some(path) = map.get_path(id) {
+                let package = path.rsplit_once('/').map_or(".", |(dir, _)| dir);
+                let lines = self.line_hits(map, id);
+                let summary = CoverageSummary::of(&lines);
+                total.add(summary);
+                packages
+                    .entry(package)
+                    .or_default()
+                    .push((path, lines, summary));
+            }
+        }
+        writeln!(out, r#"<?xml version="1.0" ?>"#)?;
+        writeln!(
+            out,
+            r#"<!DOCTYPE coverage SYSTEM "http://cobertura.sourceforge.net/xml/coverage-04.dtd">"#
+        )?;
+        writeln!(
+            out,
+            r#"<coverage line-rate="{:.4}" branch-rate="0" lines-covered="{}" lines-valid="{}" branches-covered="0" branches-valid="0" complexity="0" version="sourcier {}" timestamp="{timestamp}">"#,
+            total.line_rate(),
+            total.lines_hit,
+            total.lines_found,
+            env!("CARGO_PKG_VERSION"),
+        )?;
+        writeln!(out, "  <sources>\n    <source>.</source>\n  </sources>")?;
+        writeln!(out, "  <packages>")?;
+        for (package, files) in packages {
+            let mut summary = CoverageSummary::default();
+            for (_, _, class) in &files {
+                summary.add(*class);
+            }
+            writeln!(
+                out,
+                r#"    <package name="{}" line-rate="{:.4}" branch-rate="0" complexity="0">"#,
+                xml_attr(package),
+                summary.line_rate(),
+            )?;
+            writeln!(out, "      <classes>")?;
+            for (path, lines, summary) in files {
+                writeln!(
+                    out,
+                    r#"        <class name="{0}" filename="{0}" line-rate="{1:.4}" branch-rate="0" complexity="0">"#,
+                    xml_attr(path),
+                    summary.line_rate(),
+                )?;
+                writeln!(out, "          <methods/>\n          <lines>")?;
+                for (line, hits) in lines {
+                    writeln!(
+                        out,
+                        r#"            <line number="{line}" hits="{hits}" branch="false"/>"#
+                    )?;
+                }
+                writeln!(out, "          </lines>\n        </class>")?;
+            }
+            writeln!(out, "      </classes>\n    </package>")?;
+        }
+        writeln!(out, "  </packages>\n</coverage>")?;
+        out.flush()
+    }
+}
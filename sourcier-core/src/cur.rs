@@ -0,0 +1,153 @@
+use crate::fid::{FileId, RelativePosition};
+use crate::fvw::FileRef;
+
+/// Forward scanner over a file's content tracking line and column
+///
+/// Lines are 1-based and columns count bytes, like the positions `view`
+/// resolves: a span from [`Cursor::span_from`] views back exactly the bytes
+/// consumed since its [`Mark`].
+#[derive(Debug, Clone)]
+pub struct Cursor<'a> {
+    content: &'a [u8],
+    offset: usize,
+    line: usize,
+    // Bytes since the start of the current line
+    col: usize,
+}
+
+/// Saved location of a [`Cursor`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mark {
+    offset: usize,
+    line: usize,
+    col: usize,
+}
+
+impl Mark {
+    /// Byte offset in the content
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a> Cursor<'a> {
+    pub fn new(content: &'a [u8]) -> Self {
+        Self {
+            content,
+            offset: 0,
+            line: 1,
+            col: 0,
+        }
+    }
+
+    /// Byte offset of the next unread byte
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// 1-based line of the next unread byte
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// 1-based byte column of the next unread byte
+    pub fn column(&self) -> usize {
+        self.col + 1
+    }
+
+    pub fn is_eof(&self) -> bool {
+        self.offset >= self.content.len()
+    }
+
+    /// Unread content
+    pub fn rest(&self) -> &'a [u8] {
+        &self.content[self.offset..]
+    }
+
+    /// Next unread byte, without consuming it
+    pub fn peek(&self) -> Option<u8> {
+        self.content.get(self.offset).copied()
+    }
+
+    /// Unread byte `n` positions ahead, without consuming anything
+    pub fn peek_nth(&self, n: usize) -> Option<u8> {
+        self.content.get(self.offset + n).copied()
+    }
+
+    /// Consume one byte
+    pub fn advance(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.offset += 1;
+        if byte == b'\n' {
+            self.line += 1;
+            self.col = 0;
+        } else {
+            self.col += 1;
+        }
+        Some(byte)
+    }
+
+    /// Consume up to `n` bytes, returning them
+    pub fn advance_by(&mut self, n: usize) -> &'a [u8] {
+        let start = self.offset;
+        let end = (start + n).min(self.content.len());
+        let consumed = &self.content[start..end];
+        match memchr::memrchr(b'\n', consumed) {
+            Some(last) => {
+                self.line += memchr::memchr_iter(b'\n', consumed).count();
+                self.col = consumed.len() - last - 1;
+            }
+            None => self.col += consumed.len(),
+        }
+        self.offset = end;
+        consumed
+    }
+
+    /// Consume `byte` if it is next
+    pub fn eat(&mut self, byte: u8) -> bool {
+        let next = self.peek() == Some(byte);
+        if next {
+            self.advance();
+        }
+        next
+    }
+
+    /// Consume bytes while `pred` holds, returning them
+    pub fn eat_while(&mut self, mut pred: impl FnMut(u8) -> bool) -> &'a [u8] {
+        let len = self.rest().iter().take_while(|&&byte| pred(byte)).count();
+        self.advance_by(len)
+    }
+
+    /// Remember the current location
+    pub fn mark(&self) -> Mark {
+        Mark {
+            offset: self.offset,
+            line: self.line,
+            col: self.col,
+        }
+    }
+
+    /// Bytes consumed since `mark`
+    pub fn consumed(&self, mark: Mark) -> &'a [u8] {
+        &self.content[mark.offset..self.offset]
+    }
+
+    /// Position covering the bytes consumed since `mark`
+    ///
+    /// None when a line or column exceeds what positions can encode.
+    pub fn span_from(&self, mark: Mark) -> Option<RelativePosition> {
+        Some(RelativePosition::new(
+            mark.line.try_into().ok()?,
+            (mark.col + 1).try_into().ok()?,
+            self.line.try_into().ok()?,
+            self.col.try_into().ok()?,
+        ))
+    }
+}
+
+impl<'a, Id: FileId> FileRef<'a, Id> {
+    /// Scanner over the pinned file's content
+    pub fn cursor(&self) -> Cursor<'a> {
+        Cursor::new(self.content())
+    }
+}
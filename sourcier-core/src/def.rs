@@ -0,0 +1,151 @@
+use crate::dgn::Fix;
+use crate::fid::{AbsolutePosition, FileId};
+use std::collections::HashMap;
+use std::hash::Hash;
+
+#[derive(Debug, Clone)]
+struct Symbol<Id: FileId> {
+    definition: Option<AbsolutePosition<Id>>,
+    references: Vec<AbsolutePosition<Id>>,
+}
+
+/// Spans defining and referencing symbols, keyed by any symbol identity
+///
+/// The skeleton of go-to-definition, find-references and rename: fill it from
+/// a resolver pass, then query it by key or by cursor position.
+#[derive(Debug, Clone)]
+pub struct DefinitionIndex<K, Id: FileId> {
+    symbols: HashMap<K, Symbol<Id>>,
+    // Every recorded span with its key, for position lookups
+    spans: HashMap<Id, Vec<(AbsolutePosition<Id>, K)>>,
+}
+
+impl<K, Id: FileId> Default for DefinitionIndex<K, Id> {
+    fn default() -> Self {
+        Self {
+            symbols: HashMap::new(),
+            spans: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, Id: FileId> DefinitionIndex<K, Id> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record where `key` is defined, replacing a previous definition
+    pub fn define(&mut self, key: K, span: AbsolutePosition<Id>) {
+        let symbol = self.symbol(&key);
+        let previous = symbol.definition.replace(span);
+        if let Some(previous) = previous {
+            self.forget_span(&key, previous);
+        }
+        self.spans
+            .entry(span.file_id())
+            .or_default()
+            .push((span, key));
+    }
+
+    /// Record a use of `key`
+    pub fn reference(&mut self, key: K, span: AbsolutePosition<Id>) {
+        self.symbol(&key).references.push(span);
+        self.spans
+            .entry(span.file_id())
+            .or_default()
+            .push((span, key));
+    }
+
+    fn symbol(&mut self, key: &K) -> &mut Symbol<Id> {
+        self.symbols.entry(key.clone()).or_insert_with(|| Symbol {
+            definition: None,
+            references: Vec::new(),
+        })
+    }
+
+    fn forget_span(&mut self, key: &K, span: AbsolutePosition<Id>) {
+        if let Some(spans) = self.spans.get_mut(&span.file_id())
+            && let Some(at) = spans.iter().position(|(s, k)| *s == span && k == key)
+        {
+            spans.swap_remove(at);
+        }
+    }
+
+    /// Defining span of `key`
+    pub fn definition(&self, key: &K) -> Option<AbsolutePosition<Id>> {
+        self.symbols.get(key)?.definition
+    }
+
+    /// Recorded uses of `key`, in recording order
+    pub fn references_of(&self, key: &K) -> &[AbsolutePosition<Id>] {
+        self.symbols
+            .get(key)
+            .map_or(&[], |symbol| symbol.references.as_slice())
+    }
+
+    /// Symbol whose definition or reference spans `pos`, with its definition
+    ///
+    /// `pos` is usually a cursor, i.e. an empty span; a cursor just past an
+    /// identifier still hits it. When spans nest, the innermost one wins. The
+    /// definition is None for symbols only ever referenced.
+    pub fn definition_at(
+        &self,
+        pos: &AbsolutePosition<Id>,
+    ) -> Option<(&K, Option<AbsolutePosition<Id>>)> {
+        let (start, end) = pos.bounds();
+        let (_, key) = self
+            .spans
+            .get(&pos.file_id())?
+            .iter()
+            .filter(|(span, _)| {
+                let (span_start, span_end) = span.bounds();
+                span_start <= start && end <= span_end
+            })
+            .min_by_key(|(span, _)| {
+                let (span_start, span_end) = span.bounds();
+                (
+                    span_end.0 - span_start.0,
+                    span_end.1 as i16 - span_start.1 as i16,
+                )
+            })?;
+        Some((key, self.definition(key)))
+    }
+
+    /// Fixes replacing the definition and every reference of `key` with `new_text`
+    ///
+    /// Feed them to [`apply_fixes`](crate::apply_fixes); spans recorded twice
+    /// would conflict, so each is emitted once.
+    pub fn rename(&self, key: &K, new_text: &str) -> Vec<Fix<Id>> {
+        let Some(symbol) = self.symbols.get(key) else {
+            return Vec::new();
+        };
+        let mut fixes: Vec<Fix<Id>> = Vec::new();
+        for span in symbol.definition.iter().chain(&symbol.references) {
+            if !fixes.iter().any(|fix| fix.span == *span) {
+                fixes.push(Fix {
+                    span: *span,
+                    replacement: new_text.to_string(),
+                });
+            }
+        }
+        fixes
+    }
+
+    /// Every symbol with its definition and references, in no particular order
+    pub fn symbols(
+        &self,
+    ) -> impl Iterator<Item = (&K, Option<AbsolutePosition<Id>>, &[AbsolutePosition<Id>])> {
+        self.symbols
+            .iter()
+            .map(|(key, symbol)| (key, symbol.definition, symbol.references.as_slice()))
+    }
+
+    /// Number of known symbols
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
@@ -0,0 +1,243 @@
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+
+/// How serious a diagnostic is, from least to most severe
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    Help,
+    Note,
+    Warning,
+    Error,
+}
+
+/// Secondary span of a diagnostic with its own message
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label<Id: FileId> {
+    pub pos: AbsolutePosition<Id>,
+    pub message: String,
+}
+
+/// Machine-applicable replacement of a span's text
+///
+/// An empty span inserts `replacement`, an empty `replacement` deletes the
+/// span. See [`apply_fixes`](crate::apply_fixes).
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fix<Id: FileId> {
+    pub span: AbsolutePosition<Id>,
+    pub replacement: String,
+}
+
+/// A finding about a span of a source file
+///
+/// The common carrier for the interop layers: built here, then rendered or
+/// converted by whichever output the tool targets.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic<Id: FileId> {
+    pub severity: Severity,
+    /// Rule or error code, e.g. `unused-variable` or `E0308`
+    pub code: Option<String>,
+    pub message: String,
+    pub primary: AbsolutePosition<Id>,
+    pub labels: Vec<Label<Id>>,
+    pub notes: Vec<String>,
+    /// Suggested edits, applied together to resolve the diagnostic
+    pub fixes: Vec<Fix<Id>>,
+}
+
+impl<Id: FileId> Diagnostic<Id> {
+    pub fn new(
+        severity: Severity,
+        message: impl Into<String>,
+        primary: AbsolutePosition<Id>,
+    ) -> Self {
+        Self {
+            severity,
+            code: None,
+            message: message.into(),
+            primary,
+            labels: Vec::new(),
+            notes: Vec::new(),
+            fixes: Vec::new(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>, primary: AbsolutePosition<Id>) -> Self {
+        Self::new(Severity::Error, message, primary)
+    }
+
+    pub fn warning(message: impl Into<String>, primary: AbsolutePosition<Id>) -> Self {
+        Self::new(Severity::Warning, message, primary)
+    }
+
+    pub fn with_code(mut self, code: impl Into<String>) -> Self {
+        self.code = Some(code.into());
+        self
+    }
+
+    pub fn with_label(mut self, pos: AbsolutePosition<Id>, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            pos,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_fix(mut self, span: AbsolutePosition<Id>, replacement: impl Into<String>) -> Self {
+        self.fixes.push(Fix {
+            span,
+            replacement: replacement.into(),
+        });
+        self
+    }
+
+    /// Key ordering diagnostics by location, most severe first on ties
+    fn sort_key(&self) -> (Id, u16, u8, Reverse<Severity>) {
+        (
+            self.primary.file_id(),
+            self.primary.start_line(),
+            self.primary.start_column(),
+            Reverse(self.severity),
+        )
+    }
+}
+
+/// Collection of diagnostics with filtering and sorting
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = "", transparent))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiagnosticBag<Id: FileId> {
+    diagnostics: Vec<Diagnostic<Id>>,
+}
+
+impl<Id: FileId> Default for DiagnosticBag<Id> {
+    fn default() -> Self {
+        Self {
+            diagnostics: Vec::new(),
+        }
+    }
+}
+
+impl<Id: FileId> DiagnosticBag<Id> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic<Id>) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic<Id>> {
+        self.diagnostics.iter()
+    }
+
+    /// Check whether any diagnostic is an error
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    /// Number of diagnostics of exactly `severity`
+    pub fn count(&self, severity: Severity) -> usize {
+        self.diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == severity)
+            .count()
+    }
+
+    /// Diagnostics at least as severe as `min`
+    pub fn at_least(&self, min: Severity) -> impl Iterator<Item = &Diagnostic<Id>> {
+        self.diagnostics
+            .iter()
+            .filter(move |diagnostic| diagnostic.severity >= min)
+    }
+
+    /// Diagnostics whose primary span is in file `id`
+    pub fn in_file(&self, id: Id) -> impl Iterator<Item = &Diagnostic<Id>> {
+        self.diagnostics
+            .iter()
+            .filter(move |diagnostic| diagnostic.primary.file_id() == id)
+    }
+
+    /// Diagnostics with the given code
+    pub fn with_code<'a>(&'a self, code: &'a str) -> impl Iterator<Item = &'a Diagnostic<Id>> {
+        self.diagnostics
+            .iter()
+            .filter(move |diagnostic| diagnostic.code.as_deref() == Some(code))
+    }
+
+    /// Suggested fixes of every diagnostic, in diagnostic order
+    pub fn fixes(&self) -> impl Iterator<Item = &Fix<Id>> {
+        self.diagnostics
+            .iter()
+            .flat_map(|diagnostic| &diagnostic.fixes)
+    }
+
+    /// Keep only the diagnostics matching `keep`
+    pub fn retain(&mut self, keep: impl FnMut(&Diagnostic<Id>) -> bool) {
+        self.diagnostics.retain(keep);
+    }
+
+    /// Order by file, line and column, most severe first on the same spot
+    ///
+    /// The sort is stable, so diagnostics reported on the same spot with the
+    /// same severity keep their emission order.
+    pub fn sort(&mut self) {
+        self.diagnostics.sort_by_key(Diagnostic::sort_key);
+    }
+}
+
+impl<Id: FileId> Extend<Diagnostic<Id>> for DiagnosticBag<Id> {
+    fn extend<T: IntoIterator<Item = Diagnostic<Id>>>(&mut self, iter: T) {
+        self.diagnostics.extend(iter);
+    }
+}
+
+impl<Id: FileId> FromIterator<Diagnostic<Id>> for DiagnosticBag<Id> {
+    fn from_iter<T: IntoIterator<Item = Diagnostic<Id>>>(iter: T) -> Self {
+        Self {
+            diagnostics: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl<Id: FileId> IntoIterator for DiagnosticBag<Id> {
+    type Item = Diagnostic<Id>;
+    type IntoIter = std::vec::IntoIter<Diagnostic<Id>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.diagnostics.into_iter()
+    }
+}
+
+impl<'a, Id: FileId> IntoIterator for &'a DiagnosticBag<Id> {
+    type Item = &'a Diagnostic<Id>;
+    type IntoIter = std::slice::Iter<'a, Diagnostic<Id>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
@@ -0,0 +1,114 @@
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+use similar::{Algorithm, DiffOp};
+
+/// Line mapping between two revisions of a file, for porting many positions
+///
+/// Lines are compared without their line breaks. A span survives when every
+/// line it touches is unchanged and no line was inserted or removed inside it;
+/// columns are kept as is since the lines are identical.
+#[derive(Debug, Clone)]
+pub struct LinePorter {
+    // New 0-based line index by old 0-based line index, None for changed lines
+    lines: Vec<Option<usize>>,
+}
+
+impl LinePorter {
+    pub fn new(old_content: &[u8], new_content: &[u8]) -> Self {
+        let old: Vec<&[u8]> = old_content.split(|&b| b == b'\n').collect();
+        let new: Vec<&[u8]> = new_content.split(|&b| b == b'\n').collect();
+        let mut lines = vec![None; old.len()];
+        for op in similar::capture_diff_slices(Algorithm::Myers, &old, &new) {
+            if let DiffOp::Equal {
+                old_index,
+                new_index,
+                len,
+            } = op
+            {
+                for offset in 0..len {
+                    lines[old_index + offset] = Some(new_index + offset);
+                }
+            }
+        }
+        Self { lines }
+    }
+
+    /// New 1-based line of an unchanged old 1-based line
+    pub fn port_line(&self, line: u16) -> Option<u16> {
+        let index = (line as usize).checked_sub(1)?;
+        let new = (*self.lines.get(index)?)?;
+        (new + 1).try_into().ok()
+    }
+
+    /// Position of the same text in the new revision, if it survived
+    pub fn port<Id: FileId>(&self, pos: &AbsolutePosition<Id>) -> Option<AbsolutePosition<Id>> {
+        let start_line = self.port_line(pos.start_line())?;
+        // Every spanned line must survive, and land right after the previous
+        // one: edits or insertions inside the span change what it covers
+        let lines = pos.start_line()..=pos.end_line();
+        if lines.is_empty() {
+            return None;
+        }
+        let mut end_line = start_line;
+        for line in lines.skip(1) {
+            if self.port_line(line)? != end_line.checked_add(1)? {
+                return None;
+            }
+            end_line += 1;
+        }
+        Some(AbsolutePosition::new(
+            pos.file_id(),
+            start_line,
+            pos.start_column(),
+            end_line,
+            pos.end_column(),
+        ))
+    }
+}
+
+/// Map a span from an old revision of a file to the new one
+///
+/// None when the spanned lines were edited. Build a [`LinePorter`] instead to
+/// port several positions without diffing again.
+pub fn port_position<Id: FileId>(
+    old_content: &[u8],
+    new_content: &[u8],
+    pos: &AbsolutePosition<Id>,
+) -> Option<AbsolutePosition<Id>> {
+    LinePorter::new(old_content, new_content).port(pos)
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Unified diff from the original to the edited content of a file
+    ///
+    /// Shows what [`SourceFilesMap::flush_to_disk`] would change, with `a/` and
+    /// `b/` prefixed paths and 3 lines of context. Empty for unedited files and
+    /// unknown IDs; invalid UTF-8 is shown lossily, and binary files only get
+    /// a `Binary files ... differ` line like git prints.
+    pub fn pending_diff(&self, id: Id) -> String {
+        let (Some(path), Some(original), Some(content)) = (
+            self.get_path(id),
+            self.original_content(id),
+            self.get_content(id),
+        ) else {
+            return String::new();
+        };
+        if self.is_binary(id) || crate::lod::looks_binary(original) {
+            return format!("Binary files a/{path} and b/{path} differ\n");
+        }
+        let original = String::from_utf8_lossy(original);
+        let content = String::from_utf8_lossy(content);
+        similar::TextDiff::from_lines(original.as_ref(), content.as_ref())
+            .unified_diff()
+            .header(&format!("a/{path}"), &format!("b/{path}"))
+            .to_string()
+    }
+
+    /// Diffs of every edited file, in ID order, as one patch
+    pub fn pending_diff_all(&self) -> String {
+        self.edited_files()
+            .into_iter()
+            .map(|id| self.pending_diff(id))
+            .collect()
+    }
+}
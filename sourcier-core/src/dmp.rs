@@ -0,0 +1,160 @@
+use crate::err::SourceFilesError;
+use crate::fid::{FileId, IdWidth};
+#[cfg(feature = "view")]
+use crate::fid::{RelativePosition, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+#[cfg(feature = "view")]
+use std::ops::Range;
+
+/// Map whose ID type is picked at runtime, with `u64` IDs at the boundary
+///
+/// For frameworks that do not know the workspace size at compile time. The
+/// map starts with `u8` IDs and moves to `u16`, then `u32`, IDs by itself
+/// when [`DynSourceFilesMap::add_file`] would overflow them, keeping every ID
+/// handed out so far. The typed map stays reachable through the variants.
+#[derive(Debug, Clone)]
+pub enum DynSourceFilesMap {
+    U8(SourceFilesMap<u8>),
+    U16(SourceFilesMap<u16>),
+    U32(SourceFilesMap<u32>),
+}
+
+impl Default for DynSourceFilesMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<SourceFilesMap<u8>> for DynSourceFilesMap {
+    fn from(map: SourceFilesMap<u8>) -> Self {
+        Self::U8(map)
+    }
+}
+
+impl From<SourceFilesMap<u16>> for DynSourceFilesMap {
+    fn from(map: SourceFilesMap<u16>) -> Self {
+        Self::U16(map)
+    }
+}
+
+impl From<SourceFilesMap<u32>> for DynSourceFilesMap {
+    fn from(map: SourceFilesMap<u32>) -> Self {
+        Self::U32(map)
+    }
+}
+
+/// Run `$body` on the typed map, whatever its ID type
+macro_rules! dispatch {
+    ($self:expr, $map:ident => $body:expr) => {
+        match $self {
+            DynSourceFilesMap::U8($map) => $body,
+            DynSourceFilesMap::U16($map) => $body,
+            DynSourceFilesMap::U32($map) => $body,
+        }
+    };
+}
+
+/// Typed ID from a boundary ID, None when out of range
+fn typed<Id: FileId>(id: u64) -> Option<Id> {
+    Id::try_from(id).ok()
+}
+
+impl DynSourceFilesMap {
+    /// Empty map with `u8` IDs
+    pub fn new() -> Self {
+        Self::U8(SourceFilesMap::new())
+    }
+
+    /// Empty map with IDs of `width`, None for widths no map supports
+    ///
+    /// Every width is supported now, so this is always Some.
+    pub fn with_width(width: IdWidth) -> Option<Self> {
+        Some(match width {
+            IdWidth::U8 => Self::U8(SourceFilesMap::new()),
+            IdWidth::U16 => Self::U16(SourceFilesMap::new()),
+            IdWidth::U32 => Self::U32(SourceFilesMap::new()),
+        })
+    }
+
+    /// Width of the IDs currently in use
+    pub fn width(&self) -> IdWidth {
+        match self {
+            Self::U8(_) => IdWidth::U8,
+            Self::U16(_) => IdWidth::U16,
+            Self::U32(_) => IdWidth::U32,
+        }
+    }
+
+    /// Add a file, widening the IDs first when the map is full
+    ///
+    /// Fails like [`SourceFilesMap::add_file`] once `u32` IDs are exhausted.
+    pub fn add_file(&mut self, path: String, content: Vec<u8>) -> Result<(), SourceFilesError> {
+        if let Self::U8(map) = self
+            && map.len() >= u8::MAX_FILES
+        {
+            let (wide, _) = std::mem::take(map).widen::<u16>();
+            *self = Self::U16(wide);
+        }
+        if let Self::U16(map) = self
+            && map.len() >= u16::MAX_FILES
+        {
+            let (wide, _) = std::mem::take(map).widen::<u32>();
+            *self = Self::U32(wide);
+        }
+        dispatch!(self, map => map.add_file(path, content))
+    }
+
+    /// Finalize: order files, resolve duplicate paths and assign IDs
+    pub fn finalize(&mut self) -> Result<(), SourceFilesError> {
+        dispatch!(self, map => map.finalize())
+    }
+
+    pub fn get_id(&self, path: &str) -> Option<u64> {
+        dispatch!(self, map => map.get_id(path).map(Into::into))
+    }
+
+    pub fn get_path(&self, id: u64) -> Option<&str> {
+        dispatch!(self, map => map.get_path(typed(id)?))
+    }
+
+    pub fn get_content(&self, id: u64) -> Option<&[u8]> {
+        dispatch!(self, map => map.get_content(typed(id)?))
+    }
+
+    pub fn line_count(&self, id: u64) -> Option<usize> {
+        dispatch!(self, map => map.line_count(typed(id)?))
+    }
+
+    pub fn content_hash(&self, id: u64) -> Option<u64> {
+        dispatch!(self, map => map.content_hash(typed(id)?))
+    }
+
+    /// View a span of a file, returning None for positions of another file
+    #[cfg(feature = "view")]
+    pub fn view(&self, id: u64, pos: &impl SourceFilePosition) -> Option<&[u8]> {
+        dispatch!(self, map => map.view(typed(id)?, pos))
+    }
+
+    /// Line and column span of a byte range of a file
+    ///
+    /// Relative, since the packed absolute form depends on the ID width.
+    #[cfg(feature = "view")]
+    pub fn position(&self, id: u64, range: Range<usize>) -> Option<RelativePosition> {
+        dispatch!(self, map => Some(map.position(typed(id)?, range)?.to_relative()))
+    }
+
+    /// Iterate over `(id, path, content)` of every file, in ID order
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (u64, &str, &[u8])> + '_> {
+        dispatch!(self, map => Box::new(
+            map.iter().map(|(id, path, content)| (id.into(), path, content))
+        ))
+    }
+
+    pub fn len(&self) -> usize {
+        dispatch!(self, map => map.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        dispatch!(self, map => map.is_empty())
+    }
+}
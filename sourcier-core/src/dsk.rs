@@ -0,0 +1,492 @@
+use crate::err::SourceFilesError;
+use crate::fid::FileId;
+use crate::lod::SkipReason;
+use crate::pfl::PathFilter;
+use crate::sfm::SourceFilesMap;
+use std::fs;
+use std::io::{self, Write};
+use std::num::NonZero;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use xxhash_rust::xxh3::xxh3_64;
+
+const BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// Disk state a file was read in: modification time and content hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DiskStamp {
+    /// Nanoseconds since the Unix epoch, 0 when unknown
+    pub(crate) mtime_ns: u64,
+    /// XXH3 of the content read
+    pub(crate) hash: u64,
+}
+
+impl DiskStamp {
+    pub(crate) fn new(metadata: &fs::Metadata, content: &[u8]) -> Self {
+        Self {
+            mtime_ns: mtime_ns(metadata),
+            hash: xxh3_64(content),
+        }
+    }
+}
+
+fn mtime_ns(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_nanos() as u64)
+}
+
+/// What [`SourceFilesMap::refresh_from_disk`] found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefreshReport<Id: FileId> {
+    /// Files reloaded because their content changed on disk
+    pub changed: Vec<Id>,
+    /// Files no longer on disk, left in the map with their last content
+    pub missing: Vec<Id>,
+    /// Edited files whose content also changed on disk, left untouched
+    pub conflicts: Vec<Id>,
+    /// Files checked and found up to date
+    pub unchanged: usize,
+}
+
+impl<Id: FileId> RefreshReport<Id> {
+    /// Files whose positions may no longer match the disk
+    pub fn stale(&self) -> impl Iterator<Item = Id> + '_ {
+        self.changed
+            .iter()
+            .chain(&self.missing)
+            .chain(&self.conflicts)
+            .copied()
+    }
+
+    /// Whether every file was up to date
+    pub fn is_clean(&self) -> bool {
+        self.changed.is_empty() && self.missing.is_empty() && self.conflicts.is_empty()
+    }
+}
+
+/// Whether a file still matches the disk, see [`SourceFilesMap::verify`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    /// The disk holds the content the file was read with
+    Fresh,
+    /// The content on disk changed since the file was read
+    Modified,
+    /// The path is no longer a regular file on disk
+    Missing,
+}
+
+/// Read `path` and compare its hash with `expected`
+fn verify_file(path: &Path, expected: u64) -> io::Result<Verification> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => {}
+        Ok(_) => return Ok(Verification::Missing),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Ok(Verification::Missing);
+        }
+        Err(error) => return Err(error),
+    }
+    match fs::read(path) {
+        Ok(content) if xxh3_64(&content) == expected => Ok(Verification::Fresh),
+        Ok(_) => Ok(Verification::Modified),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(Verification::Missing),
+        Err(error) => Err(error),
+    }
+}
+
+/// How [`SourceFilesMap::flush_to_disk`] writes files
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    /// Directory map paths are relative to (None: the working directory)
+    pub root: Option<PathBuf>,
+    /// Write new lines with the line ending the original content used
+    pub preserve_line_endings: bool,
+    /// Keep a byte order mark the original content started with
+    pub preserve_bom: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self {
+            root: None,
+            preserve_line_endings: true,
+            preserve_bom: true,
+        }
+    }
+}
+
+/// Line ending and byte order mark detected on a file's original content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextFormat {
+    pub crlf: bool,
+    pub bom: bool,
+}
+
+impl TextFormat {
+    /// Format of `content`, going by its first line break
+    pub fn detect(content: &[u8]) -> Self {
+        let crlf =
+            memchr::memchr(b'\n', content).is_some_and(|at| at > 0 && content[at - 1] == b'\r');
+        Self {
+            crlf,
+            bom: content.starts_with(BOM),
+        }
+    }
+
+    /// `content` with bare `\n` turned into `\r\n` and the BOM restored as needed
+    fn apply(&self, content: &[u8], options: &WriteOptions) -> Vec<u8> {
+        let mut out = Vec::with_capacity(content.len() + BOM.len());
+        if options.preserve_bom && self.bom && !content.starts_with(BOM) {
+            out.extend_from_slice(BOM);
+        }
+        if !(options.preserve_line_endings && self.crlf) {
+            out.extend_from_slice(content);
+            return out;
+        }
+        let mut last = 0;
+        for at in memchr::memchr_iter(b'\n', content) {
+            out.extend_from_slice(&content[last..at]);
+            if at == 0 || content[at - 1] != b'\r' {
+                out.push(b'\r');
+            }
+            last = at;
+        }
+        out.extend_from_slice(&content[last..]);
+        out
+    }
+}
+
+/// `content`, an edit of `original` normalized from `raw`, with the lines
+/// kept at its start and end taken back from `raw`
+///
+/// Normalization keeps lines where they are, so the lines of `original` and
+/// `raw` pair up one to one.
+fn restore_lines(
+    raw: &[u8],
+    original: &[u8],
+    content: &[u8],
+    format: TextFormat,
+    options: &WriteOptions,
+) -> Vec<u8> {
+    fn lines(bytes: &[u8]) -> Vec<&[u8]> {
+        bytes.split_inclusive(|&b| b == b'\n').collect()
+    }
+    let (raw, original, content) = (lines(raw), lines(original), lines(content));
+    if raw.len() != original.len() {
+        return format.apply(&content.concat(), options);
+    }
+    let prefix = original
+        .iter()
+        .zip(&content)
+        .take_while(|(kept, line)| kept == line)
+        .count();
+    let suffix = original[prefix..]
+        .iter()
+        .rev()
+        .zip(content[prefix..].iter().rev())
+        .take_while(|(kept, line)| kept == line)
+        .count();
+    // A BOM starts the first raw line when it is kept
+    let format = TextFormat {
+        bom: format.bom && prefix == 0,
+        ..format
+    };
+    let mut out = raw[..prefix].concat();
+    out.extend(format.apply(&content[prefix..content.len() - suffix].concat(), options));
+    out.extend(raw[raw.len() - suffix..].concat());
+    out
+}
+
+/// Replace `path` with `content` through a temporary file in the same directory
+pub(crate) fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(format!(".sourcier-{}.tmp", std::process::id()));
+    let temp = dir.map_or_else(|| PathBuf::from(&temp_name), |dir| dir.join(&temp_name));
+
+    let result = (|| {
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&temp, metadata.permissions())?;
+        }
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Add every file under `root` not excluded by `filter`, returning how
+    /// many were added
+    ///
+    /// Directories are walked in name order and excluded ones are not entered;
+    /// paths are stored relative to `root` with `/` separators. Files rejected
+    /// by the [`LoadOptions`](crate::LoadOptions) end up in
+    /// [`SourceFilesMap::skipped_files`], oversized ones without being read.
+    /// Symbolic links to directories are not followed.
+    pub fn add_dir(&mut self, root: impl AsRef<Path>, filter: &PathFilter) -> io::Result<usize> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("add_dir", root = %root.as_ref().display()).entered();
+        let mut added = 0;
+        let walked = self.walk_dir(root.as_ref(), "", filter, &mut added);
+        #[cfg(feature = "tracing")]
+        match &walked {
+            Ok(()) => tracing::debug!(added, "added directory"),
+            Err(error) => tracing::warn!(%error, added, "failed to add directory"),
+        }
+        walked.map(|()| added)
+    }
+
+    fn walk_dir(
+        &mut self,
+        dir: &Path,
+        prefix: &str,
+        filter: &PathFilter,
+        added: &mut usize,
+    ) -> io::Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                if !filter.excludes(&path, true) {
+                    self.walk_dir(&entry.path(), &format!("{path}/"), filter, added)?;
+                }
+                continue;
+            }
+            let metadata = fs::metadata(entry.path())?;
+            if !metadata.is_file() || filter.excludes(&path, false) {
+                continue;
+            }
+            let size = metadata.len() as usize;
+            if let Some(max) = self.load_options().max_file_size.filter(|&max| size > max) {
+                self.record_skip(path, SkipReason::TooLarge { size, max });
+                continue;
+            }
+            let content = fs::read(entry.path())?;
+            let stamp = DiskStamp::new(&metadata, &content);
+            match self.add_file(path.clone(), content) {
+                Ok(()) => {
+                    self.set_disk_stamp(path, stamp);
+                    *added += 1;
+                }
+                Err(SourceFilesError::Skipped { .. }) => {}
+                Err(error) => return Err(io::Error::other(error)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Check every file against the disk under `root`, reloading the changed ones
+    ///
+    /// A file whose modification time matches the one it was read with is
+    /// trusted without being read. Others are read and hashed: only content
+    /// that really changed is reloaded, refreshing its line offsets. Files
+    /// missing from disk, or changed on disk while edited in the map, are
+    /// reported but left as they are, so IDs never change. Meant for maps
+    /// loaded from a cache, whose content may predate the disk.
+    pub fn refresh_from_disk(&mut self, root: impl AsRef<Path>) -> io::Result<RefreshReport<Id>> {
+        let root = root.as_ref();
+        let mut report = RefreshReport {
+            changed: Vec::new(),
+            missing: Vec::new(),
+            conflicts: Vec::new(),
+            unchanged: 0,
+        };
+        let files: Vec<(Id, String)> = self
+            .iter()
+            .map(|(id, path, _)| (id, path.to_string()))
+            .collect();
+        for (id, path) in files {
+            let full = root.join(&path);
+            let metadata = match fs::metadata(&full) {
+                Ok(metadata) if metadata.is_file() => metadata,
+                Ok(_) => {
+                    report.missing.push(id);
+                    continue;
+                }
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                    report.missing.push(id);
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+            let trusted = self
+                .disk_stamp(&path)
+                .filter(|stamp| stamp.mtime_ns != 0 && stamp.mtime_ns == mtime_ns(&metadata));
+            let mut content = None;
+            let disk_hash = match trusted {
+                Some(stamp) => stamp.hash,
+                None => {
+                    let read = fs::read(&full)?;
+                    let stamp = DiskStamp::new(&metadata, &read);
+                    self.set_disk_stamp(path.clone(), stamp);
+                    content = Some(read);
+                    stamp.hash
+                }
+            };
+            if self.is_edited(id) {
+                // Edits stay; they conflict once the disk moved past their base
+                if self.source_hash(id) != Some(disk_hash) {
+                    report.conflicts.push(id);
+                } else {
+                    report.unchanged += 1;
+                }
+                continue;
+            }
+            if self.source_hash(id) == Some(disk_hash) {
+                report.unchanged += 1;
+                continue;
+            }
+            let content = match content {
+                Some(content) => content,
+                None => fs::read(&full)?,
+            };
+            let (content, normalized) = self.normalization(content);
+            self.edit_content(id, |current| {
+                *current = content;
+                true
+            });
+            self.set_normalized(id, normalized);
+            self.mark_clean(id);
+            report.changed.push(id);
+        }
+        Ok(report)
+    }
+
+    /// Check a file against the disk under `root` by hashing its content there
+    ///
+    /// The disk content is compared with the hash it was last read with, or
+    /// for files not read from disk, with the content before any edit. Unlike
+    /// [`SourceFilesMap::refresh_from_disk`], modification times are not
+    /// trusted and nothing is reloaded, so out-of-band changes are caught
+    /// even when they keep the time. Unknown IDs fail with `NotFound`.
+    pub fn verify(&self, id: Id, root: impl AsRef<Path>) -> io::Result<Verification> {
+        let (path, expected) = self.verify_job(id, root.as_ref())?;
+        verify_file(&path, expected)
+    }
+
+    /// [`SourceFilesMap::verify`] every file, in ID order
+    ///
+    /// Files are read and hashed on one thread per available core.
+    pub fn verify_all(&self, root: impl AsRef<Path>) -> io::Result<Vec<(Id, Verification)>> {
+        let root = root.as_ref();
+        let ids: Vec<Id> = self.iter().map(|(id, _, _)| id).collect();
+        // IDs stay on this thread, workers only see paths and hashes
+        let jobs = ids
+            .iter()
+            .map(|&id| self.verify_job(id, root))
+            .collect::<io::Result<Vec<_>>>()?;
+        let workers = std::thread::available_parallelism()
+            .map_or(1, NonZero::get)
+            .min(jobs.len())
+            .max(1);
+        let verified = std::thread::scope(|scope| {
+            let handles: Vec<_> = jobs
+                .chunks(jobs.len().div_ceil(workers).max(1))
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|(path, expected)| verify_file(path, *expected))
+                            .collect::<io::Result<Vec<_>>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("verify worker panicked"))
+                .collect::<io::Result<Vec<_>>>()
+        })?;
+        Ok(ids
+            .into_iter()
+            .zip(verified.into_iter().flatten())
+            .collect())
+    }
+
+    /// Disk path of `id` and the hash its content there should have
+    fn verify_job(&self, id: Id, root: &Path) -> io::Result<(PathBuf, u64)> {
+        let path = self.get_path(id).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                SourceFilesError::UnknownFile { id: id.into() },
+            )
+        })?;
+        let expected = match self.disk_stamp(path) {
+            Some(stamp) => stamp.hash,
+            None => self.source_hash(id).unwrap_or_default(),
+        };
+        Ok((root.join(path), expected))
+    }
+
+    /// Write edited files back to their paths, returning the IDs written
+    ///
+    /// `ids` selects the files to write, None meaning every edited file;
+    /// unedited files are skipped either way. Each file is replaced atomically
+    /// (temporary file, then rename) and keeps the line ending and BOM detected
+    /// on its original content, while the content in the map is left as is so
+    /// positions taken on it stay valid. Lines of a normalized file that the
+    /// edits left alone are written back as they still are on disk, so its
+    /// normalization does not reach the disk. Written files count as unedited
+    /// afterwards; on error, the files written so far do too.
+    pub fn flush_to_disk(
+        &mut self,
+        ids: Option<&[Id]>,
+        options: &WriteOptions,
+    ) -> io::Result<Vec<Id>> {
+        let targets = match ids {
+            Some(ids) => ids
+                .iter()
+                .copied()
+                .filter(|&id| self.is_edited(id))
+                .collect(),
+            None => self.edited_files(),
+        };
+        let mut written = Vec::with_capacity(targets.len());
+        for id in targets {
+            let (Some(path), Some(content), Some(original)) = (
+                self.get_path(id),
+                self.get_content(id),
+                self.original_content(id),
+            ) else {
+                continue;
+            };
+            let key = path.to_string();
+            let path = match &options.root {
+                Some(root) => root.join(path),
+                None => PathBuf::from(path),
+            };
+            let bytes = match self.normalized(id) {
+                Some(normalized) => {
+                    let format = normalized.format;
+                    // The disk still holds what was normalized, unless it moved on
+                    match fs::read(&path) {
+                        Ok(raw) if xxh3_64(&raw) == normalized.source_hash => {
+                            restore_lines(&raw, original, content, format, options)
+                        }
+                        _ => format.apply(content, options),
+                    }
+                }
+                None => TextFormat::detect(original).apply(content, options),
+            };
+            write_atomic(&path, &bytes)?;
+            if let Ok(metadata) = fs::metadata(&path) {
+                self.set_disk_stamp(key, DiskStamp::new(&metadata, &bytes));
+            }
+            self.set_flushed(id, bytes);
+            self.mark_clean(id);
+            written.push(id);
+        }
+        Ok(written)
+    }
+}
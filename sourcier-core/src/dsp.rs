@@ -0,0 +1,184 @@
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Editors with a URL scheme jumping to a file location
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EditorKind {
+    /// Plain `file://` URL, opening the file with the default application
+    File,
+    VsCode,
+    VsCodium,
+    Cursor,
+    /// JetBrains IDEs (IntelliJ IDEA, RustRover, CLion, ...)
+    Idea,
+    Zed,
+    Sublime,
+    TextMate,
+}
+
+impl EditorKind {
+    /// URL template of the editor, in [`Hyperlinks`] template syntax
+    pub fn template(self) -> &'static str {
+        match self {
+            Self::File => Hyperlinks::FILE_TEMPLATE,
+            Self::VsCode => "vscode://file{path}:{line}:{col}",
+            Self::VsCodium => "vscodium://file{path}:{line}:{col}",
+            Self::Cursor => "cursor://file{path}:{line}:{col}",
+            Self::Idea => "idea://open?file={path}&line={line}&column={col}",
+            Self::Zed => "zed://file{path}:{line}:{col}",
+            Self::Sublime => "subl://open?url=file://{path}&line={line}&column={col}",
+            Self::TextMate => "txmt://open?url=file://{path}&line={line}&column={col}",
+        }
+    }
+}
+
+/// How [`PositionDisplay::hyperlink`] builds the URL of a location
+///
+/// The template expands `{path}` (absolute, percent-encoded), `{line}` and
+/// `{col}`. The default opens the file itself; editor protocols such as
+/// `vscode://file{path}:{line}:{col}` make terminals jump to the location.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hyperlinks {
+    /// Directory map paths are relative to
+    pub root: PathBuf,
+    pub template: String,
+}
+
+impl Hyperlinks {
+    pub const FILE_TEMPLATE: &'static str = "file://{path}#L{line}";
+
+    /// `file://` links to files under `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            template: Self::FILE_TEMPLATE.to_string(),
+        }
+    }
+
+    /// Links opening files under `root` in `editor`
+    pub fn for_editor(root: impl Into<PathBuf>, editor: EditorKind) -> Self {
+        Self::new(root).with_template(editor.template())
+    }
+
+    /// Use a custom URL scheme, e.g. `idea://open?file={path}&line={line}`
+    pub fn with_template(mut self, template: impl Into<String>) -> Self {
+        self.template = template.into();
+        self
+    }
+
+    /// URL of a 1-based line and column of a map path
+    pub fn url(&self, path: &str, line: u16, col: u8) -> String {
+        let absolute = self.root.join(path);
+        let mut encoded = String::new();
+        // Windows paths need a leading slash to form `file:///C:/...`
+        let absolute = absolute.to_string_lossy().replace('\\', "/");
+        if !absolute.starts_with('/') {
+            encoded.push('/');
+        }
+        for byte in absolute.bytes() {
+            match byte {
+                b'A'..=b'Z'
+                | b'a'..=b'z'
+                | b'0'..=b'9'
+                | b'-'
+                | b'.'
+                | b'_'
+                | b'~'
+                | b'/'
+                | b':' => encoded.push(byte as char),
+                _ => encoded.push_str(&format!("%{byte:02X}")),
+            }
+        }
+        self.template
+            .replace("{path}", &encoded)
+            .replace("{line}", &line.to_string())
+            .replace("{col}", &col.to_string())
+    }
+}
+
+/// Renders a position with the path of its file, see [`SourceFilesMap::display`]
+///
+/// The full form is `src/lib.rs:10:5-12:20`, the short form `src/lib.rs:10:5`,
+/// which editors and terminals recognize as a jump target. Positions of files
+/// missing from the map show `<file N>` in place of the path.
+#[derive(Debug, Clone, Copy)]
+pub struct PositionDisplay<'a, Id: FileId> {
+    map: &'a SourceFilesMap<Id>,
+    pos: AbsolutePosition<Id>,
+    short: bool,
+    link: Option<&'a Hyperlinks>,
+}
+
+impl<'a, Id: FileId> PositionDisplay<'a, Id> {
+    /// Render only the start, as `path:line:col`
+    pub fn short(mut self) -> Self {
+        self.short = true;
+        self
+    }
+
+    /// Wrap the location in an OSC 8 terminal hyperlink to its start
+    ///
+    /// Terminals without OSC 8 support print the text alone. Positions of
+    /// files missing from the map are not linked.
+    pub fn hyperlink(mut self, links: &'a Hyperlinks) -> Self {
+        self.link = Some(links);
+        self
+    }
+}
+
+impl<Id: FileId> fmt::Display for PositionDisplay<'_, Id> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pos = &self.pos;
+        let path = self.map.get_path(pos.file_id());
+        let url = path
+            .zip(self.link)
+            .map(|(path, links)| links.url(path, pos.start_line(), pos.start_column()));
+        if let Some(url) = &url {
+            write!(f, "\x1b]8;;{url}\x1b\\")?;
+        }
+        match path {
+            Some(path) => write!(f, "{path}")?,
+            None => write!(f, "<file {}>", pos.file_id().into())?,
+        }
+        write!(f, ":{}:{}", pos.start_line(), pos.start_column())?;
+        if !self.short {
+            write!(f, "-{}:{}", pos.end_line(), pos.end_column())?;
+        }
+        if url.is_some() {
+            write!(f, "\x1b]8;;\x1b\\")?;
+        }
+        Ok(())
+    }
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// URL opening the start of a position in `editor`, for files under `root`
+    ///
+    /// None when the file is not in the map. Build a [`Hyperlinks`] with
+    /// [`Hyperlinks::for_editor`] instead to link many positions.
+    pub fn editor_url(
+        &self,
+        pos: &AbsolutePosition<Id>,
+        editor: EditorKind,
+        root: impl AsRef<Path>,
+    ) -> Option<String> {
+        let path = self.get_path(pos.file_id())?;
+        Some(Hyperlinks::for_editor(root.as_ref(), editor).url(
+            path,
+            pos.start_line(),
+            pos.start_column(),
+        ))
+    }
+
+    /// Displayable `path:line:col-line:col` form of a position
+    pub fn display(&self, pos: &AbsolutePosition<Id>) -> PositionDisplay<'_, Id> {
+        PositionDisplay {
+            map: self,
+            pos: *pos,
+            short: false,
+            link: None,
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! DWARF line programs of generated code
+//!
+//! A compiler back end records, for each instruction it emits, the span of
+//! the source it was generated from in a [`LineTable`];
+//! [`LineTable::line_program`] turns the table into the gimli
+//! [`LineProgram`] that becomes `.debug_line`, so debuggers can step through
+//! the sources of a toy compiler built on this crate.
+//!
+//! Rows use the start of their span: its 1-based line and 1-based byte
+//! column. Files are named by their map paths, relative to the working
+//! directory of the compilation unit.
+
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+use gimli::write::{Address, LineProgram, LineString};
+use gimli::{Encoding, LineEncoding};
+use std::collections::HashMap;
+
+/// Code offsets and the source spans their instructions came from
+///
+/// Offsets are relative to the start of the generated code and may be
+/// pushed in any order; spans of one offset keep the order they came in.
+#[derive(Debug, Clone)]
+pub struct LineTable<Id: FileId> {
+    // Sorted by offset, stable
+    rows: Vec<(u64, AbsolutePosition<Id>)>,
+}
+
+impl<Id: FileId> Default for LineTable<Id> {
+    fn default() -> Self {
+        Self { rows: Vec::new() }
+    }
+}
+
+impl<Id: FileId> LineTable<Id> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that the instruction at `offset` was generated from `pos`
+    ///
+    /// Constant time when offsets come in increasing order, as code generators
+    /// emit them.
+    pub fn push(&mut self, offset: u64, pos: AbsolutePosition<Id>) {
+        let at = self.rows.partition_point(|(row, _)| *row <= offset);
+        self.rows.insert(at, (offset, pos));
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Rows by offset
+    pub fn rows(&self) -> &[(u64, AbsolutePosition<Id>)] {
+        &self.rows
+    }
+
+    /// Span the instruction at `offset` belongs to: that of the closest row
+    /// at or before it
+    pub fn lookup(&self, offset: u64) -> Option<AbsolutePosition<Id>> {
+        let end = self.rows.partition_point(|(row, _)| *row <= offset);
+        self.rows[..end].last().map(|&(_, pos)| pos)
+    }
+
+    /// Line program of the code loaded at `start`, `len` bytes long
+    ///
+    /// `working_dir` is the `DW_AT_comp_dir` of the unit, which the map paths
+    /// are relative to; the file of the first row is its primary source file.
+    /// The rows form a single sequence ending at `start + len`, or at the last
+    /// row when it lies past that. Rows of files missing from `map` are left
+    /// out. None when no row is left.
+    ///
+    /// # Panics
+    ///
+    /// When `working_dir` or a path is empty or holds a NUL byte, as gimli
+    /// does.
+    pub fn line_program(
+        &self,
+        map: &SourceFilesMap<Id>,
+        encoding: Encoding,
+        working_dir: &str,
+        start: Address,
+        len: u64,
+    ) -> Option<LineProgram> {
+        let rows: Vec<_> = self
+            .rows
+            .iter()
+            .filter_map(|&(offset, pos)| Some((offset, pos, map.get_path(pos.file_id())?)))
+            .collect();
+        let (_, _, primary) = rows.first()?;
+        let string = |text: &str| LineString::String(text.as_bytes().to_vec());
+        let mut program = LineProgram::new(
+            encoding,
+            LineEncoding::default(),
+            string(working_dir),
+            None,
+            string(primary),
+            None,
+        );
+        let directory = program.default_directory();
+        let mut files = HashMap::new();
+        program.begin_sequence(Some(start));
+        let mut end = len;
+        for (offset, pos, path) in rows {
+            let file = *files
+                .entry(pos.file_id())
+                .or_insert_with(|| program.add_file(string(path), directory, None));
+            let row = program.row();
+            row.address_offset = offset;
+            row.file = file;
+            row.line = pos.start_line().into();
+            row.column = pos.start_column().into();
+            program.generate_row();
+            end = end.max(offset);
+        }
+        program.end_sequence(end);
+        Some(program)
+    }
+}
@@ -0,0 +1,139 @@
+use crate::err::SourceFilesError;
+use crate::fid::{AbsolutePosition, FileId};
+use crate::fvw::FileRef;
+use crate::sfm::SourceFilesMap;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::hash::{Hash, Hasher};
+
+/// Absolute position remembering the map generation it was created in
+///
+/// Obtained through [`SourceFilesMap::tag`]. Once the map is re-finalized its
+/// IDs may point to other files, so [`SourceFilesMap::resolve`] rejects the
+/// position instead of silently resolving it against the wrong file; upgrade it
+/// with an [`IdRemapTable`](crate::IdRemapTable) first.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EpochPosition<Id: FileId> {
+    pub pos: AbsolutePosition<Id>,
+    pub epoch: u64,
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Tag a position with the current generation of the map
+    pub fn tag(&self, pos: AbsolutePosition<Id>) -> EpochPosition<Id> {
+        EpochPosition {
+            pos,
+            epoch: self.epoch(),
+        }
+    }
+
+    /// Get the position back, if it was tagged in the current generation
+    pub fn resolve(
+        &self,
+        tagged: &EpochPosition<Id>,
+    ) -> Result<AbsolutePosition<Id>, SourceFilesError> {
+        if tagged.epoch == self.epoch() {
+            Ok(tagged.pos)
+        } else {
+            Err(SourceFilesError::StaleEpoch {
+                found: tagged.epoch,
+                current: self.epoch(),
+            })
+        }
+    }
+
+    /// View a tagged span, rejecting positions from another generation
+    #[cfg(feature = "view")]
+    pub fn view_tagged(
+        &self,
+        tagged: &EpochPosition<Id>,
+    ) -> Result<Option<&[u8]>, SourceFilesError> {
+        let pos = self.resolve(tagged)?;
+        Ok(self.view(pos.file_id(), &pos))
+    }
+}
+
+/// File ID held across map rebuilds, checked against the map in debug builds
+///
+/// Obtained through [`SourceFilesMap::handle`] or [`FileRef::handle`]. A
+/// `FileRef` and its slices borrow the map, so they cannot outlive a
+/// re-finalize; tools keeping a file around past that, e.g. in the nodes of
+/// a syntax tree, keep its handle instead. Debug builds remember the map
+/// generation and panic when the handle is used after the IDs were
+/// reassigned; release builds hold the bare ID, at no cost. Handles compare
+/// and hash by ID alone, the same in both.
+#[derive(Debug, Clone, Copy)]
+pub struct FileHandle<Id: FileId> {
+    pub(crate) id: Id,
+    #[cfg(debug_assertions)]
+    pub(crate) epoch: u64,
+}
+
+impl<Id: FileId> PartialEq for FileHandle<Id> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<Id: FileId> Eq for FileHandle<Id> {}
+
+impl<Id: FileId> Hash for FileHandle<Id> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<Id: FileId> FileHandle<Id> {
+    /// ID of the file, without any check
+    pub fn id(&self) -> Id {
+        self.id
+    }
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Handle on a file, for use across rebuilds of the map
+    pub fn handle(&self, id: Id) -> FileHandle<Id> {
+        FileHandle {
+            id,
+            #[cfg(debug_assertions)]
+            epoch: self.epoch(),
+        }
+    }
+
+    /// ID of a handle, after checking in debug builds that it is current
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, when the handle was taken before the map was
+    /// re-finalized with new IDs.
+    #[track_caller]
+    pub fn handle_id(&self, handle: FileHandle<Id>) -> Id {
+        #[cfg(debug_assertions)]
+        assert!(
+            handle.epoch == self.epoch(),
+            "file handle from map generation {} used in generation {}",
+            handle.epoch,
+            self.epoch(),
+        );
+        handle.id
+    }
+
+    /// File of a handle, see [`SourceFilesMap::handle_id`]
+    #[track_caller]
+    pub fn file_by_handle(&self, handle: FileHandle<Id>) -> Option<FileRef<'_, Id>> {
+        self.file(self.handle_id(handle))
+    }
+
+    /// View a span of the file of a handle, see [`SourceFilesMap::handle_id`]
+    #[cfg(feature = "view")]
+    #[track_caller]
+    pub fn view_by_handle(
+        &self,
+        handle: FileHandle<Id>,
+        pos: &impl crate::fid::SourceFilePosition,
+    ) -> Option<&[u8]> {
+        self.view(self.handle_id(handle), pos)
+    }
+}
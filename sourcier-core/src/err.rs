@@ -0,0 +1,70 @@
+use crate::fid::{RelativePosition, SourceFilePosition};
+use crate::lod::SkipReason;
+use std::fmt;
+
+/// Errors reported by [`SourceFilesMap`](crate::SourceFilesMap) operations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SourceFilesError {
+    /// The map already holds `Id::MAX_FILES` files; `path` was not added
+    CapacityExceeded { path: String, max_files: usize },
+    /// `count` files were left after removing duplicates, more than `Id::MAX_FILES`
+    TooManyFiles { count: usize, max_files: usize },
+    /// `path` was submitted twice under [`DuplicatePolicy::Reject`](crate::DuplicatePolicy::Reject)
+    DuplicatePath { path: String },
+    /// `path` was rejected by the [`LoadOptions`](crate::LoadOptions) of the map
+    Skipped { path: String, reason: SkipReason },
+    /// A position tagged in map generation `found` was used in generation `current`
+    StaleEpoch { found: u64, current: u64 },
+    /// A position of file `found` was used where file `expected` was required
+    FileMismatch { expected: u64, found: u64 },
+    /// No file has ID `id`
+    UnknownFile { id: u64 },
+    /// The lines or columns of `position` fall outside file `id`
+    InvalidPosition { id: u64, position: RelativePosition },
+}
+
+impl fmt::Display for SourceFilesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CapacityExceeded { path, max_files } => write!(
+                f,
+                "Cannot add {}: exceeded maximum of {} files for ID type",
+                path, max_files
+            ),
+            Self::TooManyFiles { count, max_files } => write!(
+                f,
+                "Cannot finalize {} files: exceeded maximum of {} files for ID type",
+                count, max_files
+            ),
+            Self::DuplicatePath { path } => write!(f, "Duplicate path {}", path),
+            Self::Skipped { path, reason } => write!(f, "Skipped {}: {}", path, reason),
+            Self::StaleEpoch { found, current } => write!(
+                f,
+                "Position from map generation {} used in generation {}",
+                found, current
+            ),
+            Self::FileMismatch { expected, found } => {
+                write!(f, "Position belongs to file {}, not {}", found, expected)
+            }
+            Self::UnknownFile { id } => write!(f, "No file with ID {}", id),
+            Self::InvalidPosition { id, position } => write!(
+                f,
+                "Position {}:{}-{}:{} is outside file {}",
+                position.start_line(),
+                position.start_column(),
+                position.end_line(),
+                position.end_column(),
+                id
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SourceFilesError {}
+
+// Lets `?` propagate into the `Result<_, String>` APIs
+impl From<SourceFilesError> for String {
+    fn from(error: SourceFilesError) -> Self {
+        error.to_string()
+    }
+}
@@ -0,0 +1,258 @@
+//! Exports of a finalized map for tools that do not link this crate
+//!
+//! [`SourceFilesMap::export_json`] writes a single object:
+//!
+//! ```json
+//! {
+//!   "schema": 1,
+//!   "files": [
+//!     { "id": 1, "path": "src/lib.rs", "hash": "9f2c0e6d1b7a4c33", "lines": 42, "bytes": 1337 }
+//!   ],
+//!   "spans": [
+//!     { "file": 1, "path": "src/lib.rs", "start_line": 3, "start_col": 1,
+//!       "end_line": 3, "end_col": 9, "label": "unused" }
+//!   ]
+//! }
+//! ```
+//!
+//! `hash` is the XXH3-64 of the content as 16 lowercase hex digits, `lines`
+//! counts a trailing newline as starting an empty line. `spans` is only present
+//! when [`ExportOptions::spans`] is set, and `label` is null for unlabeled spans.
+//! Fields are only ever added within a schema version.
+//!
+//! [`SpanWriter`] and [`SourceFilesMap::dump_spans`] stream the same span
+//! records one per line, as JSONL or CSV.
+
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+
+/// Version of the exported JSON schema
+pub const EXPORT_SCHEMA_VERSION: u32 = 1;
+
+/// A position with an optional label, as passed to the exporters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabeledSpan<Id: FileId> {
+    pub pos: AbsolutePosition<Id>,
+    pub label: Option<String>,
+}
+
+impl<Id: FileId> LabeledSpan<Id> {
+    pub fn new(pos: AbsolutePosition<Id>, label: Option<String>) -> Self {
+        Self { pos, label }
+    }
+}
+
+/// What [`SourceFilesMap::export_json`] writes besides the file list
+#[derive(Debug, Clone)]
+pub struct ExportOptions<Id: FileId> {
+    /// Indent the output
+    pub pretty: bool,
+    /// Spans to export alongside the files; the `spans` key is omitted when None
+    pub spans: Option<Vec<LabeledSpan<Id>>>,
+}
+
+impl<Id: FileId> Default for ExportOptions<Id> {
+    fn default() -> Self {
+        Self {
+            pretty: false,
+            spans: None,
+        }
+    }
+}
+
+/// A file entry of the export
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedFile {
+    pub id: u64,
+    pub path: String,
+    pub hash: String,
+    pub lines: usize,
+    pub bytes: usize,
+}
+
+/// A span entry of the export
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExportedSpan {
+    pub file: u64,
+    /// Path of `file`, or None when the ID is not in the map
+    pub path: Option<String>,
+    pub start_line: u16,
+    pub start_col: u8,
+    pub end_line: u16,
+    pub end_col: u8,
+    pub label: Option<String>,
+}
+
+impl ExportedSpan {
+    pub(crate) fn new<Id: FileId>(map: &SourceFilesMap<Id>, span: &LabeledSpan<Id>) -> Self {
+        let id = span.pos.file_id();
+        Self {
+            file: id.into(),
+            path: map.get_path(id).map(str::to_string),
+            start_line: span.pos.start_line(),
+            start_col: span.pos.start_column(),
+            end_line: span.pos.end_line(),
+            end_col: span.pos.end_column(),
+            label: span.label.clone(),
+        }
+    }
+}
+
+/// The exported document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Export {
+    pub schema: u32,
+    pub files: Vec<ExportedFile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spans: Option<Vec<ExportedSpan>>,
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Build the export document without serializing it
+    pub fn export(&self, options: &ExportOptions<Id>) -> Export {
+        let files = self
+            .iter()
+            .map(|(id, path, content)| ExportedFile {
+                id: id.into(),
+                path: path.to_string(),
+                hash: format!("{:016x}", self.content_hash(id).unwrap_or_default()),
+                lines: self.line_count(id).unwrap_or_default(),
+                bytes: content.len(),
+            })
+            .collect();
+        let spans = options.spans.as_ref().map(|spans| {
+            spans
+                .iter()
+                .map(|span| ExportedSpan::new(self, span))
+                .collect()
+        });
+        Export {
+            schema: EXPORT_SCHEMA_VERSION,
+            files,
+            spans,
+        }
+    }
+
+    /// Write the map as JSON following the schema documented in [`crate::exp`]
+    pub fn export_json(&self, writer: impl Write, options: ExportOptions<Id>) -> io::Result<()> {
+        let export = self.export(&options);
+        if options.pretty {
+            serde_json::to_writer_pretty(writer, &export)?;
+        } else {
+            serde_json::to_writer(writer, &export)?;
+        }
+        Ok(())
+    }
+}
+
+/// Line-oriented formats of [`SpanWriter`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// One [`ExportedSpan`] JSON object per line
+    Jsonl,
+    /// RFC 4180 CSV with a header row, columns in [`ExportedSpan`] field order
+    Csv,
+}
+
+/// Streaming writer emitting one record per position
+///
+/// Records are written as they come, so datasets larger than memory can be
+/// dumped straight into pandas or duckdb. Unlabeled spans have a null (JSONL)
+/// or empty (CSV) label, unknown files a null or empty path.
+pub struct SpanWriter<W: Write> {
+    out: W,
+    format: DumpFormat,
+    header_written: bool,
+    records: usize,
+}
+
+impl<W: Write> SpanWriter<W> {
+    const CSV_HEADER: &'static str = "file,path,start_line,start_col,end_line,end_col,label";
+
+    /// Writer of `format` records to `out`, the CSV header before the first
+    pub fn new(out: W, format: DumpFormat) -> Self {
+        Self {
+            out,
+            format,
+            header_written: false,
+            records: 0,
+        }
+    }
+
+    /// Write one span, resolving its path against `map`
+    pub fn write<Id: FileId>(
+        &mut self,
+        map: &SourceFilesMap<Id>,
+        span: &LabeledSpan<Id>,
+    ) -> io::Result<()> {
+        let record = ExportedSpan::new(map, span);
+        match self.format {
+            DumpFormat::Jsonl => {
+                serde_json::to_writer(&mut self.out, &record)?;
+                self.out.write_all(b"\n")?;
+            }
+            DumpFormat::Csv => {
+                if !self.header_written {
+                    writeln!(self.out, "{}", Self::CSV_HEADER)?;
+                    self.header_written = true;
+                }
+                writeln!(
+                    self.out,
+                    "{},{},{},{},{},{},{}",
+                    record.file,
+                    csv_field(record.path.as_deref().unwrap_or_default()),
+                    record.start_line,
+                    record.start_col,
+                    record.end_line,
+                    record.end_col,
+                    csv_field(record.label.as_deref().unwrap_or_default()),
+                )?;
+            }
+        }
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Number of records written so far
+    pub fn records(&self) -> usize {
+        self.records
+    }
+
+    /// Flush and return the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.format == DumpFormat::Csv && !self.header_written {
+            writeln!(self.out, "{}", Self::CSV_HEADER)?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Quote a CSV field when it contains a separator, quote or line break
+fn csv_field(value: &str) -> std::borrow::Cow<'_, str> {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\"")).into()
+    } else {
+        value.into()
+    }
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Dump `spans` one record per line, returning the number of records written
+    pub fn dump_spans<'s>(
+        &self,
+        out: impl Write,
+        format: DumpFormat,
+        spans: impl IntoIterator<Item = &'s LabeledSpan<Id>>,
+    ) -> io::Result<usize> {
+        let mut writer = SpanWriter::new(out, format);
+        for span in spans {
+            writer.write(self, span)?;
+        }
+        let records = writer.records();
+        writer.finish()?;
+        Ok(records)
+    }
+}
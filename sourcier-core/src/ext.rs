@@ -0,0 +1,159 @@
+use crate::err::SourceFilesError;
+use crate::fid::FileId;
+use crate::sfm::SourceFilesMap;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Values of one extension type, by slab index of the file's path
+struct Column<T>(Vec<Option<T>>);
+
+/// Type-erased [`Column`], so columns of any type share one map
+trait AnyColumn: Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn clone_box(&self) -> Box<dyn AnyColumn>;
+    /// Move the value at each `from` row to its `to` row, dropping the rest
+    fn repack(&mut self, moves: &[(u32, u32)], rows: usize);
+}
+
+impl<T: Clone + Send + Sync + 'static> AnyColumn for Column<T> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn clone_box(&self) -> Box<dyn AnyColumn> {
+        Box::new(Column(self.0.clone()))
+    }
+
+    fn repack(&mut self, moves: &[(u32, u32)], rows: usize) {
+        let mut packed: Vec<Option<T>> = Vec::with_capacity(rows);
+        packed.resize_with(rows, || None);
+        for &(from, to) in moves {
+            if let Some(value) = self.0.get_mut(from as usize).and_then(Option::take) {
+                packed[to as usize] = Some(value);
+            }
+        }
+        self.0 = packed;
+    }
+}
+
+/// Typed values attached to files by [`SourceFilesMap::set_ext`]
+///
+/// Each type gets a dense column indexed like the path slab, which is in ID
+/// order once IDs are assigned; columns are repacked with the slab.
+#[derive(Default)]
+pub(crate) struct Extensions {
+    columns: HashMap<TypeId, Box<dyn AnyColumn>>,
+}
+
+impl Extensions {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    fn column<T: 'static>(&self) -> Option<&Vec<Option<T>>> {
+        let column = self.columns.get(&TypeId::of::<T>())?;
+        Some(&column.as_any().downcast_ref::<Column<T>>()?.0)
+    }
+
+    fn get_column_mut<T: 'static>(&mut self) -> Option<&mut Vec<Option<T>>> {
+        let column = self.columns.get_mut(&TypeId::of::<T>())?;
+        Some(&mut column.as_any_mut().downcast_mut::<Column<T>>()?.0)
+    }
+
+    fn column_mut<T: Clone + Send + Sync + 'static>(&mut self) -> &mut Vec<Option<T>> {
+        let column = self
+            .columns
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(Column::<T>(Vec::new())));
+        &mut column
+            .as_any_mut()
+            .downcast_mut::<Column<T>>()
+            .expect("columns are keyed by their type")
+            .0
+    }
+
+    /// Follow paths moved by a slab compaction, as `(old index, new index)`
+    pub(crate) fn repack(&mut self, moves: &[(u32, u32)], rows: usize) {
+        for column in self.columns.values_mut() {
+            column.repack(moves, rows);
+        }
+    }
+}
+
+impl Clone for Extensions {
+    fn clone(&self) -> Self {
+        Self {
+            columns: self
+                .columns
+                .iter()
+                .map(|(&type_id, column)| (type_id, column.clone_box()))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("types", &self.columns.len())
+            .finish()
+    }
+}
+
+/// Row of `id` in the extension columns
+fn row<Id: FileId>(id: Id) -> Option<usize> {
+    let raw: u64 = id.into();
+    Some(raw.checked_sub(1)? as usize)
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Attach a value of type `T` to a file, returning the one it replaces
+    ///
+    /// Plugins can keep their own per-file data this way, one value per
+    /// type and file, e.g. a parsed tree or lint state behind a newtype.
+    /// Values follow their file when IDs are reassigned and are dropped with
+    /// it, or when a later submission of its path replaces it. They are
+    /// cloned with the map, but neither serialized nor cached.
+    pub fn set_ext<T: Clone + Send + Sync + 'static>(
+        &mut self,
+        id: Id,
+        value: T,
+    ) -> Result<Option<T>, SourceFilesError> {
+        let row = row(id)
+            .filter(|_| self.get_path(id).is_some())
+            .ok_or(SourceFilesError::UnknownFile { id: id.into() })?;
+        let column = self.extensions_mut().column_mut::<T>();
+        if column.len() <= row {
+            column.resize_with(row + 1, || None);
+        }
+        Ok(column[row].replace(value))
+    }
+
+    /// Value of type `T` attached to a file
+    pub fn get_ext<T: 'static>(&self, id: Id) -> Option<&T> {
+        self.extensions().column::<T>()?.get(row(id)?)?.as_ref()
+    }
+
+    pub fn get_ext_mut<T: 'static>(&mut self, id: Id) -> Option<&mut T> {
+        let row = row(id)?;
+        self.extensions_mut()
+            .get_column_mut::<T>()?
+            .get_mut(row)?
+            .as_mut()
+    }
+
+    /// Detach the value of type `T` from a file
+    pub fn remove_ext<T: 'static>(&mut self, id: Id) -> Option<T> {
+        let row = row(id)?;
+        self.extensions_mut()
+            .get_column_mut::<T>()?
+            .get_mut(row)?
+            .take()
+    }
+}
@@ -3,11 +3,69 @@ use serde::{Deserialize, Serialize};
 use std::convert::{TryFrom, TryInto};
 use std::hash::Hash;
 use std::marker::PhantomData;
+use std::ops::{BitAnd, BitOr, Not, Shl, Shr};
+
+/// Integer a packed [`AbsolutePosition`] is stored in
+///
+/// `u64` for IDs up to 16 bits, `u128` for wider ones.
+#[cfg(feature = "serde")]
+pub trait PackedRaw: RawBits + Serialize + for<'de> Deserialize<'de> {}
+
+/// Integer a packed [`AbsolutePosition`] is stored in
+///
+/// `u64` for IDs up to 16 bits, `u128` for wider ones.
+#[cfg(not(feature = "serde"))]
+pub trait PackedRaw: RawBits {}
+
+/// Bit operations [`AbsolutePosition`] needs from its packed integer
+pub trait RawBits:
+    Copy
+    + Eq
+    + Ord
+    + Hash
+    + std::fmt::Debug
+    + Shl<u32, Output = Self>
+    + Shr<u32, Output = Self>
+    + BitAnd<Output = Self>
+    + BitOr<Output = Self>
+    + Not<Output = Self>
+    + Send
+    + Sync
+    + 'static
+{
+    /// Widen a component value
+    fn from_u64(value: u64) -> Self;
+
+    /// Low 64 bits of the value
+    fn low_u64(self) -> u64;
+}
+
+macro_rules! impl_packed_raw {
+    ($t:ty) => {
+        impl RawBits for $t {
+            fn from_u64(value: u64) -> Self {
+                value as $t
+            }
+
+            fn low_u64(self) -> u64 {
+                self as u64
+            }
+        }
+
+        impl PackedRaw for $t {}
+    };
+}
+
+impl_packed_raw!(u64);
+impl_packed_raw!(u128);
 
 /// Trait defining core file ID behavior for numeric ID types
 pub trait FileId:
     Copy + Eq + Hash + Into<u64> + TryFrom<u64> + Ord + std::fmt::Debug + 'static
 {
+    /// Integer positions with this ID type are packed into
+    type Raw: PackedRaw;
+
     /// Maximum number of files supported by this ID type
     const MAX_FILES: usize;
 
@@ -27,14 +85,16 @@ pub trait FileId:
     const END_COL_SHIFT: u32;
 
     /// Bit masks for decoding components
-    const FILE_ID_MASK: u64;
+    const FILE_ID_MASK: Self::Raw;
     const LINE_MASK: u64;
     const COL_MASK: u64;
 }
 
 macro_rules! impl_file_id {
-    ($t:ty, $bits:expr, $file_shift:expr) => {
+    ($t:ty, $raw:ty, $bits:expr, $file_shift:expr) => {
         impl FileId for $t {
+            type Raw = $raw;
+
             const MAX_FILES: usize = <$t>::MAX as usize;
             const MAX_ID: u64 = <$t>::MAX as u64 + 1;
 
@@ -44,7 +104,7 @@ macro_rules! impl_file_id {
             const START_COL_SHIFT: u32 = $file_shift - 24;
             const END_LINE_SHIFT: u32 = $file_shift - 40;
             const END_COL_SHIFT: u32 = $file_shift - 48;
-            const FILE_ID_MASK: u64 = ((1 << $bits) - 1) << $file_shift;
+            const FILE_ID_MASK: $raw = ((1 << $bits) - 1) << $file_shift;
             const LINE_MASK: u64 = 0xFFFF;
             const COL_MASK: u64 = 0xFF;
         }
@@ -52,13 +112,49 @@ macro_rules! impl_file_id {
 }
 
 // Implement for common ID types
-impl_file_id!(u8, 8, 56);
-impl_file_id!(u16, 16, 48);
+impl_file_id!(u8, u64, 8, 56);
+impl_file_id!(u16, u64, 16, 48);
+// 32 ID bits and 48 span bits overflow a u64
+impl_file_id!(u32, u128, 32, 48);
+
+/// Width of a file ID type, used to pick the smallest one fitting a workspace
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IdWidth {
+    /// `u8` IDs, up to 255 files
+    U8,
+    /// `u16` IDs, up to 65,535 files
+    U16,
+    /// `u32` IDs, packed into `u128` positions
+    U32,
+}
+
+impl IdWidth {
+    /// Smallest width able to hold `files` files
+    pub fn for_file_count(files: usize) -> Self {
+        if files <= u8::MAX_FILES {
+            Self::U8
+        } else if files <= u16::MAX_FILES {
+            Self::U16
+        } else {
+            Self::U32
+        }
+    }
+
+    /// Maximum number of files addressable with this width
+    pub fn max_files(&self) -> usize {
+        match self {
+            Self::U8 => u8::MAX_FILES,
+            Self::U16 => u16::MAX_FILES,
+            Self::U32 => u32::MAX_FILES,
+        }
+    }
+}
 
 /// Trait for extracting source position information
 pub trait SourceFilePosition {
     /// Get the source file ID or None for relative positions
-    fn source_file_id(&self) -> Option<u16>;
+    fn source_file_id(&self) -> Option<u64>;
 
     /// Get the start line number
     fn start_line(&self) -> u16;
@@ -73,70 +169,179 @@ pub trait SourceFilePosition {
     fn end_column(&self) -> u8;
 }
 
+/// How the end column of a position is read
+///
+/// Columns are 1-based either way. Positions made by this crate, and the
+/// spans `view` resolves, are [`RangeSemantics::Inclusive`]; convert with
+/// `to_exclusive` and `to_inclusive` when exchanging positions with tools
+/// whose ranges end past the span, like LSP.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum RangeSemantics {
+    /// `end_column` is the column of the last byte of the span
+    #[default]
+    Inclusive,
+    /// `end_column` is the column just past the span
+    Exclusive,
+}
+
 /// Position with absolute file reference
 ///
+/// Serializes as its packed `u64` alone: 8 bytes with fixed-width binary
+/// codecs (bincode legacy/fixint), 1 to 10 bytes with varint codecs (postcard,
+/// bincode standard), and a plain number in self-describing formats. `u32` IDs
+/// pack into a `u128` instead, twice the fixed width.
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-#[cfg_attr(
-    feature = "serde",
-    serde(bound = "Id: Serialize + serde::de::DeserializeOwned")
-)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct AbsolutePosition<Id: FileId>(u64, PhantomData<Id>);
+#[cfg_attr(feature = "serde", serde(transparent, bound = ""))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AbsolutePosition<Id: FileId>(
+    Id::Raw,
+    #[cfg_attr(feature = "serde", serde(skip))] PhantomData<Id>,
+);
 
 impl<Id: FileId> AbsolutePosition<Id> {
     /// Create a new absolute position
     pub fn new(file_id: Id, start_line: u16, start_col: u8, end_line: u16, end_col: u8) -> Self {
-        let file_id_u64: u64 = file_id.into();
+        let raw = <Id::Raw as RawBits>::from_u64;
 
-        let encoded = (file_id_u64 << Id::FILE_ID_SHIFT)
-            | ((start_line as u64) << Id::START_LINE_SHIFT)
-            | ((start_col as u64) << Id::START_COL_SHIFT)
-            | ((end_line as u64) << Id::END_LINE_SHIFT)
-            | ((end_col as u64) << Id::END_COL_SHIFT);
+        let encoded = (raw(file_id.into()) << Id::FILE_ID_SHIFT)
+            | (raw(start_line as u64) << Id::START_LINE_SHIFT)
+            | (raw(start_col as u64) << Id::START_COL_SHIFT)
+            | (raw(end_line as u64) << Id::END_LINE_SHIFT)
+            | (raw(end_col as u64) << Id::END_COL_SHIFT);
 
         Self(encoded, PhantomData)
     }
 
     /// Get the raw encoded value
-    pub fn as_raw(&self) -> u64 {
+    pub fn as_raw(&self) -> Id::Raw {
         self.0
     }
 
+    /// Decode a value from [`AbsolutePosition::as_raw`]
+    ///
+    /// Returns None when the file ID bits do not fit `Id`, or when bits no
+    /// component uses are set, since the position would not equal the one
+    /// built from its components. Lines and columns are taken as they are, so
+    /// the position may still not fit its file.
+    pub fn from_raw(raw: Id::Raw) -> Option<Self> {
+        let bits = <Id::Raw as RawBits>::from_u64;
+        // Two lines and two columns, below the file ID
+        let span = bits((1 << 48) - 1) << Id::END_COL_SHIFT;
+        if raw & !(Id::FILE_ID_MASK | span) != bits(0) {
+            return None;
+        }
+        Id::try_from(((raw & Id::FILE_ID_MASK) >> Id::FILE_ID_SHIFT).low_u64()).ok()?;
+        Some(Self(raw, PhantomData))
+    }
+
     /// Extract the file ID component
     pub fn file_id(&self) -> Id {
-        let id_value = (self.0 & Id::FILE_ID_MASK) >> Id::FILE_ID_SHIFT;
+        let id_value = ((self.0 & Id::FILE_ID_MASK) >> Id::FILE_ID_SHIFT).low_u64();
         // This should be safe since we encoded a valid Id originally
         id_value
             .try_into()
             .unwrap_or_else(|_| panic!("Invalid file ID encoding"))
     }
+
+    /// Drop the file ID, keeping only the line and column components
+    pub fn to_relative(&self) -> RelativePosition {
+        RelativePosition::new(
+            self.start_line(),
+            self.start_column(),
+            self.end_line(),
+            self.end_column(),
+        )
+    }
+
+    /// Replace the bits of one component, leaving the others untouched
+    fn with_bits(self, shift: u32, mask: u64, value: u64) -> Self {
+        let mask = <Id::Raw as RawBits>::from_u64(mask);
+        let value = <Id::Raw as RawBits>::from_u64(value);
+        Self(
+            (self.0 & !(mask << shift)) | ((value & mask) << shift),
+            PhantomData,
+        )
+    }
+
+    /// Same span in another file
+    pub fn with_file_id(self, file_id: Id) -> Self {
+        let mask = (Id::FILE_ID_MASK >> Id::FILE_ID_SHIFT).low_u64();
+        self.with_bits(Id::FILE_ID_SHIFT, mask, file_id.into())
+    }
+
+    pub fn with_start_line(self, line: u16) -> Self {
+        self.with_bits(Id::START_LINE_SHIFT, Id::LINE_MASK, line as u64)
+    }
+
+    pub fn with_start_column(self, col: u8) -> Self {
+        self.with_bits(Id::START_COL_SHIFT, Id::COL_MASK, col as u64)
+    }
+
+    pub fn with_end_line(self, line: u16) -> Self {
+        self.with_bits(Id::END_LINE_SHIFT, Id::LINE_MASK, line as u64)
+    }
+
+    pub fn with_end_column(self, col: u8) -> Self {
+        self.with_bits(Id::END_COL_SHIFT, Id::COL_MASK, col as u64)
+    }
+
+    /// Same span with an [exclusive](RangeSemantics::Exclusive) end column
+    ///
+    /// None when the end column is `u8::MAX` already.
+    pub fn to_exclusive(self) -> Option<Self> {
+        Some(self.with_end_column(self.end_column().checked_add(1)?))
+    }
+
+    /// Same span with an [inclusive](RangeSemantics::Inclusive) end column
+    ///
+    /// None when the end column is 0, which no exclusive end can be.
+    pub fn to_inclusive(self) -> Option<Self> {
+        Some(self.with_end_column(self.end_column().checked_sub(1)?))
+    }
+
+    /// Grow the span to end where `other` ends, e.g. when a parser closes a
+    /// node spanning from its first token to its last
+    pub fn widen_to(self, other: &impl SourceFilePosition) -> Self {
+        self.with_end_line(other.end_line())
+            .with_end_column(other.end_column())
+    }
+
+    /// Start and end of the span as comparable `(line, 0-based column)` points
+    pub(crate) fn bounds(&self) -> ((u16, u8), (u16, u8)) {
+        (
+            (self.start_line(), self.start_column().saturating_sub(1)),
+            (self.end_line(), self.end_column()),
+        )
+    }
 }
 
 impl<Id: FileId> SourceFilePosition for AbsolutePosition<Id> {
-    fn source_file_id(&self) -> Option<u16> {
-        let id_value = (self.0 & Id::FILE_ID_MASK) >> Id::FILE_ID_SHIFT;
-        Some(id_value as u16) // This might truncate for larger IDs, consider returning u64 instead
+    fn source_file_id(&self) -> Option<u64> {
+        Some(self.file_id().into())
     }
 
     fn start_line(&self) -> u16 {
-        ((self.0 >> Id::START_LINE_SHIFT) & Id::LINE_MASK) as u16
+        ((self.0 >> Id::START_LINE_SHIFT).low_u64() & Id::LINE_MASK) as u16
     }
 
     fn start_column(&self) -> u8 {
-        ((self.0 >> Id::START_COL_SHIFT) & Id::COL_MASK) as u8
+        ((self.0 >> Id::START_COL_SHIFT).low_u64() & Id::COL_MASK) as u8
     }
 
     fn end_line(&self) -> u16 {
-        ((self.0 >> Id::END_LINE_SHIFT) & Id::LINE_MASK) as u16
+        ((self.0 >> Id::END_LINE_SHIFT).low_u64() & Id::LINE_MASK) as u16
     }
 
     fn end_column(&self) -> u8 {
-        ((self.0 >> Id::END_COL_SHIFT) & Id::COL_MASK) as u8
+        ((self.0 >> Id::END_COL_SHIFT).low_u64() & Id::COL_MASK) as u8
     }
 }
 
 /// Position relative to a file (file ID not included)
+///
+/// Serializes as its packed `u64`, with the same sizes as [`AbsolutePosition`].
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RelativePosition(u64);
 
@@ -159,10 +364,54 @@ impl RelativePosition {
     pub fn as_raw(&self) -> u64 {
         self.0
     }
+
+    /// Decode a value from [`RelativePosition::as_raw`]
+    ///
+    /// Returns None when bits above the start line are set.
+    pub fn from_raw(raw: u64) -> Option<Self> {
+        (raw >> (Self::START_LINE_SHIFT + 16) == 0).then_some(Self(raw))
+    }
+
+    /// Replace the bits of one component, leaving the others untouched
+    fn with_bits(self, shift: u32, mask: u64, value: u64) -> Self {
+        Self((self.0 & !(mask << shift)) | ((value & mask) << shift))
+    }
+
+    pub fn with_start_line(self, line: u16) -> Self {
+        self.with_bits(Self::START_LINE_SHIFT, 0xFFFF, line as u64)
+    }
+
+    pub fn with_start_column(self, col: u8) -> Self {
+        self.with_bits(Self::START_COL_SHIFT, 0xFF, col as u64)
+    }
+
+    pub fn with_end_line(self, line: u16) -> Self {
+        self.with_bits(Self::END_LINE_SHIFT, 0xFFFF, line as u64)
+    }
+
+    pub fn with_end_column(self, col: u8) -> Self {
+        self.with_bits(0, 0xFF, col as u64)
+    }
+
+    /// Same span with an [exclusive](RangeSemantics::Exclusive) end column
+    pub fn to_exclusive(self) -> Option<Self> {
+        Some(self.with_end_column(self.end_column().checked_add(1)?))
+    }
+
+    /// Same span with an [inclusive](RangeSemantics::Inclusive) end column
+    pub fn to_inclusive(self) -> Option<Self> {
+        Some(self.with_end_column(self.end_column().checked_sub(1)?))
+    }
+
+    /// Grow the span to end where `other` ends
+    pub fn widen_to(self, other: &impl SourceFilePosition) -> Self {
+        self.with_end_line(other.end_line())
+            .with_end_column(other.end_column())
+    }
 }
 
 impl SourceFilePosition for RelativePosition {
-    fn source_file_id(&self) -> Option<u16> {
+    fn source_file_id(&self) -> Option<u64> {
         None // Relative positions have no file ID
     }
 
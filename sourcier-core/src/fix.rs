@@ -0,0 +1,215 @@
+use crate::dgn::Fix;
+use crate::fid::{AbsolutePosition, FileId};
+use crate::rmp::EditRemap;
+use crate::sfm::SourceFilesMap;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+/// Outcome of [`apply_fixes`], fixes referred to by their index in the input
+#[derive(Debug, Clone)]
+pub struct FixReport<Id: FileId> {
+    /// Applied fixes with the span of their replacement text in the new
+    /// content (None when it no longer fits a position)
+    pub applied: Vec<(usize, Option<AbsolutePosition<Id>>)>,
+    /// Fixes skipped because they overlap a fix applied before them
+    pub conflicts: Vec<usize>,
+    /// Fixes skipped because their span does not resolve in the map, or the
+    /// edited text would end past what positions can encode
+    pub unresolved: Vec<usize>,
+    /// Carries positions taken before the fixes over to the new content
+    pub remap: EditRemap<Id>,
+}
+
+impl<Id: FileId> FixReport<Id> {
+    /// Whether every fix was applied
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty() && self.unresolved.is_empty()
+    }
+}
+
+/// An accepted fix, in byte and line/column coordinates of the old content
+struct Accepted {
+    index: usize,
+    range: Range<usize>,
+    // End of the replacement text, before edits later in the file
+    new_end: (u16, u8),
+}
+
+/// Apply machine-applicable fixes to a finalized map, like `cargo fix`
+///
+/// Fixes are taken in slice order: one overlapping an already accepted fix of
+/// the same file is reported as a conflict and skipped, so earlier fixes win.
+/// Two insertions at the same point also conflict, since their order would be
+/// ambiguous. The accepted fixes of each file are applied from the end of the
+/// file backwards, so every span stays valid while editing, and each edited
+/// file is reindexed once.
+pub fn apply_fixes<Id: FileId>(map: &mut SourceFilesMap<Id>, fixes: &[Fix<Id>]) -> FixReport<Id> {
+    let mut report = FixReport {
+        applied: Vec::new(),
+        conflicts: Vec::new(),
+        unresolved: Vec::new(),
+        remap: EditRemap::new(),
+    };
+    let mut files: HashMap<Id, BTreeMap<usize, Accepted>> = HashMap::new();
+    for (index, fix) in fixes.iter().enumerate() {
+        let Some(accepted) = resolve(map, index, fix) else {
+            report.unresolved.push(index);
+            continue;
+        };
+        let accepted_in_file = files.entry(fix.span.file_id()).or_default();
+        if overlaps(accepted_in_file, &accepted.range) {
+            report.conflicts.push(index);
+            continue;
+        }
+        accepted_in_file.insert(accepted.range.start, accepted);
+    }
+
+    let mut files: Vec<_> = files.into_iter().collect();
+    files.sort_by_key(|(id, _)| *id);
+    for (id, accepted) in files {
+        // Descending order keeps the recorded spans in old coordinates
+        for fix in accepted.values().rev() {
+            let span = fixes[fix.index].span.to_relative();
+            report.remap.record(id, span, fix.new_end.0, fix.new_end.1);
+        }
+        map.edit_content(id, |content| {
+            for fix in accepted.values().rev() {
+                let replacement = fixes[fix.index].replacement.as_bytes();
+                content.splice(fix.range.clone(), replacement.iter().copied());
+            }
+            true
+        });
+        let mut delta = 0isize;
+        for fix in accepted.values() {
+            let len = fixes[fix.index].replacement.len();
+            let start = fix.range.start.saturating_add_signed(delta);
+            report
+                .applied
+                .push((fix.index, map.position(id, start..start + len)));
+            delta += len as isize - fix.range.len() as isize;
+        }
+    }
+    report.applied.sort_by_key(|(index, _)| *index);
+    report
+}
+
+/// Byte range and replacement end of a fix, if it resolves and stays encodable
+fn resolve<Id: FileId>(map: &SourceFilesMap<Id>, index: usize, fix: &Fix<Id>) -> Option<Accepted> {
+    let file = map.file(fix.span.file_id())?;
+    let range = file.byte_range(&fix.span)?;
+    let (line, col) = file.line_offsets()?.locate(range.start)?;
+    let replacement = fix.replacement.as_bytes();
+    let new_end = match memchr::memrchr(b'\n', replacement) {
+        Some(last) => (
+            line + memchr::memchr_iter(b'\n', replacement).count(),
+            replacement.len() - last - 1,
+        ),
+        None => (line, col + replacement.len()),
+    };
+    Some(Accepted {
+        index,
+        range,
+        new_end: (new_end.0.try_into().ok()?, new_end.1.try_into().ok()?),
+    })
+}
+
+/// Whether `range` overlaps, or inserts at the same point as, an accepted fix
+fn overlaps(accepted: &BTreeMap<usize, Accepted>, range: &Range<usize>) -> bool {
+    let before = accepted.range(..=range.start).next_back();
+    let after = accepted.range(range.start..).next();
+    before.is_some_and(|(_, fix)| fix.range.end > range.start || fix.range.start == range.start)
+        || after.is_some_and(|(&start, _)| start < range.end)
+}
+
+/// Two fixes that cannot be applied together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixConflict<Id: FileId> {
+    /// Index of the fix coming first in the input
+    pub first: usize,
+    pub second: usize,
+    pub first_span: AbsolutePosition<Id>,
+    pub second_span: AbsolutePosition<Id>,
+}
+
+/// Fixes split into batches that are each safe to apply at once
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixPlan<Id: FileId> {
+    /// Indices of the fixes of each batch, in input order
+    ///
+    /// No two fixes of a batch conflict. The first batch holds every fix that
+    /// does not conflict with an earlier one, i.e. "apply all safe fixes".
+    pub batches: Vec<Vec<usize>>,
+    /// Every conflicting pair, ordered by first then second index
+    pub conflicts: Vec<FixConflict<Id>>,
+}
+
+impl<Id: FileId> FixPlan<Id> {
+    /// Fixes of a batch, ready for [`apply_fixes`]
+    ///
+    /// Batches after the first are planned against the original content:
+    /// move their spans with the [`FixReport::remap`] of the batches applied
+    /// before, and drop the fixes it cannot move.
+    pub fn batch(&self, batch: usize, fixes: &[Fix<Id>]) -> Vec<Fix<Id>> {
+        self.batches
+            .get(batch)
+            .map(|indices| indices.iter().map(|&index| fixes[index].clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Whether every fix fits in a single batch
+    pub fn is_conflict_free(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/// Group fixes into conflict-free batches without touching any content
+///
+/// Fixes conflict when their spans overlap, or when both start at the same
+/// point (two insertions there would have no defined order). Batches are
+/// filled greedily in input order, so earlier fixes land in earlier batches.
+pub fn plan_fixes<Id: FileId>(fixes: &[Fix<Id>]) -> FixPlan<Id> {
+    let mut order: Vec<usize> = (0..fixes.len()).collect();
+    order.sort_by_key(|&index| (fixes[index].span.file_id(), fixes[index].span.bounds()));
+
+    let mut partners = vec![Vec::new(); fixes.len()];
+    let mut conflicts = Vec::new();
+    for (at, &a) in order.iter().enumerate() {
+        let (a_start, a_end) = fixes[a].span.bounds();
+        for &b in &order[at + 1..] {
+            let (b_start, _) = fixes[b].span.bounds();
+            if fixes[b].span.file_id() != fixes[a].span.file_id()
+                || (b_start >= a_end && b_start != a_start)
+            {
+                break;
+            }
+            let (first, second) = (a.min(b), a.max(b));
+            partners[first].push(second);
+            partners[second].push(first);
+            conflicts.push(FixConflict {
+                first,
+                second,
+                first_span: fixes[first].span,
+                second_span: fixes[second].span,
+            });
+        }
+    }
+    conflicts.sort_by_key(|conflict| (conflict.first, conflict.second));
+
+    let mut batch_of: Vec<usize> = Vec::with_capacity(fixes.len());
+    let mut batches: Vec<Vec<usize>> = Vec::new();
+    for (index, partners) in partners.iter().enumerate() {
+        let batch = (0..)
+            .find(|batch| {
+                partners
+                    .iter()
+                    .all(|&other| other > index || batch_of[other] != *batch)
+            })
+            .expect("some batch is free of partners");
+        if batch == batches.len() {
+            batches.push(Vec::new());
+        }
+        batches[batch].push(index);
+        batch_of.push(batch);
+    }
+    FixPlan { batches, conflicts }
+}
@@ -0,0 +1,104 @@
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use xxhash_rust::xxh3::Xxh3;
+
+/// Stable identifier of a span that survives line shifts
+///
+/// Hashes the file path, the spanned text and the surrounding lines with their
+/// indentation trimmed, but no line or column numbers: inserting code above a
+/// finding keeps its fingerprint, editing the finding or its neighbours does
+/// not. Identical snippets in identical contexts of one file share a
+/// fingerprint.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Fingerprint(pub u64);
+
+impl Fingerprint {
+    /// Lines hashed on each side of the span
+    pub const CONTEXT_LINES: usize = 1;
+
+    /// Fingerprint of a span (None when it does not resolve in `map`)
+    pub fn of<Id: FileId>(map: &SourceFilesMap<Id>, pos: &AbsolutePosition<Id>) -> Option<Self> {
+        let file = map.file(pos.file_id())?;
+        let text = file.view_absolute(pos).ok()??;
+        let mut hasher = Xxh3::new();
+        hasher.update(file.path().as_bytes());
+        hasher.update(&[0xff]);
+        hasher.update(text);
+        let first = pos.start_line() as usize;
+        let last = pos.end_line() as usize;
+        let context = first.saturating_sub(Self::CONTEXT_LINES).max(1)..first;
+        for line in context.chain(last + 1..=last + Self::CONTEXT_LINES) {
+            hasher.update(&[0xff]);
+            if let Some(content) = file.line(line) {
+                hasher.update(content.trim_ascii());
+            }
+        }
+        Some(Self(hasher.digest()))
+    }
+}
+
+/// Set of accepted findings, checked in later runs to report only new ones
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Baseline {
+    fingerprints: HashSet<Fingerprint>,
+}
+
+impl Baseline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Baseline accepting every resolvable position of `positions`
+    pub fn from_positions<'p, Id: FileId>(
+        map: &SourceFilesMap<Id>,
+        positions: impl IntoIterator<Item = &'p AbsolutePosition<Id>>,
+    ) -> Self {
+        let mut baseline = Self::new();
+        for pos in positions {
+            baseline.insert(map, pos);
+        }
+        baseline
+    }
+
+    /// Accept a finding, returning false if it does not resolve in `map`
+    pub fn insert<Id: FileId>(
+        &mut self,
+        map: &SourceFilesMap<Id>,
+        pos: &AbsolutePosition<Id>,
+    ) -> bool {
+        match Fingerprint::of(map, pos) {
+            Some(fingerprint) => {
+                self.fingerprints.insert(fingerprint);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Check whether a finding was accepted, wherever it moved since
+    pub fn is_suppressed<Id: FileId>(
+        &self,
+        map: &SourceFilesMap<Id>,
+        pos: &AbsolutePosition<Id>,
+    ) -> bool {
+        Fingerprint::of(map, pos).is_some_and(|fingerprint| self.contains(fingerprint))
+    }
+
+    pub fn contains(&self, fingerprint: Fingerprint) -> bool {
+        self.fingerprints.contains(&fingerprint)
+    }
+
+    pub fn len(&self) -> usize {
+        self.fingerprints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fingerprints.is_empty()
+    }
+}
@@ -0,0 +1,46 @@
+use crate::fid::FileId;
+use crate::sfm::SourceFilesMap;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Cheap, shareable, read-only handle to a finalized map
+///
+/// Cloning only bumps a reference count, so a server can hand one to every
+/// request handler while a writer builds the next generation from
+/// [`FrozenSourceFilesMap::thaw`]. Only `&self` queries are reachable, through
+/// `Deref`.
+#[derive(Debug)]
+pub struct FrozenSourceFilesMap<Id: FileId>(Arc<SourceFilesMap<Id>>);
+
+impl<Id: FileId> Clone for FrozenSourceFilesMap<Id> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<Id: FileId> Deref for FrozenSourceFilesMap<Id> {
+    type Target = SourceFilesMap<Id>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<Id: FileId> FrozenSourceFilesMap<Id> {
+    /// Get a mutable copy, taking the map without copying if this is the last handle
+    pub fn thaw(self) -> SourceFilesMap<Id> {
+        Arc::try_unwrap(self.0).unwrap_or_else(|shared| (*shared).clone())
+    }
+
+    /// Check whether two handles share the same map
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Turn a finalized map into a shareable read-only handle
+    pub fn freeze(self) -> FrozenSourceFilesMap<Id> {
+        FrozenSourceFilesMap(Arc::new(self))
+    }
+}
@@ -0,0 +1,231 @@
+#[cfg(feature = "view")]
+use crate::clo::CompactLineOffsets;
+use crate::epc::FileHandle;
+use crate::err::SourceFilesError;
+#[cfg(feature = "view")]
+use crate::fid::RelativePosition;
+#[cfg(feature = "view")]
+use crate::fid::SourceFilePosition;
+use crate::fid::{AbsolutePosition, FileId};
+use crate::sfm::SourceFilesMap;
+use std::ops::Range;
+
+/// Handle pinning queries to a single file of a [`SourceFilesMap`]
+///
+/// Obtained through [`SourceFilesMap::file`], which resolves the path, content
+/// and line offsets once so repeated queries skip the ID lookups. Relative
+/// positions are resolved against the pinned file, and absolute positions are
+/// checked against it so a span from another file is reported instead of
+/// slicing the wrong content.
+#[derive(Debug, Clone, Copy)]
+pub struct FileRef<'a, Id: FileId> {
+    #[cfg(feature = "view")]
+    map: &'a SourceFilesMap<Id>,
+    id: Id,
+    path: &'a str,
+    content: &'a [u8],
+    binary: bool,
+    #[cfg(feature = "view")]
+    lines: Option<&'a CompactLineOffsets>,
+    // Generation of the map, for the handles taken from it
+    #[cfg(debug_assertions)]
+    epoch: u64,
+}
+
+/// Alias of [`FileRef`], the per-file view guard
+pub type FileView<'a, Id> = FileRef<'a, Id>;
+
+impl<'a, Id: FileId> FileRef<'a, Id> {
+    pub(crate) fn new(map: &'a SourceFilesMap<Id>, id: Id) -> Option<Self> {
+        Some(Self {
+            #[cfg(feature = "view")]
+            map,
+            id,
+            path: map.get_path(id)?,
+            content: map.get_content(id)?,
+            binary: map.is_binary(id),
+            #[cfg(feature = "view")]
+            lines: map.line_offsets(id),
+            #[cfg(debug_assertions)]
+            epoch: map.epoch(),
+        })
+    }
+
+    /// Handle on the pinned file, for use after the borrow of the map ends
+    pub fn handle(&self) -> FileHandle<Id> {
+        FileHandle {
+            id: self.id,
+            #[cfg(debug_assertions)]
+            epoch: self.epoch,
+        }
+    }
+
+    /// Get the ID of the pinned file
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// Get the path of the pinned file
+    pub fn path(&self) -> &'a str {
+        self.path
+    }
+
+    /// Get the full content of the pinned file
+    pub fn content(&self) -> &'a [u8] {
+        self.content
+    }
+
+    /// Whether the content of the pinned file looks binary
+    pub fn is_binary(&self) -> bool {
+        self.binary
+    }
+
+    /// Slice the content by byte range (None when out of bounds)
+    pub fn bytes(&self, range: Range<usize>) -> Option<&'a [u8]> {
+        self.content.get(range)
+    }
+
+    /// Line offsets of the pinned file (None before `finalize`)
+    #[cfg(feature = "view")]
+    pub fn line_offsets(&self) -> Option<&'a CompactLineOffsets> {
+        self.lines
+    }
+
+    /// Content of a 1-based line, without its line break
+    #[cfg(feature = "view")]
+    pub fn line(&self, line: usize) -> Option<&'a [u8]> {
+        let (start, end) = self.lines?.get_line_range(line)?;
+        self.bytes(start..end)
+    }
+
+    /// UTF-16 code units before a 0-based byte column of a 1-based line
+    ///
+    /// The column editors and most protocols count in. Bytes that are not
+    /// valid UTF-8 count as one unit each; None when the line does not exist.
+    #[cfg(feature = "view")]
+    pub fn utf16_column(&self, line: usize, byte_col: usize) -> Option<usize> {
+        let text = self.line(line)?;
+        let prefix = &text[..byte_col.min(text.len())];
+        Some(
+            prefix
+                .utf8_chunks()
+                .map(|chunk| chunk.valid().encode_utf16().count() + chunk.invalid().len())
+                .sum::<usize>()
+                + byte_col.saturating_sub(text.len()),
+        )
+    }
+
+    /// 0-based byte column of a UTF-16 column of a 1-based line
+    ///
+    /// Inverse of [`FileRef::utf16_column`]; a column inside a surrogate
+    /// pair moves past its character. None when the line does not exist or is
+    /// shorter than `utf16_col`.
+    #[cfg(feature = "view")]
+    pub fn byte_column(&self, line: usize, utf16_col: usize) -> Option<usize> {
+        utf16_to_byte(self.line(line)?, utf16_col)
+    }
+
+    /// Byte offset of a 0-based UTF-16 offset in the whole content, as
+    /// JavaScript string indices count
+    pub fn utf16_offset(&self, units: usize) -> Option<usize> {
+        utf16_to_byte(self.content, units)
+    }
+
+    /// Position covering a byte range of the pinned file
+    ///
+    /// Inverse of `view`: viewing the result yields `content[range]`. None when
+    /// the range is out of bounds or a line or column exceeds what positions
+    /// can encode.
+    #[cfg(feature = "view")]
+    pub fn position(&self, range: Range<usize>) -> Option<AbsolutePosition<Id>> {
+        let lines = self.lines?;
+        if range.start > range.end {
+            return None;
+        }
+        let (start_line, start_col) = lines.locate(range.start)?;
+        let (end_line, end_col) = lines.locate(range.end)?;
+        let encode = |line: usize, column: usize| -> Option<(u16, u8)> {
+            Some((line.try_into().ok()?, column.try_into().ok()?))
+        };
+        match (encode(start_line, start_col + 1), encode(end_line, end_col)) {
+            (Some((start_line, start_col)), Some((end_line, end_col))) => Some(
+                AbsolutePosition::new(self.id, start_line, start_col, end_line, end_col),
+            ),
+            (start, _) => {
+                let (line, column) = match start {
+                    Some(_) => (end_line, end_col),
+                    None => (start_line, start_col),
+                };
+                self.map.record_overflow(self.id, line, column);
+                None
+            }
+        }
+    }
+
+    /// Zero-width position just past the last byte, see
+    /// [`SourceFilesMap::eof_position`]
+    #[cfg(feature = "view")]
+    pub fn eof_position(&self) -> Option<AbsolutePosition<Id>> {
+        self.position(self.content.len()..self.content.len())
+    }
+
+    /// Byte range covered by a position of the pinned file
+    ///
+    /// Inverse of [`FileRef::position`]. Empty spans resolve up to the very
+    /// end of the content, so insertion points can be addressed.
+    #[cfg(feature = "view")]
+    pub fn byte_range(&self, pos: &AbsolutePosition<Id>) -> Option<Range<usize>> {
+        self.check(pos).ok()?;
+        let lines = self.lines?;
+        let (start_line, _) = lines.get_line_range(pos.start_line() as usize)?;
+        let (end_line, _) = lines.get_line_range(pos.end_line() as usize)?;
+        let start = start_line + (pos.start_column() as usize).checked_sub(1)?;
+        let end = end_line + pos.end_column() as usize;
+        (start <= end && end <= self.content.len()).then_some(start..end)
+    }
+
+    /// Check that an absolute position belongs to the pinned file
+    pub fn check(&self, pos: &AbsolutePosition<Id>) -> Result<(), SourceFilesError> {
+        if pos.file_id() == self.id {
+            Ok(())
+        } else {
+            Err(SourceFilesError::FileMismatch {
+                expected: self.id.into(),
+                found: pos.file_id().into(),
+            })
+        }
+    }
+
+    /// View a span of the pinned file
+    #[cfg(feature = "view")]
+    pub fn view(&self, pos: &RelativePosition) -> Option<&'a [u8]> {
+        self.map.view_relative(self.id, pos)
+    }
+
+    /// View an absolute span, rejecting positions from other files
+    #[cfg(feature = "view")]
+    pub fn view_absolute(
+        &self,
+        pos: &AbsolutePosition<Id>,
+    ) -> Result<Option<&'a [u8]>, SourceFilesError> {
+        self.check(pos)?;
+        Ok(self.view(&pos.to_relative()))
+    }
+}
+
+/// Bytes of `text` before its first `units` UTF-16 code units, bytes that
+/// are not valid UTF-8 counting as one unit each
+fn utf16_to_byte(text: &[u8], units: usize) -> Option<usize> {
+    let (mut seen, mut byte) = (0, 0);
+    for chunk in text.utf8_chunks() {
+        let chars = chunk.valid().chars().map(|c| (c.len_utf16(), c.len_utf8()));
+        for (width, len) in chars.chain(chunk.invalid().iter().map(|_| (1, 1))) {
+            if seen >= units {
+                return Some(byte);
+            }
+            seen += width;
+            byte += len;
+        }
+    }
+    (seen >= units).then_some(byte)
+}
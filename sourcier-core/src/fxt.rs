@@ -0,0 +1,137 @@
+use crate::bld::SourceFilesMapBuilder;
+use crate::err::SourceFilesError;
+use crate::fid::FileId;
+use crate::sfm::SourceFilesMap;
+
+/// Deterministic synthetic source trees for benchmarks and tests
+///
+/// Contents are Rust-looking lines of varying length generated from a seed,
+/// so the same constructor call always yields the same bytes. About one line
+/// in [`Fixture::NEEDLE_EVERY`] carries [`Fixture::NEEDLE`], for search
+/// benchmarks.
+///
+/// ```
+/// use sourcier_core::Fixture;
+///
+/// let fixture = Fixture::small_files(100);
+/// let files = fixture.load::<u8>().unwrap();
+/// assert_eq!(files.len(), 100);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Fixture {
+    /// Paths and contents, in generation order
+    pub files: Vec<(String, Vec<u8>)>,
+}
+
+impl Fixture {
+    /// Marker written on some lines of every file
+    pub const NEEDLE: &'static str = "TODO";
+    /// Average distance in lines between two needles
+    pub const NEEDLE_EVERY: u64 = 32;
+    const SEED: u64 = 0x5EED_50C1;
+
+    /// `count` files of 256 bytes to 4 KiB spread over a shallow tree
+    pub fn small_files(count: usize) -> Self {
+        let mut rng = Rng(Self::SEED);
+        let files = (0..count)
+            .map(|i| {
+                let path = format!("src/m{}/f{i}.rs", i % 64);
+                let size = 256 + rng.below(3840) as usize;
+                (path, source_text(&mut rng, size))
+            })
+            .collect();
+        Self { files }
+    }
+
+    /// A single file of `bytes` bytes
+    pub fn large_file(bytes: usize) -> Self {
+        let mut rng = Rng(Self::SEED);
+        Self {
+            files: vec![("src/generated.rs".to_string(), source_text(&mut rng, bytes))],
+        }
+    }
+
+    /// `count` small files, each `depth` directories deep
+    ///
+    /// Paths share long prefixes, the worst case for path hashing and sorting.
+    pub fn deep_paths(count: usize, depth: usize) -> Self {
+        let mut rng = Rng(Self::SEED);
+        let files = (0..count)
+            .map(|i| {
+                let mut path = String::new();
+                for level in 0..depth {
+                    // Only the last levels tell files apart
+                    let branch = if level + 2 < depth { 0 } else { i % 97 };
+                    path.push_str(&format!("level{level}_{branch}/"));
+                }
+                path.push_str(&format!("f{i}.rs"));
+                (path, source_text(&mut rng, 256))
+            })
+            .collect();
+        Self { files }
+    }
+
+    /// Total content size
+    pub fn total_bytes(&self) -> usize {
+        self.files.iter().map(|(_, content)| content.len()).sum()
+    }
+
+    /// Builder holding a copy of every file
+    pub fn builder<Id: FileId>(&self) -> Result<SourceFilesMapBuilder<Id>, SourceFilesError> {
+        let mut builder = SourceFilesMapBuilder::new();
+        for (path, content) in &self.files {
+            builder.add_file(path.clone(), content.clone())?;
+        }
+        Ok(builder)
+    }
+
+    /// Finalized map holding a copy of every file
+    pub fn load<Id: FileId>(&self) -> Result<SourceFilesMap<Id>, SourceFilesError> {
+        self.builder()?.finalize()
+    }
+}
+
+/// splitmix64, enough to vary fixture shapes without a dependency
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+/// Exactly `size` bytes of newline-terminated lines, the last one cut short
+fn source_text(rng: &mut Rng, size: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(size + 128);
+    let mut line = 0u64;
+    while out.len() < size {
+        let indent = " ".repeat(4 * rng.below(3) as usize);
+        let text = match rng.below(4) {
+            0 => format!("{indent}fn item_{line}(x: u32) -> u32 {{\n"),
+            1 => format!(
+                "{indent}let value_{line} = x.wrapping_mul({});\n",
+                rng.next()
+            ),
+            2 => format!("{indent}}}\n"),
+            _ => format!(
+                "{indent}// {}\n",
+                "lorem ipsum ".repeat(rng.below(6) as usize)
+            ),
+        };
+        out.extend_from_slice(text.as_bytes());
+        if rng.below(Fixture::NEEDLE_EVERY) == 0 {
+            out.extend_from_slice(format!("{indent}// {}: revisit\n", Fixture::NEEDLE).as_bytes());
+        }
+        line += 1;
+    }
+    out.truncate(size);
+    out
+}
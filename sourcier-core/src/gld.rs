@@ -0,0 +1,273 @@
+//! Golden files: expected diagnostics annotated inline
+//!
+//! Fixtures state the diagnostics they expect in comments under the code,
+//! the way rust-analyzer tests do. The carets of an annotation mark the
+//! columns of the closest code line above it, and the text after them names
+//! the diagnostic:
+//!
+//! ```text
+//! fn main() {
+//!     let unused = 1;
+//!     //  ^^^^^^ expected: unused-variable
+//!     let x: u8 = "1";
+//!     //          ^^^ error: E0308
+//! }
+//! ```
+//!
+//! `expected:` accepts any severity, while a severity name (`error`,
+//! `warning`, `note`, `help`) requires that one. The text matches the code or
+//! the message of the diagnostic; carets alone match any diagnostic of the
+//! span. Annotation lines stay in the file, so diagnostics are positioned
+//! against the fixture as written. [`check_annotations`] pairs annotations
+//! with the diagnostics a tool produced and renders the leftovers of both.
+
+use crate::asr::render_snippet;
+use crate::dgn::{Diagnostic, Severity};
+use crate::fid::{AbsolutePosition, FileId};
+use crate::sfm::SourceFilesMap;
+use std::fmt::Write as _;
+
+/// A diagnostic a fixture expects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Annotation<Id: FileId> {
+    /// Span under the carets
+    pub pos: AbsolutePosition<Id>,
+    /// Severity required, None for `expected:`
+    pub severity: Option<Severity>,
+    /// Code or message expected, empty to accept any
+    pub text: String,
+}
+
+impl<Id: FileId> Annotation<Id> {
+    /// Whether `diagnostic` is the one expected
+    pub fn matches(&self, diagnostic: &Diagnostic<Id>) -> bool {
+        diagnostic.primary == self.pos
+            && self
+                .severity
+                .is_none_or(|severity| severity == diagnostic.severity)
+            && (self.text.is_empty()
+                || diagnostic.code.as_deref() == Some(self.text.as_str())
+                || diagnostic.message == self.text)
+    }
+}
+
+/// Reads annotations from the comments of fixtures
+#[derive(Debug, Clone)]
+pub struct AnnotationParser {
+    comments: Vec<String>,
+}
+
+impl Default for AnnotationParser {
+    fn default() -> Self {
+        Self {
+            comments: vec!["//".to_string(), "#".to_string(), "--".to_string()],
+        }
+    }
+}
+
+fn severity_name(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Help => "help",
+        Severity::Note => "note",
+        Severity::Warning => "warning",
+        Severity::Error => "error",
+    }
+}
+
+/// Severity named by the label of an annotation, and whether it is one
+fn label_severity(label: &str) -> Option<Option<Severity>> {
+    match label {
+        "expected" => Some(None),
+        "error" => Some(Some(Severity::Error)),
+        "warning" => Some(Some(Severity::Warning)),
+        "note" => Some(Some(Severity::Note)),
+        "help" => Some(Some(Severity::Help)),
+        _ => None,
+    }
+}
+
+impl AnnotationParser {
+    /// Parser of annotations in `//`, `#` and `--` comments
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also read annotations in comments starting with `marker`, e.g. `;`
+    pub fn with_comment(mut self, marker: impl Into<String>) -> Self {
+        self.comments.push(marker.into());
+        // Longest first, so `///` wins over `//`
+        self.comments
+            .sort_by_key(|marker| std::cmp::Reverse(marker.len()));
+        self
+    }
+
+    /// Carets and text of an annotation line: the byte range of the carets
+    /// in the line, the severity and the text
+    fn parse_line<'a>(&self, line: &'a str) -> Option<(usize, usize, Option<Severity>, &'a str)> {
+        let comment = line.trim_start();
+        let rest = self
+            .comments
+            .iter()
+            .find_map(|marker| comment.strip_prefix(marker.as_str()))?
+            .trim_start();
+        let start = line.len() - rest.len();
+        let carets = rest.len() - rest.trim_start_matches('^').len();
+        if carets == 0 {
+            return None;
+        }
+        let text = rest[carets..].trim();
+        let (severity, text) = match text.split_once(':') {
+            Some((label, message)) => match label_severity(label.trim()) {
+                Some(severity) => (severity, message.trim()),
+                None => (None, text),
+            },
+            None => (None, text),
+        };
+        Some((start, start + carets, severity, text))
+    }
+
+    /// Annotations of a file, in line order
+    ///
+    /// Carets reaching past the end of their code line are cut at its end.
+    /// Annotations above any code line, and files missing from `map` or not
+    /// finalized, yield none.
+    pub fn parse<Id: FileId>(&self, map: &SourceFilesMap<Id>, id: Id) -> Vec<Annotation<Id>> {
+        let mut annotations = Vec::new();
+        let Some(file) = map.file(id) else {
+            return annotations;
+        };
+        let Some(lines) = file.line_offsets() else {
+            return annotations;
+        };
+        // Byte range of the closest code line, without its line break
+        let mut code_line: Option<(usize, usize)> = None;
+        for line in 1..=lines.line_count() {
+            let Some((line_start, line_end)) = lines.get_line_range(line) else {
+                continue;
+            };
+            let text = file.line(line).unwrap_or_default();
+            let text = text.strip_suffix(b"\r").unwrap_or(text);
+            let parsed = std::str::from_utf8(text)
+                .ok()
+                .and_then(|text| self.parse_line(text));
+            let Some((start, end, severity, message)) = parsed else {
+                code_line = Some((line_start, line_end.min(line_start + text.len())));
+                continue;
+            };
+            let Some((code_start, code_end)) = code_line else {
+                continue;
+            };
+            let range = (code_start + start).min(code_end)..(code_start + end).min(code_end);
+            if let Some(pos) = file.position(range) {
+                annotations.push(Annotation {
+                    pos,
+                    severity,
+                    text: message.to_string(),
+                });
+            }
+        }
+        annotations
+    }
+}
+
+/// Compare the annotations of a file with the diagnostics produced for it
+///
+/// Annotations are paired with diagnostics they match, see
+/// [`Annotation::matches`], pairing as many as possible whatever their
+/// order, so a bare caret never takes the diagnostic a more specific
+/// annotation needs. Diagnostics of other files are ignored. The error lists
+/// the annotations left without a diagnostic and the diagnostics nothing
+/// expected, with snippets of their spans.
+pub fn check_annotations<'a, Id: FileId>(
+    map: &SourceFilesMap<Id>,
+    id: Id,
+    parser: &AnnotationParser,
+    diagnostics: impl IntoIterator<Item = &'a Diagnostic<Id>>,
+) -> Result<(), String> {
+    let diagnostics: Vec<_> = diagnostics
+        .into_iter()
+        .filter(|diagnostic| diagnostic.primary.file_id() == id)
+        .collect();
+    let annotations = parser.parse(map, id);
+    let edges: Vec<Vec<usize>> = annotations
+        .iter()
+        .map(|annotation| {
+            (0..diagnostics.len())
+                .filter(|&index| annotation.matches(diagnostics[index]))
+                .collect()
+        })
+        .collect();
+    // Annotation paired with each diagnostic
+    let mut owners = vec![None; diagnostics.len()];
+    for annotation in 0..annotations.len() {
+        augment(
+            annotation,
+            &edges,
+            &mut vec![false; diagnostics.len()],
+            &mut owners,
+        );
+    }
+    let missing: Vec<_> = annotations
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !owners.contains(&Some(*index)))
+        .map(|(_, annotation)| annotation)
+        .collect();
+    let unexpected: Vec<_> = diagnostics
+        .iter()
+        .zip(&owners)
+        .filter(|(_, owner)| owner.is_none())
+        .map(|(diagnostic, _)| diagnostic)
+        .collect();
+    if missing.is_empty() && unexpected.is_empty() {
+        return Ok(());
+    }
+    let path = map.get_path(id).unwrap_or_default();
+    let mut message = format!("annotations of {path} differ from the diagnostics\n");
+    for annotation in &missing {
+        let label = annotation.severity.map_or("expected", severity_name);
+        let _ = writeln!(
+            message,
+            "missing {}: {label}: {}",
+            map.display(&annotation.pos),
+            annotation.text
+        );
+        render_snippet(&mut message, map, &annotation.pos);
+    }
+    for diagnostic in &unexpected {
+        let code = diagnostic
+            .code
+            .as_deref()
+            .map(|code| format!("[{code}]"))
+            .unwrap_or_default();
+        let _ = writeln!(
+            message,
+            "unexpected {}: {}{code}: {}",
+            map.display(&diagnostic.primary),
+            severity_name(diagnostic.severity),
+            diagnostic.message
+        );
+        render_snippet(&mut message, map, &diagnostic.primary);
+    }
+    Err(message)
+}
+
+/// Pair `annotation` with one of its diagnostics, moving the annotations
+/// paired before along when that frees one (an augmenting path)
+fn augment(
+    annotation: usize,
+    edges: &[Vec<usize>],
+    seen: &mut [bool],
+    owners: &mut [Option<usize>],
+) -> bool {
+    for &diagnostic in &edges[annotation] {
+        if std::mem::replace(&mut seen[diagnostic], true) {
+            continue;
+        }
+        if owners[diagnostic].is_none_or(|other| augment(other, edges, seen, owners)) {
+            owners[diagnostic] = Some(annotation);
+            return true;
+        }
+    }
+    false
+}
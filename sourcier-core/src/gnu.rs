@@ -0,0 +1,237 @@
+//! Parser for GNU style compiler messages
+//!
+//! Most compilers and linters without a structured output can still print
+//! `path:line:col: severity: message`, the form the GNU coding standards
+//! describe and editors jump through. [`GnuParser`] reads it leniently, so
+//! legacy tools can be wrapped without a parser of their own:
+//!
+//! - the column is optional, and may follow a `.` instead of a `:`
+//! - a range may follow the start, as `-col` or `-line.col`
+//! - the severity keyword is optional and matched case-insensitively
+//! - paths may hold `:`, like Windows drive letters
+//!
+//! Columns are 1-based byte columns; pass `-fdiagnostics-column-unit=byte`
+//! to GCC, which counts display columns otherwise.
+
+use crate::dgn::{Diagnostic, DiagnosticBag, Label, Severity};
+use crate::fid::{AbsolutePosition, FileId};
+use crate::sfm::SourceFilesMap;
+use std::path::Path;
+
+/// One parsed message line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GnuMessage<'a> {
+    pub path: &'a str,
+    /// 1-based line
+    pub line: usize,
+    /// 1-based byte column, None when the message is about the whole line
+    pub column: Option<usize>,
+    /// Inclusive end of the range, as a 1-based line and byte column
+    pub end: Option<(usize, usize)>,
+    pub severity: Severity,
+    pub message: &'a str,
+}
+
+/// Diagnostics read by [`GnuParser::import`]
+#[derive(Debug, Clone)]
+pub struct GnuImport<Id: FileId> {
+    pub diagnostics: DiagnosticBag<Id>,
+    /// Lines that are not messages about spans of the map, e.g. the source
+    /// excerpts and `In function` headers compilers print around them
+    pub unresolved: Vec<String>,
+}
+
+/// Parser of `path:line:col: severity: message` lines
+///
+/// Recognizes the `error`, `fatal error`, `warning`, `note`, `remark`, `info`
+/// and `help` keywords by default; [`with_keyword`](Self::with_keyword) adds
+/// or remaps others. Messages without a keyword get the
+/// [untagged severity](Self::with_untagged_severity), `Error` by default.
+#[derive(Debug, Clone)]
+pub struct GnuParser {
+    keywords: Vec<(String, Severity)>,
+    untagged: Severity,
+}
+
+impl Default for GnuParser {
+    fn default() -> Self {
+        let keywords = [
+            ("fatal error", Severity::Error),
+            ("error", Severity::Error),
+            ("warning", Severity::Warning),
+            ("note", Severity::Note),
+            ("remark", Severity::Note),
+            ("info", Severity::Note),
+            ("help", Severity::Help),
+        ];
+        Self {
+            keywords: keywords
+                .into_iter()
+                .map(|(keyword, severity)| (keyword.to_string(), severity))
+                .collect(),
+            untagged: Severity::Error,
+        }
+    }
+}
+
+/// Leading decimal number of `text` and the rest
+fn number(text: &str) -> Option<(usize, &str)> {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    Some((text[..end].parse().ok()?, &text[end..]))
+}
+
+/// Line, column and range end of a message
+type Location = (usize, Option<usize>, Option<(usize, usize)>);
+
+/// `line[:col|.col][-col|-line.col]:` at the start of `text`, and the rest
+fn location(text: &str) -> Option<(Location, &str)> {
+    let (line, rest) = number(text)?;
+    let (column, rest) = match rest.strip_prefix([':', '.']).and_then(number) {
+        Some((column, rest)) => (Some(column), rest),
+        None => (None, rest),
+    };
+    let (end, rest) = match (column, rest.strip_prefix('-').and_then(number)) {
+        (Some(_), Some((first, rest))) => match rest.strip_prefix('.').and_then(number) {
+            Some((end_column, rest)) => (Some((first, end_column)), rest),
+            None => (Some((line, first)), rest),
+        },
+        _ => (None, rest),
+    };
+    Some(((line, column, end), rest.strip_prefix(':')?))
+}
+
+impl GnuParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read `keyword:` before a message as `severity`, replacing the
+    /// severity of a known keyword
+    pub fn with_keyword(mut self, keyword: impl Into<String>, severity: Severity) -> Self {
+        let keyword = keyword.into();
+        self.keywords
+            .retain(|(known, _)| !known.eq_ignore_ascii_case(&keyword));
+        self.keywords.push((keyword, severity));
+        // Longest first, so `fatal error` wins over `error`
+        self.keywords
+            .sort_by_key(|(known, _)| std::cmp::Reverse(known.len()));
+        self
+    }
+
+    /// Severity of messages without a known keyword
+    pub fn with_untagged_severity(mut self, severity: Severity) -> Self {
+        self.untagged = severity;
+        self
+    }
+
+    /// Severity named at the start of `message`, and the message after it
+    fn severity<'a>(&self, message: &'a str) -> (Severity, &'a str) {
+        for (keyword, severity) in &self.keywords {
+            let tagged = message
+                .get(..keyword.len())
+                .filter(|head| head.eq_ignore_ascii_case(keyword))
+                .and_then(|_| message[keyword.len()..].strip_prefix(':'));
+            if let Some(rest) = tagged {
+                return (*severity, rest.trim_start());
+            }
+        }
+        (self.untagged, message)
+    }
+
+    /// Message of a line, None when it is not one
+    ///
+    /// The path is everything before the first `:` followed by a location.
+    pub fn parse_line<'a>(&self, line: &'a str) -> Option<GnuMessage<'a>> {
+        let line = line.trim_end();
+        line.match_indices(':')
+            .filter(|&(at, _)| at > 0)
+            .find_map(|(at, _)| {
+                let ((number, column, end), rest) = location(&line[at + 1..])?;
+                let (severity, message) = self.severity(rest.trim_start());
+                Some(GnuMessage {
+                    path: &line[..at],
+                    line: number,
+                    column,
+                    end,
+                    severity,
+                    message,
+                })
+            })
+    }
+
+    /// Diagnostics of a tool's output, paths resolved against `root`
+    ///
+    /// Paths are absolute or relative to `root`, the directory the map paths
+    /// are relative to. A `note` right after a diagnostic, as GCC and Clang
+    /// print to point at a declaration, becomes a label of it rather than a
+    /// diagnostic of its own.
+    pub fn import<Id: FileId>(
+        &self,
+        map: &SourceFilesMap<Id>,
+        root: impl AsRef<Path>,
+        output: &str,
+    ) -> GnuImport<Id> {
+        let mut import = GnuImport {
+            diagnostics: DiagnosticBag::new(),
+            unresolved: Vec::new(),
+        };
+        let mut last: Option<Diagnostic<Id>> = None;
+        for line in output.lines().filter(|line| !line.trim().is_empty()) {
+            let resolved = self
+                .parse_line(line)
+                .and_then(|message| Some((message, message.position(map, root.as_ref())?)));
+            let Some((message, pos)) = resolved else {
+                import.unresolved.push(line.to_string());
+                continue;
+            };
+            match last.as_mut() {
+                Some(diagnostic) if message.severity == Severity::Note => {
+                    diagnostic.labels.push(Label {
+                        pos,
+                        message: message.message.to_string(),
+                    });
+                }
+                _ => {
+                    import.diagnostics.extend(last.take());
+                    last = Some(Diagnostic::new(message.severity, message.message, pos));
+                }
+            }
+        }
+        import.diagnostics.extend(last);
+        import
+    }
+}
+
+impl GnuMessage<'_> {
+    /// Span of the message in `map`, with its path relative to `root`
+    ///
+    /// Empty at the column when no range is given, the whole line without a
+    /// column. None when the file is not in the map or the location is past
+    /// its end.
+    pub fn position<Id: FileId>(
+        &self,
+        map: &SourceFilesMap<Id>,
+        root: impl AsRef<Path>,
+    ) -> Option<AbsolutePosition<Id>> {
+        let file = map.file(map.resolve_under(root.as_ref(), self.path)?)?;
+        let lines = file.line_offsets()?;
+        let (line_start, line_end) = lines.get_line_range(self.line)?;
+        let range = match (self.column, self.end) {
+            (None, _) => line_start..line_end,
+            (Some(column), end) => {
+                let start = line_start + column.checked_sub(1)?;
+                if start > line_end {
+                    return None;
+                }
+                let end = match end {
+                    Some((line, column)) => lines.get_line_range(line)?.0 + column,
+                    None => start,
+                };
+                start..end
+            }
+        };
+        file.position(range)
+    }
+}
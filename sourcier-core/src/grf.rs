@@ -0,0 +1,184 @@
+use crate::fid::FileId;
+use crate::rmp::IdRemapTable;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Directed edges between files, e.g. imports or includes
+///
+/// An edge `from -> to` reads "`from` depends on `to`". Edges are indexed
+/// both ways, so the dependencies and the dependents of a file are found
+/// without a scan. A map carries one, kept across ID reassignments and
+/// written with the map, see [`SourceFilesMap::graph_mut`](crate::SourceFilesMap::graph_mut).
+///
+/// With the `serde` feature a graph serializes as its edges, as raw ID pairs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileGraph<Id: FileId> {
+    forward: BTreeMap<Id, BTreeSet<Id>>,
+    reverse: BTreeMap<Id, BTreeSet<Id>>,
+}
+
+impl<Id: FileId> Default for FileGraph<Id> {
+    fn default() -> Self {
+        Self {
+            forward: BTreeMap::new(),
+            reverse: BTreeMap::new(),
+        }
+    }
+}
+
+impl<Id: FileId> FileGraph<Id> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `from` depends on `to`, returning false if it already did
+    pub fn add_edge(&mut self, from: Id, to: Id) -> bool {
+        self.reverse.entry(to).or_default().insert(from);
+        self.forward.entry(from).or_default().insert(to)
+    }
+
+    pub fn remove_edge(&mut self, from: Id, to: Id) -> bool {
+        let removed = unlink(&mut self.forward, from, to);
+        unlink(&mut self.reverse, to, from);
+        removed
+    }
+
+    /// Remove every edge from or to `id`
+    pub fn remove_file(&mut self, id: Id) {
+        for to in self.forward.remove(&id).unwrap_or_default() {
+            unlink(&mut self.reverse, to, id);
+        }
+        for from in self.reverse.remove(&id).unwrap_or_default() {
+            unlink(&mut self.forward, from, id);
+        }
+    }
+
+    pub fn contains_edge(&self, from: Id, to: Id) -> bool {
+        self.forward
+            .get(&from)
+            .is_some_and(|to_ids| to_ids.contains(&to))
+    }
+
+    /// Files `id` depends on directly, in ID order
+    pub fn dependencies(&self, id: Id) -> impl Iterator<Item = Id> + '_ {
+        self.forward.get(&id).into_iter().flatten().copied()
+    }
+
+    /// Files depending on `id` directly, in ID order
+    pub fn dependents(&self, id: Id) -> impl Iterator<Item = Id> + '_ {
+        self.reverse.get(&id).into_iter().flatten().copied()
+    }
+
+    /// Every edge as `(from, to)`, in ID order
+    pub fn edges(&self) -> impl Iterator<Item = (Id, Id)> + '_ {
+        self.forward
+            .iter()
+            .flat_map(|(&from, to_ids)| to_ids.iter().map(move |&to| (from, to)))
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.forward.values().map(BTreeSet::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.forward.is_empty()
+    }
+
+    /// Files `id` depends on through one or more edges
+    ///
+    /// Holds `id` itself only when it is part of a cycle.
+    pub fn reachable(&self, id: Id) -> BTreeSet<Id> {
+        walk(&self.forward, [id], false)
+    }
+
+    /// Files to revisit when `changed` change: `changed` and every file
+    /// depending on them, directly or not
+    pub fn affected_by(&self, changed: impl IntoIterator<Item = Id>) -> BTreeSet<Id> {
+        walk(&self.reverse, changed, true)
+    }
+
+    /// Carry the edges over to the next map generation
+    ///
+    /// Edges from or to files missing from that generation are dropped.
+    pub fn remap_ids<New: FileId>(&self, table: &IdRemapTable<Id, New>) -> FileGraph<New> {
+        let mut graph = FileGraph::new();
+        for (from, to) in self.edges() {
+            if let (Some(from), Some(to)) = (table.get(from), table.get(to)) {
+                graph.add_edge(from, to);
+            }
+        }
+        graph
+    }
+}
+
+impl<Id: FileId> FromIterator<(Id, Id)> for FileGraph<Id> {
+    fn from_iter<I: IntoIterator<Item = (Id, Id)>>(edges: I) -> Self {
+        let mut graph = Self::new();
+        for (from, to) in edges {
+            graph.add_edge(from, to);
+        }
+        graph
+    }
+}
+
+fn unlink<Id: FileId>(index: &mut BTreeMap<Id, BTreeSet<Id>>, key: Id, id: Id) -> bool {
+    let Some(ids) = index.get_mut(&key) else {
+        return false;
+    };
+    let removed = ids.remove(&id);
+    if ids.is_empty() {
+        index.remove(&key);
+    }
+    removed
+}
+
+/// Files reached from `start` following `index`, with `start` itself if
+/// `include_start`
+fn walk<Id: FileId>(
+    index: &BTreeMap<Id, BTreeSet<Id>>,
+    start: impl IntoIterator<Item = Id>,
+    include_start: bool,
+) -> BTreeSet<Id> {
+    let mut seen = BTreeSet::new();
+    let mut stack: Vec<Id> = Vec::new();
+    for id in start {
+        if include_start {
+            seen.insert(id);
+        }
+        stack.push(id);
+    }
+    while let Some(id) = stack.pop() {
+        for &next in index.get(&id).into_iter().flatten() {
+            if seen.insert(next) {
+                stack.push(next);
+            }
+        }
+    }
+    seen
+}
+
+#[cfg(feature = "serde")]
+impl<Id: FileId> Serialize for FileGraph<Id> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let edges: Vec<(u64, u64)> = self
+            .edges()
+            .map(|(from, to)| (from.into(), to.into()))
+            .collect();
+        edges.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, Id: FileId> Deserialize<'de> for FileGraph<Id> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let edges = Vec::<(u64, u64)>::deserialize(deserializer)?;
+        let id = |raw: u64| {
+            Id::try_from(raw).map_err(|_| serde::de::Error::custom("file ID out of range"))
+        };
+        edges
+            .into_iter()
+            .map(|(from, to)| Ok((id(from)?, id(to)?)))
+            .collect()
+    }
+}
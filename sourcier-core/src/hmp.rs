@@ -0,0 +1,189 @@
+//! Line heatmaps of span frequencies
+//!
+//! [`SpanHeatmap::add`] counts every line a span covers, so feeding it the
+//! spans a profiler sampled, a tracer entered or a linter flagged shows which
+//! lines are hit most. The counts come out as a ranking, as JSON with the
+//! `export` feature, or as the lines of a file shaded by heat for a terminal.
+
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+use crate::snp::gutter_width;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+/// 256-color backgrounds from cool to hot, for [`SpanHeatmap::render_ansi`]
+const HEAT: [u8; 5] = [28, 100, 136, 166, 196];
+
+/// Per-line counts of the spans added, by file
+#[derive(Debug, Clone)]
+pub struct SpanHeatmap<Id: FileId> {
+    counts: BTreeMap<Id, BTreeMap<usize, u64>>,
+}
+
+impl<Id: FileId> Default for SpanHeatmap<Id> {
+    fn default() -> Self {
+        Self {
+            counts: BTreeMap::new(),
+        }
+    }
+}
+
+impl<Id: FileId> SpanHeatmap<Id> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a span once on each of its lines
+    ///
+    /// A line the span only reaches through an empty end, like the line after
+    /// a span ending with a line break, is not counted.
+    pub fn add(&mut self, pos: AbsolutePosition<Id>) {
+        self.add_weighted(pos, 1);
+    }
+
+    /// Count a span `weight` times, e.g. with its sample count
+    pub fn add_weighted(&mut self, pos: AbsolutePosition<Id>, weight: u64) {
+        let lines = self.counts.entry(pos.file_id()).or_default();
+        let start = pos.start_line();
+        let mut end = pos.end_line().max(start);
+        if end > start && pos.end_column() == 0 {
+            end -= 1;
+        }
+        for line in start..=end {
+            let count = lines.entry(line as usize).or_insert(0);
+            *count = count.saturating_add(weight);
+        }
+    }
+
+    /// Count of a 1-based line, 0 when never hit
+    pub fn count(&self, id: Id, line: usize) -> u64 {
+        self.counts
+            .get(&id)
+            .and_then(|lines| lines.get(&line))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Hit lines of a file with their counts, by line
+    pub fn lines(&self, id: Id) -> impl Iterator<Item = (usize, u64)> + '_ {
+        self.counts
+            .get(&id)
+            .into_iter()
+            .flatten()
+            .map(|(line, count)| (*line, *count))
+    }
+
+    /// Files with hit lines, by ID
+    pub fn files(&self) -> impl Iterator<Item = Id> + '_ {
+        self.counts.keys().copied()
+    }
+
+    /// Highest count of any line
+    pub fn max(&self) -> u64 {
+        self.counts
+            .values()
+            .flat_map(|lines| lines.values())
+            .copied()
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// The `n` most hit lines, most hit first, ties by file and line
+    pub fn hottest(&self, n: usize) -> Vec<(Id, usize, u64)> {
+        let mut lines: Vec<_> = self
+            .counts
+            .iter()
+            .flat_map(|(id, lines)| lines.iter().map(|(line, count)| (*id, *line, *count)))
+            .collect();
+        lines.sort_by_key(|(id, line, count)| (std::cmp::Reverse(*count), *id, *line));
+        lines.truncate(n);
+        lines
+    }
+
+    /// Lines of a file shaded by heat, with their counts and line numbers
+    ///
+    /// Each line is prefixed with its count and number, and its text drawn on
+    /// a 256-color background from green to red relative to the hottest line
+    /// of the whole map; lines never hit are left plain. None when the file
+    /// is not in `map`.
+    pub fn render_ansi(&self, map: &SourceFilesMap<Id>, id: Id) -> Option<String> {
+        let file = map.file(id)?;
+        let line_count = file.line_offsets()?.line_count();
+        let max = self.max();
+        let number_width = gutter_width(line_count);
+        let count_width = max.to_string().len();
+        let mut out = String::new();
+        for line in 1..=line_count {
+            let text = file.line(line).unwrap_or_default();
+            // The empty line after a final line break
+            if line == line_count && line > 1 && text.is_empty() {
+                break;
+            }
+            let text = String::from_utf8_lossy(text.strip_suffix(b"\r").unwrap_or(text));
+            let count = self.count(id, line);
+            if count == 0 {
+                let _ = writeln!(out, "{:>count_width$} {line:>number_width$} │ {text}", "");
+                continue;
+            }
+            // Share of the hottest count, on the scale of `HEAT`
+            let level = (count as u128 * HEAT.len() as u128).div_ceil(max as u128) - 1;
+            let _ = writeln!(
+                out,
+                "{count:>count_width$} {line:>number_width$} │ \x1b[48;5;{}m{text}\x1b[0m",
+                HEAT[level as usize],
+            );
+        }
+        Some(out)
+    }
+}
+
+#[cfg(feature = "export")]
+mod json {
+    use super::SpanHeatmap;
+    use crate::fid::FileId;
+    use crate::sfm::SourceFilesMap;
+    use serde::{Deserialize, Serialize};
+    use std::io::{self, Write};
+
+    /// Heatmap as written by [`SpanHeatmap::write_json`]
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct HeatmapExport {
+        pub max: u64,
+        pub files: Vec<HeatmapFile>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct HeatmapFile {
+        pub id: u64,
+        /// Path of `id`, or None when the ID is not in the map
+        pub path: Option<String>,
+        /// `[line, count]` pairs of the hit lines, by line
+        pub lines: Vec<(usize, u64)>,
+    }
+
+    impl<Id: FileId> SpanHeatmap<Id> {
+        /// Counts of every file with their paths in `map`
+        pub fn export(&self, map: &SourceFilesMap<Id>) -> HeatmapExport {
+            HeatmapExport {
+                max: self.max(),
+                files: self
+                    .files()
+                    .map(|id| HeatmapFile {
+                        id: id.into(),
+                        path: map.get_path(id).map(str::to_string),
+                        lines: self.lines(id).collect(),
+                    })
+                    .collect(),
+            }
+        }
+
+        /// Write [`SpanHeatmap::export`] as JSON
+        pub fn write_json(&self, map: &SourceFilesMap<Id>, out: impl Write) -> io::Result<()> {
+            serde_json::to_writer(out, &self.export(map))?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "export")]
+pub use json::{HeatmapExport, HeatmapFile};
@@ -0,0 +1,181 @@
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+use std::collections::HashMap;
+
+/// Syntax of suppression comments recognized by [`IgnoreScanner`]
+///
+/// With the defaults, these comments are understood (rules in brackets are
+/// optional and comma separated; no brackets means every rule):
+///
+/// ```text
+/// // sourcier-ignore-next-line[unused, shadowing]
+/// let x = 1; // sourcier-ignore-line
+/// # sourcier-ignore-file[todo]
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IgnoreConfig {
+    /// Tokens opening a line comment
+    pub comment_prefixes: Vec<String>,
+    /// Directive name, suffixed with `-next-line`, `-line` or `-file`
+    pub directive: String,
+}
+
+impl Default for IgnoreConfig {
+    fn default() -> Self {
+        Self {
+            comment_prefixes: vec!["//".to_string(), "#".to_string()],
+            directive: "sourcier-ignore".to_string(),
+        }
+    }
+}
+
+/// Rules a suppression applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IgnoredRules {
+    All,
+    Only(Vec<String>),
+}
+
+impl IgnoredRules {
+    pub fn matches(&self, rule: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Only(rules) => rules.iter().any(|r| r == rule),
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        match (self, other) {
+            (Self::All, _) => {}
+            (this, Self::All) => *this = Self::All,
+            (Self::Only(rules), Self::Only(more)) => rules.extend(more),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct FileIgnores {
+    file: Option<IgnoredRules>,
+    lines: HashMap<u16, IgnoredRules>,
+}
+
+/// Finds suppression comments using the line offsets of a map
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreScanner {
+    config: IgnoreConfig,
+}
+
+impl IgnoreScanner {
+    pub fn new(config: IgnoreConfig) -> Self {
+        Self { config }
+    }
+
+    /// Scan every file of a finalized map
+    pub fn scan<Id: FileId>(&self, map: &SourceFilesMap<Id>) -> IgnoreIndex<Id> {
+        let files = map
+            .iter()
+            .filter_map(|(id, _, _)| Some((id, self.scan_file(map, id)?)))
+            .collect();
+        IgnoreIndex { files }
+    }
+
+    fn scan_file<Id: FileId>(&self, map: &SourceFilesMap<Id>, id: Id) -> Option<FileIgnores> {
+        let file = map.file(id)?;
+        let lines = file.line_offsets()?.line_count();
+        let mut ignores = FileIgnores::default();
+        for line in 1..=lines {
+            let Some((kind, rules)) = file.line(line).and_then(|text| self.directive(text)) else {
+                continue;
+            };
+            let target = match kind {
+                Directive::File => {
+                    match &mut ignores.file {
+                        Some(existing) => existing.merge(rules),
+                        None => ignores.file = Some(rules),
+                    }
+                    continue;
+                }
+                Directive::Line => line,
+                Directive::NextLine => line + 1,
+            };
+            let Ok(target) = u16::try_from(target) else {
+                continue;
+            };
+            match ignores.lines.get_mut(&target) {
+                Some(existing) => existing.merge(rules),
+                None => {
+                    ignores.lines.insert(target, rules);
+                }
+            }
+        }
+        (ignores.file.is_some() || !ignores.lines.is_empty()).then_some(ignores)
+    }
+
+    /// Parse the directive of a line, if its comment holds one
+    fn directive(&self, line: &[u8]) -> Option<(Directive, IgnoredRules)> {
+        let line = std::str::from_utf8(line).ok()?;
+        self.config.comment_prefixes.iter().find_map(|prefix| {
+            line.match_indices(prefix.as_str()).find_map(|(at, _)| {
+                let comment = line[at + prefix.len()..].trim_start();
+                let rest = comment.strip_prefix(self.config.directive.as_str())?;
+                let (kind, rest) = [
+                    (Directive::NextLine, "-next-line"),
+                    (Directive::Line, "-line"),
+                    (Directive::File, "-file"),
+                ]
+                .into_iter()
+                .find_map(|(kind, suffix)| Some((kind, rest.strip_prefix(suffix)?)))?;
+                let rules = match rest.strip_prefix('[') {
+                    Some(list) => IgnoredRules::Only(
+                        list.split_once(']')?
+                            .0
+                            .split(',')
+                            .map(|rule| rule.trim().to_string())
+                            .filter(|rule| !rule.is_empty())
+                            .collect(),
+                    ),
+                    None if rest.is_empty() || rest.starts_with(char::is_whitespace) => {
+                        IgnoredRules::All
+                    }
+                    None => return None,
+                };
+                Some((kind, rules))
+            })
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Directive {
+    NextLine,
+    Line,
+    File,
+}
+
+/// Suppressions found by [`IgnoreScanner::scan`]
+#[derive(Debug, Clone)]
+pub struct IgnoreIndex<Id: FileId> {
+    files: HashMap<Id, FileIgnores>,
+}
+
+impl<Id: FileId> IgnoreIndex<Id> {
+    /// Check whether `rule` is suppressed for the line a position starts on
+    pub fn is_ignored(&self, pos: &AbsolutePosition<Id>, rule: &str) -> bool {
+        let Some(ignores) = self.files.get(&pos.file_id()) else {
+            return false;
+        };
+        ignores
+            .file
+            .as_ref()
+            .is_some_and(|rules| rules.matches(rule))
+            || ignores
+                .lines
+                .get(&pos.start_line())
+                .is_some_and(|rules| rules.matches(rule))
+    }
+
+    /// Number of files holding at least one suppression
+    pub fn files(&self) -> usize {
+        self.files.len()
+    }
+}
@@ -0,0 +1,212 @@
+//! Import of ESLint JSON and tsc diagnostics
+//!
+//! Reads the report of `eslint --format json` and the output of
+//! `tsc --pretty false`, so findings of JavaScript and TypeScript tooling
+//! share the position model of the rest of the map. Both count columns in
+//! UTF-16 code units, as JavaScript strings do, which are converted back to
+//! bytes of the mapped files.
+//!
+//! Paths are absolute in ESLint reports and relative to the working
+//! directory in tsc output; both are resolved against `root`, the directory
+//! the map paths are relative to, the way
+//! [`apply_file_events`](SourceFilesMap::apply_file_events) resolves them.
+
+use crate::dgn::{Diagnostic, DiagnosticBag, Severity};
+use crate::fid::{AbsolutePosition, FileId};
+use crate::fvw::FileRef;
+use crate::sfm::SourceFilesMap;
+use serde::Deserialize;
+use std::path::Path;
+
+/// Result of one file in an ESLint JSON report
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EslintResult {
+    pub file_path: String,
+    #[serde(default)]
+    pub messages: Vec<EslintMessage>,
+}
+
+/// Problem reported by ESLint
+///
+/// Lines are 1-based, columns 1-based UTF-16 code units with the end column
+/// exclusive. Only the fields needed to import it are kept.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EslintMessage {
+    /// None for parse errors
+    #[serde(default)]
+    pub rule_id: Option<String>,
+    /// 1 for warnings, 2 for errors
+    pub severity: u8,
+    pub message: String,
+    #[serde(default)]
+    pub line: Option<usize>,
+    #[serde(default)]
+    pub column: Option<usize>,
+    #[serde(default)]
+    pub end_line: Option<usize>,
+    #[serde(default)]
+    pub end_column: Option<usize>,
+    #[serde(default)]
+    pub fix: Option<EslintFix>,
+}
+
+/// Autofix of an ESLint message
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct EslintFix {
+    /// 0-based UTF-16 offsets in the file, end exclusive
+    pub range: [usize; 2],
+    pub text: String,
+}
+
+/// Diagnostics read by [`SourceFilesMap::import_eslint_json`] or
+/// [`SourceFilesMap::import_tsc_output`]
+#[derive(Debug, Clone)]
+pub struct JsImport<Id: FileId> {
+    pub diagnostics: DiagnosticBag<Id>,
+    /// Problems that do not resolve in the map, as `path: message` for
+    /// ESLint and as the original lines for tsc
+    pub unresolved: Vec<String>,
+}
+
+impl<Id: FileId> JsImport<Id> {
+    fn new() -> Self {
+        Self {
+            diagnostics: DiagnosticBag::new(),
+            unresolved: Vec::new(),
+        }
+    }
+}
+
+/// Byte offset of a 1-based line and 1-based UTF-16 column
+fn offset<Id: FileId>(file: &FileRef<'_, Id>, line: usize, column: usize) -> Option<usize> {
+    let (start, _) = file.line_offsets()?.get_line_range(line)?;
+    Some(start + file.byte_column(line, column.checked_sub(1)?)?)
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Diagnostic of an ESLint message about `file`
+    fn eslint_diagnostic(
+        &self,
+        file: &FileRef<'_, Id>,
+        message: &EslintMessage,
+    ) -> Option<Diagnostic<Id>> {
+        let (line, column) = (message.line?, message.column?);
+        let start = offset(file, line, column)?;
+        let end = match (message.end_line, message.end_column) {
+            (Some(line), Some(column)) => offset(file, line, column)?,
+            _ => start,
+        };
+        let severity = match message.severity {
+            2 => Severity::Error,
+            1 => Severity::Warning,
+            _ => Severity::Note,
+        };
+        let mut diagnostic = Diagnostic::new(
+            severity,
+            message.message.clone(),
+            file.position(start..end)?,
+        );
+        if let Some(rule) = &message.rule_id {
+            diagnostic = diagnostic.with_code(rule.clone());
+        }
+        if let Some(fix) = &message.fix {
+            let [start, end] = fix.range;
+            let range = file.utf16_offset(start)?..file.utf16_offset(end)?;
+            diagnostic = diagnostic.with_fix(file.position(range)?, fix.text.clone());
+        }
+        Some(diagnostic)
+    }
+
+    /// Diagnostics of an `eslint --format json` report
+    ///
+    /// Rule IDs become codes and autofixes fixes. Messages without a line,
+    /// like the summary of a file ignored by ESLint, are left unresolved.
+    pub fn import_eslint_json(
+        &self,
+        root: impl AsRef<Path>,
+        report: &str,
+    ) -> Result<JsImport<Id>, serde_json::Error> {
+        let results: Vec<EslintResult> = serde_json::from_str(report)?;
+        let mut import = JsImport::new();
+        for result in &results {
+            let file = self
+                .resolve_under(root.as_ref(), &result.file_path)
+                .and_then(|id| self.file(id));
+            for message in &result.messages {
+                match file.and_then(|file| self.eslint_diagnostic(&file, message)) {
+                    Some(diagnostic) => import.diagnostics.push(diagnostic),
+                    None => import
+                        .unresolved
+                        .push(format!("{}: {}", result.file_path, message.message)),
+                }
+            }
+        }
+        Ok(import)
+    }
+
+    /// Diagnostic of a `path(line,col): category TScode: message` line
+    fn tsc_diagnostic(&self, root: &Path, line: &str) -> Option<Diagnostic<Id>> {
+        let (location, rest) = line.split_once("): ")?;
+        let (path, point) = location.rsplit_once('(')?;
+        let (line, column) = point.split_once(',')?;
+        let (line, column) = (line.parse().ok()?, column.parse().ok()?);
+        let (category, message) = rest.split_once(": ")?;
+        let (category, code) = category.split_once(' ').unwrap_or((category, ""));
+        let severity = match category {
+            "error" => Severity::Error,
+            "warning" => Severity::Warning,
+            "suggestion" => Severity::Help,
+            "message" => Severity::Note,
+            _ => return None,
+        };
+        let file = self.file(self.resolve_under(root, path)?)?;
+        let start = offset(&file, line, column)?;
+        let diagnostic = Diagnostic::new(severity, message, file.position(start..start)?);
+        Some(match code {
+            "" => diagnostic,
+            code => diagnostic.with_code(code),
+        })
+    }
+
+    /// Diagnostics of `tsc --pretty false` output
+    ///
+    /// tsc reports only where a problem starts, so the positions are empty
+    /// spans there. Indented lines continue the message above and become its
+    /// notes; lines that are not diagnostics of a mapped file, like errors
+    /// about the configuration, are left unresolved.
+    pub fn import_tsc_output(&self, root: impl AsRef<Path>, output: &str) -> JsImport<Id> {
+        let mut import = JsImport::new();
+        let mut last: Option<Diagnostic<Id>> = None;
+        for line in output.lines().filter(|line| !line.trim().is_empty()) {
+            if line.starts_with(char::is_whitespace) {
+                match last.as_mut() {
+                    Some(diagnostic) => diagnostic.notes.push(line.trim().to_string()),
+                    None => import.unresolved.push(line.to_string()),
+                }
+                continue;
+            }
+            import.diagnostics.extend(last.take());
+            match self.tsc_diagnostic(root.as_ref(), line) {
+                Some(diagnostic) => last = Some(diagnostic),
+                None => import.unresolved.push(line.to_string()),
+            }
+        }
+        import.diagnostics.extend(last);
+        import
+    }
+
+    /// Position of a 1-based line and 1-based UTF-16 column range, as
+    /// JavaScript tooling reports them
+    pub fn utf16_position(
+        &self,
+        id: Id,
+        (start_line, start_column): (usize, usize),
+        (end_line, end_column): (usize, usize),
+    ) -> Option<AbsolutePosition<Id>> {
+        let file = self.file(id)?;
+        let start = offset(&file, start_line, start_column)?;
+        file.position(start..offset(&file, end_line, end_column)?)
+    }
+}
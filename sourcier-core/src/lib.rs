@@ -1,15 +1,189 @@
 mod tests;
 // Public modules
+#[cfg(all(feature = "test-support", feature = "view"))]
+pub mod asr;
+pub mod bld;
+#[cfg(feature = "git")]
+pub mod blm;
+#[cfg(feature = "cdc")]
+pub mod cdc;
 pub mod clo;
+#[cfg(feature = "view")]
+pub mod cov;
+pub mod cur;
+pub mod def;
+pub mod dgn;
+#[cfg(feature = "diff")]
+pub mod dif;
+pub mod dmp;
+pub mod dsk;
+pub mod dsp;
+#[cfg(feature = "dwarf")]
+pub mod dwf;
+pub mod epc;
+pub mod err;
+#[cfg(feature = "export")]
+pub mod exp;
+mod ext;
 pub mod fid;
+#[cfg(feature = "view")]
+pub mod fix;
+#[cfg(feature = "view")]
+pub mod fpr;
+pub mod frz;
+pub mod fvw;
+#[cfg(feature = "test-support")]
+pub mod fxt;
+#[cfg(all(feature = "test-support", feature = "view"))]
+pub mod gld;
+#[cfg(feature = "view")]
+pub mod gnu;
+pub mod grf;
+#[cfg(feature = "view")]
+pub mod hmp;
+#[cfg(feature = "view")]
+pub mod ign;
+#[cfg(feature = "js")]
+pub mod jsd;
+pub mod lod;
+#[cfg(feature = "lsif")]
+pub mod lsf;
+#[cfg(feature = "lsp")]
+pub mod lsp;
+#[cfg(feature = "metrics")]
+pub mod mtr;
+pub mod nmr;
+pub mod nrm;
+pub mod obs;
+pub mod pcl;
+pub mod pfl;
+#[cfg(feature = "nom")]
+pub mod pin;
+pub mod pmp;
+pub mod pst;
+mod pth;
+pub mod rmp;
+#[cfg(feature = "edit")]
+pub mod rop;
+#[cfg(feature = "rustc")]
+pub mod rsc;
+#[cfg(feature = "rt-feedback")]
+pub mod rtf;
 pub mod sfm;
 pub mod sfp;
+mod snc;
+#[cfg(feature = "view")]
+pub mod snp;
+pub mod spa;
+#[cfg(feature = "sarif")]
+pub mod srf;
+mod stf;
+pub mod sto;
+#[cfg(feature = "view")]
+pub mod tag;
+pub mod tks;
+#[cfg(any(feature = "logos", feature = "chumsky"))]
+pub mod tok;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod wch;
+pub mod wire;
+pub mod wrn;
+pub mod wsp;
 // Re-export commonly used types for convenience
+#[cfg(all(feature = "test-support", feature = "view"))]
+pub use asr::{ExpectedSpan, check_span};
+pub use bld::SourceFilesMapBuilder;
+#[cfg(feature = "git")]
+pub use blm::BlameEntry;
+#[cfg(feature = "cdc")]
+pub use cdc::{ChunkManifest, ChunkRef, ChunkStore, Chunking};
+#[cfg(feature = "view")]
+pub use cov::{CoverageSummary, SpanCoverage};
+pub use cur::{Cursor, Mark};
+pub use def::DefinitionIndex;
+pub use dgn::{Diagnostic, DiagnosticBag, Fix, Severity};
+#[cfg(feature = "diff")]
+pub use dif::{LinePorter, port_position};
+pub use dmp::DynSourceFilesMap;
+pub use dsk::{RefreshReport, TextFormat, Verification, WriteOptions};
+pub use dsp::{EditorKind, Hyperlinks, PositionDisplay};
+#[cfg(feature = "dwarf")]
+pub use dwf::LineTable;
+pub use epc::{EpochPosition, FileHandle};
+pub use err::SourceFilesError;
+#[cfg(feature = "export")]
+pub use exp::{DumpFormat, ExportOptions, LabeledSpan, SpanWriter};
 pub use fid::{
-    AbsolutePosition, CompactAbsolutePosition, FileId, RelativePosition, SourceFilePosition,
-    StandardAbsolutePosition,
+    AbsolutePosition, CompactAbsolutePosition, FileId, IdWidth, RangeSemantics, RelativePosition,
+    SourceFilePosition, StandardAbsolutePosition,
 };
+#[cfg(feature = "view")]
+pub use fix::{FixPlan, FixReport, apply_fixes, plan_fixes};
+#[cfg(feature = "view")]
+pub use fpr::{Baseline, Fingerprint};
+pub use frz::FrozenSourceFilesMap;
+pub use fvw::{FileRef, FileView};
+#[cfg(feature = "test-support")]
+pub use fxt::Fixture;
+#[cfg(all(feature = "test-support", feature = "view"))]
+pub use gld::{Annotation, AnnotationParser, check_annotations};
+#[cfg(feature = "view")]
+pub use gnu::{GnuImport, GnuMessage, GnuParser};
+pub use grf::FileGraph;
+#[cfg(feature = "view")]
+pub use hmp::SpanHeatmap;
+#[cfg(all(feature = "view", feature = "export"))]
+pub use hmp::{HeatmapExport, HeatmapFile};
+#[cfg(feature = "view")]
+pub use ign::{IgnoreConfig, IgnoreIndex, IgnoreScanner};
+#[cfg(feature = "js")]
+pub use jsd::{EslintFix, EslintMessage, EslintResult, JsImport};
+pub use lod::{BINARY_PLACEHOLDER, LoadOptions, SkipReason, SkippedFile};
+#[cfg(feature = "lsif")]
+pub use lsf::LsifOptions;
+#[cfg(feature = "metrics")]
+pub use mtr::MetricsObserver;
+pub use nmr::NamedRanges;
+pub use nrm::{Normalize, OffsetMap};
+pub use obs::{FinalizeEvent, FinalizePhases, MapObserver};
+pub use pcl::PositionColumn;
+pub use pfl::PathFilter;
+#[cfg(feature = "nom")]
+pub use pin::PositionedInput;
+pub use pmp::{PartialSourceFilesMap, Prefetch};
+pub use pst::PositionSet;
+pub use rmp::{EditRemap, IdRemapTable};
+#[cfg(feature = "edit")]
+pub use rop::Rope;
+#[cfg(feature = "rustc")]
+pub use rsc::{RustcCode, RustcDiagnostic, RustcImport, RustcSpan};
 #[cfg(feature = "rt-feedback")]
-pub use sfm::RuntimeFeedback;
-pub use sfm::SourceFilesMap;
+pub use rtf::{FeedbackConfig, FeedbackSnapshot, FinalizeRecord, RuntimeFeedback};
+#[cfg(all(feature = "view", feature = "rt-feedback"))]
+pub use rtf::{FileTruncations, FileViewStats};
+pub use sfm::{DuplicatePolicy, FileOrder, SourceFilesMap};
 pub use sfp::{create_absolute_position, create_relative_position, print_position_info};
+#[cfg(feature = "view")]
+pub use snp::{UnderlineSegment, display_width, gutter_width, underline_segment};
+pub use spa::SpanAccumulator;
+#[cfg(feature = "sarif")]
+pub use srf::{SarifDriver, SarifLog};
+pub use sto::{ContentChunks, Storage};
+#[cfg(feature = "view")]
+pub use tag::TagsFormat;
+pub use tks::TokenSpans;
+#[cfg(feature = "logos")]
+pub use tok::PositionedLexer;
+#[cfg(feature = "tui")]
+pub use tui::SnippetView;
+pub use wch::{FileChange, FileEvent, WatchUpdate, WatchmanFile, WatchmanNotification};
+pub use wire::{CacheMetadata, FORMAT_VERSION, FileMetadata, WireError};
+pub use wrn::{Warning, WarningSink};
+pub use wsp::{Namespace, SourceWorkspace, WorkspaceMatch};
+
+// Lets macro expansions name `::sourcier_core` inside this crate too
+#[cfg(feature = "macros")]
+extern crate self as sourcier_core;
+#[cfg(feature = "macros")]
+pub use sourcier_macros::{pos, span_of};
@@ -0,0 +1,105 @@
+use crate::nrm::Normalize;
+use std::fmt;
+
+/// Bytes sniffed when looking for binary content, as many as git does
+pub(crate) const SNIFF_LEN: usize = 8000;
+/// Share of invalid UTF-8 in the sniffed bytes from which content is binary
+const INVALID_UTF8_PERCENT: usize = 30;
+
+/// Text shown in place of the content of binary files
+pub const BINARY_PLACEHOLDER: &str = "<binary file>";
+
+/// Filters and rewrites applied to files as they are added to a map
+///
+/// The default accepts everything as is. Rejected files are not added: the adding
+/// call fails with [`SourceFilesError::Skipped`](crate::SourceFilesError::Skipped)
+/// and the file is kept in [`SourceFilesMap::skipped_files`](crate::SourceFilesMap::skipped_files).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadOptions {
+    /// Largest content accepted, in bytes
+    pub max_file_size: Option<usize>,
+    /// Reject content that looks binary, see [`SourceFilesMap::is_binary`](crate::SourceFilesMap::is_binary)
+    pub skip_binary: bool,
+    /// Rewrites applied to accepted content, see [`Normalize`]
+    pub normalize: Normalize,
+}
+
+impl LoadOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_file_size(mut self, max_file_size: usize) -> Self {
+        self.max_file_size = Some(max_file_size);
+        self
+    }
+
+    pub fn with_skip_binary(mut self, skip_binary: bool) -> Self {
+        self.skip_binary = skip_binary;
+        self
+    }
+
+    /// Normalize accepted content, keeping the offsets of the content as
+    /// added, see [`SourceFilesMap::original_position`](crate::SourceFilesMap::original_position)
+    pub fn with_normalize(mut self, normalize: Normalize) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    /// Why `content` is rejected, if it is
+    pub fn check(&self, content: &[u8]) -> Option<SkipReason> {
+        if let Some(max) = self.max_file_size.filter(|&max| content.len() > max) {
+            return Some(SkipReason::TooLarge {
+                size: content.len(),
+                max,
+            });
+        }
+        if self.skip_binary && looks_binary(content) {
+            return Some(SkipReason::Binary);
+        }
+        None
+    }
+}
+
+/// Whether the first 8000 bytes of `content` hold a NUL byte or are mostly
+/// not UTF-8
+pub(crate) fn looks_binary(content: &[u8]) -> bool {
+    let sniffed = &content[..content.len().min(SNIFF_LEN)];
+    if memchr::memchr(0, sniffed).is_some() {
+        return true;
+    }
+    let invalid: usize = sniffed
+        .utf8_chunks()
+        .map(|chunk| chunk.invalid().len())
+        .sum();
+    invalid * 100 > sniffed.len() * INVALID_UTF8_PERCENT
+}
+
+/// Why a file was not added
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Content of `size` bytes, over the `max` allowed
+    TooLarge {
+        size: usize,
+        max: usize,
+    },
+    Binary,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLarge { size, max } => {
+                write!(f, "{} bytes exceeds the limit of {} bytes", size, max)
+            }
+            Self::Binary => write!(f, "binary content"),
+        }
+    }
+}
+
+/// A file rejected by the [`LoadOptions`] of a map
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: SkipReason,
+}
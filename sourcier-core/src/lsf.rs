@@ -0,0 +1,237 @@
+//! LSIF dumps of a definition index
+//!
+//! [`DefinitionIndex::write_lsif`] writes the index as an LSIF 0.6 graph, one
+//! JSON vertex or edge per line, which code hosts like Sourcegraph and GitHub
+//! upload to offer go-to-definition and find-references without running a
+//! language server. Every mapped file holding a span becomes a document,
+//! every span a range, and every symbol a result set linking its ranges to
+//! its definition and reference results.
+//!
+//! Ranges use 0-based lines and UTF-16 characters, the encoding declared in
+//! the metadata. Documents and symbols are written in position order, so a
+//! dump of the same index is byte for byte the same.
+
+use crate::def::DefinitionIndex;
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::hash::Hash;
+use std::io::{self, Write};
+
+/// LSIF version written in the metadata
+pub const LSIF_VERSION: &str = "0.6.0";
+
+/// Where the dumped project lives, for the document URIs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LsifOptions {
+    /// URI of the directory the map paths are relative to, e.g.
+    /// `file:///home/me/project`
+    pub project_root: String,
+    /// Language of the documents, e.g. `rust`
+    pub language_id: String,
+}
+
+impl LsifOptions {
+    pub fn new(project_root: impl Into<String>, language_id: impl Into<String>) -> Self {
+        Self {
+            project_root: project_root.into(),
+            language_id: language_id.into(),
+        }
+    }
+
+    /// URI of a map path, percent-encoding what URIs do not allow
+    fn uri(&self, path: &str) -> String {
+        let mut uri = self.project_root.trim_end_matches('/').to_string();
+        uri.push('/');
+        for byte in path.bytes() {
+            match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                    uri.push(byte as char)
+                }
+                _ => uri.push_str(&format!("%{byte:02X}")),
+            }
+        }
+        uri
+    }
+}
+
+/// Numbers the elements of a dump as it writes them
+struct Emitter<W: Write> {
+    out: W,
+    next: u64,
+}
+
+impl<W: Write> Emitter<W> {
+    /// Write an element with the next ID and return the ID
+    fn emit(&mut self, kind: &str, label: &str, mut fields: Value) -> io::Result<u64> {
+        self.next += 1;
+        fields["id"] = json!(self.next);
+        fields["type"] = json!(kind);
+        fields["label"] = json!(label);
+        serde_json::to_writer(&mut self.out, &fields)?;
+        self.out.write_all(b"\n")?;
+        Ok(self.next)
+    }
+
+    fn vertex(&mut self, label: &str, fields: Value) -> io::Result<u64> {
+        self.emit("vertex", label, fields)
+    }
+
+    fn edge(&mut self, label: &str, fields: Value) -> io::Result<u64> {
+        self.emit("edge", label, fields)
+    }
+}
+
+/// LSIF range of a span: 0-based lines and UTF-16 characters
+fn range<Id: FileId>(map: &SourceFilesMap<Id>, pos: &AbsolutePosition<Id>) -> Option<Value> {
+    let file = map.file(pos.file_id())?;
+    let point = |line: u16, byte_col: usize| -> Option<Value> {
+        let character = file.utf16_column(line as usize, byte_col)?;
+        Some(json!({ "line": line.checked_sub(1)?, "character": character }))
+    };
+    Some(json!({
+        "start": point(pos.start_line(), (pos.start_column() as usize).saturating_sub(1))?,
+        "end": point(pos.end_line(), pos.end_column() as usize)?,
+    }))
+}
+
+/// Order of spans in a dump
+fn sort_key<Id: FileId>(pos: &AbsolutePosition<Id>) -> (Id, u16, u8, u16, u8) {
+    (
+        pos.file_id(),
+        pos.start_line(),
+        pos.start_column(),
+        pos.end_line(),
+        pos.end_column(),
+    )
+}
+
+impl<K: Eq + Hash + Clone, Id: FileId> DefinitionIndex<K, Id> {
+    /// Write the index as an LSIF dump of the files of `map`
+    ///
+    /// Spans of files missing from `map` or past the end of their line are
+    /// left out, as are symbols left without any span.
+    pub fn write_lsif(
+        &self,
+        map: &SourceFilesMap<Id>,
+        options: &LsifOptions,
+        out: impl Write,
+    ) -> io::Result<()> {
+        // Symbols with their definition and deduplicated references, in the
+        // order of their first span
+        let mut symbols: Vec<_> = self
+            .symbols()
+            .map(|(_, definition, references)| {
+                let definition = definition.filter(|pos| range(map, pos).is_some());
+                let mut references: Vec<_> = references
+                    .iter()
+                    .filter(|pos| Some(**pos) != definition && range(map, pos).is_some())
+                    .copied()
+                    .collect();
+                references.sort_by_key(sort_key);
+                references.dedup();
+                (definition, references)
+            })
+            .filter(|(definition, references)| definition.is_some() || !references.is_empty())
+            .collect();
+        symbols.sort_by_key(|(definition, references)| {
+            definition
+                .or(references.first().copied())
+                .map(|pos| sort_key(&pos))
+        });
+
+        let mut documents: BTreeMap<Id, Vec<AbsolutePosition<Id>>> = BTreeMap::new();
+        for (definition, references) in &symbols {
+            for pos in definition.iter().chain(references) {
+                documents.entry(pos.file_id()).or_default().push(*pos);
+            }
+        }
+
+        let mut emitter = Emitter { out, next: 0 };
+        emitter.vertex(
+            "metaData",
+            json!({
+                "version": LSIF_VERSION,
+                "projectRoot": options.project_root,
+                "positionEncoding": "utf-16",
+                "toolInfo": { "name": "sourcier", "version": env!("CARGO_PKG_VERSION") },
+            }),
+        )?;
+        let project = emitter.vertex("project", json!({ "kind": options.language_id }))?;
+
+        let mut ranges: BTreeMap<(Id, u16, u8, u16, u8), u64> = BTreeMap::new();
+        let mut document_ids = BTreeMap::new();
+        for (id, spans) in &mut documents {
+            let path = map.get_path(*id).unwrap_or_default();
+            let document = emitter.vertex(
+                "document",
+                json!({ "uri": options.uri(path), "languageId": options.language_id }),
+            )?;
+            document_ids.insert(*id, document);
+            spans.sort_by_key(sort_key);
+            spans.dedup();
+            let mut contained = Vec::new();
+            for pos in spans.iter() {
+                let Some(fields) = range(map, pos) else {
+                    continue;
+                };
+                let vertex = emitter.vertex("range", fields)?;
+                ranges.insert(sort_key(pos), vertex);
+                contained.push(vertex);
+            }
+            emitter.edge("contains", json!({ "outV": document, "inVs": contained }))?;
+        }
+        let documents: Vec<_> = document_ids.values().copied().collect();
+        emitter.edge("contains", json!({ "outV": project, "inVs": documents }))?;
+
+        // `item` edges, grouped by the document holding the ranges
+        let items = |emitter: &mut Emitter<_>,
+                     result: u64,
+                     spans: &[AbsolutePosition<Id>],
+                     property: Option<&str>|
+         -> io::Result<()> {
+            let mut by_document: BTreeMap<Id, Vec<u64>> = BTreeMap::new();
+            for pos in spans {
+                by_document
+                    .entry(pos.file_id())
+                    .or_default()
+                    .push(ranges[&sort_key(pos)]);
+            }
+            for (id, in_vs) in by_document {
+                let mut fields =
+                    json!({ "outV": result, "inVs": in_vs, "document": document_ids[&id] });
+                if let Some(property) = property {
+                    fields["property"] = json!(property);
+                }
+                emitter.edge("item", fields)?;
+            }
+            Ok(())
+        };
+
+        for (definition, references) in &symbols {
+            let result_set = emitter.vertex("resultSet", json!({}))?;
+            for pos in definition.iter().chain(references) {
+                let range = ranges[&sort_key(pos)];
+                emitter.edge("next", json!({ "outV": range, "inV": result_set }))?;
+            }
+            let definitions: Vec<_> = definition.iter().copied().collect();
+            if !definitions.is_empty() {
+                let result = emitter.vertex("definitionResult", json!({}))?;
+                emitter.edge(
+                    "textDocument/definition",
+                    json!({ "outV": result_set, "inV": result }),
+                )?;
+                items(&mut emitter, result, &definitions, None)?;
+            }
+            let result = emitter.vertex("referenceResult", json!({}))?;
+            emitter.edge(
+                "textDocument/references",
+                json!({ "outV": result_set, "inV": result }),
+            )?;
+            items(&mut emitter, result, &definitions, Some("definitions"))?;
+            items(&mut emitter, result, references, Some("references"))?;
+        }
+        emitter.out.flush()
+    }
+}
@@ -0,0 +1,112 @@
+use crate::dgn::{Diagnostic, DiagnosticBag, Severity};
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+use crate::wch::{FileChange, FileEvent, relative_path};
+use lsp_types::{
+    DiagnosticRelatedInformation, DiagnosticSeverity, FileChangeType, Location, NumberOrString,
+    Position, Range, Uri,
+};
+use std::path::Path;
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// LSP range of a span: 0-based lines and UTF-16 columns
+    ///
+    /// None when the file or one of the lines is not in the map.
+    pub fn lsp_range(&self, pos: &AbsolutePosition<Id>) -> Option<Range> {
+        let file = self.file(pos.file_id())?;
+        let start_line = pos.start_line() as usize;
+        let end_line = pos.end_line() as usize;
+        let start =
+            file.utf16_column(start_line, (pos.start_column() as usize).saturating_sub(1))?;
+        let end = file.utf16_column(end_line, pos.end_column() as usize)?;
+        Some(Range::new(
+            Position::new(start_line.checked_sub(1)? as u32, start as u32),
+            Position::new(end_line.checked_sub(1)? as u32, end as u32),
+        ))
+    }
+}
+
+fn severity(severity: Severity) -> DiagnosticSeverity {
+    match severity {
+        Severity::Error => DiagnosticSeverity::ERROR,
+        Severity::Warning => DiagnosticSeverity::WARNING,
+        Severity::Note => DiagnosticSeverity::INFORMATION,
+        Severity::Help => DiagnosticSeverity::HINT,
+    }
+}
+
+impl<Id: FileId> Diagnostic<Id> {
+    /// LSP diagnostic for the document holding the primary span
+    ///
+    /// `uri` turns map paths into document URIs for the related information
+    /// built from labels; labels it returns None for are dropped. Notes are
+    /// appended to the message, one per line. None when the primary span does
+    /// not resolve in `map`.
+    pub fn to_lsp(
+        &self,
+        map: &SourceFilesMap<Id>,
+        uri: impl Fn(&str) -> Option<Uri>,
+    ) -> Option<lsp_types::Diagnostic> {
+        let related: Vec<_> = self
+            .labels
+            .iter()
+            .filter_map(|label| {
+                let path = map.get_path(label.pos.file_id())?;
+                Some(DiagnosticRelatedInformation {
+                    location: Location::new(uri(path)?, map.lsp_range(&label.pos)?),
+                    message: label.message.clone(),
+                })
+            })
+            .collect();
+        let mut message = self.message.clone();
+        for note in &self.notes {
+            message.push('\n');
+            message.push_str(note);
+        }
+        Some(lsp_types::Diagnostic {
+            range: map.lsp_range(&self.primary)?,
+            severity: Some(severity(self.severity)),
+            code: self.code.clone().map(NumberOrString::String),
+            message,
+            related_information: (!related.is_empty()).then_some(related),
+            ..Default::default()
+        })
+    }
+}
+
+impl<Id: FileId> DiagnosticBag<Id> {
+    /// Diagnostics of one document, ready for `textDocument/publishDiagnostics`
+    ///
+    /// See [`Diagnostic::to_lsp`] for how `uri` is used.
+    pub fn to_lsp_diagnostics(
+        &self,
+        map: &SourceFilesMap<Id>,
+        file_id: Id,
+        uri: impl Fn(&str) -> Option<Uri>,
+    ) -> Vec<lsp_types::Diagnostic> {
+        self.in_file(file_id)
+            .filter_map(|diagnostic| diagnostic.to_lsp(map, &uri))
+            .collect()
+    }
+}
+
+impl FileEvent {
+    /// Event of a `workspace/didChangeWatchedFiles` notification, for a path
+    /// relative to `root`
+    ///
+    /// None for URIs that are not `file:` URIs under `root`.
+    pub fn from_lsp(event: &lsp_types::FileEvent, root: impl AsRef<Path>) -> Option<Self> {
+        let change = match event.typ {
+            FileChangeType::CREATED => FileChange::Created,
+            FileChangeType::CHANGED => FileChange::Changed,
+            FileChangeType::DELETED => FileChange::Deleted,
+            _ => return None,
+        };
+        if event.uri.scheme()?.as_str() != "file" {
+            return None;
+        }
+        let path = event.uri.path().as_estr().decode().into_string().ok()?;
+        let path = relative_path(root.as_ref(), Path::new(path.as_ref()))?;
+        Some(Self::new(path, change))
+    }
+}
@@ -0,0 +1,66 @@
+use crate::fid::FileId;
+use crate::obs::{FinalizeEvent, MapObserver};
+#[cfg(feature = "rt-feedback")]
+use crate::rtf::RuntimeFeedback;
+
+/// Observer publishing map activity through the `metrics` facade
+///
+/// Register it with [`SourceFilesMap::add_observer`](crate::SourceFilesMap::add_observer);
+/// whichever recorder the host application installed (Prometheus, StatsD, ...)
+/// receives the values. Metric names are prefixed with `sourcier_`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MetricsObserver;
+
+impl MetricsObserver {
+    pub const FILES_SUBMITTED: &'static str = "sourcier_files_submitted_total";
+    pub const FILES_INDEXED: &'static str = "sourcier_files_indexed";
+    pub const BYTES_STORED: &'static str = "sourcier_bytes_stored";
+    pub const FINALIZE_SECONDS: &'static str = "sourcier_finalize_duration_seconds";
+    pub const FINALIZATIONS: &'static str = "sourcier_finalizations_total";
+    pub const VIEWS: &'static str = "sourcier_views_total";
+    pub const VIEW_BYTES: &'static str = "sourcier_view_bytes_total";
+    pub const EDITS: &'static str = "sourcier_edits_total";
+}
+
+impl<Id: FileId> MapObserver<Id> for MetricsObserver {
+    fn on_add_file(&self, _path: &str, _size: usize) {
+        metrics::counter!(Self::FILES_SUBMITTED).increment(1);
+    }
+
+    fn on_finalize(&self, event: &FinalizeEvent<'_>) {
+        metrics::counter!(Self::FINALIZATIONS).increment(1);
+        metrics::gauge!(Self::FILES_INDEXED).set(event.files() as f64);
+        metrics::gauge!(Self::BYTES_STORED).set(event.bytes as f64);
+        metrics::histogram!(Self::FINALIZE_SECONDS).record(event.elapsed.as_secs_f64());
+    }
+
+    fn on_view(&self, _id: Id, len: Option<usize>) {
+        match len {
+            Some(len) => {
+                metrics::counter!(Self::VIEWS, "result" => "hit").increment(1);
+                metrics::counter!(Self::VIEW_BYTES).increment(len as u64);
+            }
+            None => metrics::counter!(Self::VIEWS, "result" => "miss").increment(1),
+        }
+    }
+
+    fn on_edit(&self, _id: Id, _old_size: usize, _new_size: usize) {
+        metrics::counter!(Self::EDITS).increment(1);
+    }
+}
+
+#[cfg(feature = "rt-feedback")]
+impl RuntimeFeedback {
+    /// Publish the session-wide counters as `sourcier_feedback_*` gauges
+    pub fn publish_metrics(&self) {
+        let snapshot = self.snapshot();
+        metrics::gauge!("sourcier_feedback_finalizations").set(snapshot.usage_count as f64);
+        metrics::gauge!("sourcier_feedback_files").set(snapshot.cumulative_files as f64);
+        metrics::gauge!("sourcier_feedback_bytes").set(snapshot.cumulative_bytes as f64);
+        metrics::gauge!("sourcier_feedback_peak_files").set(snapshot.peak_files as f64);
+        metrics::gauge!("sourcier_feedback_max_file_size").set(snapshot.max_file_size as f64);
+        if let Some(p95) = snapshot.p95() {
+            metrics::gauge!("sourcier_feedback_p95_file_size").set(p95 as f64);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use crate::fid::{AbsolutePosition, FileId};
+use crate::rmp::{EditRemap, IdRemapTable};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+/// Bookmarks: spans under human-readable keys such as `fn:parse_expr`
+///
+/// Keys are kept sorted, so iteration and serialized output are
+/// deterministic. Spans are carried across edits with [`NamedRanges::remap`]
+/// and across map generations with [`NamedRanges::remap_ids`].
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = "", transparent))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamedRanges<Id: FileId> {
+    ranges: BTreeMap<String, AbsolutePosition<Id>>,
+}
+
+impl<Id: FileId> Default for NamedRanges<Id> {
+    fn default() -> Self {
+        Self {
+            ranges: BTreeMap::new(),
+        }
+    }
+}
+
+impl<Id: FileId> NamedRanges<Id> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name a span, returning the span the key held before
+    pub fn insert(
+        &mut self,
+        key: impl Into<String>,
+        pos: AbsolutePosition<Id>,
+    ) -> Option<AbsolutePosition<Id>> {
+        self.ranges.insert(key.into(), pos)
+    }
+
+    pub fn get(&self, key: &str) -> Option<AbsolutePosition<Id>> {
+        self.ranges.get(key).copied()
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<AbsolutePosition<Id>> {
+        self.ranges.remove(key)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.ranges.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// Every named span, by key
+    pub fn iter(&self) -> impl Iterator<Item = (&str, AbsolutePosition<Id>)> {
+        self.ranges.iter().map(|(key, pos)| (key.as_str(), *pos))
+    }
+
+    /// Keys starting with `prefix`, e.g. every `fn:` bookmark
+    pub fn with_prefix<'a>(
+        &'a self,
+        prefix: &'a str,
+    ) -> impl Iterator<Item = (&'a str, AbsolutePosition<Id>)> {
+        self.ranges
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(move |(key, _)| key.starts_with(prefix))
+            .map(|(key, pos)| (key.as_str(), *pos))
+    }
+
+    /// Named spans of one file
+    pub fn in_file(&self, id: Id) -> impl Iterator<Item = (&str, AbsolutePosition<Id>)> {
+        self.iter().filter(move |(_, pos)| pos.file_id() == id)
+    }
+
+    /// Move every span across `edits`, returning the keys dropped because an
+    /// edit overlapped their span
+    pub fn remap(&mut self, edits: &EditRemap<Id>) -> Vec<String> {
+        self.retain_mapped(|pos| edits.apply(pos))
+    }
+
+    /// Move every span to the file IDs of a newer map, returning the keys
+    /// dropped because their file is gone
+    pub fn remap_ids(&mut self, table: &IdRemapTable<Id>) -> Vec<String> {
+        self.retain_mapped(|pos| table.apply(pos))
+    }
+
+    fn retain_mapped(
+        &mut self,
+        mut map: impl FnMut(&AbsolutePosition<Id>) -> Option<AbsolutePosition<Id>>,
+    ) -> Vec<String> {
+        let mut dropped = Vec::new();
+        self.ranges.retain(|key, pos| match map(pos) {
+            Some(moved) => {
+                *pos = moved;
+                true
+            }
+            None => {
+                dropped.push(key.clone());
+                false
+            }
+        });
+        dropped
+    }
+}
+
+impl<Id: FileId, K: Into<String>> FromIterator<(K, AbsolutePosition<Id>)> for NamedRanges<Id> {
+    fn from_iter<T: IntoIterator<Item = (K, AbsolutePosition<Id>)>>(iter: T) -> Self {
+        Self {
+            ranges: iter
+                .into_iter()
+                .map(|(key, pos)| (key.into(), pos))
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use crate::dsk::TextFormat;
+use crate::fid::FileId;
+#[cfg(feature = "view")]
+use crate::fid::{AbsolutePosition, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+use std::ops::Range;
+
+/// Rewrites applied to content as it is added, see [`LoadOptions::with_normalize`](crate::LoadOptions::with_normalize)
+///
+/// Every pass keeps lines where they are, so line numbers are the same in the
+/// normalized and the added content; only columns move. The default rewrites
+/// nothing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Normalize {
+    /// Expand tabs with spaces up to the next multiple of this many columns
+    pub tab_width: Option<usize>,
+    /// Turn `\r\n` line endings into `\n`
+    pub crlf_to_lf: bool,
+    /// Drop the spaces and tabs ending each line
+    pub trim_trailing_whitespace: bool,
+}
+
+impl Normalize {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = Some(tab_width.max(1));
+        self
+    }
+
+    pub fn with_crlf_to_lf(mut self, crlf_to_lf: bool) -> Self {
+        self.crlf_to_lf = crlf_to_lf;
+        self
+    }
+
+    pub fn with_trim_trailing_whitespace(mut self, trim: bool) -> Self {
+        self.trim_trailing_whitespace = trim;
+        self
+    }
+
+    /// Whether any pass is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.tab_width.is_some() || self.crlf_to_lf || self.trim_trailing_whitespace
+    }
+
+    /// Normalized `content`, with the offsets of its bytes in `content`
+    pub fn apply(&self, content: &[u8]) -> (Vec<u8>, OffsetMap) {
+        let mut out = Emitter::with_capacity(content.len());
+        let mut start = 0;
+        while start < content.len() {
+            let newline = memchr::memchr(b'\n', &content[start..]).map(|at| start + at);
+            let end = newline.unwrap_or(content.len());
+            let mut body = end;
+            let crlf = newline.is_some() && body > start && content[body - 1] == b'\r';
+            if crlf {
+                body -= 1;
+            }
+            let kept_cr = crlf && !self.crlf_to_lf;
+            if self.trim_trailing_whitespace {
+                while body > start && matches!(content[body - 1], b' ' | b'\t') {
+                    body -= 1;
+                }
+            }
+            self.emit_line(&mut out, content, start..body);
+            if kept_cr {
+                out.copy(end - 1, b"\r");
+            }
+            if let Some(newline) = newline {
+                out.copy(newline, b"\n");
+            }
+            start = end + 1;
+        }
+        out.finish(content.len())
+    }
+
+    /// Emit one line without its ending, expanding tabs
+    fn emit_line(&self, out: &mut Emitter, content: &[u8], line: Range<usize>) {
+        let Some(width) = self.tab_width.map(|width| width.max(1)) else {
+            out.copy(line.start, &content[line]);
+            return;
+        };
+        let line_start = out.bytes.len();
+        let mut run = line.start;
+        for at in memchr::memchr_iter(b'\t', &content[line.clone()]).map(|at| line.start + at) {
+            out.copy(run, &content[run..at]);
+            let column = out.bytes.len() - line_start;
+            out.expand(at, width - column % width);
+            run = at + 1;
+        }
+        out.copy(run, &content[run..line.end]);
+    }
+}
+
+/// Normalized output and the points where its offsets stop following the input
+struct Emitter {
+    bytes: Vec<u8>,
+    points: Vec<(usize, usize)>,
+    // Input offset continuing the current one-to-one run, if any
+    next: Option<usize>,
+}
+
+impl Emitter {
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            bytes: Vec::with_capacity(capacity),
+            points: Vec::new(),
+            next: None,
+        }
+    }
+
+    /// Copy `bytes` read at `from` in the input
+    fn copy(&mut self, from: usize, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        if self.next != Some(from) {
+            self.points.push((self.bytes.len(), from));
+        }
+        self.bytes.extend_from_slice(bytes);
+        self.next = Some(from + bytes.len());
+    }
+
+    /// Replace the byte at `from` in the input with `spaces` spaces
+    fn expand(&mut self, from: usize, spaces: usize) {
+        self.points.push((self.bytes.len(), from));
+        self.bytes.resize(self.bytes.len() + spaces, b' ');
+        self.next = None;
+    }
+
+    fn finish(mut self, input_len: usize) -> (Vec<u8>, OffsetMap) {
+        if self.next != Some(input_len) {
+            self.points.push((self.bytes.len(), input_len));
+        }
+        // A single run from the start maps every offset to itself
+        if self.points == [(0, 0)] && self.bytes.len() == input_len {
+            self.points.clear();
+        }
+        (
+            self.bytes,
+            OffsetMap {
+                points: self.points,
+            },
+        )
+    }
+}
+
+/// Offsets of normalized content back in the content as it was added
+///
+/// Kept as the points where normalized offsets stop following the added
+/// ones one to one. Offsets inside a tab expansion map to the tab, and
+/// offsets at removed bytes to the byte following them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OffsetMap {
+    // (normalized, added) offsets, both increasing
+    points: Vec<(usize, usize)>,
+}
+
+impl OffsetMap {
+    /// Whether normalization changed nothing
+    pub fn is_identity(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Offset in the added content of a normalized `offset`
+    pub fn original_offset(&self, offset: usize) -> usize {
+        let index = self
+            .points
+            .partition_point(|&(normalized, _)| normalized <= offset);
+        let Some(&(normalized, added)) = index.checked_sub(1).and_then(|i| self.points.get(i))
+        else {
+            return offset;
+        };
+        let mapped = added + (offset - normalized);
+        match self.points.get(index) {
+            // Stay before the bytes the next point skips or expands
+            Some(&(_, next)) => mapped.min(next.saturating_sub(1).max(added)),
+            None => mapped,
+        }
+    }
+
+    /// Range in the added content of a normalized `range`
+    pub fn original_range(&self, range: Range<usize>) -> Range<usize> {
+        self.original_offset(range.start)..self.original_offset(range.end)
+    }
+}
+
+/// Normalization of one file, kept by path
+#[derive(Debug, Clone)]
+pub(crate) struct Normalized {
+    /// None once the content was edited
+    pub(crate) offsets: Option<OffsetMap>,
+    /// XXH3 of the content as added, before normalization
+    pub(crate) source_hash: u64,
+    /// Line ending and BOM of the content as added
+    pub(crate) format: TextFormat,
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Offsets of a file in its content as added, when normalization
+    /// changed it
+    pub fn offset_map(&self, id: Id) -> Option<&OffsetMap> {
+        self.normalized(id)?.offsets.as_ref()
+    }
+
+    /// Byte range in the content as added of a `range` of the content in
+    /// the map
+    pub fn original_range(&self, id: Id, range: Range<usize>) -> Option<Range<usize>> {
+        self.get_content(id)?;
+        Some(match self.offset_map(id) {
+            Some(offsets) => offsets.original_range(range),
+            None => range,
+        })
+    }
+
+    /// Position in the content as added of a position in the normalized
+    /// content, for reporting against the file as it is on disk
+    ///
+    /// Lines are kept; columns are recomputed from the [`OffsetMap`]. None
+    /// for positions of another file, out of bounds or whose columns no
+    /// longer fit.
+    #[cfg(feature = "view")]
+    pub fn original_position(
+        &self,
+        id: Id,
+        pos: &AbsolutePosition<Id>,
+    ) -> Option<AbsolutePosition<Id>> {
+        let range = self.file(id)?.byte_range(pos)?;
+        let Some(offsets) = self.offset_map(id) else {
+            return Some(*pos);
+        };
+        let lines = self.line_offsets(id)?;
+        let column = |line: u16, offset: usize| -> Option<usize> {
+            let (line_start, _) = lines.get_line_range(line as usize)?;
+            Some(offsets.original_offset(offset) - offsets.original_offset(line_start))
+        };
+        let start_col = column(pos.start_line(), range.start)? + 1;
+        let end_col = column(pos.end_line(), range.end)?;
+        Some(AbsolutePosition::new(
+            id,
+            pos.start_line(),
+            start_col.try_into().ok()?,
+            pos.end_line(),
+            end_col.try_into().ok()?,
+        ))
+    }
+}
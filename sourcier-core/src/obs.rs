@@ -0,0 +1,132 @@
+use crate::fid::FileId;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Hooks into the lifecycle of a [`SourceFilesMap`](crate::SourceFilesMap)
+///
+/// Every method has an empty default, so observers only implement the events
+/// they care about. Observers are shared (`Arc`) and called through `&self`,
+/// including from read-only queries like `view`, so they must be thread safe.
+pub trait MapObserver<Id: FileId>: Send + Sync {
+    /// A file was stored by `add_file`
+    ///
+    /// Files skipped, rejected, dropped past capacity or discarded as a kept
+    /// duplicate fire nothing. Outside [`FileOrder::Insertion`](crate::FileOrder::Insertion),
+    /// duplicates are only resolved by `finalize`, so each one fires.
+    fn on_add_file(&self, _path: &str, _size: usize) {}
+
+    /// The map filled up to [`CAPACITY_WARNING_PERCENT`] of `Id::MAX_FILES`
+    ///
+    /// Fired once, by the `add_file` call crossing the threshold.
+    fn on_near_capacity(&self, _files: usize, _max_files: usize) {}
+
+    /// The map was finalized
+    fn on_finalize(&self, _event: &FinalizeEvent<'_>) {}
+
+    /// A span was viewed; `len` is None when the view could not be resolved
+    fn on_view(&self, _id: Id, _len: Option<usize>) {}
+
+    /// The content of a file was replaced
+    fn on_edit(&self, _id: Id, _old_size: usize, _new_size: usize) {}
+}
+
+/// Fill level of a map, in percent of `Id::MAX_FILES`, triggering
+/// [`MapObserver::on_near_capacity`]
+pub const CAPACITY_WARNING_PERCENT: usize = 90;
+
+/// Summary of a finalization passed to [`MapObserver::on_finalize`]
+#[derive(Debug, Clone, Copy)]
+pub struct FinalizeEvent<'a> {
+    /// Sizes of the retained files, in ID order
+    pub file_sizes: &'a [usize],
+    /// Total bytes of the retained files
+    pub bytes: u64,
+    /// Total line count, when line offsets were computed (`view` feature)
+    pub lines: Option<u64>,
+    /// Wall-clock time spent in `finalize` up to the notification
+    pub elapsed: Duration,
+    /// Breakdown of `elapsed` by finalize phase
+    pub phases: FinalizePhases,
+}
+
+/// Wall-clock durations of the phases of `finalize`
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FinalizePhases {
+    /// Ordering files by path
+    pub sort: Duration,
+    /// Dropping duplicate paths
+    pub dedup: Duration,
+    /// Assigning IDs and consolidating contents
+    pub consolidation: Duration,
+    /// Computing line offset tables (zero without the `view` feature)
+    pub line_offsets: Duration,
+}
+
+impl FinalizePhases {
+    /// Sum of all phases
+    pub fn total(&self) -> Duration {
+        self.sort + self.dedup + self.consolidation + self.line_offsets
+    }
+
+    /// Phases as `(name, duration)` pairs, in execution order
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, Duration)> {
+        [
+            ("sort", self.sort),
+            ("dedup", self.dedup),
+            ("consolidation", self.consolidation),
+            ("line_offsets", self.line_offsets),
+        ]
+        .into_iter()
+    }
+}
+
+impl FinalizeEvent<'_> {
+    /// Number of files retained by the finalization
+    pub fn files(&self) -> usize {
+        self.file_sizes.len()
+    }
+
+    /// Size of the largest retained file
+    pub fn max_file_size(&self) -> usize {
+        self.file_sizes.iter().copied().max().unwrap_or(0)
+    }
+}
+
+/// Registered observers of a map
+pub(crate) struct Observers<Id: FileId>(Vec<Arc<dyn MapObserver<Id>>>);
+
+impl<Id: FileId> Observers<Id> {
+    pub(crate) fn push(&mut self, observer: Arc<dyn MapObserver<Id>>) {
+        self.0.push(observer);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub(crate) fn each(&self, mut f: impl FnMut(&dyn MapObserver<Id>)) {
+        for observer in &self.0 {
+            f(observer.as_ref());
+        }
+    }
+}
+
+impl<Id: FileId> Default for Observers<Id> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<Id: FileId> Clone for Observers<Id> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<Id: FileId> fmt::Debug for Observers<Id> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
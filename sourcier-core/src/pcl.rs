@@ -0,0 +1,301 @@
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+use std::fmt;
+
+/// Positions per packed block; random access decodes at most this many
+const BLOCK_LEN: usize = 64;
+/// Fields packed per position after the first of a block
+const FIELDS: usize = 5;
+
+/// Unpacked components of a position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fields {
+    file: u64,
+    start_line: u16,
+    start_col: u8,
+    end_line: u16,
+    end_col: u8,
+}
+
+impl Fields {
+    fn of<Id: FileId>(pos: &AbsolutePosition<Id>) -> Self {
+        Self {
+            file: pos.file_id().into(),
+            start_line: pos.start_line(),
+            start_col: pos.start_column(),
+            end_line: pos.end_line(),
+            end_col: pos.end_column(),
+        }
+    }
+
+    fn position<Id: FileId>(&self) -> AbsolutePosition<Id> {
+        let file = Id::try_from(self.file)
+            .unwrap_or_else(|_| unreachable!("packed IDs come from valid positions"));
+        AbsolutePosition::new(
+            file,
+            self.start_line,
+            self.start_col,
+            self.end_line,
+            self.end_col,
+        )
+    }
+
+    /// Small numbers describing `self` given the previous position
+    ///
+    /// Columns on the line of the previous span, or on the start line of the
+    /// span itself, are stored as deltas, others as is.
+    fn encode(&self, prev: &Self) -> [u64; FIELDS] {
+        let same_line = self.file == prev.file && self.start_line == prev.start_line;
+        let one_line = self.end_line == self.start_line;
+        [
+            zigzag(self.file as i64 - prev.file as i64),
+            zigzag(self.start_line as i64 - prev.start_line as i64),
+            if same_line {
+                zigzag(self.start_col as i64 - prev.end_col as i64)
+            } else {
+                self.start_col as u64
+            },
+            zigzag(self.end_line as i64 - self.start_line as i64),
+            if one_line {
+                zigzag(self.end_col as i64 - self.start_col as i64)
+            } else {
+                self.end_col as u64
+            },
+        ]
+    }
+
+    fn decode(fields: [u64; FIELDS], prev: &Self) -> Self {
+        let file = (prev.file as i64 + unzigzag(fields[0])) as u64;
+        let start_line = (prev.start_line as i64 + unzigzag(fields[1])) as u16;
+        let start_col = if file == prev.file && start_line == prev.start_line {
+            (prev.end_col as i64 + unzigzag(fields[2])) as u8
+        } else {
+            fields[2] as u8
+        };
+        let end_line = (start_line as i64 + unzigzag(fields[3])) as u16;
+        let end_col = if end_line == start_line {
+            (start_col as i64 + unzigzag(fields[4])) as u8
+        } else {
+            fields[4] as u8
+        };
+        Self {
+            file,
+            start_line,
+            start_col,
+            end_line,
+            end_col,
+        }
+    }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// A sealed run of [`BLOCK_LEN`] positions
+#[derive(Debug, Clone)]
+struct Block {
+    first: Fields,
+    /// Bit offset of the block in the packed words
+    offset: usize,
+    /// Bits per field, the same for every position of the block
+    widths: [u8; FIELDS],
+}
+
+/// Compact column of many positions, e.g. one per token
+///
+/// Positions are cut into blocks of 64; within a block each one is stored as
+/// small deltas from the one before, bit-packed at the width the block needs.
+/// Token tables, where neighbours share a file and a line, take a fraction of
+/// the 8 bytes per position of a `Vec<AbsolutePosition>`. Access by index
+/// decodes from the start of the block, so it stays O(1); iterating decodes
+/// each position once.
+#[derive(Clone)]
+pub struct PositionColumn<Id: FileId> {
+    blocks: Vec<Block>,
+    words: Vec<u64>,
+    /// Bits used in `words`
+    bits: usize,
+    /// Positions not yet sealed into a block
+    tail: Vec<AbsolutePosition<Id>>,
+}
+
+impl<Id: FileId> Default for PositionColumn<Id> {
+    fn default() -> Self {
+        Self {
+            blocks: Vec::new(),
+            words: Vec::new(),
+            bits: 0,
+            tail: Vec::new(),
+        }
+    }
+}
+
+impl<Id: FileId> fmt::Debug for PositionColumn<Id> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PositionColumn")
+            .field("len", &self.len())
+            .field("heap_size", &self.heap_size())
+            .finish()
+    }
+}
+
+impl<Id: FileId> PositionColumn<Id> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, pos: AbsolutePosition<Id>) {
+        self.tail.push(pos);
+        if self.tail.len() == BLOCK_LEN {
+            self.seal();
+        }
+    }
+
+    /// Pack the full tail into a block
+    fn seal(&mut self) {
+        let first = Fields::of(&self.tail[0]);
+        let mut prev = first;
+        let encoded: Vec<[u64; FIELDS]> = self.tail[1..]
+            .iter()
+            .map(|pos| {
+                let fields = Fields::of(pos);
+                let encoded = fields.encode(&prev);
+                prev = fields;
+                encoded
+            })
+            .collect();
+        let mut widths = [0u8; FIELDS];
+        for fields in &encoded {
+            for (width, value) in widths.iter_mut().zip(fields) {
+                *width = (*width).max((u64::BITS - value.leading_zeros()) as u8);
+            }
+        }
+        self.blocks.push(Block {
+            first,
+            offset: self.bits,
+            widths,
+        });
+        for fields in &encoded {
+            for (&width, &value) in widths.iter().zip(fields) {
+                self.write(value, width);
+            }
+        }
+        self.tail.clear();
+    }
+
+    fn write(&mut self, value: u64, width: u8) {
+        if width == 0 {
+            return;
+        }
+        let (word, shift) = (self.bits / 64, self.bits % 64);
+        if word == self.words.len() {
+            self.words.push(0);
+        }
+        self.words[word] |= value << shift;
+        if shift + width as usize > 64 {
+            self.words.push(value >> (64 - shift));
+        }
+        self.bits += width as usize;
+    }
+
+    fn read(&self, at: usize, width: u8) -> u64 {
+        if width == 0 {
+            return 0;
+        }
+        let (word, shift) = (at / 64, at % 64);
+        let mut value = self.words[word] >> shift;
+        if shift + width as usize > 64 {
+            value |= self.words[word + 1] << (64 - shift);
+        }
+        if width < 64 {
+            value &= (1 << width) - 1;
+        }
+        value
+    }
+
+    /// Fields of the position packed at bit `at` of a block, after `prev`
+    fn decode_next(&self, block: &Block, at: &mut usize, prev: &Fields) -> Fields {
+        let mut encoded = [0; FIELDS];
+        for (value, &width) in encoded.iter_mut().zip(&block.widths) {
+            *value = self.read(*at, width);
+            *at += width as usize;
+        }
+        Fields::decode(encoded, prev)
+    }
+
+    /// Fields of the `index`th position of a block, decoding its predecessors
+    fn decode(&self, block: &Block, index: usize) -> Fields {
+        let mut fields = block.first;
+        let mut at = block.offset;
+        for _ in 0..index {
+            fields = self.decode_next(block, &mut at, &fields);
+        }
+        fields
+    }
+
+    pub fn get(&self, index: usize) -> Option<AbsolutePosition<Id>> {
+        let (block, within) = (index / BLOCK_LEN, index % BLOCK_LEN);
+        match self.blocks.get(block) {
+            Some(block) => Some(self.decode(block, within).position()),
+            None if block == self.blocks.len() => self.tail.get(within).copied(),
+            None => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len() * BLOCK_LEN + self.tail.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Positions in push order
+    pub fn iter(&self) -> impl Iterator<Item = AbsolutePosition<Id>> + '_ {
+        let packed = self.blocks.iter().flat_map(move |block| {
+            let mut fields = block.first;
+            let mut at = block.offset;
+            (0..BLOCK_LEN).map(move |index| {
+                if index > 0 {
+                    fields = self.decode_next(block, &mut at, &fields);
+                }
+                fields.position()
+            })
+        });
+        packed.chain(self.tail.iter().copied())
+    }
+
+    /// Bytes allocated on the heap, to compare with other layouts
+    pub fn heap_size(&self) -> usize {
+        self.blocks.capacity() * size_of::<Block>()
+            + self.words.capacity() * size_of::<u64>()
+            + self.tail.capacity() * size_of::<AbsolutePosition<Id>>()
+    }
+
+    /// Release spare capacity, e.g. once a token table is complete
+    pub fn shrink_to_fit(&mut self) {
+        self.blocks.shrink_to_fit();
+        self.words.shrink_to_fit();
+        self.tail.shrink_to_fit();
+    }
+}
+
+impl<Id: FileId> Extend<AbsolutePosition<Id>> for PositionColumn<Id> {
+    fn extend<I: IntoIterator<Item = AbsolutePosition<Id>>>(&mut self, positions: I) {
+        for pos in positions {
+            self.push(pos);
+        }
+    }
+}
+
+impl<Id: FileId> FromIterator<AbsolutePosition<Id>> for PositionColumn<Id> {
+    fn from_iter<I: IntoIterator<Item = AbsolutePosition<Id>>>(positions: I) -> Self {
+        let mut column = Self::new();
+        column.extend(positions);
+        column
+    }
+}
@@ -0,0 +1,237 @@
+/// One element of a compiled pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    /// `*`: any characters but `/`
+    Star,
+    /// `?`: one character but `/`
+    Question,
+    /// `[a-z]` or `[!a-z]`: one character but `/`
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    /// `**/`: nothing, or whole directories
+    AnyDirs,
+    /// `**` elsewhere: anything, `/` included
+    AnyPath,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    tokens: Vec<Token>,
+    negated: bool,
+    dir_only: bool,
+}
+
+/// Exclude rules with `.gitignore` semantics, compiled once and matched many times
+///
+/// Patterns follow gitignore: `#` starts a comment, `!` re-includes, a
+/// trailing `/` only matches directories, a pattern with another `/` is
+/// anchored to the root while one without matches names at any depth, and
+/// `*`, `?`, `[...]` and `**` are globs. The last matching rule wins, and a
+/// path inside an excluded directory stays excluded, as in git. Paths are
+/// relative to the root and use `/` separators.
+///
+/// ```
+/// use sourcier_core::PathFilter;
+///
+/// let filter = PathFilter::new(["target/", "*.png", "!docs/*.png"]);
+/// assert!(filter.is_excluded("target/debug/main.rs", false));
+/// assert!(filter.is_excluded("assets/logo.png", false));
+/// assert!(!filter.is_excluded("docs/logo.png", false));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathFilter {
+    rules: Vec<Rule>,
+}
+
+impl PathFilter {
+    /// Compile one rule per pattern
+    pub fn new<S: AsRef<str>>(patterns: impl IntoIterator<Item = S>) -> Self {
+        let mut filter = Self::default();
+        for pattern in patterns {
+            filter.add(pattern.as_ref());
+        }
+        filter
+    }
+
+    /// Compile the lines of a `.gitignore` file
+    pub fn from_gitignore(text: &str) -> Self {
+        Self::new(text.lines())
+    }
+
+    /// Compile a pattern after the existing rules, so it takes precedence
+    ///
+    /// Blank lines and comments are ignored.
+    pub fn add(&mut self, pattern: &str) {
+        if let Some(rule) = Rule::compile(pattern) {
+            self.rules.push(rule);
+        }
+    }
+
+    /// Builder-style variant of [`PathFilter::add`]
+    pub fn with(mut self, pattern: &str) -> Self {
+        self.add(pattern);
+        self
+    }
+
+    /// Number of compiled rules
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether `path`, or a directory it is in, is excluded
+    pub fn is_excluded(&self, path: &str, is_dir: bool) -> bool {
+        let path = path.trim_start_matches("./").trim_matches('/');
+        let ancestors = path.match_indices('/').map(|(at, _)| &path[..at]);
+        ancestors
+            .map(|dir| (dir, true))
+            .chain([(path, is_dir)])
+            .any(|(path, is_dir)| self.excludes(path, is_dir))
+    }
+
+    /// Whether the rules alone exclude `path`, disregarding its directories
+    ///
+    /// Enough when walking a tree top-down, since excluded directories are
+    /// not entered.
+    pub fn excludes(&self, path: &str, is_dir: bool) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+        let path: Vec<char> = path.chars().collect();
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && matches(&rule.tokens, &path))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
+
+impl Rule {
+    fn compile(pattern: &str) -> Option<Self> {
+        let mut pattern = pattern.trim_end_matches(['\r', '\n']);
+        // Trailing spaces are ignored unless escaped
+        if !pattern.ends_with("\\ ") {
+            pattern = pattern.trim_end_matches(' ');
+        }
+        if pattern.is_empty() || pattern.starts_with('#') {
+            return None;
+        }
+        let negated = pattern.starts_with('!');
+        if negated {
+            pattern = &pattern[1..];
+        }
+        if let Some(escaped) = pattern.strip_prefix('\\')
+            && escaped.starts_with(['#', '!'])
+        {
+            pattern = escaped;
+        }
+        let dir_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        let anchored = pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        if pattern.is_empty() {
+            return None;
+        }
+
+        let mut tokens = Vec::new();
+        if !anchored {
+            tokens.push(Token::AnyDirs);
+        }
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut at = 0;
+        while at < chars.len() {
+            match chars[at] {
+                '*' if chars.get(at + 1) == Some(&'*') && (at == 0 || chars[at - 1] == '/') => {
+                    at += 2;
+                    if chars.get(at) == Some(&'/') {
+                        at += 1;
+                        tokens.push(Token::AnyDirs);
+                    } else {
+                        tokens.push(Token::AnyPath);
+                    }
+                    continue;
+                }
+                '*' => tokens.push(Token::Star),
+                '?' => tokens.push(Token::Question),
+                '[' => match class(&chars[at..]) {
+                    Some((token, len)) => {
+                        tokens.push(token);
+                        at += len;
+                        continue;
+                    }
+                    None => tokens.push(Token::Literal('[')),
+                },
+                '\\' if at + 1 < chars.len() => {
+                    at += 1;
+                    tokens.push(Token::Literal(chars[at]));
+                }
+                c => tokens.push(Token::Literal(c)),
+            }
+            at += 1;
+        }
+        Some(Self {
+            tokens,
+            negated,
+            dir_only,
+        })
+    }
+}
+
+/// Character class at the start of `chars`, with the characters it spans
+fn class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut at = 1;
+    let negated = matches!(chars.get(at), Some('!' | '^'));
+    if negated {
+        at += 1;
+    }
+    let mut ranges = Vec::new();
+    // A `]` right after the opening bracket is a member
+    let first = at;
+    loop {
+        let c = *chars.get(at)?;
+        if c == ']' && at > first {
+            return Some((Token::Class { ranges, negated }, at + 1));
+        }
+        if chars.get(at + 1) == Some(&'-') && chars.get(at + 2).is_some_and(|&end| end != ']') {
+            ranges.push((c, chars[at + 2]));
+            at += 3;
+        } else {
+            ranges.push((c, c));
+            at += 1;
+        }
+    }
+}
+
+/// Whether `tokens` match the whole of `path`
+fn matches(tokens: &[Token], path: &[char]) -> bool {
+    let Some((token, rest)) = tokens.split_first() else {
+        return path.is_empty();
+    };
+    match token {
+        Token::Literal(c) => path.first() == Some(c) && matches(rest, &path[1..]),
+        Token::Question => path.first().is_some_and(|&c| c != '/') && matches(rest, &path[1..]),
+        Token::Class { ranges, negated } => path.first().is_some_and(|&c| {
+            c != '/'
+                && ranges.iter().any(|&(low, high)| low <= c && c <= high) != *negated
+                && matches(rest, &path[1..])
+        }),
+        Token::Star => {
+            let run = path.iter().take_while(|&&c| c != '/').count();
+            (0..=run).any(|len| matches(rest, &path[len..]))
+        }
+        Token::AnyDirs => {
+            matches(rest, path)
+                || path
+                    .iter()
+                    .enumerate()
+                    .any(|(at, &c)| c == '/' && matches(rest, &path[at + 1..]))
+        }
+        Token::AnyPath => (0..=path.len()).any(|len| matches(rest, &path[len..])),
+    }
+}
@@ -0,0 +1,168 @@
+use crate::fid::{AbsolutePosition, FileId};
+use crate::fvw::FileRef;
+use nom::{
+    AsBytes, Compare, CompareResult, FindSubstring, FindToken, Input, Needed, Offset, ParseTo,
+};
+use std::iter::{Copied, Enumerate};
+use std::slice::Iter;
+
+/// `nom` input over a file, remembering where each fragment sits
+///
+/// Behaves like `&[u8]` for parsers, while every output fragment knows its
+/// byte offset in the file, so [`PositionedInput::to_position`] turns what a
+/// parser consumed into an [`AbsolutePosition`] without manual tracking.
+#[derive(Debug, Clone, Copy)]
+pub struct PositionedInput<'a, Id: FileId> {
+    file: FileRef<'a, Id>,
+    offset: usize,
+    fragment: &'a [u8],
+}
+
+impl<'a, Id: FileId> PositionedInput<'a, Id> {
+    /// Input covering the whole file
+    pub fn new(file: FileRef<'a, Id>) -> Self {
+        Self {
+            file,
+            offset: 0,
+            fragment: file.content(),
+        }
+    }
+
+    /// Bytes of this fragment
+    pub fn fragment(&self) -> &'a [u8] {
+        self.fragment
+    }
+
+    /// Byte offset of the fragment in the file
+    pub fn location_offset(&self) -> usize {
+        self.offset
+    }
+
+    /// ID of the file the fragment belongs to
+    pub fn file_id(&self) -> Id {
+        self.file.id()
+    }
+
+    /// Position of this fragment, e.g. the output of `tag` or `take_while`
+    pub fn to_position(&self) -> Option<AbsolutePosition<Id>> {
+        self.file
+            .position(self.offset..self.offset + self.fragment.len())
+    }
+
+    /// Position of what was consumed between this input and `rest`
+    pub fn position_until(&self, rest: &Self) -> Option<AbsolutePosition<Id>> {
+        self.file.position(self.offset..rest.offset)
+    }
+
+    fn slice(&self, start: usize, end: usize) -> Self {
+        Self {
+            file: self.file,
+            offset: self.offset + start,
+            fragment: &self.fragment[start..end],
+        }
+    }
+}
+
+impl<'a, Id: FileId> FileRef<'a, Id> {
+    /// `nom` input over the pinned file
+    pub fn nom_input(&self) -> PositionedInput<'a, Id> {
+        PositionedInput::new(*self)
+    }
+}
+
+impl<'a, Id: FileId> Input for PositionedInput<'a, Id> {
+    type Item = u8;
+    type Iter = Copied<Iter<'a, u8>>;
+    type IterIndices = Enumerate<Self::Iter>;
+
+    fn input_len(&self) -> usize {
+        self.fragment.len()
+    }
+
+    fn take(&self, index: usize) -> Self {
+        self.slice(0, index)
+    }
+
+    fn take_from(&self, index: usize) -> Self {
+        self.slice(index, self.fragment.len())
+    }
+
+    fn take_split(&self, index: usize) -> (Self, Self) {
+        (self.take_from(index), self.take(index))
+    }
+
+    fn position<P>(&self, predicate: P) -> Option<usize>
+    where
+        P: Fn(Self::Item) -> bool,
+    {
+        self.fragment.iter().position(|&b| predicate(b))
+    }
+
+    fn iter_elements(&self) -> Self::Iter {
+        self.fragment.iter().copied()
+    }
+
+    fn iter_indices(&self) -> Self::IterIndices {
+        self.iter_elements().enumerate()
+    }
+
+    fn slice_index(&self, count: usize) -> Result<usize, Needed> {
+        self.fragment.slice_index(count)
+    }
+}
+
+impl<Id: FileId> Offset for PositionedInput<'_, Id> {
+    fn offset(&self, second: &Self) -> usize {
+        second.offset - self.offset
+    }
+}
+
+impl<Id: FileId> AsBytes for PositionedInput<'_, Id> {
+    fn as_bytes(&self) -> &[u8] {
+        self.fragment
+    }
+}
+
+impl<'b, Id: FileId> Compare<&'b [u8]> for PositionedInput<'_, Id> {
+    fn compare(&self, t: &'b [u8]) -> CompareResult {
+        self.fragment.compare(t)
+    }
+
+    fn compare_no_case(&self, t: &'b [u8]) -> CompareResult {
+        self.fragment.compare_no_case(t)
+    }
+}
+
+impl<'b, Id: FileId> Compare<&'b str> for PositionedInput<'_, Id> {
+    fn compare(&self, t: &'b str) -> CompareResult {
+        self.fragment.compare(t)
+    }
+
+    fn compare_no_case(&self, t: &'b str) -> CompareResult {
+        self.fragment.compare_no_case(t)
+    }
+}
+
+impl<'b, Id: FileId> FindSubstring<&'b [u8]> for PositionedInput<'_, Id> {
+    fn find_substring(&self, substr: &'b [u8]) -> Option<usize> {
+        self.fragment.find_substring(substr)
+    }
+}
+
+impl<'b, Id: FileId> FindSubstring<&'b str> for PositionedInput<'_, Id> {
+    fn find_substring(&self, substr: &'b str) -> Option<usize> {
+        self.fragment.find_substring(substr)
+    }
+}
+
+impl<Id: FileId> FindToken<u8> for PositionedInput<'_, Id> {
+    fn find_token(&self, token: u8) -> bool {
+        self.fragment.find_token(token)
+    }
+}
+
+impl<R: std::str::FromStr, Id: FileId> ParseTo<R> for PositionedInput<'_, Id> {
+    fn parse_to(&self) -> Option<R> {
+        self.fragment.parse_to()
+    }
+}
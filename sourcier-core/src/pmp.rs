@@ -0,0 +1,586 @@
+use crate::fid::FileId;
+#[cfg(feature = "view")]
+use crate::fid::{AbsolutePosition, SourceFilePosition};
+use crate::pfl::PathFilter;
+use crate::sfm::SourceFilesMap;
+use crate::snc::{Arc, AtomicUsize, Condvar, Mutex, Ordering, thread};
+use crate::wire::{
+    WireError, read_bytes, read_graph, read_metadata, read_preamble, read_record_head, read_stamp,
+};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::num::NonZero;
+#[cfg(feature = "view")]
+use std::ops::Range;
+
+/// Where the content of one file lies in the cache, and its state here
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    offset: u64,
+    len: u64,
+    loaded: bool,
+    /// Line offsets were computed, and survive eviction
+    indexed: bool,
+    /// Exempt from eviction
+    pinned: bool,
+}
+
+/// Whether `path` matches the patterns `selected` was built from
+///
+/// The patterns are compiled as a [`PathFilter`], whose matches are the paths
+/// it would exclude.
+fn is_selected(selected: &PathFilter, path: &str) -> bool {
+    selected.is_excluded(path, false)
+}
+
+/// Loaded slots ordered by last use, least recent first
+#[derive(Debug)]
+struct Recency {
+    // Last use of each slot, 0 for slots not loaded
+    ticks: Vec<u64>,
+    order: BTreeMap<u64, usize>,
+    clock: u64,
+}
+
+impl Recency {
+    fn new(slots: usize) -> Self {
+        Self {
+            ticks: vec![0; slots],
+            order: BTreeMap::new(),
+            clock: 0,
+        }
+    }
+
+    /// Mark a loaded slot as the most recently used
+    fn touch(&mut self, index: usize) {
+        self.forget(index);
+        self.clock += 1;
+        self.ticks[index] = self.clock;
+        self.order.insert(self.clock, index);
+    }
+
+    /// Drop an unloaded slot from the order
+    fn forget(&mut self, index: usize) {
+        let tick = std::mem::take(&mut self.ticks[index]);
+        if tick != 0 {
+            self.order.remove(&tick);
+        }
+    }
+}
+
+/// Content read by prefetch workers, by slot index, not yet in the map
+type Inbox = Arc<Mutex<Vec<(usize, Vec<u8>)>>>;
+
+/// Progress shared between prefetch workers and their [`Prefetch`] handle
+#[derive(Debug, Default)]
+struct Progress {
+    state: Mutex<ProgressState>,
+    finished: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct ProgressState {
+    remaining: usize,
+    read: usize,
+    error: Option<WireError>,
+}
+
+/// Handle on a background prefetch started by
+/// [`PartialSourceFilesMap::prefetch`]
+///
+/// Dropping the handle does not stop the workers.
+#[derive(Debug, Clone)]
+pub struct Prefetch {
+    progress: Arc<Progress>,
+}
+
+impl Prefetch {
+    /// Files still to be read
+    pub fn remaining(&self) -> usize {
+        self.progress.state.lock().expect("prefetch lock").remaining
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Block until every file was read, returning how many were, or the
+    /// first error a worker hit
+    ///
+    /// Every call, from any clone of the handle, reports the same outcome.
+    pub fn wait(&self) -> Result<usize, WireError> {
+        let mut state = self.progress.state.lock().expect("prefetch lock");
+        while state.remaining > 0 {
+            state = self.progress.finished.wait(state).expect("prefetch lock");
+        }
+        match &state.error {
+            Some(error) => Err(copy_error(error)),
+            None => Ok(state.read),
+        }
+    }
+}
+
+/// Copy of a stored worker error; I/O errors keep their kind and message
+fn copy_error(error: &WireError) -> WireError {
+    match error {
+        WireError::BadMagic => WireError::BadMagic,
+        WireError::FormatVersion { found, supported } => WireError::FormatVersion {
+            found: *found,
+            supported: supported.clone(),
+        },
+        WireError::IdWidth { expected, found } => WireError::IdWidth {
+            expected: *expected,
+            found: *found,
+        },
+        WireError::Corrupt(message) => WireError::Corrupt(message.clone()),
+        WireError::Io(error) => WireError::Io(io::Error::new(error.kind(), error.to_string())),
+    }
+}
+
+/// Map opened from a cache with only some files loaded
+///
+/// Built by [`SourceFilesMap::load_subset`]. Every path and ID of the cache
+/// is known up front, so IDs match those of the full map, but content is
+/// read from `R` only for the files selected so far; queries on other files
+/// return None until [`PartialSourceFilesMap::load`] brings them in.
+///
+/// With a [memory budget](PartialSourceFilesMap::with_memory_budget), the
+/// least recently used files are unloaded again once loaded content exceeds
+/// it, keeping their line offsets.
+#[derive(Debug)]
+pub struct PartialSourceFilesMap<Id: FileId, R> {
+    // Every file, with empty content until loaded
+    map: SourceFilesMap<Id>,
+    slots: Vec<Slot>,
+    reader: R,
+    inbox: Inbox,
+    budget: Option<usize>,
+    // Bytes of content loaded
+    resident: usize,
+    // Locked so `&self` reads count as uses
+    recency: Mutex<Recency>,
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Open a cache written by [`SourceFilesMap::write_cache`], loading only
+    /// the files matching `patterns`
+    ///
+    /// Patterns follow [`PathFilter`] syntax, so `crates/foo/` selects every
+    /// file under that directory and `*.rs` every Rust file. The content of
+    /// other files is skipped by seeking, and stays loadable from `reader`.
+    pub fn load_subset<R: Read + Seek, S: AsRef<str>>(
+        mut reader: R,
+        patterns: impl IntoIterator<Item = S>,
+    ) -> Result<PartialSourceFilesMap<Id, R>, WireError> {
+        let selected = PathFilter::new(patterns);
+        let (header, count) = read_preamble::<Id>(&mut reader)?;
+        let start = reader.stream_position()?;
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(start))?;
+
+        // The count is not trusted until that many files were read
+        let capacity = count.min(1 << 16) as usize;
+        let mut files = Vec::with_capacity(capacity);
+        let mut slots = Vec::with_capacity(capacity);
+        let mut stamps = Vec::new();
+        let mut select = |reader: &mut R, path: String, offset: u64, len: u64| {
+            if offset.checked_add(len).is_none_or(|stop| stop > end) {
+                return Err(WireError::Corrupt("truncated input".to_string()));
+            }
+            let loaded = is_selected(&selected, &path);
+            let content = if loaded {
+                reader.seek(SeekFrom::Start(offset))?;
+                read_bytes(reader, len)?
+            } else {
+                Vec::new()
+            };
+            slots.push(Slot {
+                offset,
+                len,
+                loaded,
+                indexed: loaded,
+                pinned: false,
+            });
+            files.push((path, content));
+            Ok(())
+        };
+        let graph = if header.version >= 4 {
+            // Contents follow the metadata back to back
+            let metadata = read_metadata::<Id>(&mut reader, header.version, count)?;
+            let mut offset = reader.stream_position()?;
+            for file in metadata.iter().map(|(_, file)| file) {
+                stamps.push((file.path.clone(), file.stamp()));
+                select(&mut reader, file.path.clone(), offset, file.len)?;
+                offset += file.len;
+            }
+            metadata.graph().clone()
+        } else {
+            for _ in 0..count {
+                let (path, len) = read_record_head(&mut reader)?;
+                let offset = reader.stream_position()?;
+                select(&mut reader, path.clone(), offset, len)?;
+                reader.seek(SeekFrom::Start(offset + len))?;
+                if let Some(stamp) = read_stamp(&mut reader, header.version)? {
+                    stamps.push((path, stamp));
+                }
+            }
+            read_graph(&mut reader, header.version)?
+        };
+        let mut map = Self::from_finalized(files).map_err(WireError::Corrupt)?;
+        for (path, stamp) in stamps {
+            map.set_disk_stamp(path, stamp);
+        }
+        map.set_graph(graph)
+            .map_err(|error| WireError::Corrupt(error.to_string()))?;
+        let resident = files_len(&slots);
+        let mut recency = Recency::new(slots.len());
+        for (index, slot) in slots.iter().enumerate() {
+            if slot.loaded {
+                recency.touch(index);
+            }
+        }
+        Ok(PartialSourceFilesMap {
+            map,
+            slots,
+            reader,
+            inbox: Inbox::default(),
+            budget: None,
+            resident,
+            recency: Mutex::new(recency),
+        })
+    }
+}
+
+impl<Id: FileId, R: Read + Seek> PartialSourceFilesMap<Id, R> {
+    fn slot(&self, id: Id) -> Option<&Slot> {
+        let raw: u64 = id.into();
+        self.slots.get(raw.checked_sub(1)? as usize)
+    }
+
+    fn slot_mut(&mut self, id: Id) -> Option<&mut Slot> {
+        let raw: u64 = id.into();
+        self.slots.get_mut(raw.checked_sub(1)? as usize)
+    }
+
+    /// ID of a loaded file, None for unknown IDs and files not loaded
+    ///
+    /// Counts as a use of the file for eviction.
+    fn loaded(&self, id: Id) -> Option<Id> {
+        self.slot(id)?.loaded.then_some(id)?;
+        let raw: u64 = id.into();
+        self.recency
+            .lock()
+            .expect("recency lock")
+            .touch(raw as usize - 1);
+        Some(id)
+    }
+
+    /// Cap the bytes of loaded content, evicting least recently used files
+    ///
+    /// Pinned files are never evicted, so they may keep the map above budget.
+    pub fn with_memory_budget(mut self, bytes: usize) -> Self {
+        self.budget = Some(bytes);
+        self.evict_over_budget(None);
+        self
+    }
+
+    pub fn memory_budget(&self) -> Option<usize> {
+        self.budget
+    }
+
+    /// Bytes of content currently loaded
+    pub fn resident_bytes(&self) -> usize {
+        self.resident
+    }
+
+    /// Exempt a file from eviction, returning false for unknown IDs
+    ///
+    /// Pinning does not load the file.
+    pub fn pin(&mut self, id: Id) -> bool {
+        self.slot_mut(id).map(|slot| slot.pinned = true).is_some()
+    }
+
+    pub fn unpin(&mut self, id: Id) -> bool {
+        let unpinned = self.slot_mut(id).map(|slot| slot.pinned = false).is_some();
+        self.evict_over_budget(None);
+        unpinned
+    }
+
+    /// Put content read from the cache into the map
+    fn fill(&mut self, index: usize, content: Vec<u8>) {
+        let id = Id::try_from(index as u64 + 1)
+            .unwrap_or_else(|_| unreachable!("slots come from valid IDs"));
+        let slot = &mut self.slots[index];
+        let reindex = !slot.indexed;
+        (slot.loaded, slot.indexed) = (true, true);
+        self.resident += content.len();
+        self.map.load_content(id, content, reindex);
+        self.loaded(id);
+    }
+
+    /// Unload least recently used files until the budget is met, sparing `keep`
+    fn evict_over_budget(&mut self, keep: Option<Id>) {
+        let Some(budget) = self.budget else {
+            return;
+        };
+        let keep = keep.map(|id| Into::<u64>::into(id) as usize - 1);
+        let mut recency = self.recency.lock().expect("recency lock");
+        while self.resident > budget {
+            let Some(index) = recency
+                .order
+                .values()
+                .copied()
+                .find(|&index| !self.slots[index].pinned && Some(index) != keep)
+            else {
+                return;
+            };
+            recency.forget(index);
+            let id = Id::try_from(index as u64 + 1)
+                .unwrap_or_else(|_| unreachable!("slots come from valid IDs"));
+            self.slots[index].loaded = false;
+            self.resident -= self.slots[index].len as usize;
+            self.map.load_content(id, Vec::new(), false);
+        }
+    }
+
+    /// Content of a file, read from the cache first unless already loaded
+    ///
+    /// Returns None for unknown IDs. Loading may evict other files.
+    pub fn load(&mut self, id: Id) -> Result<Option<&[u8]>, WireError> {
+        self.apply_prefetched();
+        let Some(&slot) = self.slot(id) else {
+            return Ok(None);
+        };
+        if !slot.loaded {
+            let content = read_slot(&mut self.reader, &slot)?;
+            let raw: u64 = id.into();
+            self.fill(raw as usize - 1, content);
+        }
+        self.evict_over_budget(Some(id));
+        Ok(self.loaded(id).and_then(|id| self.map.get_content(id)))
+    }
+
+    /// Load every file matching `patterns`, returning how many were read
+    pub fn load_matching<S: AsRef<str>>(
+        &mut self,
+        patterns: impl IntoIterator<Item = S>,
+    ) -> Result<usize, WireError> {
+        self.apply_prefetched();
+        let selected = PathFilter::new(patterns);
+        let pending: Vec<Id> = self
+            .map
+            .iter()
+            .filter(|&(id, path, _)| self.loaded(id).is_none() && is_selected(&selected, path))
+            .map(|(id, _, _)| id)
+            .collect();
+        for &id in &pending {
+            self.load(id)?;
+        }
+        Ok(pending.len())
+    }
+
+    /// Start reading files into memory on background threads
+    ///
+    /// Each worker reads the cache through its own reader from `open`, e.g.
+    /// a new handle on the cache file, so the map stays usable meanwhile.
+    /// Files read are moved into the map by its next `&mut` call, such as
+    /// [`PartialSourceFilesMap::apply_prefetched`] once the returned handle
+    /// reports them finished. Loaded and unknown IDs are ignored.
+    pub fn prefetch<F, Src>(&self, ids: impl IntoIterator<Item = Id>, open: F) -> Prefetch
+    where
+        F: Fn() -> io::Result<Src> + Send + Sync + 'static,
+        Src: Read + Seek,
+    {
+        let mut jobs: Vec<(usize, Slot)> = ids
+            .into_iter()
+            .filter(|&id| self.loaded(id).is_none())
+            .filter_map(|id| {
+                let slot = *self.slot(id)?;
+                let raw: u64 = id.into();
+                Some((raw as usize - 1, slot))
+            })
+            .collect();
+        // Cache order keeps each worker's reads mostly sequential
+        jobs.sort_unstable_by_key(|(_, slot)| slot.offset);
+        jobs.dedup_by_key(|(index, _)| *index);
+
+        let progress = Arc::new(Progress::default());
+        progress.state.lock().expect("prefetch lock").remaining = jobs.len();
+        let workers = std::thread::available_parallelism()
+            .map_or(1, NonZero::get)
+            .min(jobs.len());
+        let (jobs, next, open) = (
+            Arc::new(jobs),
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(open),
+        );
+        for _ in 0..workers {
+            let (jobs, next, open) = (jobs.clone(), next.clone(), open.clone());
+            let (inbox, progress) = (self.inbox.clone(), progress.clone());
+            thread::spawn(move || prefetch_worker(&jobs, &next, &*open, &inbox, &progress));
+        }
+        Prefetch { progress }
+    }
+
+    /// Prefetch every file matching `patterns`, like [`PartialSourceFilesMap::load_matching`]
+    pub fn prefetch_matching<S, F, Src>(
+        &self,
+        patterns: impl IntoIterator<Item = S>,
+        open: F,
+    ) -> Prefetch
+    where
+        S: AsRef<str>,
+        F: Fn() -> io::Result<Src> + Send + Sync + 'static,
+        Src: Read + Seek,
+    {
+        let selected = PathFilter::new(patterns);
+        let ids: Vec<Id> = self
+            .map
+            .iter()
+            .filter(|(_, path, _)| is_selected(&selected, path))
+            .map(|(id, _, _)| id)
+            .collect();
+        self.prefetch(ids, open)
+    }
+
+    /// Move the files prefetched so far into the map, returning how many
+    pub fn apply_prefetched(&mut self) -> usize {
+        let ready = std::mem::take(&mut *self.inbox.lock().expect("prefetch lock"));
+        let mut applied = 0;
+        for (index, content) in ready {
+            if !self.slots[index].loaded {
+                self.fill(index, content);
+                applied += 1;
+            }
+        }
+        self.evict_over_budget(None);
+        applied
+    }
+
+    /// Load the remaining files and return the complete map, whatever the budget
+    pub fn into_map(mut self) -> Result<SourceFilesMap<Id>, WireError> {
+        self.budget = None;
+        let ids: Vec<Id> = self.map.iter().map(|(id, _, _)| id).collect();
+        for id in ids {
+            self.load(id)?;
+        }
+        Ok(self.map)
+    }
+
+    pub fn is_loaded(&self, id: Id) -> bool {
+        self.loaded(id).is_some()
+    }
+
+    /// IDs of the loaded files, in order
+    pub fn loaded_ids(&self) -> impl Iterator<Item = Id> + '_ {
+        self.map
+            .iter()
+            .map(|(id, _, _)| id)
+            .filter(|&id| self.is_loaded(id))
+    }
+
+    /// Resolve a path to its ID, whether or not the file is loaded
+    pub fn get_id(&self, path: &str) -> Option<Id> {
+        self.map.get_id(path)
+    }
+
+    pub fn get_path(&self, id: Id) -> Option<&str> {
+        self.map.get_path(id)
+    }
+
+    /// Content of a loaded file
+    pub fn get_content(&self, id: Id) -> Option<&[u8]> {
+        self.map.get_content(self.loaded(id)?)
+    }
+
+    pub fn line_count(&self, id: Id) -> Option<usize> {
+        self.map.line_count(self.loaded(id)?)
+    }
+
+    pub fn content_hash(&self, id: Id) -> Option<u64> {
+        self.map.content_hash(self.loaded(id)?)
+    }
+
+    /// View a span of a loaded file
+    #[cfg(feature = "view")]
+    pub fn view(&self, id: Id, pos: &impl SourceFilePosition) -> Option<&[u8]> {
+        self.map.view(self.loaded(id)?, pos)
+    }
+
+    /// Line and column span of a byte range of a file loaded at least once
+    ///
+    /// Evicted files keep their line offsets, so this needs no reload.
+    #[cfg(feature = "view")]
+    pub fn position(&self, id: Id, range: Range<usize>) -> Option<AbsolutePosition<Id>> {
+        let indexed = self.slot(id)?.indexed && range.end <= self.slot(id)?.len as usize;
+        self.map.position(id, range).filter(|_| indexed)
+    }
+
+    /// Number of files in the cache, loaded or not
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+/// Bytes of content loaded in `slots`
+fn files_len(slots: &[Slot]) -> usize {
+    slots
+        .iter()
+        .filter(|slot| slot.loaded)
+        .map(|slot| slot.len as usize)
+        .sum()
+}
+
+/// Content of one file, read from a cache at its slot
+fn read_slot(reader: &mut (impl Read + Seek), slot: &Slot) -> Result<Vec<u8>, WireError> {
+    reader.seek(SeekFrom::Start(slot.offset))?;
+    read_bytes(reader, slot.len)
+}
+
+/// Read jobs until none are left, taking the next one from `next`
+fn prefetch_worker<Src: Read + Seek>(
+    jobs: &[(usize, Slot)],
+    next: &AtomicUsize,
+    open: &dyn Fn() -> io::Result<Src>,
+    inbox: &Mutex<Vec<(usize, Vec<u8>)>>,
+    progress: &Progress,
+) {
+    let fail = |state: &mut ProgressState, error: WireError| {
+        state.error.get_or_insert(error);
+    };
+    // A worker that cannot open the cache still drains jobs, so waiting ends
+    let mut reader = match open() {
+        Ok(reader) => Some(reader),
+        Err(error) => {
+            fail(
+                &mut progress.state.lock().expect("prefetch lock"),
+                error.into(),
+            );
+            None
+        }
+    };
+    loop {
+        let at = next.fetch_add(1, Ordering::Relaxed);
+        let Some((index, slot)) = jobs.get(at) else {
+            return;
+        };
+        let content = reader.as_mut().map(|reader| read_slot(reader, slot));
+        let mut state = progress.state.lock().expect("prefetch lock");
+        match content {
+            Some(Ok(content)) => {
+                inbox.lock().expect("prefetch lock").push((*index, content));
+                state.read += 1;
+            }
+            Some(Err(error)) => fail(&mut state, error),
+            None => {}
+        }
+        state.remaining -= 1;
+        if state.remaining == 0 {
+            progress.finished.notify_all();
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+
+/// Start of a span as a 0-based `(line, column)`, comparable with its end
+fn start_of(pos: &impl SourceFilePosition) -> (u16, u8) {
+    (pos.start_line(), pos.start_column().saturating_sub(1))
+}
+
+fn end_of(pos: &impl SourceFilePosition) -> (u16, u8) {
+    (pos.end_line(), pos.end_column())
+}
+
+/// Positions kept sorted by file, then start, then end, in one flat vector
+///
+/// Lookups are binary searches, which suits mostly disjoint data such as
+/// token spans; [`PositionSet::merge_overlapping`] folds the overlaps that
+/// do occur. The packed encoding orders the same way, so sorting compares
+/// raw values only.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PositionSet<Id: FileId> {
+    positions: Vec<AbsolutePosition<Id>>,
+}
+
+impl<Id: FileId> Default for PositionSet<Id> {
+    fn default() -> Self {
+        Self {
+            positions: Vec::new(),
+        }
+    }
+}
+
+impl<Id: FileId> PositionSet<Id> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Index of the first position not ordered before `raw`
+    fn lower_bound(&self, raw: Id::Raw) -> usize {
+        self.positions.partition_point(|pos| pos.as_raw() < raw)
+    }
+
+    /// Add a position, returning false if it was already there
+    pub fn insert(&mut self, pos: AbsolutePosition<Id>) -> bool {
+        match self
+            .positions
+            .binary_search_by_key(&pos.as_raw(), AbsolutePosition::as_raw)
+        {
+            Ok(_) => false,
+            Err(at) => {
+                self.positions.insert(at, pos);
+                true
+            }
+        }
+    }
+
+    pub fn remove(&mut self, pos: &AbsolutePosition<Id>) -> bool {
+        match self
+            .positions
+            .binary_search_by_key(&pos.as_raw(), AbsolutePosition::as_raw)
+        {
+            Ok(at) => {
+                self.positions.remove(at);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    pub fn contains(&self, pos: &AbsolutePosition<Id>) -> bool {
+        self.positions
+            .binary_search_by_key(&pos.as_raw(), AbsolutePosition::as_raw)
+            .is_ok()
+    }
+
+    /// First position of the same file starting at or after the start of `pos`
+    pub fn first_at_or_after(&self, pos: &AbsolutePosition<Id>) -> Option<AbsolutePosition<Id>> {
+        let from = pos.with_end_line(0).with_end_column(0);
+        self.positions
+            .get(self.lower_bound(from.as_raw()))
+            .filter(|found| found.file_id() == pos.file_id())
+            .copied()
+    }
+
+    /// Positions of the file of `span` starting within it, in order
+    pub fn starting_in(
+        &self,
+        span: &AbsolutePosition<Id>,
+    ) -> impl Iterator<Item = AbsolutePosition<Id>> + '_ {
+        let from = span.with_end_line(0).with_end_column(0);
+        let (file, end) = (span.file_id(), end_of(span));
+        self.positions[self.lower_bound(from.as_raw())..]
+            .iter()
+            .take_while(move |pos| pos.file_id() == file && start_of(*pos) < end)
+            .copied()
+    }
+
+    /// Positions of one file, in order
+    pub fn in_file(&self, id: Id) -> impl Iterator<Item = AbsolutePosition<Id>> + '_ {
+        let from = AbsolutePosition::new(id, 0, 0, 0, 0);
+        self.positions[self.lower_bound(from.as_raw())..]
+            .iter()
+            .take_while(move |pos| pos.file_id() == id)
+            .copied()
+    }
+
+    /// Replace every group of overlapping positions by the span enclosing it
+    ///
+    /// Positions that merely touch are kept apart. Returns how many positions
+    /// were folded away.
+    pub fn merge_overlapping(&mut self) -> usize {
+        let before = self.positions.len();
+        let mut merged: Vec<AbsolutePosition<Id>> = Vec::with_capacity(before);
+        for pos in self.positions.drain(..) {
+            match merged.last_mut() {
+                Some(last) if last.file_id() == pos.file_id() && start_of(&pos) < end_of(last) => {
+                    if end_of(&pos) > end_of(last) {
+                        *last = last.widen_to(&pos);
+                    }
+                }
+                _ => merged.push(pos),
+            }
+        }
+        self.positions = merged;
+        before - self.positions.len()
+    }
+
+    /// Positions in order
+    pub fn iter(&self) -> impl Iterator<Item = AbsolutePosition<Id>> + '_ {
+        self.positions.iter().copied()
+    }
+
+    pub fn as_slice(&self) -> &[AbsolutePosition<Id>] {
+        &self.positions
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+}
+
+impl<Id: FileId> Extend<AbsolutePosition<Id>> for PositionSet<Id> {
+    fn extend<I: IntoIterator<Item = AbsolutePosition<Id>>>(&mut self, positions: I) {
+        self.positions.extend(positions);
+        self.positions
+            .sort_unstable_by_key(AbsolutePosition::as_raw);
+        self.positions.dedup();
+    }
+}
+
+impl<Id: FileId> FromIterator<AbsolutePosition<Id>> for PositionSet<Id> {
+    fn from_iter<I: IntoIterator<Item = AbsolutePosition<Id>>>(positions: I) -> Self {
+        let mut set = Self::new();
+        set.extend(positions);
+        set
+    }
+}
@@ -0,0 +1,269 @@
+use crate::fid::FileId;
+use std::collections::HashMap;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Every path of a map, packed back to back in one buffer
+///
+/// Files hold the index of their path instead of a `String` each, so bulk
+/// loading appends to one buffer instead of allocating per file. Once
+/// [compacted](PathSlab::compact) in file order, the index of a path is its
+/// file's index too, and looking a path up by ID reads two neighbouring
+/// offsets without touching the file entries.
+#[derive(Debug, Clone)]
+pub(crate) struct PathSlab {
+    buf: String,
+    // Start of each path in `buf`, in push order, then the end of the last
+    bounds: Vec<u32>,
+}
+
+impl PathSlab {
+    pub(crate) fn with_capacity(paths: usize, bytes: usize) -> Self {
+        let mut bounds = Vec::with_capacity(paths + 1);
+        bounds.push(0);
+        Self {
+            buf: String::with_capacity(bytes),
+            bounds,
+        }
+    }
+
+    /// Append a path, returning its index
+    pub(crate) fn push(&mut self, path: &str) -> u32 {
+        self.buf.push_str(path);
+        let end = u32::try_from(self.buf.len()).expect("path slab exceeds 4 GiB");
+        self.bounds.push(end);
+        self.bounds.len() as u32 - 2
+    }
+
+    /// Path at `index`, which must come from this slab
+    pub(crate) fn get(&self, index: u32) -> &str {
+        self.try_get(index).expect("indices come from this slab")
+    }
+
+    pub(crate) fn try_get(&self, index: u32) -> Option<&str> {
+        let index = index as usize;
+        let end = *self.bounds.get(index + 1)? as usize;
+        Some(&self.buf[self.bounds[index] as usize..end])
+    }
+
+    /// Rewrite the slab with only the paths of `indices`, in their order,
+    /// renumbering them from 0
+    pub(crate) fn compact<'a>(&mut self, indices: impl IntoIterator<Item = &'a mut u32>) {
+        let mut packed = Self::with_capacity(self.bounds.len() - 1, self.buf.len());
+        for index in indices {
+            *index = packed.push(self.get(*index));
+        }
+        packed.buf.shrink_to_fit();
+        packed.bounds.shrink_to_fit();
+        *self = packed;
+    }
+}
+
+/// Path to ID lookup over the paths of a [`PathSlab`] compacted in ID order
+///
+/// Keys are path hashes, checked against the slab on lookup, so the index
+/// owns no copy of the paths; a path whose hash is already taken by another
+/// one is kept aside with its own `String`. A [frozen](PathIndex::freeze)
+/// index swaps both maps for a [`PerfectHash`] until the next insertion.
+#[derive(Debug, Clone)]
+pub(crate) struct PathIndex<Id> {
+    by_hash: HashMap<u64, Id>,
+    colliding: HashMap<String, Id>,
+    perfect: Option<PerfectHash>,
+}
+
+/// Slab index of the path of `id`
+fn slot<Id: FileId>(id: Id) -> u32 {
+    let raw: u64 = id.into();
+    raw as u32 - 1
+}
+
+impl<Id: FileId> PathIndex<Id> {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            by_hash: HashMap::with_capacity(capacity),
+            colliding: HashMap::new(),
+            perfect: None,
+        }
+    }
+
+    pub(crate) fn get(&self, paths: &PathSlab, path: &str) -> Option<Id> {
+        if let Some(perfect) = &self.perfect {
+            let index = perfect.get(paths, path)?;
+            return Id::try_from(u64::from(index) + 1).ok();
+        }
+        match self.by_hash.get(&xxh3_64(path.as_bytes())) {
+            Some(&id) if paths.try_get(slot(id)) == Some(path) => Some(id),
+            Some(_) => self.colliding.get(path).copied(),
+            None => None,
+        }
+    }
+
+    /// Map the path of `id` to it, `paths` holding it at the index of `id`
+    pub(crate) fn insert(&mut self, paths: &PathSlab, id: Id) {
+        self.thaw(paths);
+        let path = paths.get(slot(id));
+        let hash = xxh3_64(path.as_bytes());
+        match self.by_hash.get(&hash) {
+            Some(&other) if paths.try_get(slot(other)) != Some(path) => {
+                self.colliding.insert(path.to_string(), id);
+            }
+            _ => {
+                self.by_hash.insert(hash, id);
+            }
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match &self.perfect {
+            Some(perfect) => perfect.len(),
+            None => self.by_hash.len() + self.colliding.len(),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.by_hash.clear();
+        self.colliding.clear();
+        self.perfect = None;
+    }
+
+    /// Replace the hash maps with a perfect hash over the same paths
+    ///
+    /// Keeps the maps when the paths have no perfect hash, e.g. when two of
+    /// them share a 64-bit hash.
+    pub(crate) fn freeze(&mut self, paths: &PathSlab) {
+        if self.perfect.is_some() {
+            return;
+        }
+        let indices = self
+            .by_hash
+            .values()
+            .chain(self.colliding.values())
+            .map(|&id| slot(id));
+        if let Some(perfect) = PerfectHash::build(paths, indices) {
+            self.by_hash = HashMap::new();
+            self.colliding = HashMap::new();
+            self.perfect = Some(perfect);
+        }
+    }
+
+    pub(crate) fn is_frozen(&self) -> bool {
+        self.perfect.is_some()
+    }
+
+    /// Go back to the hash maps so that paths can be added
+    fn thaw(&mut self, paths: &PathSlab) {
+        let Some(perfect) = self.perfect.take() else {
+            return;
+        };
+        self.by_hash.reserve(perfect.len());
+        for index in perfect.indices() {
+            if let Ok(id) = Id::try_from(u64::from(index) + 1) {
+                self.insert(paths, id);
+            }
+        }
+    }
+
+    /// Same lookup with every ID converted by `convert`
+    pub(crate) fn map_ids<New>(self, convert: impl Fn(Id) -> New) -> PathIndex<New> {
+        PathIndex {
+            by_hash: self
+                .by_hash
+                .into_iter()
+                .map(|(hash, id)| (hash, convert(id)))
+                .collect(),
+            colliding: self
+                .colliding
+                .into_iter()
+                .map(|(path, id)| (path, convert(id)))
+                .collect(),
+            perfect: self.perfect,
+        }
+    }
+}
+
+/// Perfect hash from paths to their slab index
+///
+/// Built with hash-and-displace: paths are split into buckets of about
+/// [`PerfectHash::BUCKET_SIZE`] by their hash, and each bucket gets the first
+/// pilot that moves all of its paths to free slots. A few spare slots keep
+/// the last buckets from searching through every pilot. A lookup hashes the path
+/// once, reads one pilot and one slot and compares one path, without probing,
+/// and the whole table costs under five bytes per path.
+#[derive(Debug, Clone)]
+pub(crate) struct PerfectHash {
+    pilots: Vec<u16>,
+    // Slab index of the path hashed to each slot, `u32::MAX` for spare ones
+    slots: Vec<u32>,
+    len: usize,
+}
+
+impl PerfectHash {
+    const BUCKET_SIZE: usize = 4;
+
+    /// Hash the paths at `indices`, giving up when some bucket finds no pilot
+    pub(crate) fn build(paths: &PathSlab, indices: impl Iterator<Item = u32>) -> Option<Self> {
+        let keys: Vec<(u64, u32)> = indices
+            .map(|index| (xxh3_64(paths.get(index).as_bytes()), index))
+            .collect();
+        let buckets = keys.len().div_ceil(Self::BUCKET_SIZE).max(1);
+        let mut members: Vec<Vec<(u64, u32)>> = vec![Vec::new(); buckets];
+        for &(hash, index) in &keys {
+            members[Self::bucket(hash, buckets)].push((hash, index));
+        }
+        // Crowded buckets first, while most slots are still free
+        let mut order: Vec<usize> = (0..buckets).collect();
+        order.sort_by_key(|&bucket| std::cmp::Reverse(members[bucket].len()));
+
+        let mut table = Self {
+            pilots: vec![0; buckets],
+            slots: vec![u32::MAX; keys.len() + keys.len() / 32 + 1],
+            len: keys.len(),
+        };
+        let mut taken = Vec::with_capacity(Self::BUCKET_SIZE * 2);
+        for bucket in order.into_iter().take_while(|&b| !members[b].is_empty()) {
+            let pilot = (0..=u16::MAX).find(|&pilot| {
+                taken.clear();
+                members[bucket].iter().all(|&(hash, _)| {
+                    let slot = table.slot(hash, pilot);
+                    let free = table.slots[slot] == u32::MAX && !taken.contains(&slot);
+                    taken.push(slot);
+                    free
+                })
+            })?;
+            table.pilots[bucket] = pilot;
+            for &(hash, index) in &members[bucket] {
+                let slot = table.slot(hash, pilot);
+                table.slots[slot] = index;
+            }
+        }
+        Some(table)
+    }
+
+    /// Slab index of `path`, when it is one of the hashed paths
+    pub(crate) fn get(&self, paths: &PathSlab, path: &str) -> Option<u32> {
+        let hash = xxh3_64(path.as_bytes());
+        let pilot = self.pilots[Self::bucket(hash, self.pilots.len())];
+        let index = self.slots[self.slot(hash, pilot)];
+        (paths.try_get(index) == Some(path)).then_some(index)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    fn indices(&self) -> impl Iterator<Item = u32> + '_ {
+        self.slots
+            .iter()
+            .copied()
+            .filter(|&index| index != u32::MAX)
+    }
+
+    fn bucket(hash: u64, buckets: usize) -> usize {
+        ((hash >> 32) % buckets as u64) as usize
+    }
+
+    fn slot(&self, hash: u64, pilot: u16) -> usize {
+        let mixed = hash ^ (u64::from(pilot) + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        (mixed.rotate_left(17) % self.slots.len() as u64) as usize
+    }
+}
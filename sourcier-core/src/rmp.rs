@@ -0,0 +1,204 @@
+use crate::fid::{AbsolutePosition, FileId, RelativePosition, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+
+/// Translation of file IDs from one map generation to the next
+///
+/// Built with [`SourceFilesMap::remap_to`], persisted next to cached positions
+/// and replayed with [`IdRemapTable::apply`]. Files missing from the newer
+/// generation map to None. The newer generation may use a wider ID type, see
+/// [`SourceFilesMap::widen`].
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdRemapTable<Id: FileId, New: FileId = Id> {
+    // Raw new ID by old ID - 1
+    ids: Vec<Option<u64>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    _id: PhantomData<(Id, New)>,
+}
+
+impl<Id: FileId, New: FileId> IdRemapTable<Id, New> {
+    /// Table mapping every ID of a map with `files` files to itself
+    pub fn identity(files: usize) -> Self {
+        Self::from_raw((1..=files as u64).map(Some).collect())
+    }
+
+    pub(crate) fn from_raw(ids: Vec<Option<u64>>) -> Self {
+        Self {
+            ids,
+            _id: PhantomData,
+        }
+    }
+
+    /// New ID of `old`, or None if the file is gone
+    pub fn get(&self, old: Id) -> Option<New> {
+        let raw: u64 = old.into();
+        let new = (*self.ids.get(raw.checked_sub(1)? as usize)?)?;
+        New::try_from(new).ok()
+    }
+
+    /// Rewrite the file ID of a position, keeping its span
+    pub fn apply(&self, pos: &AbsolutePosition<Id>) -> Option<AbsolutePosition<New>> {
+        let id = self.get(pos.file_id())?;
+        Some(AbsolutePosition::new(
+            id,
+            pos.start_line(),
+            pos.start_column(),
+            pos.end_line(),
+            pos.end_column(),
+        ))
+    }
+
+    /// Table equivalent to applying `self`, then `next`
+    pub fn then<Next: FileId>(&self, next: &IdRemapTable<New, Next>) -> IdRemapTable<Id, Next> {
+        let ids = self
+            .ids
+            .iter()
+            .map(|id| {
+                let index = id.and_then(|id| id.checked_sub(1))?;
+                *next.ids.get(index as usize)?
+            })
+            .collect();
+        IdRemapTable::from_raw(ids)
+    }
+
+    /// Number of old IDs covered by the table
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+/// One replacement inside a file, in line/column coordinates
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanEdit {
+    /// Raw ID of the edited file
+    pub file: u64,
+    /// Replaced span, in coordinates before the edit
+    pub replaced: RelativePosition,
+    /// Line where the replacement ends, in coordinates after the edit
+    pub new_end_line: u16,
+    /// Column where the replacement ends, in coordinates after the edit
+    pub new_end_col: u8,
+}
+
+/// Ordered list of in-file replacements moving spans around
+///
+/// Spans entirely before an edit are kept, spans entirely after it are
+/// shifted, and spans overlapping a replaced range are dropped since there is
+/// no meaningful place for them any more.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(bound = ""))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditRemap<Id: FileId> {
+    edits: Vec<SpanEdit>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    _id: PhantomData<Id>,
+}
+
+impl<Id: FileId> Default for EditRemap<Id> {
+    fn default() -> Self {
+        Self {
+            edits: Vec::new(),
+            _id: PhantomData,
+        }
+    }
+}
+
+impl<Id: FileId> EditRemap<Id> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `replaced` in `file` was replaced by text ending at
+    /// `new_end_line:new_end_col`; edits are replayed in recording order
+    pub fn record(
+        &mut self,
+        file: Id,
+        replaced: RelativePosition,
+        new_end_line: u16,
+        new_end_col: u8,
+    ) {
+        self.edits.push(SpanEdit {
+            file: file.into(),
+            replaced,
+            new_end_line,
+            new_end_col,
+        });
+    }
+
+    /// Recorded edits, in replay order
+    pub fn edits(&self) -> &[SpanEdit] {
+        &self.edits
+    }
+
+    /// Move a position across every recorded edit, or None if an edit overlapped it
+    pub fn apply(&self, pos: &AbsolutePosition<Id>) -> Option<AbsolutePosition<Id>> {
+        let raw: u64 = pos.file_id().into();
+        let mut start = (pos.start_line(), pos.start_column());
+        let mut end = (pos.end_line(), pos.end_column());
+        for edit in self.edits.iter().filter(|edit| edit.file == raw) {
+            // Start columns are 1-based, end columns 0-based exclusive
+            let old_start = (
+                edit.replaced.start_line(),
+                edit.replaced.start_column().saturating_sub(1),
+            );
+            let old_end = (edit.replaced.end_line(), edit.replaced.end_column());
+            if end <= old_start {
+                continue;
+            }
+            if (start.0, start.1.saturating_sub(1)) < old_end {
+                return None;
+            }
+            let new_end = (edit.new_end_line, edit.new_end_col);
+            start = Self::shift(start, old_end, new_end)?;
+            end = Self::shift(end, old_end, new_end)?;
+        }
+        Some(AbsolutePosition::new(
+            pos.file_id(),
+            start.0,
+            start.1,
+            end.0,
+            end.1,
+        ))
+    }
+
+    /// Move a point located after an edit ending at `old_end`, now ending at `new_end`
+    fn shift(point: (u16, u8), old_end: (u16, u8), new_end: (u16, u8)) -> Option<(u16, u8)> {
+        let line = point.0 as i64 + new_end.0 as i64 - old_end.0 as i64;
+        let col = if point.0 == old_end.0 {
+            point.1 as i64 + new_end.1 as i64 - old_end.1 as i64
+        } else {
+            point.1 as i64
+        };
+        Some((line.try_into().ok()?, col.try_into().ok()?))
+    }
+
+    /// Remap equivalent to applying `self`, then `next`
+    pub fn then(&self, next: &Self) -> Self {
+        let mut edits = self.edits.clone();
+        edits.extend_from_slice(&next.edits);
+        Self {
+            edits,
+            _id: PhantomData,
+        }
+    }
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Table upgrading IDs of this map to the IDs of `newer`, matching files by path
+    pub fn remap_to(&self, newer: &SourceFilesMap<Id>) -> IdRemapTable<Id> {
+        let ids = self
+            .iter()
+            .map(|(_, path, _)| newer.get_id(path).map(Into::into))
+            .collect();
+        IdRemapTable::from_raw(ids)
+    }
+}
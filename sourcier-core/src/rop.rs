@@ -0,0 +1,366 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+
+/// Largest leaf built from contiguous bytes; edits may leave smaller ones
+const MAX_LEAF: usize = 1024;
+
+#[derive(Clone)]
+enum Node {
+    Leaf(Vec<u8>),
+    Branch {
+        left: Box<Node>,
+        right: Box<Node>,
+        len: usize,
+        newlines: usize,
+        height: u8,
+    },
+}
+
+impl Node {
+    fn len(&self) -> usize {
+        match self {
+            Self::Leaf(bytes) => bytes.len(),
+            Self::Branch { len, .. } => *len,
+        }
+    }
+
+    fn newlines(&self) -> usize {
+        match self {
+            Self::Leaf(bytes) => memchr::memchr_iter(b'\n', bytes).count(),
+            Self::Branch { newlines, .. } => *newlines,
+        }
+    }
+
+    fn height(&self) -> u8 {
+        match self {
+            Self::Leaf(_) => 0,
+            Self::Branch { height, .. } => *height,
+        }
+    }
+
+    /// Branch over two nodes, without balancing
+    fn branch(left: Node, right: Node) -> Node {
+        Self::Branch {
+            len: left.len() + right.len(),
+            newlines: left.newlines() + right.newlines(),
+            height: left.height().max(right.height()) + 1,
+            left: Box::new(left),
+            right: Box::new(right),
+        }
+    }
+
+    /// Balanced tree over `bytes` cut into full leaves
+    fn build(bytes: &[u8]) -> Node {
+        if bytes.len() <= MAX_LEAF {
+            return Self::Leaf(bytes.to_vec());
+        }
+        // Split on a leaf boundary so every leaf but the last is full
+        let leaves = bytes.len().div_ceil(MAX_LEAF);
+        let mid = leaves / 2 * MAX_LEAF;
+        Self::branch(Self::build(&bytes[..mid]), Self::build(&bytes[mid..]))
+    }
+
+    fn into_children(self) -> (Node, Node) {
+        match self {
+            Self::Branch { left, right, .. } => (*left, *right),
+            Self::Leaf(_) => unreachable!("only branches have children"),
+        }
+    }
+}
+
+/// Concatenate two balanced trees into a balanced tree, AVL style
+fn join(left: Node, right: Node) -> Node {
+    if left.len() == 0 {
+        return right;
+    }
+    if right.len() == 0 {
+        return left;
+    }
+    let (left, right) = match (left, right) {
+        (Node::Leaf(mut left), Node::Leaf(right)) if left.len() + right.len() <= MAX_LEAF => {
+            left.extend_from_slice(&right);
+            return Node::Leaf(left);
+        }
+        pair => pair,
+    };
+    if left.height() > right.height() + 1 {
+        let (outer, inner) = left.into_children();
+        rebalance(outer, join(inner, right))
+    } else if right.height() > left.height() + 1 {
+        let (inner, outer) = right.into_children();
+        rebalance(join(left, inner), outer)
+    } else {
+        Node::branch(left, right)
+    }
+}
+
+/// Branch over two trees whose heights differ by at most 2, rotated to balance
+fn rebalance(left: Node, right: Node) -> Node {
+    if left.height() > right.height() + 1 {
+        let (outer, inner) = left.into_children();
+        if outer.height() >= inner.height() {
+            Node::branch(outer, Node::branch(inner, right))
+        } else {
+            let (inner_left, inner_right) = inner.into_children();
+            Node::branch(
+                Node::branch(outer, inner_left),
+                Node::branch(inner_right, right),
+            )
+        }
+    } else if right.height() > left.height() + 1 {
+        let (inner, outer) = right.into_children();
+        if outer.height() >= inner.height() {
+            Node::branch(Node::branch(left, inner), outer)
+        } else {
+            let (inner_left, inner_right) = inner.into_children();
+            Node::branch(
+                Node::branch(left, inner_left),
+                Node::branch(inner_right, outer),
+            )
+        }
+    } else {
+        Node::branch(left, right)
+    }
+}
+
+/// Split a tree at a byte offset within it
+fn split(node: Node, at: usize) -> (Node, Node) {
+    match node {
+        Node::Leaf(mut bytes) => {
+            let right = bytes.split_off(at);
+            (Node::Leaf(bytes), Node::Leaf(right))
+        }
+        Node::Branch { left, right, .. } => {
+            let left_len = left.len();
+            if at < left_len {
+                let (a, b) = split(*left, at);
+                (a, join(b, *right))
+            } else if at > left_len {
+                let (a, b) = split(*right, at - left_len);
+                (join(*left, a), b)
+            } else {
+                (*left, *right)
+            }
+        }
+    }
+}
+
+/// Byte buffer as a balanced tree of chunks
+///
+/// Replacing a range costs O(log n) plus the replacement length, instead of
+/// moving the whole tail of a contiguous buffer, and every node counts its
+/// line breaks so finding a line is O(log n) too. Bytes are not required to
+/// be UTF-8.
+#[derive(Clone)]
+pub struct Rope {
+    root: Node,
+}
+
+impl Default for Rope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Rope")
+            .field("len", &self.len())
+            .field("lines", &self.line_count())
+            .finish()
+    }
+}
+
+impl From<&[u8]> for Rope {
+    fn from(bytes: &[u8]) -> Self {
+        Self {
+            root: Node::build(bytes),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Rope {
+    fn from(bytes: Vec<u8>) -> Self {
+        if bytes.len() <= MAX_LEAF {
+            return Self {
+                root: Node::Leaf(bytes),
+            };
+        }
+        Self::from(bytes.as_slice())
+    }
+}
+
+impl Rope {
+    pub fn new() -> Self {
+        Self {
+            root: Node::Leaf(Vec::new()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.root.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of lines, counting a trailing newline as starting an empty line
+    pub fn line_count(&self) -> usize {
+        self.root.newlines() + 1
+    }
+
+    /// Replace a byte range (false, leaving the rope untouched, when out of bounds)
+    pub fn replace(&mut self, range: Range<usize>, replacement: &[u8]) -> bool {
+        if range.start > range.end || range.end > self.len() {
+            return false;
+        }
+        let root = std::mem::replace(&mut self.root, Node::Leaf(Vec::new()));
+        let (head, tail) = split(root, range.end);
+        let (head, _) = split(head, range.start);
+        self.root = join(join(head, Node::build(replacement)), tail);
+        true
+    }
+
+    pub fn insert(&mut self, at: usize, bytes: &[u8]) -> bool {
+        self.replace(at..at, bytes)
+    }
+
+    pub fn remove(&mut self, range: Range<usize>) -> bool {
+        self.replace(range, &[])
+    }
+
+    /// Byte offset where a 1-based line starts
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        match line {
+            0 => None,
+            1 => Some(0),
+            _ => self.newline(line - 1).map(|at| at + 1),
+        }
+    }
+
+    /// Offset of the `n`th line break, counting from 1
+    fn newline(&self, mut n: usize) -> Option<usize> {
+        if n > self.root.newlines() {
+            return None;
+        }
+        let mut node = &self.root;
+        let mut offset = 0;
+        loop {
+            match node {
+                Node::Leaf(bytes) => {
+                    return memchr::memchr_iter(b'\n', bytes)
+                        .nth(n - 1)
+                        .map(|at| offset + at);
+                }
+                Node::Branch { left, right, .. } => {
+                    let left_newlines = left.newlines();
+                    if n <= left_newlines {
+                        node = left;
+                    } else {
+                        n -= left_newlines;
+                        offset += left.len();
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+
+    /// A byte range, borrowed when it lies within one chunk
+    pub fn slice(&self, range: Range<usize>) -> Option<Cow<'_, [u8]>> {
+        if range.start > range.end || range.end > self.len() {
+            return None;
+        }
+        if let Some(bytes) = self.chunk_slice(range.clone()) {
+            return Some(Cow::Borrowed(bytes));
+        }
+        let mut out = Vec::with_capacity(range.len());
+        let mut offset = 0;
+        for chunk in self.chunks() {
+            let chunk_range = offset..offset + chunk.len();
+            offset = chunk_range.end;
+            if chunk_range.end <= range.start {
+                continue;
+            }
+            if chunk_range.start >= range.end {
+                break;
+            }
+            let from = range.start.saturating_sub(chunk_range.start);
+            let to = (range.end - chunk_range.start).min(chunk.len());
+            out.extend_from_slice(&chunk[from..to]);
+        }
+        Some(Cow::Owned(out))
+    }
+
+    /// A byte range lying within one chunk, without copying
+    pub(crate) fn chunk_slice(&self, range: Range<usize>) -> Option<&[u8]> {
+        let mut node = &self.root;
+        let mut offset = 0;
+        loop {
+            match node {
+                Node::Leaf(bytes) => {
+                    return bytes.get(range.start - offset..range.end - offset);
+                }
+                Node::Branch { left, right, .. } => {
+                    let left_end = offset + left.len();
+                    if range.end <= left_end {
+                        node = left;
+                    } else if range.start >= left_end {
+                        offset = left_end;
+                        node = right;
+                    } else {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The contiguous pieces of the rope, in order
+    pub fn chunks(&self) -> Chunks<'_> {
+        Chunks {
+            stack: vec![&self.root],
+        }
+    }
+
+    /// Copy the rope into one contiguous buffer
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.len());
+        for chunk in self.chunks() {
+            out.extend_from_slice(chunk);
+        }
+        out
+    }
+}
+
+/// Iterator over the chunks of a [`Rope`]
+#[derive(Debug, Clone)]
+pub struct Chunks<'a> {
+    stack: Vec<&'a Node>,
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Node({} bytes)", self.len())
+    }
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.stack.pop() {
+            match node {
+                Node::Leaf(bytes) if bytes.is_empty() => {}
+                Node::Leaf(bytes) => return Some(bytes),
+                Node::Branch { left, right, .. } => {
+                    self.stack.push(right);
+                    self.stack.push(left);
+                }
+            }
+        }
+        None
+    }
+}
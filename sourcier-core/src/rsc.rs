@@ -0,0 +1,221 @@
+//! Import of rustc and clippy JSON diagnostics
+//!
+//! Reads what `rustc --error-format=json` prints, or the `compiler-message`
+//! lines of `cargo build --message-format=json` (clippy included), and turns
+//! each diagnostic into a [`Diagnostic`] resolved against the map, so wrapper
+//! tools can merge compiler findings with their own before rendering them.
+//!
+//! Spans are resolved from their byte offsets, the line and column rustc also
+//! reports being in characters. Secondary spans become labels, machine
+//! applicable suggestions become fixes, and the other children become labels
+//! when they point into the map or notes otherwise.
+
+use crate::dgn::{Diagnostic, DiagnosticBag, Fix, Severity};
+use crate::fid::{AbsolutePosition, FileId};
+use crate::sfm::SourceFilesMap;
+use serde::Deserialize;
+
+/// Diagnostic as rustc emits it in JSON
+///
+/// Only the fields needed to import it are kept; children use the same shape.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RustcDiagnostic {
+    pub message: String,
+    #[serde(default)]
+    pub code: Option<RustcCode>,
+    /// `error`, `warning`, `note`, `help`, `failure-note` or
+    /// `error: internal compiler error`
+    pub level: String,
+    #[serde(default)]
+    pub spans: Vec<RustcSpan>,
+    #[serde(default)]
+    pub children: Vec<RustcDiagnostic>,
+    /// Human readable form, as rustc would have printed it
+    #[serde(default)]
+    pub rendered: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RustcCode {
+    /// e.g. `E0308` or `clippy::needless_return`
+    pub code: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RustcSpan {
+    /// Path as passed to rustc, usually relative to the workspace root
+    pub file_name: String,
+    /// 0-based byte offsets in the file
+    pub byte_start: usize,
+    pub byte_end: usize,
+    /// 1-based lines and character columns
+    pub line_start: usize,
+    pub line_end: usize,
+    pub column_start: usize,
+    pub column_end: usize,
+    pub is_primary: bool,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub suggested_replacement: Option<String>,
+    /// `MachineApplicable`, `MaybeIncorrect`, `HasPlaceholders` or
+    /// `Unspecified`
+    #[serde(default)]
+    pub suggestion_applicability: Option<String>,
+}
+
+/// Line of `cargo --message-format=json` output
+#[derive(Deserialize)]
+struct CargoMessage {
+    reason: String,
+    #[serde(default)]
+    message: Option<RustcDiagnostic>,
+}
+
+impl RustcSpan {
+    /// Position of the span, None when its file does not resolve or its
+    /// offsets are past the end of the file
+    pub fn position<Id: FileId>(
+        &self,
+        map: &SourceFilesMap<Id>,
+        resolve: impl Fn(&str) -> Option<Id>,
+    ) -> Option<AbsolutePosition<Id>> {
+        let id = resolve(&self.file_name)?;
+        map.position(id, self.byte_start..self.byte_end)
+    }
+
+    fn is_machine_applicable(&self) -> bool {
+        self.suggestion_applicability.as_deref() == Some("MachineApplicable")
+    }
+}
+
+fn severity(level: &str) -> Severity {
+    match level {
+        "error" | "error: internal compiler error" => Severity::Error,
+        "warning" => Severity::Warning,
+        "help" => Severity::Help,
+        _ => Severity::Note,
+    }
+}
+
+impl RustcDiagnostic {
+    /// Diagnostic of one line of rustc or cargo JSON output
+    ///
+    /// None for the cargo messages that are not compiler messages, like
+    /// `compiler-artifact` or `build-finished`.
+    pub fn parse_line(line: &str) -> Result<Option<Self>, serde_json::Error> {
+        let value: serde_json::Value = serde_json::from_str(line)?;
+        if value.get("reason").is_none() {
+            return serde_json::from_value(value).map(Some);
+        }
+        let message: CargoMessage = serde_json::from_value(value)?;
+        Ok(message
+            .message
+            .filter(|_| message.reason == "compiler-message"))
+    }
+
+    pub fn severity(&self) -> Severity {
+        severity(&self.level)
+    }
+
+    /// First span marked primary, or the first span when none is
+    pub fn primary_span(&self) -> Option<&RustcSpan> {
+        self.spans
+            .iter()
+            .find(|span| span.is_primary)
+            .or(self.spans.first())
+    }
+
+    /// Diagnostic resolving file names as paths of `map`
+    pub fn to_diagnostic<Id: FileId>(&self, map: &SourceFilesMap<Id>) -> Option<Diagnostic<Id>> {
+        self.to_diagnostic_with(map, |path| {
+            map.get_id(path.strip_prefix("./").unwrap_or(path))
+        })
+    }
+
+    /// Diagnostic resolving file names with `resolve`, e.g. to strip the
+    /// prefix of a package inside the map
+    ///
+    /// None when the primary span does not resolve, as for the
+    /// `aborting due to previous error` summaries which have no span.
+    pub fn to_diagnostic_with<Id: FileId>(
+        &self,
+        map: &SourceFilesMap<Id>,
+        resolve: impl Fn(&str) -> Option<Id>,
+    ) -> Option<Diagnostic<Id>> {
+        let primary_span = self.primary_span()?;
+        let primary = primary_span.position(map, &resolve)?;
+        let mut diagnostic = Diagnostic::new(self.severity(), self.message.clone(), primary);
+        if let Some(code) = &self.code {
+            diagnostic = diagnostic.with_code(code.code.clone());
+        }
+        for span in &self.spans {
+            let Some(label) = &span.label else {
+                continue;
+            };
+            if let Some(pos) = span.position(map, &resolve) {
+                diagnostic = diagnostic.with_label(pos, label.clone());
+            }
+        }
+        for child in &self.children {
+            let note = format!("{}: {}", child.level, child.message);
+            let fixes: Vec<_> = child
+                .spans
+                .iter()
+                .filter(|span| span.is_machine_applicable())
+                .filter_map(|span| {
+                    Some(Fix {
+                        span: span.position(map, &resolve)?,
+                        replacement: span.suggested_replacement.clone()?,
+                    })
+                })
+                .collect();
+            if !fixes.is_empty() {
+                diagnostic.fixes.extend(fixes);
+                diagnostic = diagnostic.with_note(note);
+                continue;
+            }
+            match child
+                .primary_span()
+                .and_then(|span| span.position(map, &resolve))
+            {
+                Some(pos) => diagnostic = diagnostic.with_label(pos, note),
+                None => diagnostic = diagnostic.with_note(note),
+            }
+        }
+        Some(diagnostic)
+    }
+}
+
+/// Diagnostics read by [`SourceFilesMap::import_rustc_json`]
+#[derive(Debug, Clone)]
+pub struct RustcImport<Id: FileId> {
+    pub diagnostics: DiagnosticBag<Id>,
+    /// Diagnostics without a primary span in the map: summaries, or spans of
+    /// files the map does not hold
+    pub unresolved: Vec<RustcDiagnostic>,
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Diagnostics of rustc or cargo JSON output, one message per line
+    ///
+    /// Blank lines and non-compiler cargo messages are skipped, see
+    /// [`RustcDiagnostic::parse_line`]; file names are resolved as in
+    /// [`RustcDiagnostic::to_diagnostic`].
+    pub fn import_rustc_json(&self, output: &str) -> Result<RustcImport<Id>, serde_json::Error> {
+        let mut import = RustcImport {
+            diagnostics: DiagnosticBag::new(),
+            unresolved: Vec::new(),
+        };
+        for line in output.lines().filter(|line| !line.trim().is_empty()) {
+            let Some(rustc) = RustcDiagnostic::parse_line(line)? else {
+                continue;
+            };
+            match rustc.to_diagnostic(self) {
+                Some(diagnostic) => import.diagnostics.push(diagnostic),
+                None => import.unresolved.push(rustc),
+            }
+        }
+        Ok(import)
+    }
+}
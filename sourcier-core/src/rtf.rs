@@ -0,0 +1,767 @@
+use crate::dsk::write_atomic;
+use crate::fid::{FileId, IdWidth};
+use crate::obs::{FinalizeEvent, FinalizePhases, MapObserver};
+use crate::wire::WireError;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+/// Session statistics shared between maps through an `Arc<RuntimeFeedback>`
+///
+/// Every counter is an atomic, so concurrent finalizes never wait on each
+/// other and no lock is ever handed out to user code. Readers take a
+/// [`FeedbackSnapshot`]; values updated while the snapshot is taken may be
+/// observed from slightly different points in time.
+#[derive(Debug)]
+pub struct RuntimeFeedback {
+    total_files: AtomicU64,
+    total_bytes: AtomicU64,
+    max_file_size: AtomicU64,
+    usage_count: AtomicU64,
+    cumulative_files: AtomicU64,
+    cumulative_bytes: AtomicU64,
+    peak_files: AtomicU64,
+    history: [RecordSlot; RuntimeFeedback::HISTORY],
+    size_buckets: [AtomicU64; RuntimeFeedback::SIZE_BUCKETS],
+    cumulative_lines: AtomicU64,
+    phase_nanos: [AtomicU64; 4],
+    config: FeedbackConfig,
+    sampler: Sampler,
+}
+
+/// Tuning of how much work statistics collection may cost
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeedbackConfig {
+    /// Only 1 in `sample_rate` operations updates statistics (1 records all)
+    ///
+    /// Sampled view counters are scaled back up by the rate, so they estimate
+    /// the true totals; finalization records only cover sampled finalizes.
+    pub sample_rate: u32,
+}
+
+impl Default for FeedbackConfig {
+    fn default() -> Self {
+        Self { sample_rate: 1 }
+    }
+}
+
+/// Deterministic 1-in-N operation sampler
+#[derive(Debug, Default)]
+pub(crate) struct Sampler {
+    rate: u64,
+    clock: AtomicU64,
+}
+
+impl Sampler {
+    pub(crate) fn new(rate: u32) -> Self {
+        Self {
+            rate: rate.max(1) as u64,
+            clock: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether the current operation should be recorded
+    pub(crate) fn sample(&self) -> bool {
+        self.rate <= 1
+            || self
+                .clock
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(self.rate)
+    }
+
+    /// Weight of one recorded operation
+    #[cfg(feature = "view")]
+    pub(crate) fn weight(&self) -> u64 {
+        self.rate.max(1)
+    }
+}
+
+impl Clone for Sampler {
+    fn clone(&self) -> Self {
+        Self {
+            rate: self.rate,
+            clock: AtomicU64::new(self.clock.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct RecordSlot {
+    files: AtomicU64,
+    bytes: AtomicU64,
+    max_file_size: AtomicU64,
+}
+
+/// Statistics of a single finalization
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FinalizeRecord {
+    pub files: usize,
+    pub bytes: u64,
+    pub max_file_size: usize,
+}
+
+/// Plain copy of the [`RuntimeFeedback`] counters at one point in time
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeedbackSnapshot {
+    /// Files in the most recent finalization
+    pub total_files: usize,
+    /// Bytes in the most recent finalization
+    pub total_bytes: u64,
+    /// Largest file seen across all finalizations
+    pub max_file_size: usize,
+    pub usage_count: u32,
+    /// Files summed over all finalizations
+    pub cumulative_files: u64,
+    /// Bytes summed over all finalizations
+    pub cumulative_bytes: u64,
+    /// Largest file count of a single finalization
+    pub peak_files: usize,
+    /// Most recent finalizations, oldest first (at most `RuntimeFeedback::HISTORY`)
+    pub records: Vec<FinalizeRecord>,
+    /// File counts per log2 size bucket; bucket `i` holds sizes below `2^i`
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub size_histogram: Vec<u64>,
+    /// Lines summed over all finalizations (only counted with the `view` feature)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub cumulative_lines: u64,
+    /// Time spent in each finalize phase, summed over all finalizations
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub phases: FinalizePhases,
+}
+
+impl FeedbackSnapshot {
+    /// Mean number of files per finalization
+    pub fn mean_files(&self) -> usize {
+        if self.usage_count == 0 {
+            return 0;
+        }
+        (self.cumulative_files / self.usage_count as u64) as usize
+    }
+
+    /// Mean file size across all finalizations (None before any file was seen)
+    pub fn mean_file_size(&self) -> Option<usize> {
+        if self.cumulative_files == 0 {
+            return None;
+        }
+        Some((self.cumulative_bytes / self.cumulative_files) as usize)
+    }
+
+    /// Mean number of bytes per line (None before any line was counted)
+    pub fn mean_line_length(&self) -> Option<usize> {
+        if self.cumulative_lines == 0 {
+            return None;
+        }
+        Some((self.cumulative_bytes / self.cumulative_lines).max(1) as usize)
+    }
+
+    /// Estimated file size at percentile `p` (0.0..=1.0), from the histogram
+    ///
+    /// Returns the upper bound of the bucket holding the percentile, capped at
+    /// the largest file seen, or None before any file size was recorded.
+    pub fn size_percentile(&self, p: f64) -> Option<usize> {
+        let total: u64 = self.size_histogram.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let rank = ((total as f64 * p.clamp(0.0, 1.0)).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.size_histogram.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let upper = 1u64
+                    .checked_shl(bucket as u32)
+                    .map_or(u64::MAX, |bound| bound - 1) as usize;
+                return Some(upper.min(self.max_file_size));
+            }
+        }
+        Some(self.max_file_size)
+    }
+
+    /// Median file size estimate
+    pub fn p50(&self) -> Option<usize> {
+        self.size_percentile(0.50)
+    }
+
+    /// 95th percentile file size estimate
+    pub fn p95(&self) -> Option<usize> {
+        self.size_percentile(0.95)
+    }
+}
+
+impl Default for RuntimeFeedback {
+    fn default() -> Self {
+        Self {
+            total_files: AtomicU64::new(0),
+            total_bytes: AtomicU64::new(0),
+            max_file_size: AtomicU64::new(0),
+            usage_count: AtomicU64::new(0),
+            cumulative_files: AtomicU64::new(0),
+            cumulative_bytes: AtomicU64::new(0),
+            peak_files: AtomicU64::new(0),
+            history: std::array::from_fn(|_| RecordSlot::default()),
+            size_buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            cumulative_lines: AtomicU64::new(0),
+            phase_nanos: std::array::from_fn(|_| AtomicU64::new(0)),
+            config: FeedbackConfig::default(),
+            sampler: Sampler::new(1),
+        }
+    }
+}
+
+/// Process-wide named feedback contexts, see [`RuntimeFeedback::global`]
+static GLOBAL: LazyLock<Mutex<HashMap<String, Arc<RuntimeFeedback>>>> =
+    LazyLock::new(Default::default);
+
+impl From<FeedbackSnapshot> for RuntimeFeedback {
+    fn from(snapshot: FeedbackSnapshot) -> Self {
+        let feedback = Self::default();
+        // Keep the records the usage count accounts for, at most `HISTORY`;
+        // a snapshot counting fewer uses than it holds records is not trusted
+        let usage_count = snapshot.usage_count as usize;
+        let kept = snapshot.records.len().min(Self::HISTORY).min(usage_count);
+        let skip = snapshot.records.len() - kept;
+        let first = usage_count - kept;
+        for (i, record) in snapshot.records.iter().skip(skip).enumerate() {
+            feedback.history[(first + i) % Self::HISTORY].store(record);
+        }
+        feedback.set(&feedback.total_files, snapshot.total_files as u64);
+        feedback.set(&feedback.total_bytes, snapshot.total_bytes);
+        feedback.set(&feedback.max_file_size, snapshot.max_file_size as u64);
+        feedback.set(&feedback.usage_count, snapshot.usage_count as u64);
+        feedback.set(&feedback.cumulative_files, snapshot.cumulative_files);
+        feedback.set(&feedback.cumulative_bytes, snapshot.cumulative_bytes);
+        feedback.set(&feedback.peak_files, snapshot.peak_files as u64);
+        feedback.set(&feedback.cumulative_lines, snapshot.cumulative_lines);
+        feedback.add_phases(&snapshot.phases);
+        for (bucket, count) in feedback.size_buckets.iter().zip(&snapshot.size_histogram) {
+            bucket.store(*count, Ordering::Relaxed);
+        }
+        feedback
+    }
+}
+
+impl RecordSlot {
+    fn store(&self, record: &FinalizeRecord) {
+        self.files.store(record.files as u64, Ordering::Relaxed);
+        self.bytes.store(record.bytes, Ordering::Relaxed);
+        self.max_file_size
+            .store(record.max_file_size as u64, Ordering::Relaxed);
+    }
+
+    fn load(&self) -> FinalizeRecord {
+        FinalizeRecord {
+            files: self.files.load(Ordering::Relaxed) as usize,
+            bytes: self.bytes.load(Ordering::Relaxed),
+            max_file_size: self.max_file_size.load(Ordering::Relaxed) as usize,
+        }
+    }
+}
+
+impl RuntimeFeedback {
+    /// Number of per-finalization records kept
+    pub const HISTORY: usize = 32;
+
+    /// Number of log2 size buckets (covers every `u64` size)
+    pub const SIZE_BUCKETS: usize = 65;
+
+    const MAGIC: &'static [u8; 4] = b"SRFB";
+    const VERSION: u8 = 1;
+
+    fn set(&self, counter: &AtomicU64, value: u64) {
+        counter.store(value, Ordering::Relaxed);
+    }
+
+    fn get(&self, counter: &AtomicU64) -> u64 {
+        counter.load(Ordering::Relaxed)
+    }
+
+    /// Create an empty context with the given tuning
+    pub fn with_config(config: FeedbackConfig) -> Self {
+        Self {
+            config,
+            sampler: Sampler::new(config.sample_rate),
+            ..Self::default()
+        }
+    }
+
+    /// Tuning this context was created with
+    pub fn config(&self) -> FeedbackConfig {
+        self.config
+    }
+
+    /// Get the process-global context registered under `name`, creating it on first use
+    ///
+    /// Subsystems naming the same context share adaptive sizing without
+    /// threading handles through their constructors.
+    pub fn global(name: &str) -> Arc<RuntimeFeedback> {
+        let mut registry = GLOBAL.lock().unwrap_or_else(|e| e.into_inner());
+        registry.entry(name.to_string()).or_default().clone()
+    }
+
+    /// Register `feedback` (e.g. loaded from disk) as the global context `name`
+    ///
+    /// Returns the context it replaced, if any.
+    pub fn set_global(name: &str, feedback: Arc<RuntimeFeedback>) -> Option<Arc<RuntimeFeedback>> {
+        let mut registry = GLOBAL.lock().unwrap_or_else(|e| e.into_inner());
+        registry.insert(name.to_string(), feedback)
+    }
+
+    /// Remove the global context `name`, returning it
+    pub fn remove_global(name: &str) -> Option<Arc<RuntimeFeedback>> {
+        let mut registry = GLOBAL.lock().unwrap_or_else(|e| e.into_inner());
+        registry.remove(name)
+    }
+
+    /// Names of all registered global contexts, sorted
+    pub fn global_names() -> Vec<String> {
+        let registry = GLOBAL.lock().unwrap_or_else(|e| e.into_inner());
+        let mut names: Vec<String> = registry.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Fold a finalization into the session statistics
+    pub fn record(&self, record: FinalizeRecord) {
+        let slot = self.usage_count.fetch_add(1, Ordering::Relaxed) as usize;
+        self.history[slot % Self::HISTORY].store(&record);
+        self.set(&self.total_files, record.files as u64);
+        self.set(&self.total_bytes, record.bytes);
+        self.max_file_size
+            .fetch_max(record.max_file_size as u64, Ordering::Relaxed);
+        self.cumulative_files
+            .fetch_add(record.files as u64, Ordering::Relaxed);
+        self.cumulative_bytes
+            .fetch_add(record.bytes, Ordering::Relaxed);
+        self.peak_files
+            .fetch_max(record.files as u64, Ordering::Relaxed);
+    }
+
+    /// Count one file of `size` bytes in the size histogram
+    pub fn record_file_size(&self, size: usize) {
+        let bucket = (usize::BITS - size.leading_zeros()) as usize;
+        self.size_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count lines of indexed files, used to pre-size line offset tables
+    pub fn record_lines(&self, lines: u64) {
+        self.cumulative_lines.fetch_add(lines, Ordering::Relaxed);
+    }
+
+    /// Add phase durations to the cumulative finalize timings
+    pub fn add_phases(&self, phases: &FinalizePhases) {
+        for (counter, (_, duration)) in self.phase_nanos.iter().zip(phases.iter()) {
+            counter.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Time spent in each finalize phase, summed over all finalizations
+    pub fn phases(&self) -> FinalizePhases {
+        let nanos = |phase: usize| Duration::from_nanos(self.get(&self.phase_nanos[phase]));
+        FinalizePhases {
+            sort: nanos(0),
+            dedup: nanos(1),
+            consolidation: nanos(2),
+            line_offsets: nanos(3),
+        }
+    }
+
+    /// Smallest ID type fitting the largest map seen, with 25% headroom
+    pub fn recommended_id_type(&self) -> IdWidth {
+        let peak = self.peak_files();
+        IdWidth::for_file_count(peak + peak / 4)
+    }
+
+    /// Files in the most recent finalization
+    pub fn total_files(&self) -> usize {
+        self.get(&self.total_files) as usize
+    }
+
+    /// Number of finalizations recorded
+    pub fn usage_count(&self) -> u32 {
+        self.get(&self.usage_count) as u32
+    }
+
+    /// Largest file count of a single finalization
+    pub fn peak_files(&self) -> usize {
+        self.get(&self.peak_files) as usize
+    }
+
+    /// Copy all counters out
+    pub fn snapshot(&self) -> FeedbackSnapshot {
+        let usage_count = self.get(&self.usage_count) as usize;
+        let kept = usage_count.min(Self::HISTORY);
+        let records = (usage_count - kept..usage_count)
+            .map(|i| self.history[i % Self::HISTORY].load())
+            .collect();
+
+        FeedbackSnapshot {
+            total_files: self.get(&self.total_files) as usize,
+            total_bytes: self.get(&self.total_bytes),
+            max_file_size: self.get(&self.max_file_size) as usize,
+            usage_count: usage_count as u32,
+            cumulative_files: self.get(&self.cumulative_files),
+            cumulative_bytes: self.get(&self.cumulative_bytes),
+            peak_files: self.get(&self.peak_files) as usize,
+            records,
+            size_histogram: self
+                .size_buckets
+                .iter()
+                .map(|bucket| bucket.load(Ordering::Relaxed))
+                .collect(),
+            cumulative_lines: self.get(&self.cumulative_lines),
+            phases: self.phases(),
+        }
+    }
+
+    /// Persist the statistics so the next run starts warm
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span =
+            tracing::debug_span!("feedback_save", path = %path.as_ref().display()).entered();
+        let mut buf = Vec::new();
+        self.write_to(&mut buf)?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(bytes = buf.len(), "serialized runtime feedback");
+        // A crash mid-write must not leave a truncated blob for the next run
+        write_atomic(path.as_ref(), &buf)
+    }
+
+    /// Load statistics written by [`RuntimeFeedback::save`]
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        #[cfg(feature = "tracing")]
+        let _span =
+            tracing::debug_span!("feedback_load", path = %path.as_ref().display()).entered();
+        let feedback = Self::read_from(&mut io::BufReader::new(std::fs::File::open(path)?));
+        #[cfg(feature = "tracing")]
+        match &feedback {
+            Ok(feedback) => tracing::debug!(
+                finalizations = feedback.usage_count(),
+                "deserialized runtime feedback"
+            ),
+            Err(error) => tracing::warn!(%error, "failed to load runtime feedback"),
+        }
+        feedback
+    }
+
+    /// Encode as a little-endian binary blob with a magic and version header
+    ///
+    /// Sections added after the first release are appended at the end, so a
+    /// blob from an older release simply stops early and loads with defaults.
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let snapshot = self.snapshot();
+        out.write_all(Self::MAGIC)?;
+        out.write_all(&[Self::VERSION])?;
+        for value in [
+            snapshot.total_files as u64,
+            snapshot.total_bytes,
+            snapshot.max_file_size as u64,
+            snapshot.usage_count as u64,
+            snapshot.cumulative_files,
+            snapshot.cumulative_bytes,
+            snapshot.peak_files as u64,
+            snapshot.records.len() as u64,
+        ] {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        for record in &snapshot.records {
+            out.write_all(&(record.files as u64).to_le_bytes())?;
+            out.write_all(&record.bytes.to_le_bytes())?;
+            out.write_all(&(record.max_file_size as u64).to_le_bytes())?;
+        }
+        out.write_all(&(snapshot.size_histogram.len() as u64).to_le_bytes())?;
+        for count in &snapshot.size_histogram {
+            out.write_all(&count.to_le_bytes())?;
+        }
+        out.write_all(&snapshot.cumulative_lines.to_le_bytes())?;
+        for (_, duration) in snapshot.phases.iter() {
+            out.write_all(&(duration.as_nanos() as u64).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Decode a blob produced by [`RuntimeFeedback::write_to`]
+    pub fn read_from(input: &mut impl Read) -> io::Result<Self> {
+        let mut header = [0u8; 5];
+        input.read_exact(&mut header)?;
+        if &header[..4] != Self::MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                WireError::BadMagic,
+            ));
+        }
+        if header[4] != Self::VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                WireError::FormatVersion {
+                    found: header[4] as u16,
+                    supported: Self::VERSION as u16..=Self::VERSION as u16,
+                },
+            ));
+        }
+        let mut snapshot = FeedbackSnapshot {
+            total_files: Self::word(input)? as usize,
+            total_bytes: Self::word(input)?,
+            max_file_size: Self::word(input)? as usize,
+            usage_count: Self::word(input)? as u32,
+            cumulative_files: Self::word(input)?,
+            cumulative_bytes: Self::word(input)?,
+            peak_files: Self::word(input)? as usize,
+            records: Vec::new(),
+            size_histogram: Vec::new(),
+            cumulative_lines: 0,
+            phases: FinalizePhases::default(),
+        };
+        for _ in 0..Self::word(input)? {
+            snapshot.records.push(FinalizeRecord {
+                files: Self::word(input)? as usize,
+                bytes: Self::word(input)?,
+                max_file_size: Self::word(input)? as usize,
+            });
+        }
+        if snapshot.records.len() > snapshot.usage_count as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "More finalize records than recorded finalizations",
+            ));
+        }
+        if snapshot.peak_files > IdWidth::U32.max_files() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Peak file count beyond every ID type",
+            ));
+        }
+        if let Some(buckets) = Self::section(input)? {
+            if buckets as usize > Self::SIZE_BUCKETS {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Too many size histogram buckets",
+                ));
+            }
+            for _ in 0..buckets {
+                snapshot.size_histogram.push(Self::word(input)?);
+            }
+        }
+        if let Some(lines) = Self::section(input)? {
+            snapshot.cumulative_lines = lines;
+        }
+        if let Some(sort) = Self::section(input)? {
+            snapshot.phases = FinalizePhases {
+                sort: Duration::from_nanos(sort),
+                dedup: Duration::from_nanos(Self::word(input)?),
+                consolidation: Duration::from_nanos(Self::word(input)?),
+                line_offsets: Duration::from_nanos(Self::word(input)?),
+            };
+        }
+        Ok(snapshot.into())
+    }
+
+    /// Read a little-endian word
+    fn word(input: &mut impl Read) -> io::Result<u64> {
+        let mut word = [0u8; 8];
+        input.read_exact(&mut word)?;
+        Ok(u64::from_le_bytes(word))
+    }
+
+    /// Read the header word of an optional trailing section (None at end of input)
+    fn section(input: &mut impl Read) -> io::Result<Option<u64>> {
+        match Self::word(input) {
+            Ok(word) => Ok(Some(word)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<Id: FileId> MapObserver<Id> for RuntimeFeedback {
+    fn on_finalize(&self, event: &FinalizeEvent<'_>) {
+        if !self.sampler.sample() {
+            return;
+        }
+        for &size in event.file_sizes {
+            self.record_file_size(size);
+        }
+        if let Some(lines) = event.lines {
+            self.record_lines(lines);
+        }
+        self.add_phases(&event.phases);
+        self.record(FinalizeRecord {
+            files: event.files(),
+            bytes: event.bytes,
+            max_file_size: event.max_file_size(),
+        });
+    }
+}
+
+/// View counters of one file, see [`SourceFilesMap::hot_files`](crate::SourceFilesMap::hot_files)
+#[cfg(feature = "view")]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileViewStats {
+    /// Successful views of the file
+    pub hits: u64,
+    /// Bytes returned by those views
+    pub bytes: u64,
+}
+
+/// Positions of one file that could not be encoded, see
+/// [`SourceFilesMap::truncation_report`](crate::SourceFilesMap::truncation_report)
+#[cfg(feature = "view")]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileTruncations {
+    /// Spans reaching a line past `u16::MAX`
+    pub lines: u64,
+    /// Other spans, reaching a column past `u8::MAX`
+    pub columns: u64,
+}
+
+#[cfg(feature = "view")]
+impl FileTruncations {
+    pub fn total(&self) -> u64 {
+        self.lines + self.columns
+    }
+}
+
+/// Per-file view counters of a map, indexed like its files
+#[cfg(feature = "view")]
+#[derive(Debug, Default)]
+pub(crate) struct ViewStats {
+    hits: Vec<AtomicU64>,
+    bytes: Vec<AtomicU64>,
+    // Never sampled, as truncations are rare and each one matters
+    truncated_lines: Vec<AtomicU64>,
+    truncated_columns: Vec<AtomicU64>,
+    sampler: Sampler,
+}
+
+#[cfg(feature = "view")]
+impl ViewStats {
+    /// Drop all counters and track `files` files
+    pub(crate) fn reset(&mut self, files: usize) {
+        self.hits = (0..files).map(|_| AtomicU64::new(0)).collect();
+        self.bytes = (0..files).map(|_| AtomicU64::new(0)).collect();
+        self.truncated_lines = (0..files).map(|_| AtomicU64::new(0)).collect();
+        self.truncated_columns = (0..files).map(|_| AtomicU64::new(0)).collect();
+    }
+
+    /// Record only 1 in `rate` views, scaling counters back up
+    pub(crate) fn set_sample_rate(&mut self, rate: u32) {
+        self.sampler = Sampler::new(rate);
+    }
+
+    pub(crate) fn record(&self, index: usize, len: usize) {
+        if !self.sampler.sample() {
+            return;
+        }
+        let weight = self.sampler.weight();
+        if let (Some(hits), Some(bytes)) = (self.hits.get(index), self.bytes.get(index)) {
+            hits.fetch_add(weight, Ordering::Relaxed);
+            bytes.fetch_add(len as u64 * weight, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn get(&self, index: usize) -> Option<FileViewStats> {
+        Some(FileViewStats {
+            hits: self.hits.get(index)?.load(Ordering::Relaxed),
+            bytes: self.bytes.get(index)?.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Count a span of a file that no position could encode
+    pub(crate) fn record_truncation(&self, index: usize, line: bool) {
+        let counters = if line {
+            &self.truncated_lines
+        } else {
+            &self.truncated_columns
+        };
+        if let Some(counter) = counters.get(index) {
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Indexes of the files with truncations, most truncated first
+    pub(crate) fn truncations(&self) -> Vec<(usize, FileTruncations)> {
+        let mut all: Vec<(usize, FileTruncations)> = self
+            .truncated_lines
+            .iter()
+            .zip(&self.truncated_columns)
+            .map(|(lines, columns)| FileTruncations {
+                lines: lines.load(Ordering::Relaxed),
+                columns: columns.load(Ordering::Relaxed),
+            })
+            .enumerate()
+            .filter(|(_, counts)| counts.total() > 0)
+            .collect();
+        all.sort_by(|(a_index, a), (b_index, b)| {
+            b.total().cmp(&a.total()).then(a_index.cmp(b_index))
+        });
+        all
+    }
+
+    /// Indexes of the `n` most viewed files, by hits then bytes
+    pub(crate) fn hottest(&self, n: usize) -> Vec<(usize, FileViewStats)> {
+        let mut all: Vec<(usize, FileViewStats)> = (0..self.hits.len())
+            .filter_map(|index| Some((index, self.get(index)?)))
+            .filter(|(_, stats)| stats.hits > 0)
+            .collect();
+        all.sort_by(|(a_index, a), (b_index, b)| {
+            (b.hits, b.bytes)
+                .cmp(&(a.hits, a.bytes))
+                .then(a_index.cmp(b_index))
+        });
+        all.truncate(n);
+        all
+    }
+}
+
+#[cfg(feature = "view")]
+impl Clone for ViewStats {
+    fn clone(&self) -> Self {
+        let copy = |counters: &[AtomicU64]| {
+            counters
+                .iter()
+                .map(|c| AtomicU64::new(c.load(Ordering::Relaxed)))
+                .collect()
+        };
+        Self {
+            hits: copy(&self.hits),
+            bytes: copy(&self.bytes),
+            truncated_lines: copy(&self.truncated_lines),
+            truncated_columns: copy(&self.truncated_columns),
+            sampler: self.sampler.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for RuntimeFeedback {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.snapshot().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for RuntimeFeedback {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let snapshot = FeedbackSnapshot::deserialize(deserializer)?;
+        if snapshot.records.len() > snapshot.usage_count as usize {
+            return Err(serde::de::Error::custom(
+                "more finalize records than recorded finalizations",
+            ));
+        }
+        if snapshot.peak_files > IdWidth::U32.max_files() {
+            return Err(serde::de::Error::custom(
+                "peak file count beyond every ID type",
+            ));
+        }
+        Ok(snapshot.into())
+    }
+}
@@ -2,53 +2,240 @@
 use crate::SourceFilePosition;
 #[cfg(feature = "view")]
 use crate::clo::CompactLineOffsets;
+use crate::dsk::{DiskStamp, TextFormat};
+use crate::err::SourceFilesError;
+use crate::ext::Extensions;
+#[cfg(feature = "view")]
+use crate::fid::AbsolutePosition;
 use crate::fid::FileId;
+#[cfg(feature = "view")]
+use crate::fid::{RangeSemantics, RelativePosition};
+use crate::fvw::FileRef;
+use crate::grf::FileGraph;
+#[cfg(feature = "view")]
+use crate::lod::BINARY_PLACEHOLDER;
+use crate::lod::{LoadOptions, SkipReason, SkippedFile};
+use crate::nrm::Normalized;
+use crate::sto::{Content, ContentChunks, Storage};
+use crate::wrn::{Warning, WarningSink, Warnings};
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+#[cfg(feature = "view")]
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
+use std::ops::Range;
+use std::time::{Duration, Instant};
 
+use crate::obs::{CAPACITY_WARNING_PERCENT, FinalizeEvent, FinalizePhases, MapObserver, Observers};
+use crate::pth::{PathIndex, PathSlab};
+use crate::rmp::IdRemapTable;
 #[cfg(feature = "rt-feedback")]
-use std::sync::{Arc, Mutex};
+use crate::rtf::RuntimeFeedback;
+#[cfg(all(feature = "view", feature = "rt-feedback"))]
+use crate::rtf::{FileTruncations, FileViewStats, ViewStats};
+use std::sync::{Arc, OnceLock};
 
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-#[cfg_attr(
-    feature = "serde",
-    serde(bound = "Id: Serialize + serde::de::DeserializeOwned")
-)]
+/// Registry of source files addressed by compact numeric IDs
+///
+/// With the `serde` feature a map serializes as its files in ID order plus its
+/// sizing hints and [`FileGraph`] edges; IDs and line offsets are implied by
+/// that order and rebuilt on deserialization, so no field depends on the ID
+/// type.
 #[derive(Debug, Clone)]
 pub struct SourceFilesMap<Id: FileId> {
     files: Vec<FileEntry>,
-    path_to_id: HashMap<String, Id>,
+    // Paths of every entry, in ID order once finalized
+    paths: PathSlab,
+    path_to_id: PathIndex<Id>,
+    // Whether assigning IDs freezes `path_to_id` into a perfect hash
+    phf_lookup: bool,
     avg_file_size: usize,
     expected_files: usize,
 
     // Feature-gated view state
     #[cfg(feature = "view")]
-    #[cfg_attr(feature = "serde", serde(skip))]
     line_offsets: HashMap<Id, CompactLineOffsets>,
-    // Feature-gated feedback state
-    #[cfg(feature = "rt-feedback")]
-    #[cfg_attr(feature = "serde", serde(skip))]
-    feedback: Option<Arc<Mutex<RuntimeFeedback>>>,
+    #[cfg(feature = "view")]
+    line_length_hint: Option<usize>,
+    #[cfg(all(feature = "view", feature = "rt-feedback"))]
+    view_stats: ViewStats,
+    dropped: Vec<String>,
+    warnings: Warnings,
+    load_options: LoadOptions,
+    skipped: Vec<SkippedFile>,
+    observers: Observers<Id>,
+    order: FileOrder,
+    duplicates: DuplicatePolicy,
+    epoch: u64,
+    // Content before the first edit of each edited file, by path so that
+    // reassigned IDs keep it
+    originals: HashMap<String, Vec<u8>>,
+    // Disk state each file was last read in, by path like `originals`
+    stamps: HashMap<String, DiskStamp>,
+    graph: FileGraph<Id>,
+    // Edges of `graph` by path while IDs are being reassigned
+    graph_paths: Vec<(String, String)>,
+    extensions: Extensions,
 }
 
-#[cfg(feature = "rt-feedback")]
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
-#[derive(Debug, Default)]
-pub struct RuntimeFeedback {
-    pub total_files: usize,
-    pub total_bytes: u64,
-    pub max_file_size: usize,
-    pub usage_count: u32,
+/// Order in which IDs are assigned
+///
+/// Ties keep submission order and duplicated paths are resolved by the
+/// [`DuplicatePolicy`], so the same `add_file` sequence always yields the same
+/// IDs.
+#[derive(Clone, Default)]
+pub enum FileOrder {
+    /// Byte-wise path order
+    #[default]
+    Path,
+    /// Order of the `add_file` calls
+    ///
+    /// IDs are assigned by `add_file` itself and usable right away; `finalize`
+    /// keeps them and only validates the map.
+    Insertion,
+    /// Order of a key derived from each path, e.g. a workspace-relative path
+    Key(Arc<dyn Fn(&str) -> String + Send + Sync>),
+}
+
+/// What to do when a path is submitted more than once
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Keep the first submitted content
+    #[default]
+    KeepFirst,
+    /// Keep the last submitted content
+    KeepLast,
+    /// Fail with [`SourceFilesError::DuplicatePath`]
+    Reject,
+}
+
+impl std::fmt::Debug for FileOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Path => write!(f, "Path"),
+            Self::Insertion => write!(f, "Insertion"),
+            Self::Key(_) => write!(f, "Key(..)"),
+        }
+    }
 }
 
-#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone)]
 struct FileEntry {
+    // Index in the map's `paths`
+    path: u32,
+    content: Content,
+    // Sniffed whenever the content is set
+    binary: bool,
+    // Handle given out by `content_arc`, dropped whenever the content changes
+    shared: OnceLock<Arc<[u8]>>,
+    // How the content as added was normalized, when that changed it
+    normalized: Option<Box<Normalized>>,
+}
+
+impl FileEntry {
+    fn new(path: u32, content: Content) -> Self {
+        Self {
+            path,
+            binary: content.sniff(),
+            content,
+            shared: OnceLock::new(),
+            normalized: None,
+        }
+    }
+
+    fn set_content(&mut self, content: Content) {
+        self.content = content;
+        self.binary = self.content.sniff();
+        self.shared = OnceLock::new();
+    }
+}
+
+/// Serialized form of a file
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+#[serde(rename = "FileEntry")]
+struct EntryRepr<'a> {
+    path: &'a str,
+    content: &'a Content,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(rename = "FileEntry")]
+struct OwnedEntryRepr {
     path: String,
-    content: Vec<u8>,
+    content: Content,
+}
+
+/// Serialized form of a map: only what cannot be rebuilt from the files
+#[cfg(feature = "serde")]
+#[derive(Serialize)]
+#[serde(rename = "SourceFilesMap", bound = "")]
+struct MapRepr<'a, Id: FileId> {
+    files: Vec<EntryRepr<'a>>,
+    avg_file_size: usize,
+    expected_files: usize,
+    epoch: u64,
+    graph: &'a FileGraph<Id>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+#[serde(rename = "SourceFilesMap")]
+#[serde(bound = "")]
+struct OwnedMapRepr<Id: FileId> {
+    files: Vec<OwnedEntryRepr>,
+    avg_file_size: usize,
+    expected_files: usize,
+    // Absent from maps serialized before epochs existed
+    #[serde(default)]
+    epoch: u64,
+    // Absent from maps serialized before the graph existed
+    #[serde(default)]
+    graph: FileGraph<Id>,
+}
+
+#[cfg(feature = "serde")]
+impl<Id: FileId> Serialize for SourceFilesMap<Id> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MapRepr {
+            files: self
+                .files
+                .iter()
+                .map(|entry| EntryRepr {
+                    path: self.paths.get(entry.path),
+                    content: &entry.content,
+                })
+                .collect(),
+            avg_file_size: self.avg_file_size,
+            expected_files: self.expected_files,
+            epoch: self.epoch,
+            graph: &self.graph,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, Id: FileId> Deserialize<'de> for SourceFilesMap<Id> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = OwnedMapRepr::<Id>::deserialize(deserializer)?;
+        let files = repr
+            .files
+            .into_iter()
+            .map(|entry| (entry.path, entry.content.into_vec()))
+            .collect();
+        let mut map = Self::from_finalized(files).map_err(serde::de::Error::custom)?;
+        map.avg_file_size = repr.avg_file_size;
+        map.expected_files = repr.expected_files;
+        map.epoch = repr.epoch;
+        map.set_graph(repr.graph)
+            .map_err(serde::de::Error::custom)?;
+        Ok(map)
+    }
 }
+
 impl<Id: FileId> Default for SourceFilesMap<Id> {
     fn default() -> Self {
         Self::new()
@@ -58,135 +245,964 @@ impl<Id: FileId> Default for SourceFilesMap<Id> {
 impl<Id: FileId> SourceFilesMap<Id> {
     const DEFAULT_FILE_COUNT: usize = 100;
     const DEFAULT_AVG_SIZE: usize = 2048;
+    const DEFAULT_PATH_LEN: usize = 32;
     /// Create a new map with conservative defaults for small projects
     pub fn new() -> Self {
         // Default heuristics: 100 files @ 2KB average
 
         Self {
             files: Vec::with_capacity(Self::DEFAULT_FILE_COUNT),
-            path_to_id: HashMap::with_capacity(Self::DEFAULT_FILE_COUNT),
+            paths: PathSlab::with_capacity(
+                Self::DEFAULT_FILE_COUNT,
+                Self::DEFAULT_FILE_COUNT * Self::DEFAULT_PATH_LEN,
+            ),
+            path_to_id: PathIndex::with_capacity(Self::DEFAULT_FILE_COUNT),
+            phf_lookup: false,
             avg_file_size: Self::DEFAULT_AVG_SIZE,
             expected_files: Self::DEFAULT_FILE_COUNT,
             #[cfg(feature = "view")]
             line_offsets: HashMap::with_capacity(Self::DEFAULT_FILE_COUNT),
-            #[cfg(feature = "rt-feedback")]
-            feedback: None,
+            #[cfg(feature = "view")]
+            line_length_hint: None,
+            #[cfg(all(feature = "view", feature = "rt-feedback"))]
+            view_stats: ViewStats::default(),
+            dropped: Vec::new(),
+            warnings: Warnings::default(),
+            load_options: LoadOptions::default(),
+            skipped: Vec::new(),
+            observers: Observers::default(),
+            order: FileOrder::default(),
+            duplicates: DuplicatePolicy::default(),
+            epoch: 0,
+            originals: HashMap::new(),
+            stamps: HashMap::new(),
+            graph: FileGraph::new(),
+            graph_paths: Vec::new(),
+            extensions: Extensions::default(),
         }
     }
     #[cfg(feature = "view")]
-    fn compute_line_offsets(content: &[u8]) -> CompactLineOffsets {
-        CompactLineOffsets::compute(content)
+    fn compute_line_offsets(content: &[u8], line_length_hint: Option<usize>) -> CompactLineOffsets {
+        match line_length_hint {
+            // Reserve an extra eighth so files slightly denser than average fit too
+            Some(len) => {
+                let expected = content.len() / len + 1;
+                CompactLineOffsets::compute_with_capacity(content, expected + expected / 8)
+            }
+            None => CompactLineOffsets::compute(content),
+        }
     }
     /// Create new instance with optional feedback context
     #[cfg(feature = "rt-feedback")]
-    pub fn with_feedback(feedback: Option<Arc<Mutex<RuntimeFeedback>>>) -> Self {
-        let (expected, avg_size) = feedback.as_ref().map_or_else(
-            || (Self::DEFAULT_FILE_COUNT, Self::DEFAULT_AVG_SIZE), // Defaults
+    pub fn with_feedback(feedback: Option<Arc<RuntimeFeedback>>) -> Self {
+        let (expected, avg_size, _line_length) = feedback.as_ref().map_or_else(
+            || (Self::DEFAULT_FILE_COUNT, Self::DEFAULT_AVG_SIZE, None), // Defaults
             |f| {
-                let data = f.lock().unwrap();
-                let expected = (data.total_files * 120) / 100; // 20% buffer
-                let avg_size = if data.total_files > 0 {
-                    (data.total_bytes / data.total_files as u64) as usize
-                } else {
-                    Self::DEFAULT_AVG_SIZE
-                };
-                (expected, avg_size)
+                let data = f.snapshot();
+                // Size for the largest map seen so far, not just the last one
+                // 20% buffer, never past what the ID type can hold
+                let expected = (data.peak_files.saturating_mul(120) / 100).min(Id::MAX_FILES);
+                // p95 keeps one giant generated file from skewing every buffer
+                let avg_size = data
+                    .p95()
+                    .or_else(|| data.mean_file_size())
+                    .unwrap_or(Self::DEFAULT_AVG_SIZE);
+                (expected, avg_size, data.mean_line_length())
             },
         );
 
-        Self {
+        let mut map = Self {
             files: Vec::with_capacity(expected),
-            path_to_id: HashMap::with_capacity(expected),
+            paths: PathSlab::with_capacity(expected, expected * Self::DEFAULT_PATH_LEN),
+            path_to_id: PathIndex::with_capacity(expected),
+            phf_lookup: false,
             avg_file_size: avg_size,
             #[cfg(feature = "view")]
             line_offsets: HashMap::with_capacity(expected),
+            #[cfg(feature = "view")]
+            line_length_hint: _line_length,
+            #[cfg(feature = "view")]
+            view_stats: ViewStats::default(),
             expected_files: expected,
-            feedback,
+            dropped: Vec::new(),
+            warnings: Warnings::default(),
+            load_options: LoadOptions::default(),
+            skipped: Vec::new(),
+            observers: Observers::default(),
+            order: FileOrder::default(),
+            duplicates: DuplicatePolicy::default(),
+            epoch: 0,
+            originals: HashMap::new(),
+            stamps: HashMap::new(),
+            graph: FileGraph::new(),
+            graph_paths: Vec::new(),
+            extensions: Extensions::default(),
+        };
+        // Feedback is just another observer once the capacities are derived
+        if let Some(feedback) = feedback {
+            #[cfg(feature = "view")]
+            map.view_stats
+                .set_sample_rate(feedback.config().sample_rate);
+            map.add_observer(feedback);
         }
+        map
+    }
+
+    /// Create new instance sized and tracked by the global feedback context `name`
+    #[cfg(feature = "rt-feedback")]
+    pub fn with_global_feedback(name: &str) -> Self {
+        Self::with_feedback(Some(RuntimeFeedback::global(name)))
+    }
+
+    /// Register an observer notified of lifecycle events
+    pub fn add_observer(&mut self, observer: Arc<dyn MapObserver<Id>>) {
+        self.observers.push(observer);
+    }
+
+    /// Builder-style variant of [`SourceFilesMap::add_observer`]
+    pub fn with_observer(mut self, observer: Arc<dyn MapObserver<Id>>) -> Self {
+        self.add_observer(observer);
+        self
+    }
+
+    /// Set the order in which `finalize` assigns IDs
+    pub fn set_order(&mut self, order: FileOrder) {
+        self.order = order;
+    }
+
+    /// Builder-style variant of [`SourceFilesMap::set_order`]
+    pub fn with_order(mut self, order: FileOrder) -> Self {
+        self.set_order(order);
+        self
+    }
+
+    /// Set how paths submitted more than once are resolved
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        self.duplicates = policy;
+    }
+
+    /// Builder-style variant of [`SourceFilesMap::set_duplicate_policy`]
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.set_duplicate_policy(policy);
+        self
+    }
+
+    /// Look paths up through a perfect hash once IDs are assigned
+    ///
+    /// `finalize` then replaces the path hash map with a table of under five
+    /// bytes per path, where [`SourceFilesMap::get_id`] hashes once and
+    /// compares one path instead of probing. Files getting their ID from
+    /// `add_file`, in [`FileOrder::Insertion`], bring the hash map back until
+    /// the next `finalize`.
+    pub fn set_phf_lookup(&mut self, enabled: bool) {
+        self.phf_lookup = enabled;
+    }
+
+    /// Builder-style variant of [`SourceFilesMap::set_phf_lookup`]
+    pub fn with_phf_lookup(mut self, enabled: bool) -> Self {
+        self.set_phf_lookup(enabled);
+        self
+    }
+
+    /// Whether path lookups currently go through a perfect hash
+    pub fn has_phf_lookup(&self) -> bool {
+        self.path_to_id.is_frozen()
+    }
+
+    /// Send warnings to `sink` instead of keeping them for
+    /// [`SourceFilesMap::take_warnings`]
+    pub fn set_warning_sink(&mut self, sink: Arc<dyn WarningSink>) {
+        self.warnings.set_sink(sink);
+    }
+
+    /// Builder-style variant of [`SourceFilesMap::set_warning_sink`]
+    pub fn with_warning_sink(mut self, sink: Arc<dyn WarningSink>) -> Self {
+        self.set_warning_sink(sink);
+        self
+    }
+
+    /// Warnings recorded since the last call, oldest first
+    ///
+    /// Always empty once a [`WarningSink`] is set.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        self.warnings.take()
+    }
+
+    /// Report a span of file `id` reaching a line or column no position encodes
+    #[cfg(feature = "view")]
+    pub(crate) fn record_overflow(&self, id: Id, line: usize, column: usize) {
+        let raw_id: u64 = id.into();
+        #[cfg(feature = "rt-feedback")]
+        self.view_stats
+            .record_truncation(raw_id as usize - 1, u16::try_from(line).is_err());
+        self.warnings.push(Warning::PositionOverflow {
+            id: raw_id,
+            line,
+            column,
+        });
+    }
+
+    /// Set the filters applied to files added from now on
+    pub fn set_load_options(&mut self, options: LoadOptions) {
+        self.load_options = options;
+    }
+
+    /// Builder-style variant of [`SourceFilesMap::set_load_options`]
+    pub fn with_load_options(mut self, options: LoadOptions) -> Self {
+        self.set_load_options(options);
+        self
+    }
+
+    pub fn load_options(&self) -> &LoadOptions {
+        &self.load_options
     }
 
     /// Add a file with content (bytes preferred over String)
-    pub fn add_file(&mut self, path: String, content: Vec<u8>) {
+    ///
+    /// Fails once the map holds `Id::MAX_FILES` files; the rejected path is
+    /// also kept in [`SourceFilesMap::dropped_files`]. Files rejected by the
+    /// [`LoadOptions`] fail too and are kept in
+    /// [`SourceFilesMap::skipped_files`]. With
+    /// [`FileOrder::Insertion`] duplicates are resolved here rather than in
+    /// `finalize`.
+    pub fn add_file(&mut self, path: String, content: Vec<u8>) -> Result<(), SourceFilesError> {
+        self.push_file(path, Content::Contiguous(content))
+            .map(|_| ())
+    }
+
+    /// Add a file held as [`Storage::Bytes`], without copying its content
+    ///
+    /// Otherwise the same as [`SourceFilesMap::add_file`].
+    #[cfg(feature = "bytes")]
+    pub fn add_file_bytes(
+        &mut self,
+        path: String,
+        content: bytes::Bytes,
+    ) -> Result<(), SourceFilesError> {
+        self.push_file(path, Content::Bytes(content)).map(|_| ())
+    }
+
+    /// Add a file and get its ID right away
+    ///
+    /// Puts the map in [`FileOrder::Insertion`], since sorting in `finalize`
+    /// would invalidate the returned ID; files added before keep their
+    /// submission order. The file is viewable immediately, making `finalize`
+    /// an optional validation step. A duplicate path returns the ID it already
+    /// has, unless the [`DuplicatePolicy`] rejects it.
+    pub fn insert_file(&mut self, path: String, content: Vec<u8>) -> Result<Id, SourceFilesError> {
+        if !matches!(self.order, FileOrder::Insertion) {
+            self.order = FileOrder::Insertion;
+            self.dedup()?;
+            self.assign_ids()
+                .expect("files within capacity always have an ID");
+            #[cfg(feature = "view")]
+            self.index_lines()
+                .expect("files within capacity always have an ID");
+        }
+        Ok(self
+            .push_file(path, Content::Contiguous(content))?
+            .expect("insertion order assigns IDs on add"))
+    }
+
+    /// Shared body of `add_file` and `insert_file`, returning the ID when
+    /// assigned on the spot
+    fn push_file(
+        &mut self,
+        path: String,
+        content: Content,
+    ) -> Result<Option<Id>, SourceFilesError> {
+        if let Some(reason) = self.load_options.check(content.bytes()) {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(path = %path, %reason, "skipped file");
+            self.record_skip(path.clone(), reason);
+            return Err(SourceFilesError::Skipped { path, reason });
+        }
+        let (content, normalized) = if self.load_options.normalize.is_enabled() {
+            let storage = content.storage();
+            let (bytes, normalized) = self.normalization(content.into_vec());
+            (Content::new(bytes, storage), normalized.map(Box::new))
+        } else {
+            (content, None)
+        };
+        let size = content.len();
+        let streaming = matches!(self.order, FileOrder::Insertion);
+        if let Some(id) = self
+            .path_to_id
+            .get(&self.paths, &path)
+            .filter(|_| streaming)
+        {
+            match self.duplicates {
+                DuplicatePolicy::KeepFirst => {}
+                DuplicatePolicy::KeepLast => {
+                    let raw: u64 = id.into();
+                    let entry = &mut self.files[raw as usize - 1];
+                    entry.set_content(content);
+                    entry.normalized = normalized;
+                    #[cfg(feature = "view")]
+                    self.index_file(id);
+                    self.observers
+                        .each(|observer| observer.on_add_file(&path, size));
+                }
+                DuplicatePolicy::Reject => return Err(SourceFilesError::DuplicatePath { path }),
+            }
+            return Ok(Some(id));
+        }
         if self.files.len() < Id::MAX_FILES {
-            self.files.push(FileEntry { path, content });
+            let id = Id::try_from(self.files.len() as u64 + 1)
+                .ok()
+                .filter(|_| streaming);
+            // The slab follows `files`, so the path lands at the index of `id`
+            let at = self.paths.push(&path);
+            if let Some(id) = id {
+                self.path_to_id.insert(&self.paths, id);
+            }
+            self.files.push(FileEntry {
+                normalized,
+                ..FileEntry::new(at, content)
+            });
+            self.observers
+                .each(|observer| observer.on_add_file(&path, size));
+            #[cfg(feature = "view")]
+            if let Some(id) = id {
+                self.index_file(id);
+            }
+            if self.files.len() == Id::MAX_FILES * CAPACITY_WARNING_PERCENT / 100 {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(
+                    files = self.files.len(),
+                    max_files = Id::MAX_FILES,
+                    "source files map is close to its file ID capacity"
+                );
+                self.observers
+                    .each(|observer| observer.on_near_capacity(self.files.len(), Id::MAX_FILES));
+            }
+            Ok(id)
+        } else {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(path = %path, max_files = Id::MAX_FILES, "dropped file past capacity");
+            self.dropped.push(path.clone());
+            self.warnings.push(Warning::Dropped {
+                path: path.clone(),
+                max_files: Id::MAX_FILES,
+            });
+            Err(SourceFilesError::CapacityExceeded {
+                path,
+                max_files: Id::MAX_FILES,
+            })
+        }
+    }
+
+    /// Replace the content of every submission of `path` not yet finalized
+    pub(crate) fn replace_pending(&mut self, path: &str, content: Vec<u8>) -> bool {
+        let mut found = false;
+        let paths = &self.paths;
+        for entry in self
+            .files
+            .iter_mut()
+            .filter(|entry| paths.get(entry.path) == path)
+        {
+            let storage = entry.content.storage();
+            entry.set_content(Content::new(content.clone(), storage));
+            found = true;
+        }
+        #[cfg(feature = "view")]
+        if let Some(id) = self.path_to_id.get(&self.paths, path) {
+            self.index_file(id);
         }
+        found
     }
 
-    /// Finalize with path-based sorting and deduplication
-    pub fn finalize(&mut self) -> Result<(), String> {
-        // Sort by path first, then by content size for potential grouping
-        self.files.sort_unstable_by(|a, b| {
-            a.path
-                .cmp(&b.path)
-                .then_with(|| a.content.len().cmp(&b.content.len()))
+    /// Remove every submission of `path` not yet finalized
+    pub(crate) fn remove_pending(&mut self, path: &str) -> bool {
+        let before = self.files.len();
+        let paths = &self.paths;
+        self.files.retain(|entry| paths.get(entry.path) != path);
+        if self.files.len() == before {
+            return false;
+        }
+        let edges = self.graph_by_path();
+        self.graph_paths.extend(edges);
+        self.compact_paths();
+        // Later insertion-order IDs shift down by one
+        if matches!(self.order, FileOrder::Insertion) {
+            self.epoch += 1;
+            self.assign_ids()
+                .expect("files within capacity always have an ID");
+            #[cfg(feature = "view")]
+            self.index_lines()
+                .expect("files within capacity always have an ID");
+        }
+        true
+    }
+
+    /// `content` rewritten by the normalization of the load options, with
+    /// its offsets when it changed, without recording them
+    pub(crate) fn normalization(&self, content: Vec<u8>) -> (Vec<u8>, Option<Normalized>) {
+        let passes = self.load_options.normalize;
+        if !passes.is_enabled() {
+            return (content, None);
+        }
+        let (normalized, offsets) = passes.apply(&content);
+        if offsets.is_identity() {
+            return (content, None);
+        }
+        let normalized_file = Normalized {
+            offsets: Some(offsets),
+            source_hash: xxhash_rust::xxh3::xxh3_64(&content),
+            format: TextFormat::detect(&content),
+        };
+        (normalized, Some(normalized_file))
+    }
+
+    /// Record how the content of `id` was normalized, or that it was not
+    pub(crate) fn set_normalized(&mut self, id: Id, normalized: Option<Normalized>) {
+        let raw: u64 = id.into();
+        if let Some(entry) = raw
+            .checked_sub(1)
+            .and_then(|index| self.files.get_mut(index as usize))
+        {
+            entry.normalized = normalized.map(Box::new);
+        }
+    }
+
+    /// Take `written`, flushed over the normalized file `id`, as its content
+    /// as added
+    pub(crate) fn set_flushed(&mut self, id: Id, written: Vec<u8>) {
+        if self.normalized(id).is_none() {
+            return;
+        }
+        let source_hash = xxhash_rust::xxh3::xxh3_64(&written);
+        let format = TextFormat::detect(&written);
+        let normalized = match self.normalization(written) {
+            (normalized, record) if self.get_content(id) == Some(normalized.as_slice()) => record,
+            // Edited lines the normalization would still rewrite
+            _ => Some(Normalized {
+                offsets: None,
+                source_hash,
+                format,
+            }),
+        };
+        self.set_normalized(id, normalized);
+    }
+
+    pub(crate) fn normalized(&self, id: Id) -> Option<&Normalized> {
+        let raw: u64 = id.into();
+        let index = raw.checked_sub(1)? as usize;
+        self.files.get(index)?.normalized.as_deref()
+    }
+
+    /// Hash of a file as it was added, before normalization and edits
+    pub(crate) fn source_hash(&self, id: Id) -> Option<u64> {
+        if let Some(normalized) = self.normalized(id) {
+            return Some(normalized.source_hash);
+        }
+        match self.original_content(id) {
+            Some(original) => Some(xxhash_rust::xxh3::xxh3_64(original)),
+            None => self.content_hash(id),
+        }
+    }
+
+    /// Paths rejected by `add_file` because the map was full
+    pub fn dropped_files(&self) -> &[String] {
+        &self.dropped
+    }
+
+    /// Record a file rejected before its content was read
+    pub(crate) fn record_skip(&mut self, path: String, reason: SkipReason) {
+        self.warnings.push(Warning::Skipped {
+            path: path.clone(),
+            reason,
         });
+        self.skipped.push(SkippedFile { path, reason });
+    }
+
+    /// Files rejected by the [`LoadOptions`], in submission order
+    pub fn skipped_files(&self) -> &[SkippedFile] {
+        &self.skipped
+    }
+
+    /// Finalize: order files, resolve duplicate paths and assign IDs
+    ///
+    /// IDs are 1-based indices in the [`FileOrder`] of the map (path order by
+    /// default), after duplicates are resolved by its [`DuplicatePolicy`].
+    pub fn finalize(&mut self) -> Result<(), SourceFilesError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("finalize", submitted = self.files.len()).entered();
+        let started = Instant::now();
+        let mut phases = FinalizePhases::default();
+        let mut phase_start = started;
+        // Insertion order already assigned every ID, finalize keeps them
+        let keeps_ids =
+            matches!(self.order, FileOrder::Insertion) && self.path_to_id.len() == self.files.len();
+        let mut lap = |phase: &mut Duration| {
+            let now = Instant::now();
+            *phase = now - phase_start;
+            phase_start = now;
+        };
 
-        // Deduplicate paths while keeping first occurrence
-        self.files.dedup_by(|a, b| a.path == b.path);
+        // Stable sorts keep submission order among equal keys
+        let paths = &self.paths;
+        match &self.order {
+            FileOrder::Path => self
+                .files
+                .sort_by(|a, b| paths.get(a.path).cmp(paths.get(b.path))),
+            FileOrder::Insertion => {}
+            FileOrder::Key(key) => self
+                .files
+                .sort_by_cached_key(|entry| key(paths.get(entry.path))),
+        }
+        lap(&mut phases.sort);
+
+        self.dedup()?;
+        lap(&mut phases.dedup);
 
         // Check capacity constraints
         if self.files.len() > Id::MAX_FILES {
-            return Err(format!(
-                "Exceeded maximum of {} files for ID type",
-                Id::MAX_FILES
-            ));
+            return Err(self.too_many_files());
         }
 
         // Preallocate content storage in bulk (heuristic-based)
         let total_bytes = self.avg_file_size * self.expected_files;
         let mut consolidated = Vec::with_capacity(total_bytes);
 
-        // Build ID mapping and consolidate memory
-        self.path_to_id.clear();
-        for (idx, entry) in self.files.iter_mut().enumerate() {
-            // Move content to consolidated storage
-            consolidated.extend_from_slice(&entry.content);
-
-            // Store ID mapping
-            let id = (idx + 1) as u64;
-            let id = id.try_into().map_err(|_| "ID conversion failed")?;
-            self.path_to_id.insert(entry.path.clone(), id);
+        // Consolidate memory, then build the ID mapping
+        for entry in &self.files {
+            consolidated.extend_from_slice(entry.content.bytes());
         }
+        self.assign_ids()?;
 
         // Replace individual content vectors with slices into consolidated storage
         let mut offset = 0;
         for entry in &mut self.files {
             let len = entry.content.len();
-            entry.content = consolidated[offset..offset + len].to_vec();
+            // Shared buffers stay shared, as their holders expect
+            #[cfg(feature = "bytes")]
+            if entry.content.storage() == Storage::Bytes {
+                offset += len;
+                continue;
+            }
+            entry
+                .content
+                .set(consolidated[offset..offset + len].to_vec());
             offset += len;
         }
-        #[cfg(feature = "rt-feedback")]
-        if let Some(feedback) = &self.feedback {
-            let total_bytes = self.files.iter().map(|e| e.content.len() as u64).sum();
-
-            let max_size = self
+        lap(&mut phases.consolidation);
+        #[cfg(feature = "view")]
+        {
+            self.index_lines()?;
+            lap(&mut phases.line_offsets);
+        }
+        if !self.observers.is_empty() {
+            let file_sizes: Vec<usize> = self.files.iter().map(|e| e.content.len()).collect();
+            #[cfg(feature = "view")]
+            let lines = Some(
+                self.line_offsets
+                    .values()
+                    .map(|offsets| offsets.line_count() as u64)
+                    .sum(),
+            );
+            #[cfg(not(feature = "view"))]
+            let lines = None;
+            let event = FinalizeEvent {
+                elapsed: started.elapsed(),
+                bytes: file_sizes.iter().map(|&size| size as u64).sum(),
+                file_sizes: &file_sizes,
+                lines,
+                phases,
+            };
+            self.observers.each(|observer| observer.on_finalize(&event));
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            files = self.files.len(),
+            bytes = self
                 .files
                 .iter()
-                .map(|e| e.content.len())
-                .max()
-                .unwrap_or(0);
+                .map(|e| e.content.len() as u64)
+                .sum::<u64>(),
+            elapsed_us = started.elapsed().as_micros() as u64,
+            "finalized source files map"
+        );
+        if !keeps_ids {
+            self.epoch += 1;
+        }
+        Ok(())
+    }
+
+    /// Generation of the ID assignment
+    ///
+    /// Bumped by `finalize`, except in [`FileOrder::Insertion`] where it keeps
+    /// the IDs handed out by `add_file`, and when removing a file shifts those
+    /// IDs. See [`SourceFilesMap::tag`] to detect stale positions.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Drop duplicate paths according to the duplicate policy
+    fn dedup(&mut self) -> Result<(), SourceFilesError> {
+        // Sorted by path, duplicates are adjacent and the first one is the oldest
+        if let (FileOrder::Path, DuplicatePolicy::KeepFirst) = (&self.order, self.duplicates) {
+            let paths = &self.paths;
+            self.files
+                .dedup_by(|a, b| paths.get(a.path) == paths.get(b.path));
+            return Ok(());
+        }
+        let mut keep = vec![false; self.files.len()];
+        let mut seen = HashSet::with_capacity(self.files.len());
+        let indices: Box<dyn Iterator<Item = usize>> = match self.duplicates {
+            DuplicatePolicy::KeepLast => Box::new((0..self.files.len()).rev()),
+            _ => Box::new(0..self.files.len()),
+        };
+        for idx in indices {
+            let path = self.paths.get(self.files[idx].path);
+            if seen.insert(path) {
+                keep[idx] = true;
+            } else if self.duplicates == DuplicatePolicy::Reject {
+                return Err(SourceFilesError::DuplicatePath {
+                    path: path.to_string(),
+                });
+            }
+        }
+        let mut keep = keep.into_iter();
+        self.files.retain(|_| keep.next().unwrap_or(false));
+        Ok(())
+    }
 
-            let mut data = feedback.lock().unwrap();
-            data.total_files = self.files.len();
-            data.total_bytes = total_bytes;
-            data.max_file_size = max_size;
-            data.usage_count += 1;
+    /// Move the map to a wider ID type, once it outgrows `Id`
+    ///
+    /// Files keep their order, content, pending edits and line offsets, so
+    /// the conversion is cheap and the returned table maps every ID to the
+    /// same number; replay it with [`IdRemapTable::apply`] to carry
+    /// positions over. Observers are typed by `Id` and are not carried over.
+    /// Converting to a narrower type fails to compile. Paths in
+    /// [`SourceFilesMap::dropped_files`] can then be added again.
+    pub fn widen<Wide: FileId>(self) -> (SourceFilesMap<Wide>, IdRemapTable<Id, Wide>) {
+        const {
+            assert!(
+                Wide::MAX_FILES >= Id::MAX_FILES,
+                "widen needs a wider ID type"
+            )
+        };
+        let widen_id = |id: Id| {
+            Wide::try_from(id.into())
+                .unwrap_or_else(|_| unreachable!("a wider ID type holds every ID"))
+        };
+        let remap = IdRemapTable::identity(self.path_to_id.len());
+        let map = SourceFilesMap {
+            files: self.files,
+            paths: self.paths,
+            path_to_id: self.path_to_id.map_ids(widen_id),
+            phf_lookup: self.phf_lookup,
+            avg_file_size: self.avg_file_size,
+            expected_files: self.expected_files,
+            #[cfg(feature = "view")]
+            line_offsets: self
+                .line_offsets
+                .into_iter()
+                .map(|(id, offsets)| (widen_id(id), offsets))
+                .collect(),
+            #[cfg(feature = "view")]
+            line_length_hint: self.line_length_hint,
+            #[cfg(all(feature = "view", feature = "rt-feedback"))]
+            view_stats: self.view_stats,
+            dropped: self.dropped,
+            warnings: self.warnings,
+            load_options: self.load_options,
+            skipped: self.skipped,
+            observers: Observers::default(),
+            order: self.order,
+            duplicates: self.duplicates,
+            epoch: self.epoch,
+            originals: self.originals,
+            stamps: self.stamps,
+            graph: self.graph.remap_ids(&remap),
+            graph_paths: self.graph_paths,
+            extensions: self.extensions,
+        };
+        (map, remap)
+    }
+
+    /// Rebuild a map from files already in ID order (e.g. loaded from a cache)
+    pub(crate) fn from_finalized(files: Vec<(String, Vec<u8>)>) -> Result<Self, String> {
+        let mut map = Self::new();
+        map.files = files
+            .into_iter()
+            .map(|(path, content)| {
+                FileEntry::new(map.paths.push(&path), Content::Contiguous(content))
+            })
+            .collect();
+        map.assign_ids()?;
+        if map.path_to_id.len() != map.files.len() {
+            return Err("duplicate paths".to_string());
         }
         #[cfg(feature = "view")]
+        map.index_lines()?;
+        Ok(map)
+    }
+
+    /// Map every path to its ID, which is its 1-based index in `files`
+    ///
+    /// Also repacks the paths in ID order, dropping those of removed files.
+    pub(crate) fn assign_ids(&mut self) -> Result<(), SourceFilesError> {
+        let edges = self.graph_by_path();
+        self.graph_paths.extend(edges);
+        self.path_to_id.clear();
+        self.compact_paths();
+        for idx in 0..self.files.len() {
+            let id = (idx + 1) as u64;
+            let id = id.try_into().map_err(|_| self.too_many_files())?;
+            self.path_to_id.insert(&self.paths, id);
+        }
+        if self.phf_lookup {
+            self.path_to_id.freeze(&self.paths);
+        }
+        for (from, to) in std::mem::take(&mut self.graph_paths) {
+            if let (Some(from), Some(to)) = (self.get_id(&from), self.get_id(&to)) {
+                self.graph.add_edge(from, to);
+            }
+        }
+        Ok(())
+    }
+
+    /// Error for holding more files than the ID type can number
+    fn too_many_files(&self) -> SourceFilesError {
+        SourceFilesError::TooManyFiles {
+            count: self.files.len(),
+            max_files: Id::MAX_FILES,
+        }
+    }
+
+    /// Repack the paths in `files` order, moving extension values along
+    fn compact_paths(&mut self) {
+        let before: Vec<u32> = self.files.iter().map(|entry| entry.path).collect();
+        self.paths
+            .compact(self.files.iter_mut().map(|entry| &mut entry.path));
+        if !self.extensions.is_empty() {
+            let moves: Vec<(u32, u32)> = before
+                .into_iter()
+                .zip(self.files.iter().map(|entry| entry.path))
+                .collect();
+            self.extensions.repack(&moves, self.files.len());
+        }
+    }
+
+    pub(crate) fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub(crate) fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Take the edges of the graph out by path, before the paths are repacked
+    ///
+    /// Until then the path of every assigned ID is at its index in the slab.
+    fn graph_by_path(&mut self) -> Vec<(String, String)> {
+        let paths = &self.paths;
+        let path = |id: Id| {
+            let raw: u64 = id.into();
+            paths
+                .try_get(raw.checked_sub(1)? as u32)
+                .map(str::to_string)
+        };
+        std::mem::take(&mut self.graph)
+            .edges()
+            .filter_map(|(from, to)| Some((path(from)?, path(to)?)))
+            .collect()
+    }
+
+    /// Edges between the files of the map, e.g. imports or includes
+    pub fn graph(&self) -> &FileGraph<Id> {
+        &self.graph
+    }
+
+    /// Edges to record between the files of the map
+    ///
+    /// Edges follow their files when IDs are reassigned and are dropped with
+    /// them. Edges from or to unknown IDs are dropped on reassignment and not
+    /// persisted.
+    pub fn graph_mut(&mut self) -> &mut FileGraph<Id> {
+        &mut self.graph
+    }
+
+    /// Replace the graph, failing if an edge names an unknown ID
+    pub fn set_graph(&mut self, graph: FileGraph<Id>) -> Result<(), SourceFilesError> {
+        if let Some(id) = graph
+            .edges()
+            .flat_map(|(from, to)| [from, to])
+            .find(|&id| self.get_path(id).is_none())
         {
-            for (idx, entry) in self.files.iter().enumerate() {
-                let raw_id = (idx + 1) as u64;
-                let id = Id::try_from(raw_id).map_err(|_| "ID conversion failed")?;
-                let offsets = Self::compute_line_offsets(&entry.content);
-                self.line_offsets.insert(id, offsets);
+            return Err(SourceFilesError::UnknownFile { id: id.into() });
+        }
+        self.graph = graph;
+        Ok(())
+    }
+
+    /// Compute the line offsets of one file
+    ///
+    /// Rope-backed files find lines through their rope instead.
+    #[cfg(feature = "view")]
+    fn index_file(&mut self, id: Id) {
+        let raw: u64 = id.into();
+        let content = &self.files[raw as usize - 1].content;
+        if content.has_lazy_lines() {
+            self.line_offsets.remove(&id);
+            return;
+        }
+        let offsets = Self::compute_line_offsets(content.bytes(), self.line_length_hint);
+        self.line_offsets.insert(id, offsets);
+    }
+
+    /// Recompute the line offsets of every file
+    #[cfg(feature = "view")]
+    pub(crate) fn index_lines(&mut self) -> Result<(), SourceFilesError> {
+        self.line_offsets.clear();
+        for (idx, entry) in self.files.iter().enumerate() {
+            let raw_id = (idx + 1) as u64;
+            let id = Id::try_from(raw_id).map_err(|_| self.too_many_files())?;
+            if entry.content.has_lazy_lines() {
+                continue;
             }
+            let offsets = Self::compute_line_offsets(entry.content.bytes(), self.line_length_hint);
+            self.line_offsets.insert(id, offsets);
         }
+        #[cfg(feature = "rt-feedback")]
+        self.view_stats.reset(self.files.len());
         Ok(())
     }
+
+    /// View a span of a file, returning None for positions of another file
+    ///
+    /// Spans of rope-backed files crossing a chunk boundary make the file
+    /// contiguous first; [`SourceFilesMap::view_cow`] copies just the span.
+    ///
+    /// Empty files and zero-width spans, whose end column is one less than
+    /// their start column, behave like any other content and span:
+    ///
+    /// | Content   | Range of `position` | Position        | `view`        |
+    /// |-----------|---------------------|-----------------|---------------|
+    /// | `""`      | `0..0`              | `1:1-1:0`       | `Some(b"")`   |
+    /// | `""`      | `0..1`              | None            | -             |
+    /// | `"ab"`    | `1..1`              | `1:2-1:1`       | `Some(b"")`   |
+    /// | `"ab"`    | `2..2` (the end)    | `1:3-1:2`       | `Some(b"")`   |
+    /// | `"ab\n"`  | `3..3` (the end)    | `2:1-2:0`       | `Some(b"")`   |
+    /// | `"ab"`    | `2..1`              | None            | -             |
+    /// | any       | -                   | line 0          | None          |
+    ///
+    /// Line offsets of an empty file hold one empty line, as every file has
+    /// one line more than it has line breaks.
     #[cfg(feature = "view")]
     pub fn view(&self, id: Id, pos: &impl SourceFilePosition) -> Option<&[u8]> {
-        let content = self.get_content(id)?;
-        let line_offsets = self.line_offsets.get(&id)?;
+        self.try_view(id, pos).ok()
+    }
+
+    /// Variant of [`SourceFilesMap::view`] telling why a span does not resolve
+    #[cfg(feature = "view")]
+    pub fn try_view(
+        &self,
+        id: Id,
+        pos: &impl SourceFilePosition,
+    ) -> Result<&[u8], SourceFilesError> {
+        let view = self
+            .resolve_view(id, pos)
+            .map(|(content, range)| content.contiguous_slice(range));
+        self.record_view(id, view.as_ref().ok().map(|view| view.len()));
+        view
+    }
+
+    /// Variant of [`SourceFilesMap::view`] reading the end column of `pos`
+    /// with `semantics`
+    #[cfg(feature = "view")]
+    pub fn view_as(
+        &self,
+        id: Id,
+        pos: &impl SourceFilePosition,
+        semantics: RangeSemantics,
+    ) -> Option<&[u8]> {
+        match semantics {
+            RangeSemantics::Inclusive => self.view(id, pos),
+            RangeSemantics::Exclusive => {
+                let raw_id: u64 = id.into();
+                if pos.source_file_id().is_some_and(|found| found != raw_id) {
+                    return None;
+                }
+                let relative = RelativePosition::new(
+                    pos.start_line(),
+                    pos.start_column(),
+                    pos.end_line(),
+                    pos.end_column(),
+                );
+                self.view(id, &relative.to_inclusive()?)
+            }
+        }
+    }
+
+    /// View a span of a file without requiring contiguous storage
+    ///
+    /// Borrowed unless the span crosses chunks of a rope-backed file, in which
+    /// case only the span is copied.
+    #[cfg(feature = "view")]
+    pub fn view_cow(&self, id: Id, pos: &impl SourceFilePosition) -> Option<Cow<'_, [u8]>> {
+        let view = self
+            .resolve_view(id, pos)
+            .ok()
+            .map(|(content, range)| content.slice(range));
+        self.record_view(id, view.as_ref().map(|view| view.len()));
+        view
+    }
+
+    /// View a span of a file as [`bytes::Bytes`], outliving the borrow of the map
+    ///
+    /// A slice of the shared buffer for [`Storage::Bytes`] files, so no bytes
+    /// are copied; spans of other storages are copied.
+    #[cfg(all(feature = "view", feature = "bytes"))]
+    pub fn view_bytes(&self, id: Id, pos: &impl SourceFilePosition) -> Option<bytes::Bytes> {
+        let view = self
+            .resolve_view(id, pos)
+            .ok()
+            .map(|(content, range)| content.shared(range));
+        self.record_view(id, view.as_ref().map(bytes::Bytes::len));
+        view
+    }
+
+    #[cfg(feature = "view")]
+    fn record_view(&self, id: Id, len: Option<usize>) {
+        #[cfg(feature = "rt-feedback")]
+        if let Some(len) = len {
+            let raw_id: u64 = id.into();
+            self.view_stats.record(raw_id as usize - 1, len);
+        }
+        self.observers.each(|observer| observer.on_view(id, len));
+    }
+
+    /// Content and byte range of a span, if it resolves in the file
+    #[cfg(feature = "view")]
+    fn resolve_view(
+        &self,
+        id: Id,
+        pos: &impl SourceFilePosition,
+    ) -> Result<(&Content, Range<usize>), SourceFilesError> {
+        let raw_id: u64 = id.into();
+        if let Some(pos_id) = pos.source_file_id()
+            && pos_id != raw_id
+        {
+            return Err(SourceFilesError::FileMismatch {
+                expected: raw_id,
+                found: pos_id,
+            });
+        }
+        let content = self
+            .entry(id)
+            .ok_or(SourceFilesError::UnknownFile { id: raw_id })?;
+        let invalid = || SourceFilesError::InvalidPosition {
+            id: raw_id,
+            position: RelativePosition::new(
+                pos.start_line(),
+                pos.start_column(),
+                pos.end_line(),
+                pos.end_column(),
+            ),
+        };
 
         let start_line = pos.start_line() as usize;
         let start_col = pos.start_column() as usize;
@@ -195,40 +1211,402 @@ impl<Id: FileId> SourceFilesMap<Id> {
 
         // Early validation
         if start_line == 0 || end_line == 0 || start_line > end_line {
-            return None;
+            return Err(invalid());
         }
 
-        // Compute line ranges more efficiently
-        let start_range = line_offsets.get_line_range(start_line)?;
-        let end_range = line_offsets.get_line_range(end_line)?;
+        let start_byte = self
+            .line_start(id, content, start_line)
+            .ok_or_else(invalid)?
+            + start_col.saturating_sub(1);
+        let end_byte = self.line_start(id, content, end_line).ok_or_else(invalid)? + end_col;
 
-        let start_byte = start_range.0 + start_col.saturating_sub(1);
-        let end_byte = end_range.0 + end_col;
+        // Zero-width spans resolve anywhere up to the end, empty files included
+        if start_byte > end_byte || end_byte > content.len() {
+            return Err(invalid());
+        }
 
-        // Bounds checking
-        if start_byte >= content.len() || end_byte > content.len() || start_byte > end_byte {
-            return None;
+        Ok((content, start_byte..end_byte))
+    }
+
+    /// Byte offset where a 1-based line of a file starts
+    #[cfg(feature = "view")]
+    fn line_start(&self, id: Id, content: &Content, line: usize) -> Option<usize> {
+        #[cfg(feature = "edit")]
+        if let Some(rope) = content.as_rope() {
+            return rope.line_start(line);
         }
+        let _ = content;
+        Some(self.line_offsets.get(&id)?.get_line_range(line)?.0)
+    }
+
+    /// View a span as text, or [`BINARY_PLACEHOLDER`] for binary files
+    ///
+    /// Invalid UTF-8 in text files is replaced lossily.
+    #[cfg(feature = "view")]
+    pub fn view_str(&self, id: Id, pos: &impl SourceFilePosition) -> Option<Cow<'_, str>> {
+        let view = self.view_cow(id, pos)?;
+        Some(if self.is_binary(id) {
+            Cow::Borrowed(BINARY_PLACEHOLDER)
+        } else {
+            match view {
+                Cow::Borrowed(bytes) => String::from_utf8_lossy(bytes),
+                Cow::Owned(bytes) => Cow::Owned(
+                    String::from_utf8(bytes)
+                        .unwrap_or_else(|error| String::from_utf8_lossy(error.as_bytes()).into()),
+                ),
+            }
+        })
+    }
+
+    /// View a span relative to the given file
+    #[cfg(feature = "view")]
+    pub fn view_relative(&self, id: Id, pos: &RelativePosition) -> Option<&[u8]> {
+        self.view(id, pos)
+    }
 
-        Some(&content[start_byte..end_byte])
+    /// Get a handle pinning queries to one file (returns None for invalid IDs)
+    pub fn file(&self, id: Id) -> Option<FileRef<'_, Id>> {
+        FileRef::new(self, id)
     }
+
+    /// Slice a file's content by byte range (None for invalid IDs or ranges)
+    pub fn bytes(&self, id: Id, range: Range<usize>) -> Option<&[u8]> {
+        self.get_content(id)?.get(range)
+    }
+
+    /// Position covering a byte range of a file, see [`FileRef::position`]
+    #[cfg(feature = "view")]
+    pub fn position(&self, id: Id, range: Range<usize>) -> Option<AbsolutePosition<Id>> {
+        self.file(id)?.position(range)
+    }
+
+    /// Zero-width position just past the last byte of a file
+    ///
+    /// On the empty line after a trailing newline, or at the end of the last
+    /// line otherwise; viewing it yields an empty span, and replacing it
+    /// appends to the file. None for invalid IDs or when the end of the file
+    /// is past what positions encode.
+    #[cfg(feature = "view")]
+    pub fn eof_position(&self, id: Id) -> Option<AbsolutePosition<Id>> {
+        self.file(id)?.eof_position()
+    }
+
+    /// Variant of [`SourceFilesMap::position`] with the end column in `semantics`
+    #[cfg(feature = "view")]
+    pub fn position_as(
+        &self,
+        id: Id,
+        range: Range<usize>,
+        semantics: RangeSemantics,
+    ) -> Option<AbsolutePosition<Id>> {
+        let pos = self.position(id, range)?;
+        match semantics {
+            RangeSemantics::Inclusive => Some(pos),
+            RangeSemantics::Exclusive => pos.to_exclusive(),
+        }
+    }
+
+    /// Line offsets of a file, once computed
+    #[cfg(feature = "view")]
+    pub(crate) fn line_offsets(&self, id: Id) -> Option<&CompactLineOffsets> {
+        self.line_offsets
+            .get(&id)
+            .or_else(|| self.entry(id)?.lazy_lines())
+    }
+
+    /// View counters of a file (None for invalid IDs)
+    #[cfg(all(feature = "view", feature = "rt-feedback"))]
+    pub fn view_stats(&self, id: Id) -> Option<FileViewStats> {
+        let raw_id: u64 = id.into();
+        self.view_stats.get(raw_id.checked_sub(1)? as usize)
+    }
+
+    /// The `n` most viewed files since the last finalize, by hits then bytes
+    #[cfg(all(feature = "view", feature = "rt-feedback"))]
+    pub fn hot_files(&self, n: usize) -> Vec<(Id, FileViewStats)> {
+        self.view_stats
+            .hottest(n)
+            .into_iter()
+            .filter_map(|(index, stats)| Some((Id::try_from(index as u64 + 1).ok()?, stats)))
+            .collect()
+    }
+
+    /// Files whose spans could not all be encoded since the last finalize,
+    /// most truncated first
+    #[cfg(all(feature = "view", feature = "rt-feedback"))]
+    pub fn truncation_report(&self) -> Vec<(Id, FileTruncations)> {
+        self.view_stats
+            .truncations()
+            .into_iter()
+            .filter_map(|(index, counts)| Some((Id::try_from(index as u64 + 1).ok()?, counts)))
+            .collect()
+    }
+
+    fn entry(&self, id: Id) -> Option<&Content> {
+        let raw_id: u64 = id.into();
+        let index = raw_id.checked_sub(1)? as usize;
+        self.files.get(index).map(|e| &e.content)
+    }
+
     /// Get immutable view of file content
+    ///
+    /// Rope-backed files are copied into a contiguous buffer on the first call
+    /// after an edit.
     pub fn get_content(&self, id: Id) -> Option<&[u8]> {
+        self.entry(id).map(Content::bytes)
+    }
+
+    /// Contiguous pieces of a file's content, in order (none for invalid IDs)
+    ///
+    /// A single piece for contiguous storage; rope-backed files yield their
+    /// chunks without building a contiguous copy.
+    pub fn content_chunks(&self, id: Id) -> ContentChunks<'_> {
+        self.entry(id)
+            .map_or_else(ContentChunks::empty, Content::chunks)
+    }
+
+    /// A file's content as [`bytes::Bytes`] (None for invalid IDs)
+    ///
+    /// Shares the buffer of [`Storage::Bytes`] files; other storages are copied.
+    #[cfg(feature = "bytes")]
+    pub fn content_bytes(&self, id: Id) -> Option<bytes::Bytes> {
+        self.entry(id)
+            .map(|content| content.shared(0..content.len()))
+    }
+
+    /// Shared handle on a file's content (None for invalid IDs)
+    ///
+    /// The handle stays valid after the map is edited or dropped, so
+    /// background workers can hold it across threads. The content is copied
+    /// on the first call and the same handle is returned until the file is
+    /// edited.
+    pub fn content_arc(&self, id: Id) -> Option<Arc<[u8]>> {
         let raw_id: u64 = id.into();
-        let index = (raw_id - 1) as usize;
-        self.files.get(index).map(|e| e.content.as_slice())
+        let entry = self.files.get(raw_id.checked_sub(1)? as usize)?;
+        Some(Arc::clone(
+            entry
+                .shared
+                .get_or_init(|| Arc::from(entry.content.bytes())),
+        ))
+    }
+
+    /// How a file's content is stored (None for invalid IDs)
+    pub fn storage(&self, id: Id) -> Option<Storage> {
+        self.entry(id).map(Content::storage)
+    }
+
+    /// Move a file's content to another storage (false for invalid IDs)
+    ///
+    /// Use [`Storage::Rope`] for files edited keystroke by keystroke through
+    /// [`SourceFilesMap::replace_range`].
+    pub fn set_storage(&mut self, id: Id, storage: Storage) -> bool {
+        #[cfg(feature = "view")]
+        let indexed = self
+            .get_path(id)
+            .is_some_and(|path| self.path_to_id.get(&self.paths, path) == Some(id));
+        let raw: u64 = id.into();
+        let Some(entry) = raw
+            .checked_sub(1)
+            .and_then(|index| self.files.get_mut(index as usize))
+        else {
+            return false;
+        };
+        entry.content.set_storage(storage);
+        #[cfg(feature = "view")]
+        if indexed {
+            self.index_file(id);
+        }
+        true
+    }
+
+    /// Replace a byte range of a file's content (false for invalid IDs or ranges)
+    ///
+    /// Line offsets are recomputed and observers see [`MapObserver::on_edit`].
+    /// IDs and the epoch are kept; positions past the range move, see
+    /// [`EditRemap`](crate::EditRemap) to carry them over. With
+    /// [`Storage::Rope`] the edit is O(log n) and line offsets are left to be
+    /// found through the rope.
+    pub fn replace_range(&mut self, id: Id, range: Range<usize>, replacement: &[u8]) -> bool {
+        self.edit_entry(id, |content| content.replace(range, replacement))
+    }
+
+    /// Whether a file's content looks binary, sniffed whenever it is set
+    ///
+    /// A file is binary when its first 8000 bytes hold a NUL byte or are
+    /// mostly invalid UTF-8. False for unknown IDs.
+    pub fn is_binary(&self, id: Id) -> bool {
+        let raw: u64 = id.into();
+        raw.checked_sub(1)
+            .and_then(|index| self.files.get(index as usize))
+            .is_some_and(|entry| entry.binary)
+    }
+
+    /// Whether a file's content changed since it was added or last flushed
+    pub fn is_edited(&self, id: Id) -> bool {
+        self.original_content(id).is_some()
+    }
+
+    /// IDs of the edited files, in ID order
+    pub fn edited_files(&self) -> Vec<Id> {
+        let mut ids: Vec<Id> = self
+            .originals
+            .keys()
+            .filter_map(|path| self.get_id(path))
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Content of an edited file before its first edit (None when unedited)
+    pub fn original_content(&self, id: Id) -> Option<&[u8]> {
+        self.originals.get(self.get_path(id)?).map(Vec::as_slice)
+    }
+
+    /// Disk state a file was last read in, if it was read from disk
+    pub(crate) fn disk_stamp(&self, path: &str) -> Option<DiskStamp> {
+        self.stamps.get(path).copied()
+    }
+
+    pub(crate) fn set_disk_stamp(&mut self, path: String, stamp: DiskStamp) {
+        self.stamps.insert(path, stamp);
+    }
+
+    /// Accept the current content of a file as its original
+    pub(crate) fn mark_clean(&mut self, id: Id) {
+        if let Some(path) = self.get_path(id) {
+            let path = path.to_string();
+            self.originals.remove(&path);
+        }
+    }
+
+    /// Set a file's content as loaded from elsewhere, not as an edit
+    ///
+    /// With `reindex` false the line offsets are kept, which is only right
+    /// when they were computed for the same content.
+    #[cfg_attr(not(feature = "view"), allow(unused_variables))]
+    pub(crate) fn load_content(&mut self, id: Id, content: Vec<u8>, reindex: bool) {
+        let raw: u64 = id.into();
+        if let Some(entry) = raw
+            .checked_sub(1)
+            .and_then(|index| self.files.get_mut(index as usize))
+        {
+            let storage = entry.content.storage();
+            entry.set_content(Content::new(content, storage));
+            // Offsets recorded when the file was added describe other content
+            entry.normalized = None;
+            #[cfg(feature = "view")]
+            if reindex {
+                self.index_file(id);
+            }
+        }
+    }
+
+    /// Run `edit` on a file's content, then reindex it and notify observers
+    /// if it returned true
+    pub(crate) fn edit_content(&mut self, id: Id, edit: impl FnOnce(&mut Vec<u8>) -> bool) -> bool {
+        self.edit_entry(id, |content| content.edit(edit))
+    }
+
+    fn edit_entry(&mut self, id: Id, edit: impl FnOnce(&mut Content) -> bool) -> bool {
+        let raw: u64 = id.into();
+        let Some(entry) = raw
+            .checked_sub(1)
+            .and_then(|index| self.files.get_mut(index as usize))
+        else {
+            return false;
+        };
+        let path = self.paths.get(entry.path);
+        let old_size = entry.content.len();
+        let before = (!self.originals.contains_key(path)).then(|| entry.content.bytes().to_vec());
+        if !edit(&mut entry.content) {
+            return false;
+        }
+        entry.binary = entry.content.sniff();
+        entry.shared = OnceLock::new();
+        if let Some(before) = before {
+            self.originals.insert(path.to_string(), before);
+        }
+        // Edits are made to the normalized content, which the offsets recorded
+        // when the file was added no longer describe
+        if let Some(normalized) = &mut entry.normalized {
+            normalized.offsets = None;
+        }
+        let new_size = entry.content.len();
+        #[cfg(feature = "view")]
+        if self.line_offsets.contains_key(&id) {
+            self.index_file(id);
+        }
+        self.observers
+            .each(|observer| observer.on_edit(id, old_size, new_size));
+        true
+    }
+
+    /// Stable 64-bit hash (XXH3) of a file's content (returns None for invalid IDs)
+    pub fn content_hash(&self, id: Id) -> Option<u64> {
+        let content = self.entry(id)?;
+        let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+        for chunk in content.chunks() {
+            hasher.update(chunk);
+        }
+        Some(hasher.digest())
+    }
+
+    /// Stable 128-bit hash (XXH3) of the whole map, as a cache key for
+    /// artifacts derived from it
+    ///
+    /// Covers the ID width, the load options and every path with the hash of
+    /// its content, in ID order: equal fingerprints mean the same files with
+    /// the same IDs and content. None while files are waiting for `finalize`
+    /// to get an ID.
+    pub fn fingerprint(&self) -> Option<u128> {
+        if self.path_to_id.len() != self.files.len() {
+            return None;
+        }
+        let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+        hasher.update(&Id::FILE_ID_BITS.to_le_bytes());
+        let options = &self.load_options;
+        let max_file_size = options.max_file_size.map_or(u64::MAX, |size| size as u64);
+        hasher.update(&max_file_size.to_le_bytes());
+        let normalize = &options.normalize;
+        let tab_width = normalize.tab_width.map_or(0, |width| width as u64);
+        hasher.update(&tab_width.to_le_bytes());
+        hasher.update(&[
+            options.skip_binary as u8,
+            normalize.crlf_to_lf as u8,
+            normalize.trim_trailing_whitespace as u8,
+        ]);
+        hasher.update(&(self.files.len() as u64).to_le_bytes());
+        for (id, path, _) in self.iter() {
+            hasher.update(&(path.len() as u64).to_le_bytes());
+            hasher.update(path.as_bytes());
+            hasher.update(&self.content_hash(id)?.to_le_bytes());
+        }
+        Some(hasher.digest128())
+    }
+
+    /// Number of lines in a file, counting a trailing newline as starting an empty line
+    pub fn line_count(&self, id: Id) -> Option<usize> {
+        self.entry(id).map(Content::line_count)
     }
 
     /// Get file ID for a path (returns None for unknown files)
     pub fn get_id(&self, path: &str) -> Option<Id> {
-        self.path_to_id.get(path).copied()
+        self.path_to_id.get(&self.paths, path)
     }
 
     /// Get file path for an ID (returns None for invalid IDs)
     pub fn get_path(&self, id: Id) -> Option<&str> {
         let raw_id: u64 = id.into();
-        let index = (raw_id - 1) as usize;
-        self.files.get(index).map(|s| s.path.as_str())
+        let index = raw_id.checked_sub(1)? as usize;
+        self.paths.try_get(index as u32)
+    }
+
+    /// Iterate over `(id, path, content)` of every file, in ID order
+    pub fn iter(&self) -> impl Iterator<Item = (Id, &str, &[u8])> {
+        self.files.iter().enumerate().filter_map(|(idx, entry)| {
+            let id = Id::try_from(idx as u64 + 1).ok()?;
+            Some((id, self.paths.get(entry.path), entry.content.bytes()))
+        })
     }
 
     /// Get total number of registered files
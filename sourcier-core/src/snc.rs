@@ -0,0 +1,20 @@
+// Synchronization primitives of the concurrent code paths
+//
+// Built with `--cfg sourcier_loom`, these are loom's instrumented versions,
+// so the models in the `loom_models` tests explore every interleaving of
+// that code. New concurrent code imports its primitives from here to be
+// covered by the same harness, run as shown in the README.
+
+#[cfg(sourcier_loom)]
+pub(crate) use loom::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(sourcier_loom)]
+pub(crate) use loom::sync::{Arc, Condvar, Mutex};
+#[cfg(sourcier_loom)]
+pub(crate) use loom::thread;
+
+#[cfg(not(sourcier_loom))]
+pub(crate) use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(not(sourcier_loom))]
+pub(crate) use std::sync::{Arc, Condvar, Mutex};
+#[cfg(not(sourcier_loom))]
+pub(crate) use std::thread;
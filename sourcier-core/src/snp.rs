@@ -0,0 +1,102 @@
+//! Layout math of source snippets: line number gutters and underlines
+//!
+//! Independent of any output, so diagnostics drawn by other means than
+//! plain text, e.g. in a TUI, line up the same way. Display columns count
+//! one per character and expand tabs to the next tab stop; wide characters
+//! count as one column too.
+
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+
+/// Tab stop distance used when none is given
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// Columns taken by the numbers of lines up to `last_line`, e.g. 3 for 120
+pub fn gutter_width(last_line: usize) -> usize {
+    last_line.max(1).ilog10() as usize + 1
+}
+
+/// Display columns of `text`, starting on a tab stop
+///
+/// Bytes that are not valid UTF-8 take one column each.
+pub fn display_width(text: &[u8], tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+    let mut width = 0;
+    for chunk in text.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            width += match c {
+                '\t' => tab_width - width % tab_width,
+                _ => 1,
+            };
+        }
+        width += chunk.invalid().len();
+    }
+    width
+}
+
+/// Part of one line under a span, in display columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnderlineSegment {
+    /// 1-based line
+    pub line: usize,
+    /// 0-based display column the underline starts at
+    pub start: usize,
+    /// Display columns under the span, 0 for an empty span
+    pub width: usize,
+}
+
+impl UnderlineSegment {
+    /// Carets to draw: the width, but at least one so empty spans show
+    pub fn carets(&self) -> usize {
+        self.width.max(1)
+    }
+}
+
+/// Underline of `span` on `line`, whose text without line break is `text`
+///
+/// A trailing `\r` is not underlined. None when the span does not reach the
+/// line.
+pub fn underline_segment(
+    text: &[u8],
+    line: usize,
+    span: &impl SourceFilePosition,
+    tab_width: usize,
+) -> Option<UnderlineSegment> {
+    let (first, last) = (span.start_line() as usize, span.end_line() as usize);
+    if line < first || line > last {
+        return None;
+    }
+    let text = text.strip_suffix(b"\r").unwrap_or(text);
+    let start = match line == first {
+        true => (span.start_column() as usize).saturating_sub(1),
+        false => 0,
+    }
+    .min(text.len());
+    let end = match line == last {
+        true => span.end_column() as usize,
+        false => text.len(),
+    }
+    .clamp(start, text.len());
+    let start_col = display_width(&text[..start], tab_width);
+    Some(UnderlineSegment {
+        line,
+        start: start_col,
+        width: display_width(&text[..end], tab_width) - start_col,
+    })
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Underline of `pos` on every line it covers, see [`underline_segment`]
+    pub fn underline_segments(
+        &self,
+        pos: &AbsolutePosition<Id>,
+        tab_width: usize,
+    ) -> Vec<UnderlineSegment> {
+        let Some(file) = self.file(pos.file_id()) else {
+            return Vec::new();
+        };
+        (pos.start_line() as usize..=pos.end_line() as usize)
+            .filter_map(|line| underline_segment(file.line(line)?, line, pos, tab_width))
+            .collect()
+    }
+}
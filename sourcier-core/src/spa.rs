@@ -0,0 +1,92 @@
+use crate::err::SourceFilesError;
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+
+/// Fold of child positions into the tightest span enclosing them all
+///
+/// Feed it the spans of a node's children in any order and read the node's
+/// span from [`SpanAccumulator::span`]. Every span must belong to the same
+/// file: one from another file is rejected and leaves the accumulator as it
+/// was.
+///
+/// ```
+/// use sourcier_core::{AbsolutePosition, SpanAccumulator};
+///
+/// let mut span = SpanAccumulator::<u8>::new();
+/// span.push(&AbsolutePosition::new(1, 2, 5, 2, 9))?;
+/// span.push(&AbsolutePosition::new(1, 1, 3, 1, 7))?;
+/// assert_eq!(span.span(), Some(AbsolutePosition::new(1, 1, 3, 2, 9)));
+/// assert!(span.push(&AbsolutePosition::new(2, 1, 1, 1, 1)).is_err());
+/// # Ok::<(), sourcier_core::SourceFilesError>(())
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanAccumulator<Id: FileId> {
+    span: Option<AbsolutePosition<Id>>,
+}
+
+impl<Id: FileId> Default for SpanAccumulator<Id> {
+    fn default() -> Self {
+        Self { span: None }
+    }
+}
+
+impl<Id: FileId> SpanAccumulator<Id> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Grow the span to cover `pos`, failing if it is in another file
+    pub fn push(&mut self, pos: &AbsolutePosition<Id>) -> Result<(), SourceFilesError> {
+        let Some(span) = self.span else {
+            self.span = Some(*pos);
+            return Ok(());
+        };
+        if span.file_id() != pos.file_id() {
+            return Err(SourceFilesError::FileMismatch {
+                expected: span.file_id().into(),
+                found: pos.file_id().into(),
+            });
+        }
+        let mut span = span;
+        if (pos.start_line(), pos.start_column()) < (span.start_line(), span.start_column()) {
+            span = span
+                .with_start_line(pos.start_line())
+                .with_start_column(pos.start_column());
+        }
+        if (pos.end_line(), pos.end_column()) > (span.end_line(), span.end_column()) {
+            span = span.widen_to(pos);
+        }
+        self.span = Some(span);
+        Ok(())
+    }
+
+    /// Builder-style variant of [`SpanAccumulator::push`]
+    pub fn with(mut self, pos: &AbsolutePosition<Id>) -> Result<Self, SourceFilesError> {
+        self.push(pos)?;
+        Ok(self)
+    }
+
+    /// Enclosing span of every position pushed so far, None before the first
+    pub fn span(&self) -> Option<AbsolutePosition<Id>> {
+        self.span
+    }
+
+    /// File of the positions pushed so far
+    pub fn file_id(&self) -> Option<Id> {
+        self.span.map(|span| span.file_id())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.span.is_none()
+    }
+
+    /// Tightest span enclosing `positions`, None when there are none
+    pub fn enclose<'p>(
+        positions: impl IntoIterator<Item = &'p AbsolutePosition<Id>>,
+    ) -> Result<Option<AbsolutePosition<Id>>, SourceFilesError> {
+        let mut span = Self::new();
+        for pos in positions {
+            span.push(pos)?;
+        }
+        Ok(span.span())
+    }
+}
@@ -0,0 +1,366 @@
+//! SARIF 2.1.0 logs of diagnostics, as ingested by GitHub code scanning
+//!
+//! One run is written per log, with a rule for every distinct diagnostic code.
+//! Regions use 1-based lines and UTF-16 columns (the SARIF default column
+//! kind) and carry the spanned text as a snippet. Secondary labels become
+//! related locations and the suggested fixes a single SARIF fix. Spans of files
+//! missing from the map are left out of the locations.
+
+use crate::dgn::{Diagnostic, DiagnosticBag, Fix, Severity};
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+use crate::wsp::{Namespace, SourceWorkspace};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+
+/// Schema the written logs declare
+pub const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// Analysis tool named in the log
+///
+/// `rules` is filled from the diagnostic codes when the log is built.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifDriver {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub information_uri: Option<String>,
+    #[serde(default)]
+    pub rules: Vec<SarifRule>,
+}
+
+impl SarifDriver {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            version: None,
+            information_uri: None,
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn with_version(mut self, version: impl Into<String>) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    pub fn with_information_uri(mut self, uri: impl Into<String>) -> Self {
+        self.information_uri = Some(uri.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SarifRule {
+    pub id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SarifTool {
+    pub driver: SarifDriver,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SarifText {
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifRegion {
+    pub start_line: u32,
+    pub start_column: u32,
+    pub end_line: u32,
+    pub end_column: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<SarifText>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SarifArtifactLocation {
+    pub uri: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifPhysicalLocation {
+    pub artifact_location: SarifArtifactLocation,
+    pub region: SarifRegion,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifLocation {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<usize>,
+    pub physical_location: SarifPhysicalLocation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<SarifText>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifReplacement {
+    pub deleted_region: SarifRegion,
+    pub inserted_content: SarifText,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifArtifactChange {
+    pub artifact_location: SarifArtifactLocation,
+    pub replacements: Vec<SarifReplacement>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifFix {
+    pub artifact_changes: Vec<SarifArtifactChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifResult {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule_id: Option<String>,
+    pub level: String,
+    pub message: SarifText,
+    pub locations: Vec<SarifLocation>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub related_locations: Vec<SarifLocation>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fixes: Vec<SarifFix>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SarifRun {
+    pub tool: SarifTool,
+    pub column_kind: String,
+    pub results: Vec<SarifResult>,
+}
+
+/// The SARIF log document
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SarifLog {
+    #[serde(rename = "$schema")]
+    pub schema: String,
+    pub version: String,
+    pub runs: Vec<SarifRun>,
+}
+
+fn level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Note | Severity::Help => "note",
+    }
+}
+
+/// Region of a span with UTF-16 columns and its text as snippet
+fn region<Id: FileId>(
+    map: &SourceFilesMap<Id>,
+    pos: &AbsolutePosition<Id>,
+) -> Option<(String, SarifRegion)> {
+    let file = map.file(pos.file_id())?;
+    let start_line = pos.start_line() as usize;
+    let end_line = pos.end_line() as usize;
+    let start_col =
+        file.utf16_column(start_line, (pos.start_column() as usize).saturating_sub(1))?;
+    let end_col = file.utf16_column(end_line, pos.end_column() as usize)?;
+    // Binary content has no meaningful snippet
+    let snippet = file
+        .view_absolute(pos)
+        .ok()
+        .flatten()
+        .filter(|_| !file.is_binary())
+        .map(|text| SarifText {
+            text: String::from_utf8_lossy(text).into_owned(),
+        });
+    Some((
+        file.path().to_string(),
+        SarifRegion {
+            start_line: start_line as u32,
+            start_column: start_col as u32 + 1,
+            end_line: end_line as u32,
+            end_column: end_col as u32 + 1,
+            snippet,
+        },
+    ))
+}
+
+fn location<Id: FileId>(
+    map: &SourceFilesMap<Id>,
+    pos: &AbsolutePosition<Id>,
+    id: Option<usize>,
+    message: Option<&str>,
+) -> Option<SarifLocation> {
+    let (uri, region) = region(map, pos)?;
+    Some(SarifLocation {
+        id,
+        physical_location: SarifPhysicalLocation {
+            artifact_location: SarifArtifactLocation { uri },
+            region,
+        },
+        message: message.map(|text| SarifText {
+            text: text.to_string(),
+        }),
+    })
+}
+
+/// One SARIF fix holding every replacement, grouped by file
+fn fix<Id: FileId>(map: &SourceFilesMap<Id>, fixes: &[Fix<Id>]) -> Option<SarifFix> {
+    let mut artifact_changes: Vec<SarifArtifactChange> = Vec::new();
+    for fix in fixes {
+        let (uri, mut deleted_region) = region(map, &fix.span)?;
+        deleted_region.snippet = None;
+        let replacement = SarifReplacement {
+            deleted_region,
+            inserted_content: SarifText {
+                text: fix.replacement.clone(),
+            },
+        };
+        match artifact_changes
+            .iter_mut()
+            .find(|change| change.artifact_location.uri == uri)
+        {
+            Some(change) => change.replacements.push(replacement),
+            None => artifact_changes.push(SarifArtifactChange {
+                artifact_location: SarifArtifactLocation { uri },
+                replacements: vec![replacement],
+            }),
+        }
+    }
+    (!artifact_changes.is_empty()).then_some(SarifFix { artifact_changes })
+}
+
+/// Log with one run of `driver`, with a rule for every code of `diagnostics`
+fn log<'a, Id: FileId + 'a>(
+    mut driver: SarifDriver,
+    diagnostics: impl Iterator<Item = &'a Diagnostic<Id>>,
+    results: Vec<SarifResult>,
+) -> SarifLog {
+    for code in diagnostics.filter_map(|d| d.code.as_deref()) {
+        if !driver.rules.iter().any(|rule| rule.id == code) {
+            driver.rules.push(SarifRule {
+                id: code.to_string(),
+            });
+        }
+    }
+    SarifLog {
+        schema: SARIF_SCHEMA.to_string(),
+        version: "2.1.0".to_string(),
+        runs: vec![SarifRun {
+            tool: SarifTool { driver },
+            column_kind: "utf16CodeUnits".to_string(),
+            results,
+        }],
+    }
+}
+
+impl<Id: FileId> Diagnostic<Id> {
+    /// SARIF result of this diagnostic, resolving spans against `map`
+    pub fn to_sarif(&self, map: &SourceFilesMap<Id>) -> SarifResult {
+        SarifResult {
+            rule_id: self.code.clone(),
+            level: level(self.severity).to_string(),
+            message: SarifText {
+                text: self.message.clone(),
+            },
+            locations: location(map, &self.primary, None, None)
+                .into_iter()
+                .collect(),
+            related_locations: self
+                .labels
+                .iter()
+                .enumerate()
+                .filter_map(|(i, label)| location(map, &label.pos, Some(i), Some(&label.message)))
+                .collect(),
+            fixes: fix(map, &self.fixes).into_iter().collect(),
+        }
+    }
+}
+
+impl<Id: FileId> DiagnosticBag<Id> {
+    /// SARIF log with one run of `driver` holding every diagnostic
+    pub fn to_sarif(&self, map: &SourceFilesMap<Id>, driver: SarifDriver) -> SarifLog {
+        log(
+            driver,
+            self.iter(),
+            self.iter().map(|d| d.to_sarif(map)).collect(),
+        )
+    }
+
+    /// Write the SARIF log as JSON
+    pub fn write_sarif(
+        &self,
+        writer: impl Write,
+        map: &SourceFilesMap<Id>,
+        driver: SarifDriver,
+    ) -> io::Result<()> {
+        serde_json::to_writer_pretty(writer, &self.to_sarif(map, driver))?;
+        Ok(())
+    }
+}
+
+impl<Id: FileId> SourceWorkspace<Id> {
+    /// SARIF log with one run of `driver` holding the diagnostics of every
+    /// namespace
+    ///
+    /// URIs are prefixed with the namespace name as a directory, so maps
+    /// named after their package root report workspace-relative paths.
+    /// Diagnostics of unknown namespaces are left out.
+    pub fn to_sarif<'a>(
+        &self,
+        diagnostics: impl IntoIterator<Item = (Namespace, &'a DiagnosticBag<Id>)>,
+        driver: SarifDriver,
+    ) -> SarifLog
+    where
+        Id: 'a,
+    {
+        let bags: Vec<_> = diagnostics
+            .into_iter()
+            .filter_map(|(namespace, bag)| Some((self.name(namespace)?, self.map(namespace)?, bag)))
+            .collect();
+        let results = bags
+            .iter()
+            .flat_map(|(name, map, bag)| {
+                bag.iter().map(move |d| {
+                    let mut result = d.to_sarif(map);
+                    prefix_uris(&mut result, name);
+                    result
+                })
+            })
+            .collect();
+        log(
+            driver,
+            bags.iter().flat_map(|(_, _, bag)| bag.iter()),
+            results,
+        )
+    }
+}
+
+fn prefix_uris(result: &mut SarifResult, name: &str) {
+    if name.is_empty() {
+        return;
+    }
+    let prefix = |location: &mut SarifArtifactLocation| {
+        location.uri = format!("{}/{}", name.trim_end_matches('/'), location.uri);
+    };
+    for location in result
+        .locations
+        .iter_mut()
+        .chain(&mut result.related_locations)
+    {
+        prefix(&mut location.physical_location.artifact_location);
+    }
+    for change in result
+        .fixes
+        .iter_mut()
+        .flat_map(|fix| &mut fix.artifact_changes)
+    {
+        prefix(&mut change.artifact_location);
+    }
+}
@@ -0,0 +1,35 @@
+/// Embed files at compile time as a lazily finalized [`SourceFilesMap`](crate::SourceFilesMap)
+///
+/// Paths are relative to the manifest directory of the calling crate and end
+/// up in the map as written. The files are read by `include_bytes!`, so
+/// editing them triggers a rebuild; the map is built and finalized on first
+/// access. The ID type comes from the static's type:
+///
+/// ```
+/// use sourcier_core::{SourceFilesMap, static_files};
+/// use std::sync::LazyLock;
+///
+/// static SOURCES: LazyLock<SourceFilesMap<u8>> = static_files! { "Cargo.toml", "src/lib.rs" };
+///
+/// assert_eq!(SOURCES.get_id("src/lib.rs"), Some(2));
+/// ```
+///
+/// First access panics if the files do not fit the ID type.
+#[macro_export]
+macro_rules! static_files {
+    ($($path:literal),* $(,)?) => {
+        ::std::sync::LazyLock::new(|| {
+            let mut map = $crate::SourceFilesMap::new();
+            $(
+                map.add_file(
+                    ::std::string::String::from($path),
+                    ::std::include_bytes!(::std::concat!(::std::env!("CARGO_MANIFEST_DIR"), "/", $path))
+                        .to_vec(),
+                )
+                .expect("embedded files fit the file ID type");
+            )*
+            map.finalize().expect("embedded files fit the file ID type");
+            map
+        })
+    };
+}
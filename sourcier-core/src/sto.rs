@@ -0,0 +1,316 @@
+#[cfg(feature = "view")]
+use crate::clo::CompactLineOffsets;
+#[cfg(feature = "edit")]
+use crate::lod::SNIFF_LEN;
+use crate::lod::looks_binary;
+#[cfg(feature = "edit")]
+use crate::rop::{Chunks, Rope};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "view")]
+use std::borrow::Cow;
+use std::ops::Range;
+#[cfg(feature = "edit")]
+use std::sync::OnceLock;
+
+/// How the content of a file is held in memory
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Storage {
+    /// One contiguous buffer
+    #[default]
+    Contiguous,
+    /// A [`Rope`](crate::Rope), for files edited often: replacing a range is
+    /// O(log n), and contiguous content is rebuilt only when asked for
+    #[cfg(feature = "edit")]
+    Rope,
+    /// A [`bytes::Bytes`] buffer, whose slices can be handed out without
+    /// copying or borrowing the map, see
+    /// [`SourceFilesMap::content_bytes`](crate::SourceFilesMap::content_bytes)
+    #[cfg(feature = "bytes")]
+    Bytes,
+}
+
+/// Content of a file in its [`Storage`]
+#[derive(Debug, Clone)]
+pub(crate) enum Content {
+    Contiguous(Vec<u8>),
+    #[cfg(feature = "edit")]
+    Rope {
+        rope: Rope,
+        // Caches filled on demand and cleared by every edit
+        flat: OnceLock<Vec<u8>>,
+        #[cfg(feature = "view")]
+        lines: OnceLock<CompactLineOffsets>,
+    },
+    #[cfg(feature = "bytes")]
+    Bytes(bytes::Bytes),
+}
+
+impl Content {
+    pub(crate) fn new(bytes: Vec<u8>, storage: Storage) -> Self {
+        match storage {
+            Storage::Contiguous => Self::Contiguous(bytes),
+            #[cfg(feature = "edit")]
+            Storage::Rope => Self::rope(Rope::from(bytes)),
+            #[cfg(feature = "bytes")]
+            Storage::Bytes => Self::Bytes(bytes.into()),
+        }
+    }
+
+    #[cfg(feature = "edit")]
+    fn rope(rope: Rope) -> Self {
+        Self::Rope {
+            rope,
+            flat: OnceLock::new(),
+            #[cfg(feature = "view")]
+            lines: OnceLock::new(),
+        }
+    }
+
+    pub(crate) fn storage(&self) -> Storage {
+        match self {
+            Self::Contiguous(_) => Storage::Contiguous,
+            #[cfg(feature = "edit")]
+            Self::Rope { .. } => Storage::Rope,
+            #[cfg(feature = "bytes")]
+            Self::Bytes(_) => Storage::Bytes,
+        }
+    }
+
+    /// The rope of rope-backed content
+    #[cfg(all(feature = "edit", feature = "view"))]
+    pub(crate) fn as_rope(&self) -> Option<&Rope> {
+        match self {
+            Self::Rope { rope, .. } => Some(rope),
+            _ => None,
+        }
+    }
+
+    /// Contiguous bytes, built and cached on first use for a rope
+    pub(crate) fn bytes(&self) -> &[u8] {
+        match self {
+            Self::Contiguous(bytes) => bytes,
+            #[cfg(feature = "edit")]
+            Self::Rope { rope, flat, .. } => flat.get_or_init(|| rope.to_vec()),
+            #[cfg(feature = "bytes")]
+            Self::Bytes(bytes) => bytes,
+        }
+    }
+
+    /// A byte range within bounds, borrowed unless it crosses rope chunks
+    #[cfg(feature = "view")]
+    pub(crate) fn slice(&self, range: Range<usize>) -> Cow<'_, [u8]> {
+        match self {
+            Self::Contiguous(bytes) => Cow::Borrowed(&bytes[range]),
+            #[cfg(feature = "bytes")]
+            Self::Bytes(bytes) => Cow::Borrowed(&bytes[range]),
+            #[cfg(feature = "edit")]
+            Self::Rope { rope, flat, .. } => match flat.get() {
+                Some(bytes) => Cow::Borrowed(&bytes[range]),
+                None => rope.slice(range).expect("range checked against the length"),
+            },
+        }
+    }
+
+    /// A byte range within bounds as one slice, making a rope contiguous
+    /// unless the range lies within one chunk
+    #[cfg(feature = "view")]
+    pub(crate) fn contiguous_slice(&self, range: Range<usize>) -> &[u8] {
+        #[cfg(feature = "edit")]
+        if let Self::Rope { rope, .. } = self
+            && let Some(bytes) = rope.chunk_slice(range.clone())
+        {
+            return bytes;
+        }
+        &self.bytes()[range]
+    }
+
+    pub(crate) fn chunks(&self) -> ContentChunks<'_> {
+        #[cfg(feature = "edit")]
+        if let Self::Rope { rope, .. } = self {
+            return ContentChunks {
+                inner: ChunksInner::Rope(rope.chunks()),
+            };
+        }
+        let bytes = self.bytes();
+        ContentChunks {
+            inner: ChunksInner::Single(Some(bytes).filter(|bytes| !bytes.is_empty())),
+        }
+    }
+
+    /// A byte range within bounds as [`bytes::Bytes`], sharing the buffer
+    /// of [`Storage::Bytes`] content and copied otherwise
+    #[cfg(feature = "bytes")]
+    pub(crate) fn shared(&self, range: Range<usize>) -> bytes::Bytes {
+        match self {
+            Self::Bytes(bytes) => bytes.slice(range),
+            #[cfg(feature = "edit")]
+            Self::Rope { rope, .. } => bytes::Bytes::copy_from_slice(
+                &rope.slice(range).expect("range checked against the length"),
+            ),
+            _ => bytes::Bytes::copy_from_slice(&self.bytes()[range]),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            #[cfg(feature = "edit")]
+            Self::Rope { rope, .. } => rope.len(),
+            _ => self.bytes().len(),
+        }
+    }
+
+    pub(crate) fn into_vec(self) -> Vec<u8> {
+        match self {
+            Self::Contiguous(bytes) => bytes,
+            #[cfg(feature = "edit")]
+            Self::Rope { rope, flat, .. } => flat.into_inner().unwrap_or_else(|| rope.to_vec()),
+            #[cfg(feature = "bytes")]
+            Self::Bytes(bytes) => bytes.into(),
+        }
+    }
+
+    /// Switch storage, keeping the bytes
+    pub(crate) fn set_storage(&mut self, storage: Storage) {
+        if self.storage() != storage {
+            let bytes = std::mem::replace(self, Self::Contiguous(Vec::new())).into_vec();
+            *self = Self::new(bytes, storage);
+        }
+    }
+
+    /// Replace every byte, keeping the storage
+    pub(crate) fn set(&mut self, bytes: Vec<u8>) {
+        *self = Self::new(bytes, self.storage());
+    }
+
+    /// Run `edit` on contiguous bytes, rebuilding the storage afterwards if needed
+    pub(crate) fn edit(&mut self, edit: impl FnOnce(&mut Vec<u8>) -> bool) -> bool {
+        match self {
+            Self::Contiguous(bytes) => edit(bytes),
+            #[cfg(any(feature = "edit", feature = "bytes"))]
+            _ => {
+                let storage = self.storage();
+                let mut bytes = std::mem::replace(self, Self::Contiguous(Vec::new())).into_vec();
+                let edited = edit(&mut bytes);
+                *self = Self::new(bytes, storage);
+                edited
+            }
+        }
+    }
+
+    /// Replace a byte range (false when out of bounds)
+    pub(crate) fn replace(&mut self, range: Range<usize>, replacement: &[u8]) -> bool {
+        match self {
+            #[cfg(feature = "edit")]
+            Self::Rope { rope, .. } => {
+                if !rope.replace(range, replacement) {
+                    return false;
+                }
+                let rope = std::mem::take(rope);
+                *self = Self::rope(rope);
+                true
+            }
+            _ => self.edit(|bytes| {
+                if range.start > range.end || range.end > bytes.len() {
+                    return false;
+                }
+                bytes.splice(range, replacement.iter().copied());
+                true
+            }),
+        }
+    }
+
+    /// Number of lines, counting a trailing newline as starting an empty line
+    pub(crate) fn line_count(&self) -> usize {
+        match self {
+            #[cfg(feature = "edit")]
+            Self::Rope { rope, .. } => rope.line_count(),
+            _ => memchr::memchr_iter(b'\n', self.bytes()).count() + 1,
+        }
+    }
+
+    /// Whether line offsets are computed by [`Content::lazy_lines`] rather
+    /// than indexed by the map
+    #[cfg(feature = "view")]
+    pub(crate) fn has_lazy_lines(&self) -> bool {
+        #[cfg(feature = "edit")]
+        if let Self::Rope { .. } = self {
+            return true;
+        }
+        false
+    }
+
+    /// Line offsets of rope-backed content, computed on first use
+    ///
+    /// None for the storages whose offsets the map indexes up front.
+    #[cfg(feature = "view")]
+    pub(crate) fn lazy_lines(&self) -> Option<&CompactLineOffsets> {
+        match self {
+            #[cfg(feature = "edit")]
+            Self::Rope { lines, .. } => {
+                Some(lines.get_or_init(|| CompactLineOffsets::compute(self.bytes())))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the content looks binary, reading only its first bytes
+    pub(crate) fn sniff(&self) -> bool {
+        match self {
+            #[cfg(feature = "edit")]
+            Self::Rope { rope, .. } => rope
+                .slice(0..rope.len().min(SNIFF_LEN))
+                .is_some_and(|head| looks_binary(&head)),
+            _ => looks_binary(self.bytes()),
+        }
+    }
+}
+
+/// Iterator over the contiguous pieces of a file's content, see
+/// [`SourceFilesMap::content_chunks`](crate::SourceFilesMap::content_chunks)
+#[derive(Debug, Clone)]
+pub struct ContentChunks<'a> {
+    inner: ChunksInner<'a>,
+}
+
+#[derive(Debug, Clone)]
+enum ChunksInner<'a> {
+    Single(Option<&'a [u8]>),
+    #[cfg(feature = "edit")]
+    Rope(Chunks<'a>),
+}
+
+impl ContentChunks<'_> {
+    pub(crate) fn empty() -> Self {
+        Self {
+            inner: ChunksInner::Single(None),
+        }
+    }
+}
+
+impl<'a> Iterator for ContentChunks<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.inner {
+            ChunksInner::Single(chunk) => chunk.take(),
+            #[cfg(feature = "edit")]
+            ChunksInner::Rope(chunks) => chunks.next(),
+        }
+    }
+}
+
+// Serialized as plain bytes, whatever the storage
+#[cfg(feature = "serde")]
+impl Serialize for Content {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.bytes().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Content {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<u8>::deserialize(deserializer).map(Self::Contiguous)
+    }
+}
@@ -0,0 +1,152 @@
+//! ctags and etags files of named spans
+//!
+//! [`SourceFilesMap::write_tags`] writes the tag files Vim and Emacs jump
+//! through, from any list of names and spans: the entries of
+//! [`NamedRanges`](crate::NamedRanges), or the definitions of a
+//! [`DefinitionIndex`](crate::DefinitionIndex):
+//!
+//! ```ignore
+//! let definitions = index
+//!     .symbols()
+//!     .filter_map(|(key, definition, _)| Some((key.to_string(), definition?)));
+//! map.write_tags(definitions, TagsFormat::Ctags, File::create("tags")?)?;
+//! ```
+//!
+//! Line numbers and search patterns come from the mapped contents, so the
+//! tags match the files as the map holds them.
+
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+use crate::sfm::SourceFilesMap;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::io::{self, Write};
+
+/// Layout of a tags file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagsFormat {
+    /// Extended `tags` format of Universal and Exuberant Ctags, sorted by
+    /// name, as Vim reads it
+    Ctags,
+    /// `TAGS` format of etags, one section per file, as Emacs reads it
+    Etags,
+}
+
+/// A tag resolved against the map
+struct Tag<'a> {
+    name: String,
+    path: &'a str,
+    /// 1-based line
+    line: usize,
+    /// Byte offset of the line in the file
+    line_start: usize,
+    /// The line, without its line break
+    text: &'a [u8],
+    /// Bytes of `text` up to the end of the span
+    prefix_len: usize,
+}
+
+/// Ctags search pattern matching exactly `line`
+fn ctags_pattern(out: &mut impl Write, line: &[u8]) -> io::Result<()> {
+    out.write_all(b"/^")?;
+    for &byte in line {
+        if matches!(byte, b'\\' | b'/') {
+            out.write_all(b"\\")?;
+        }
+        out.write_all(&[byte])?;
+    }
+    out.write_all(b"$/")
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    fn resolve_tag<N: Display>(&self, name: N, pos: &AbsolutePosition<Id>) -> Option<Tag<'_>> {
+        let name = name.to_string();
+        if name.is_empty() || name.contains(['\t', '\n', '\r', '\x7f', '\x01']) {
+            return None;
+        }
+        let path = self.get_path(pos.file_id())?;
+        // Both formats end the path at a tab or line break
+        if path.contains(['\t', '\n', '\r']) {
+            return None;
+        }
+        let line = pos.start_line() as usize;
+        let (line_start, line_end) = self.line_offsets(pos.file_id())?.get_line_range(line)?;
+        let content = self.get_content(pos.file_id())?;
+        let text = content.get(line_start..line_end)?;
+        let text = text.strip_suffix(b"\r").unwrap_or(text);
+        let prefix_len = if pos.end_line() == pos.start_line() {
+            (pos.end_column() as usize).min(text.len())
+        } else {
+            text.len()
+        };
+        Some(Tag {
+            name,
+            path,
+            line,
+            line_start,
+            text,
+            prefix_len,
+        })
+    }
+
+    /// Write a tags file of named spans, returning the number of tags written
+    ///
+    /// Tags point at the first line of their span. Spans of files missing
+    /// from the map, and names or paths holding tabs or line breaks, are left
+    /// out.
+    pub fn write_tags<N: Display>(
+        &self,
+        tags: impl IntoIterator<Item = (N, AbsolutePosition<Id>)>,
+        format: TagsFormat,
+        mut out: impl Write,
+    ) -> io::Result<usize> {
+        let tags: Vec<_> = tags
+            .into_iter()
+            .filter_map(|(name, pos)| self.resolve_tag(name, &pos))
+            .collect();
+        let count = tags.len();
+        match format {
+            TagsFormat::Ctags => write_ctags(tags, &mut out)?,
+            TagsFormat::Etags => write_etags(tags, &mut out)?,
+        }
+        out.flush()?;
+        Ok(count)
+    }
+}
+
+fn write_ctags(mut tags: Vec<Tag<'_>>, out: &mut impl Write) -> io::Result<()> {
+    tags.sort_by(|a, b| (&a.name, a.path, a.line).cmp(&(&b.name, b.path, b.line)));
+    writeln!(out, "!_TAG_FILE_FORMAT\t2\t/extended format/")?;
+    writeln!(
+        out,
+        "!_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted, 2=foldcase/"
+    )?;
+    writeln!(out, "!_TAG_PROGRAM_NAME\tsourcier\t//")?;
+    for tag in &tags {
+        write!(out, "{}\t{}\t", tag.name, tag.path)?;
+        ctags_pattern(out, tag.text)?;
+        writeln!(out, ";\"\tline:{}", tag.line)?;
+    }
+    Ok(())
+}
+
+fn write_etags(tags: Vec<Tag<'_>>, out: &mut impl Write) -> io::Result<()> {
+    let mut files: BTreeMap<&str, Vec<Tag<'_>>> = BTreeMap::new();
+    for tag in tags {
+        files.entry(tag.path).or_default().push(tag);
+    }
+    for (path, mut tags) in files {
+        tags.sort_by_key(|tag| tag.line);
+        let mut section = Vec::new();
+        for tag in &tags {
+            section.extend_from_slice(&tag.text[..tag.prefix_len]);
+            writeln!(
+                section,
+                "\x7f{}\x01{},{}",
+                tag.name, tag.line, tag.line_start
+            )?;
+        }
+        write!(out, "\x0c\n{},{}\n", path, section.len())?;
+        out.write_all(&section)?;
+    }
+    Ok(())
+}
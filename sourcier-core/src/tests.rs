@@ -5,6 +5,7 @@ mod test_utils {
     macro_rules! test_suite {
         ($name:ident { $($test:ident $body:block)* }) => {
             mod $name {
+                #![allow(unused_imports)]
                 use super::*;
                 $(
                     #[test]
@@ -23,11 +24,24 @@ mod test_utils {
     macro_rules! add_files {
         ($files:expr => { $($path:literal $content:expr),* $(,)? }) => {
             $(
-                $files.add_file($path.to_string(), $content.to_vec());
+                $files.add_file($path.to_string(), $content.to_vec())?;
             )*
         };
     }
 
+    // Finalized map of the given files, added in order
+    #[allow(dead_code)]
+    pub(crate) fn finalized<Id: crate::FileId, C: AsRef<[u8]>>(
+        mut files: crate::SourceFilesMap<Id>,
+        contents: &[(&str, C)],
+    ) -> Result<crate::SourceFilesMap<Id>, String> {
+        for (path, content) in contents {
+            files.add_file(path.to_string(), content.as_ref().to_vec())?;
+        }
+        files.finalize()?;
+        Ok(files)
+    }
+
     // Snapshots with context-aware naming
     macro_rules! assert_position_snapshot {
         ($pos:expr) => {{
@@ -75,6 +89,7 @@ mod test_utils {
                                 line!()
                             ));
 
+                        #[allow(clippy::redundant_closure_call)]
                         let result: Result<_, String> = (|| -> Result<_, String> {
                             $body
                         })();
@@ -176,9 +191,9 @@ test_utils::exhaustive_test_suite!(core_functionality {
     test_max_files {
         let mut files = SourceFilesMap::<u8>::new();
         for i in 0..u8::MAX {
-            files.add_file(format!("file_{}.rs", i), vec![]);
+            files.add_file(format!("file_{}.rs", i), vec![])?;
         }
-        files.finalize()
+        files.finalize().map_err(|e| e.to_string())
     }
 
     test_duplicate_paths {
@@ -187,7 +202,7 @@ test_utils::exhaustive_test_suite!(core_functionality {
             "dup.rs" b"content",
             "dup.rs" b"different"
         });
-        files.finalize()
+        files.finalize().map_err(|e| e.to_string())
     }
 });
 
@@ -195,21 +210,21 @@ test_utils::exhaustive_test_suite!(core_functionality {
 #[cfg(test)]
 mod rt_feedback {
     use crate::*;
-    use std::sync::{Arc, Mutex};
+    use std::sync::Arc;
 
     // Macro to simplify file addition with optional content
     macro_rules! add_file {
         ($files:expr, $path:expr) => {
-            $files.add_file($path.to_string(), Vec::new())
+            $files.add_file($path.to_string(), Vec::new())?
         };
         ($files:expr, $path:expr, $content:expr) => {
-            $files.add_file($path.to_string(), $content.to_vec())
+            $files.add_file($path.to_string(), $content.to_vec())?
         };
     }
 
     // Helper function to create a runtime feedback context
-    fn create_feedback_context() -> Arc<Mutex<RuntimeFeedback>> {
-        Arc::new(Mutex::new(RuntimeFeedback::default()))
+    fn create_feedback_context() -> Arc<RuntimeFeedback> {
+        Arc::new(RuntimeFeedback::default())
     }
 
     #[test]
@@ -228,7 +243,7 @@ mod rt_feedback {
         files_map.finalize()?;
 
         // Check feedback state
-        let feedback_data = feedback.lock().unwrap();
+        let feedback_data = feedback.snapshot();
         assert_eq!(feedback_data.total_files, 3);
         assert_eq!(feedback_data.usage_count, 1);
 
@@ -250,7 +265,7 @@ mod rt_feedback {
         files_map2.finalize()?;
 
         // Check feedback state
-        let feedback_data = feedback.lock().unwrap();
+        let feedback_data = feedback.snapshot();
         assert_eq!(feedback_data.total_files, 2); // Second finalization overwrites first
         assert_eq!(feedback_data.usage_count, 2);
 
@@ -273,7 +288,7 @@ mod rt_feedback {
         files_map.finalize()?;
 
         // Check feedback state
-        let feedback_data = feedback.lock().unwrap();
+        let feedback_data = feedback.snapshot();
         assert_eq!(feedback_data.total_files, 3);
 
         Ok(())
@@ -293,12 +308,324 @@ mod rt_feedback {
         files_map2.finalize()?;
 
         // Check feedback state
-        let feedback_data = feedback.lock().unwrap();
+        let feedback_data = feedback.snapshot();
         assert_eq!(feedback_data.total_files, 1); // Only most recent finalization counts
         assert_eq!(feedback_data.usage_count, 2);
 
         Ok(())
     }
+
+    #[test]
+    fn feedback_accumulates_across_finalizations() -> Result<(), String> {
+        let feedback = create_feedback_context();
+
+        let mut files_map1 = SourceFilesMap::<u8>::with_feedback(Some(feedback.clone()));
+        add_file!(files_map1, "a.rs", b"0123456789");
+        add_file!(files_map1, "b.rs", b"01234567890123456789");
+        add_file!(files_map1, "c.rs", b"");
+        files_map1.finalize()?;
+
+        let mut files_map2 = SourceFilesMap::<u8>::with_feedback(Some(feedback.clone()));
+        add_file!(files_map2, "d.rs", b"012345");
+        files_map2.finalize()?;
+
+        let feedback_data = feedback.snapshot();
+        assert_eq!(feedback_data.cumulative_files, 4);
+        assert_eq!(feedback_data.cumulative_bytes, 36);
+        assert_eq!(feedback_data.peak_files, 3);
+        assert_eq!(feedback_data.max_file_size, 20);
+        assert_eq!(feedback_data.mean_files(), 2);
+        assert_eq!(feedback_data.mean_file_size(), Some(9));
+        assert_eq!(
+            feedback_data
+                .records
+                .iter()
+                .map(|r| r.files)
+                .collect::<Vec<_>>(),
+            vec![3, 1]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn feedback_persistence_round_trip() -> Result<(), String> {
+        let feedback = create_feedback_context();
+        let mut files_map = SourceFilesMap::<u8>::with_feedback(Some(feedback.clone()));
+        for i in 0..50 {
+            add_file!(files_map, format!("src/file_{}.rs", i), b"fn f() {}");
+        }
+        files_map.finalize()?;
+
+        let path = std::env::temp_dir().join(format!("sourcier-feedback-{}", std::process::id()));
+        feedback.save(&path).map_err(|e| e.to_string())?;
+        let loaded = RuntimeFeedback::load(&path).map_err(|e| e.to_string())?;
+        std::fs::remove_file(&path).map_err(|e| e.to_string())?;
+
+        assert_eq!(loaded.peak_files(), 50);
+        assert_eq!(loaded.snapshot(), feedback.snapshot());
+
+        // A warm start sizes the next map from the previous run
+        let warm = SourceFilesMap::<u8>::with_feedback(Some(Arc::new(loaded)));
+        let cold = SourceFilesMap::<u8>::with_feedback(None);
+        assert!(format!("{:?}", warm).contains("expected_files: 60"));
+        assert!(format!("{:?}", cold).contains("expected_files: 100"));
+
+        assert!(RuntimeFeedback::read_from(&mut &b"nope"[..]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn feedback_with_an_impossible_peak() -> Result<(), String> {
+        let mut blob = Vec::new();
+        create_feedback_context()
+            .write_to(&mut blob)
+            .map_err(|e| e.to_string())?;
+        // Magic, version, then the peak after six other words
+        blob[53..61].copy_from_slice(&(u64::MAX / 100).to_le_bytes());
+        assert!(RuntimeFeedback::read_from(&mut blob.as_slice()).is_err());
+
+        // Snapshots built in memory are not checked, so sizing saturates
+        let feedback = RuntimeFeedback::from(FeedbackSnapshot {
+            peak_files: usize::MAX / 100,
+            ..FeedbackSnapshot::default()
+        });
+        let files = SourceFilesMap::<u8>::with_feedback(Some(Arc::new(feedback)));
+        assert!(format!("{:?}", files).contains("expected_files: 255"));
+        Ok(())
+    }
+
+    #[test]
+    fn feedback_size_percentiles() -> Result<(), String> {
+        let feedback = create_feedback_context();
+        let mut files_map = SourceFilesMap::<u8>::with_feedback(Some(feedback.clone()));
+        for i in 0..99 {
+            add_file!(files_map, format!("small_{}.rs", i), [b'x'; 100]);
+        }
+        add_file!(files_map, "generated.rs", vec![b'x'; 1 << 20]);
+        files_map.finalize()?;
+
+        let feedback_data = feedback.snapshot();
+        assert_eq!(feedback_data.size_histogram[7], 99); // 64..=127 bytes
+        assert_eq!(feedback_data.size_histogram[21], 1); // 1 MiB
+        assert_eq!(feedback_data.p50(), Some(127));
+        assert_eq!(feedback_data.p95(), Some(127));
+        assert_eq!(feedback_data.size_percentile(1.0), Some(1 << 20));
+        assert!(feedback_data.mean_file_size().unwrap() > 10_000);
+
+        // The next map is sized from the p95, not the outlier-skewed mean
+        let next = SourceFilesMap::<u8>::with_feedback(Some(feedback.clone()));
+        assert!(format!("{:?}", next).contains("avg_file_size: 127"));
+
+        // Blobs without the histogram section still load
+        let mut blob = Vec::new();
+        feedback.write_to(&mut blob).map_err(|e| e.to_string())?;
+        blob.truncate(blob.len() - 8 * (RuntimeFeedback::SIZE_BUCKETS + 6));
+        let legacy = RuntimeFeedback::read_from(&mut blob.as_slice()).map_err(|e| e.to_string())?;
+        assert_eq!(legacy.snapshot().p95(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn feedback_from_inconsistent_snapshot() {
+        let record = |files| FinalizeRecord {
+            files,
+            bytes: 0,
+            max_file_size: 0,
+        };
+        // More records than finalizations counted, as untrusted input may hold
+        let feedback = RuntimeFeedback::from(FeedbackSnapshot {
+            usage_count: 1,
+            records: vec![record(1), record(2), record(3)],
+            ..FeedbackSnapshot::default()
+        });
+        let snapshot = feedback.snapshot();
+        assert_eq!(snapshot.usage_count, 1);
+        assert_eq!(snapshot.records, vec![record(3)]);
+
+        let empty = RuntimeFeedback::from(FeedbackSnapshot {
+            records: vec![record(1)],
+            ..FeedbackSnapshot::default()
+        });
+        assert!(empty.snapshot().records.is_empty());
+    }
+
+    #[test]
+    fn feedback_percentile_of_last_bucket() {
+        let mut size_histogram = vec![0; RuntimeFeedback::SIZE_BUCKETS];
+        size_histogram[64] = 1;
+        let snapshot = FeedbackSnapshot {
+            max_file_size: usize::MAX,
+            size_histogram,
+            ..FeedbackSnapshot::default()
+        };
+        assert_eq!(snapshot.size_percentile(1.0), Some(u64::MAX as usize));
+    }
+
+    #[cfg(feature = "view")]
+    #[test]
+    fn feedback_line_length_hint() -> Result<(), String> {
+        let feedback = create_feedback_context();
+        let mut files_map = SourceFilesMap::<u8>::with_feedback(Some(feedback.clone()));
+        add_file!(files_map, "a.rs", b"0123456789\n0123456789\n0123456789\n");
+        add_file!(files_map, "b.rs", b"0123456789\n");
+        files_map.finalize()?;
+
+        let feedback_data = feedback.snapshot();
+        assert_eq!(feedback_data.cumulative_lines, 6);
+        assert_eq!(feedback_data.mean_line_length(), Some(7));
+
+        let next = SourceFilesMap::<u8>::with_feedback(Some(feedback.clone()));
+        assert!(format!("{:?}", next).contains("line_length_hint: Some(7)"));
+        Ok(())
+    }
+
+    #[test]
+    fn feedback_phase_timings() -> Result<(), String> {
+        let feedback = create_feedback_context();
+        let mut files_map = SourceFilesMap::<u8>::with_feedback(Some(feedback.clone()));
+        for i in 0..200 {
+            add_file!(files_map, format!("src/{}.rs", 200 - i), b"fn f() {}\n");
+        }
+        files_map.finalize()?;
+
+        let phases = feedback.phases();
+        assert!(phases.total() > std::time::Duration::ZERO);
+        assert_eq!(
+            phases.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            ["sort", "dedup", "consolidation", "line_offsets"]
+        );
+
+        let mut blob = Vec::new();
+        feedback.write_to(&mut blob).map_err(|e| e.to_string())?;
+        let loaded = RuntimeFeedback::read_from(&mut blob.as_slice()).map_err(|e| e.to_string())?;
+        assert_eq!(loaded.phases(), phases);
+        Ok(())
+    }
+
+    #[test]
+    fn feedback_recommends_id_width() -> Result<(), String> {
+        let feedback = create_feedback_context();
+        assert_eq!(feedback.recommended_id_type(), IdWidth::U8);
+
+        let mut files_map = SourceFilesMap::<u16>::with_feedback(Some(feedback.clone()));
+        for i in 0..230 {
+            add_file!(files_map, format!("{}.rs", i));
+        }
+        files_map.finalize()?;
+
+        // 230 files fit in u8, but not with 25% headroom
+        assert_eq!(feedback.recommended_id_type(), IdWidth::U16);
+        assert_eq!(IdWidth::for_file_count(70_000), IdWidth::U32);
+        Ok(())
+    }
+
+    #[test]
+    fn feedback_global_contexts() -> Result<(), String> {
+        let mut files_map = SourceFilesMap::<u8>::with_global_feedback("tests::indexer");
+        add_file!(files_map, "a.rs");
+        add_file!(files_map, "b.rs");
+        files_map.finalize()?;
+
+        let shared = RuntimeFeedback::global("tests::indexer");
+        assert_eq!(shared.peak_files(), 2);
+        assert!(Arc::ptr_eq(
+            &shared,
+            &RuntimeFeedback::global("tests::indexer")
+        ));
+        assert_eq!(RuntimeFeedback::global("tests::other").usage_count(), 0);
+        assert!(RuntimeFeedback::global_names().contains(&"tests::indexer".to_string()));
+
+        let replaced = RuntimeFeedback::set_global("tests::indexer", create_feedback_context());
+        assert!(Arc::ptr_eq(&replaced.unwrap(), &shared));
+        assert_eq!(RuntimeFeedback::global("tests::indexer").usage_count(), 0);
+
+        RuntimeFeedback::remove_global("tests::indexer");
+        RuntimeFeedback::remove_global("tests::other");
+        Ok(())
+    }
+
+    #[cfg(feature = "view")]
+    #[test]
+    fn feedback_hot_files() -> Result<(), String> {
+        let mut files_map = SourceFilesMap::<u8>::new();
+        add_file!(files_map, "cold.rs", b"fn cold() {}");
+        add_file!(files_map, "hot.rs", b"fn hot() {}");
+        add_file!(files_map, "warm.rs", b"fn warm() {}");
+        files_map.finalize()?;
+
+        let hot = files_map.get_id("hot.rs").unwrap();
+        let warm = files_map.get_id("warm.rs").unwrap();
+        for _ in 0..3 {
+            files_map.view(hot, &create_relative_position(1, 1, 1, 6));
+        }
+        files_map.view(warm, &create_relative_position(1, 1, 1, 2));
+        files_map.view(warm, &create_relative_position(7, 1, 7, 2)); // miss
+
+        let hottest = files_map.hot_files(5);
+        assert_eq!(hottest.len(), 2);
+        assert_eq!(hottest[0], (hot, FileViewStats { hits: 3, bytes: 18 }));
+        assert_eq!(hottest[1].0, warm);
+        assert_eq!(
+            files_map.view_stats(warm),
+            Some(FileViewStats { hits: 1, bytes: 2 })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn feedback_sampling() -> Result<(), String> {
+        let feedback = Arc::new(RuntimeFeedback::with_config(FeedbackConfig {
+            sample_rate: 4,
+        }));
+        for i in 0..8 {
+            let mut files_map = SourceFilesMap::<u8>::with_feedback(Some(feedback.clone()));
+            add_file!(files_map, format!("{}.rs", i), b"fn f() {}");
+            files_map.finalize()?;
+
+            #[cfg(feature = "view")]
+            if i == 7 {
+                let id = files_map.get_id("7.rs").unwrap();
+                for _ in 0..8 {
+                    files_map.view(id, &create_relative_position(1, 1, 1, 2));
+                }
+                // 2 of 8 views sampled, each weighted by the rate
+                let stats = files_map.view_stats(id).unwrap();
+                assert_eq!((stats.hits, stats.bytes), (8, 16));
+            }
+        }
+        assert_eq!(feedback.usage_count(), 2);
+        assert_eq!(feedback.config().sample_rate, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn feedback_parallel_finalizations() -> Result<(), String> {
+        let feedback = create_feedback_context();
+
+        std::thread::scope(|scope| {
+            for worker in 0..8 {
+                let feedback = feedback.clone();
+                scope.spawn(move || {
+                    for round in 0..10 {
+                        let mut files_map =
+                            SourceFilesMap::<u8>::with_feedback(Some(feedback.clone()));
+                        files_map
+                            .add_file(format!("w{}/r{}.rs", worker, round), b"abc".to_vec())
+                            .unwrap();
+                        files_map.finalize().unwrap();
+                    }
+                });
+            }
+        });
+
+        let feedback_data = feedback.snapshot();
+        assert_eq!(feedback_data.usage_count, 80);
+        assert_eq!(feedback_data.cumulative_files, 80);
+        assert_eq!(feedback_data.cumulative_bytes, 240);
+        assert_eq!(feedback_data.records.len(), RuntimeFeedback::HISTORY);
+        Ok(())
+    }
 }
 
 #[cfg(feature = "view")]
@@ -321,6 +648,47 @@ mod view {
             let content = unsafe { std::str::from_utf8_unchecked(files.view(file_id, &pos).unwrap()) };
             insta::assert_debug_snapshot!(content);
         }
+
+        test_file_view_pinning {
+            let mut files = SourceFilesMap::<u8>::new();
+            add_files!(files => {
+                "a.txt" b"alpha\nbeta",
+                "b.txt" b"gamma\ndelta"
+            });
+            files.finalize()?;
+
+            let a = files.get_id("a.txt").unwrap();
+            let b = files.get_id("b.txt").unwrap();
+            let file: FileView<'_, u8> = files.file(a).unwrap();
+            assert_eq!(file.path(), "a.txt");
+            assert_eq!(file.view(&create_relative_position(2, 1, 2, 4)), Some(&b"beta"[..]));
+            assert_eq!(files.view_relative(b, &create_relative_position(2, 1, 2, 5)), Some(&b"delta"[..]));
+
+            // Absolute positions from another file are rejected, not sliced
+            let foreign = create_absolute_position(b, 1, 1, 1, 5);
+            assert!(file.view_absolute(&foreign).is_err());
+            assert_eq!(files.view(a, &foreign), None);
+            let own = create_absolute_position(a, 1, 1, 1, 5);
+            assert_eq!(file.view_absolute(&own)?, Some(&b"alpha"[..]));
+            assert!(files.file(0).is_none());
+        }
+
+        test_file_ref_byte_ranges {
+            let mut files = SourceFilesMap::<u8>::new();
+            add_files!(files => {
+                "a.txt" b"alpha\nbeta\n"
+            });
+            files.finalize()?;
+
+            let file = files.file(1).unwrap();
+            assert_eq!(file.bytes(6..10), Some(&b"beta"[..]));
+            assert_eq!(files.bytes(1, 0..5), Some(&b"alpha"[..]));
+            assert_eq!(file.bytes(8..20), None);
+            assert_eq!(files.bytes(2, 0..1), None);
+            assert_eq!(file.line(2), Some(&b"beta"[..]));
+            assert_eq!(file.line(3), Some(&b""[..]));
+            assert_eq!(file.line_offsets().map(|lines| lines.line_count()), Some(3));
+        }
     });
 }
 
@@ -398,3 +766,5239 @@ mod comprehensive {
         Ok(())
     });
 }
+
+#[cfg(test)]
+mod observers {
+    use crate::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct CountingObserver {
+        added: AtomicUsize,
+        finalized_files: AtomicUsize,
+        view_hits: AtomicUsize,
+        view_misses: AtomicUsize,
+        near_capacity: AtomicUsize,
+    }
+
+    impl MapObserver<u8> for CountingObserver {
+        fn on_add_file(&self, _path: &str, _size: usize) {
+            self.added.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_near_capacity(&self, files: usize, max_files: usize) {
+            assert!(files < max_files);
+            self.near_capacity.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_finalize(&self, event: &FinalizeEvent<'_>) {
+            self.finalized_files.store(event.files(), Ordering::Relaxed);
+        }
+
+        fn on_view(&self, _id: u8, len: Option<usize>) {
+            match len {
+                Some(_) => self.view_hits.fetch_add(1, Ordering::Relaxed),
+                None => self.view_misses.fetch_add(1, Ordering::Relaxed),
+            };
+        }
+    }
+
+    #[test]
+    fn observer_receives_lifecycle_events() -> Result<(), String> {
+        let observer = Arc::new(CountingObserver::default());
+        let mut files = SourceFilesMap::<u8>::new().with_observer(observer.clone());
+        files.add_file("a.rs".to_string(), b"fn a() {}".to_vec())?;
+        files.add_file("a.rs".to_string(), b"fn a() {}".to_vec())?;
+        files.add_file("b.rs".to_string(), b"fn b() {}".to_vec())?;
+        files.finalize()?;
+
+        assert_eq!(observer.added.load(Ordering::Relaxed), 3);
+        assert_eq!(observer.finalized_files.load(Ordering::Relaxed), 2);
+
+        #[cfg(feature = "view")]
+        {
+            let id = files.get_id("a.rs").unwrap();
+            files.view(id, &create_relative_position(1, 1, 1, 2));
+            files.view(id, &create_relative_position(9, 1, 9, 2));
+            assert_eq!(observer.view_hits.load(Ordering::Relaxed), 1);
+            assert_eq!(observer.view_misses.load(Ordering::Relaxed), 1);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn observer_warned_near_capacity() {
+        let observer = Arc::new(CountingObserver::default());
+        let mut files = SourceFilesMap::<u8>::new().with_observer(observer.clone());
+        // 90% of 255 files is 229
+        for i in 0..228 {
+            files.add_file(format!("{}.rs", i), Vec::new()).unwrap();
+        }
+        assert_eq!(observer.near_capacity.load(Ordering::Relaxed), 0);
+        for i in 228..255 {
+            files.add_file(format!("{}.rs", i), Vec::new()).unwrap();
+        }
+        assert_eq!(observer.near_capacity.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn observer_skips_files_not_stored() -> Result<(), String> {
+        let observer = Arc::new(CountingObserver::default());
+        let mut files = SourceFilesMap::<u8>::new()
+            .with_order(FileOrder::Insertion)
+            .with_load_options(LoadOptions::new().with_max_file_size(4))
+            .with_observer(observer.clone());
+        files.add_file("a.rs".to_string(), b"a".to_vec())?;
+        // A discarded duplicate and a skipped file
+        files.add_file("a.rs".to_string(), b"b".to_vec())?;
+        assert!(
+            files
+                .add_file("big.rs".to_string(), b"too big".to_vec())
+                .is_err()
+        );
+        assert_eq!(observer.added.load(Ordering::Relaxed), 1);
+
+        let mut files = SourceFilesMap::<u8>::new()
+            .with_order(FileOrder::Insertion)
+            .with_duplicate_policy(DuplicatePolicy::Reject)
+            .with_observer(observer.clone());
+        files.add_file("a.rs".to_string(), b"a".to_vec())?;
+        assert!(files.add_file("a.rs".to_string(), b"b".to_vec()).is_err());
+        for i in 1..=u8::MAX_FILES {
+            let _ = files.add_file(format!("{i}.rs"), Vec::new());
+        }
+        // One file each in both maps, then 254 more until the map is full
+        assert_eq!(observer.added.load(Ordering::Relaxed), 2 + 254);
+        Ok(())
+    }
+
+    #[test]
+    fn add_file_past_capacity_is_reported() {
+        let mut files = SourceFilesMap::<u8>::new();
+        for i in 0..u8::MAX_FILES {
+            files.add_file(format!("{}.rs", i), Vec::new()).unwrap();
+        }
+        let error = files.add_file("overflow.rs".to_string(), Vec::new());
+        assert_eq!(
+            error,
+            Err(SourceFilesError::CapacityExceeded {
+                path: "overflow.rs".to_string(),
+                max_files: 255
+            })
+        );
+        assert_eq!(files.dropped_files(), ["overflow.rs"]);
+        assert_eq!(files.len(), 255);
+    }
+}
+
+#[cfg(test)]
+mod wire_compat {
+    use crate::wire::{SUPPORTED_VERSIONS, WireHeader, negotiate};
+    use crate::*;
+
+    // Checked-in fixtures; a failing test here means the on-disk format changed
+    const MAP_V1_U8: &[u8] = include_bytes!("../fixtures/wire/map_v1_u8.bin");
+    const MAP_V2_U8: &[u8] = include_bytes!("../fixtures/wire/map_v2_u8.bin");
+    const MAP_V3_U8: &[u8] = include_bytes!("../fixtures/wire/map_v3_u8.bin");
+    const MAP_V4_U8: &[u8] = include_bytes!("../fixtures/wire/map_v4_u8.bin");
+    #[cfg(feature = "rt-feedback")]
+    const FEEDBACK_V1: &[u8] = include_bytes!("../fixtures/wire/feedback_v1.bin");
+
+    fn fixture_map() -> Result<SourceFilesMap<u8>, String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("src/lib.rs".to_string(), b"pub mod parse;\n".to_vec())?;
+        files.add_file("src/parse.rs".to_string(), b"fn parse() {}\n".to_vec())?;
+        files.add_file("empty.txt".to_string(), Vec::new())?;
+        files.finalize()?;
+        let (lib, parse) = (files.get_id("src/lib.rs"), files.get_id("src/parse.rs"));
+        if let (Some(lib), Some(parse)) = (lib, parse) {
+            files.graph_mut().add_edge(lib, parse);
+        }
+        Ok(files)
+    }
+
+    #[cfg(feature = "rt-feedback")]
+    fn fixture_feedback() -> RuntimeFeedback {
+        let mut size_histogram = vec![0; RuntimeFeedback::SIZE_BUCKETS];
+        size_histogram[4] = 2;
+        RuntimeFeedback::from(FeedbackSnapshot {
+            total_files: 2,
+            total_bytes: 29,
+            max_file_size: 15,
+            usage_count: 1,
+            cumulative_files: 2,
+            cumulative_bytes: 29,
+            peak_files: 2,
+            records: vec![FinalizeRecord {
+                files: 2,
+                bytes: 29,
+                max_file_size: 15,
+            }],
+            size_histogram,
+            cumulative_lines: 4,
+            phases: FinalizePhases::default(),
+        })
+    }
+
+    #[test]
+    fn map_v1_fixture_loads() -> Result<(), String> {
+        let files =
+            SourceFilesMap::<u8>::read_cache(&mut &MAP_V1_U8[..]).map_err(|e| e.to_string())?;
+        let expected = fixture_map()?;
+        assert_eq!(
+            files.iter().collect::<Vec<_>>(),
+            expected.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            files.get_id("src/parse.rs"),
+            expected.get_id("src/parse.rs")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn map_v1_encoding_is_stable() -> Result<(), String> {
+        let mut encoded = Vec::new();
+        fixture_map()?
+            .write_cache_version(&mut encoded, 1)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(encoded, MAP_V1_U8);
+        Ok(())
+    }
+
+    #[test]
+    fn map_v2_fixture_is_stable() -> Result<(), String> {
+        let files =
+            SourceFilesMap::<u8>::read_cache(&mut &MAP_V2_U8[..]).map_err(|e| e.to_string())?;
+        assert_eq!(
+            files.iter().collect::<Vec<_>>(),
+            fixture_map()?.iter().collect::<Vec<_>>()
+        );
+        assert!(files.graph().is_empty());
+        let mut encoded = Vec::new();
+        fixture_map()?
+            .write_cache_version(&mut encoded, 2)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(encoded, MAP_V2_U8);
+        Ok(())
+    }
+
+    #[test]
+    fn map_v3_fixture_is_stable() -> Result<(), String> {
+        let files =
+            SourceFilesMap::<u8>::read_cache(&mut &MAP_V3_U8[..]).map_err(|e| e.to_string())?;
+        let expected = fixture_map()?;
+        assert_eq!(
+            files.iter().collect::<Vec<_>>(),
+            expected.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(files.graph(), expected.graph());
+        let mut encoded = Vec::new();
+        expected
+            .write_cache_version(&mut encoded, 3)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(encoded, MAP_V3_U8);
+        Ok(())
+    }
+
+    #[test]
+    fn map_v4_fixture_is_stable() -> Result<(), String> {
+        let files =
+            SourceFilesMap::<u8>::read_cache(&mut &MAP_V4_U8[..]).map_err(|e| e.to_string())?;
+        let expected = fixture_map()?;
+        assert_eq!(
+            files.iter().collect::<Vec<_>>(),
+            expected.iter().collect::<Vec<_>>()
+        );
+        assert_eq!(files.graph(), expected.graph());
+        let mut encoded = Vec::new();
+        expected
+            .write_cache(&mut encoded)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(encoded, MAP_V4_U8);
+        Ok(())
+    }
+
+    #[test]
+    fn metadata_opens_without_contents() -> Result<(), String> {
+        let expected = fixture_map()?;
+        let parse = expected.get_id("src/parse.rs").ok_or("parse")?;
+        // Everything past the metadata segment is contents
+        let contents: usize = expected.iter().map(|(_, _, content)| content.len()).sum();
+        let metadata_only = &MAP_V4_U8[..MAP_V4_U8.len() - contents];
+        let metadata = SourceFilesMap::<u8>::open_metadata_only(&mut &metadata_only[..])
+            .map_err(|e| e.to_string())?;
+        assert_eq!(metadata.version, 4);
+        assert_eq!(metadata.len(), 3);
+        assert_eq!(metadata.get_id("src/parse.rs"), Some(parse));
+        assert_eq!(metadata.get_path(parse), Some("src/parse.rs"));
+        assert_eq!(metadata.line_count(parse), expected.line_count(parse));
+        let file = metadata.get(parse).ok_or("file")?;
+        assert_eq!((file.len, file.mtime_ns), (14, 0));
+        assert_eq!(Some(file.hash), expected.content_hash(parse));
+        assert_eq!(metadata.content_len(), contents as u64);
+        assert_eq!(metadata.graph(), expected.graph());
+
+        // Older versions describe the same files
+        for old in [MAP_V1_U8, MAP_V3_U8] {
+            let from_old = SourceFilesMap::<u8>::open_metadata_only(&mut &old[..])
+                .map_err(|e| e.to_string())?;
+            assert_eq!(
+                from_old.iter().collect::<Vec<_>>(),
+                metadata.iter().collect::<Vec<_>>()
+            );
+        }
+        let mut truncated = MAP_V4_U8[..30].to_vec();
+        truncated.extend_from_slice(&[0; 8]);
+        assert!(SourceFilesMap::<u8>::open_metadata_only(&mut truncated.as_slice()).is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "rt-feedback")]
+    #[test]
+    fn feedback_v1_fixture_is_stable() -> Result<(), String> {
+        let loaded =
+            RuntimeFeedback::read_from(&mut &FEEDBACK_V1[..]).map_err(|e| e.to_string())?;
+        assert_eq!(loaded.snapshot(), fixture_feedback().snapshot());
+        let mut encoded = Vec::new();
+        fixture_feedback()
+            .write_to(&mut encoded)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(encoded, FEEDBACK_V1);
+        Ok(())
+    }
+
+    #[test]
+    fn subsets_load_selected_files_and_the_rest_on_demand() -> Result<(), String> {
+        let err = |e: WireError| e.to_string();
+        let mut partial =
+            SourceFilesMap::<u8>::load_subset(std::io::Cursor::new(MAP_V2_U8), ["src/"])
+                .map_err(err)?;
+        let lib = partial.get_id("src/lib.rs").ok_or("lib")?;
+        let empty = partial.get_id("empty.txt").ok_or("empty")?;
+        assert_eq!(partial.len(), 3);
+        assert_eq!(partial.loaded_ids().count(), 2);
+        assert_eq!(partial.get_content(lib), Some(&b"pub mod parse;\n"[..]));
+        assert_eq!(partial.line_count(lib), Some(2));
+        assert!(!partial.is_loaded(empty));
+        assert_eq!(partial.get_content(empty), None);
+        assert_eq!(partial.load(empty).map_err(err)?, Some(&b""[..]));
+        assert!(partial.is_loaded(empty));
+        assert_eq!(partial.load(9).map_err(err)?, None);
+
+        // Old versions and globs, then everything at once
+        let mut partial =
+            SourceFilesMap::<u8>::load_subset(std::io::Cursor::new(MAP_V1_U8), ["*.txt"])
+                .map_err(err)?;
+        assert_eq!(partial.loaded_ids().collect::<Vec<_>>(), [empty]);
+        assert_eq!(partial.load_matching(["parse.rs"]).map_err(err)?, 1);
+        let files = partial.into_map().map_err(err)?;
+        assert_eq!(
+            files.iter().collect::<Vec<_>>(),
+            fixture_map()?.iter().collect::<Vec<_>>()
+        );
+
+        let mut partial =
+            SourceFilesMap::<u8>::load_subset(std::io::Cursor::new(MAP_V4_U8), ["src/"])
+                .map_err(err)?;
+        assert_eq!(partial.loaded_ids().count(), 2);
+        assert_eq!(partial.get_content(lib), Some(&b"pub mod parse;\n"[..]));
+        assert_eq!(partial.load(empty).map_err(err)?, Some(&b""[..]));
+        assert_eq!(partial.content_hash(lib), fixture_map()?.content_hash(lib));
+        let truncated = std::io::Cursor::new(&MAP_V4_U8[..MAP_V4_U8.len() - 10]);
+        assert!(matches!(
+            SourceFilesMap::<u8>::load_subset(truncated, ["none/"]),
+            Err(WireError::Corrupt(_))
+        ));
+
+        let truncated = std::io::Cursor::new(&MAP_V2_U8[..MAP_V2_U8.len() - 40]);
+        assert!(matches!(
+            SourceFilesMap::<u8>::load_subset(truncated, ["none/"]),
+            Err(WireError::Corrupt(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn prefetch_reads_files_in_the_background() -> Result<(), String> {
+        let err = |e: WireError| e.to_string();
+        let path =
+            std::env::temp_dir().join(format!("sourcier-prefetch-{}.bin", std::process::id()));
+        std::fs::write(&path, MAP_V2_U8).map_err(|e| e.to_string())?;
+        let file = std::fs::File::open(&path).map_err(|e| e.to_string())?;
+        let mut partial = SourceFilesMap::<u8>::load_subset(file, ["*.txt"]).map_err(err)?;
+        let parse = partial.get_id("src/parse.rs").ok_or("parse")?;
+
+        let cache = path.clone();
+        let prefetch = partial.prefetch_matching(["src/"], move || std::fs::File::open(&cache));
+        let read = prefetch.wait().map_err(err);
+        let missing = partial
+            .prefetch([parse], || std::fs::File::open("/nonexistent/sourcier.bin"))
+            .wait();
+        std::fs::remove_file(&path).map_err(|e| e.to_string())?;
+
+        assert_eq!(read?, 2);
+        assert!(prefetch.is_finished());
+        assert!(matches!(missing, Err(WireError::Io(_))));
+        // The error stays reported however often the outcome is asked for
+        let failed = partial.prefetch([parse], || std::fs::File::open("/nonexistent"));
+        let first = failed.wait().map_err(|e| e.to_string());
+        let second = failed.clone().wait().map_err(|e| e.to_string());
+        assert!(first.is_err());
+        assert_eq!(first, second);
+        assert_eq!(partial.get_content(parse), None);
+        assert_eq!(partial.apply_prefetched(), 2);
+        assert_eq!(partial.get_content(parse), Some(&b"fn parse() {}\n"[..]));
+        assert_eq!(partial.loaded_ids().count(), 3);
+        // Loaded files are skipped without opening the cache
+        let again = partial.prefetch([parse], || std::fs::File::open("/nonexistent"));
+        assert_eq!(again.wait().map_err(err)?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn memory_budget_evicts_least_recently_used_files() -> Result<(), String> {
+        let err = |e: WireError| e.to_string();
+        let partial = SourceFilesMap::<u8>::load_subset(std::io::Cursor::new(MAP_V2_U8), ["src/"])
+            .map_err(err)?;
+        let lib = partial.get_id("src/lib.rs").ok_or("lib")?;
+        let parse = partial.get_id("src/parse.rs").ok_or("parse")?;
+        assert_eq!(partial.resident_bytes(), 29);
+        assert!(partial.get_content(parse).is_some());
+
+        let mut partial = partial.with_memory_budget(20);
+        assert_eq!(partial.resident_bytes(), 14);
+        assert!(!partial.is_loaded(lib) && partial.is_loaded(parse));
+        #[cfg(feature = "view")]
+        {
+            let expected = fixture_map()?.position(lib, 4..7);
+            assert!(expected.is_some());
+            assert_eq!(partial.position(lib, 4..7), expected);
+        }
+
+        // Pinned files stay, even over budget, until unpinned
+        assert!(partial.pin(parse));
+        assert_eq!(
+            partial.load(lib).map_err(err)?,
+            Some(&b"pub mod parse;\n"[..])
+        );
+        assert_eq!(partial.resident_bytes(), 29);
+        assert!(partial.unpin(parse));
+        assert!(partial.is_loaded(lib) && !partial.is_loaded(parse));
+        assert!(!partial.pin(9));
+
+        let files = partial.into_map().map_err(err)?;
+        assert_eq!(
+            files.iter().collect::<Vec<_>>(),
+            fixture_map()?.iter().collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn version_and_width_mismatches_are_reported() {
+        let mut future = MAP_V1_U8.to_vec();
+        future[8..10].copy_from_slice(&99u16.to_le_bytes());
+        assert!(matches!(
+            SourceFilesMap::<u8>::read_cache(&mut future.as_slice()),
+            Err(WireError::FormatVersion { found: 99, .. })
+        ));
+        assert!(matches!(
+            SourceFilesMap::<u16>::read_cache(&mut &MAP_V1_U8[..]),
+            Err(WireError::IdWidth {
+                expected: 16,
+                found: 8
+            })
+        ));
+        assert!(matches!(
+            SourceFilesMap::<u8>::read_cache(&mut &b"NOTSOURCIER!"[..]),
+            Err(WireError::BadMagic)
+        ));
+        assert!(matches!(
+            SourceFilesMap::<u8>::read_cache(&mut &MAP_V1_U8[..MAP_V1_U8.len() - 3]),
+            Err(WireError::Corrupt(_))
+        ));
+        assert_eq!(
+            WireHeader::read_from(&mut &MAP_V1_U8[..]).unwrap(),
+            WireHeader {
+                version: 1,
+                id_bits: 8
+            }
+        );
+    }
+
+    #[test]
+    fn corrupt_file_counts_fail_without_allocating() {
+        for version in [3u16, 4] {
+            let mut input = wire::MAP_MAGIC.to_vec();
+            input.extend(version.to_le_bytes());
+            input.extend([32, 0]);
+            input.extend(u64::from(u32::MAX).to_le_bytes());
+            // The input ends where the first file should start
+            assert!(SourceFilesMap::<u32>::read_cache(&mut input.as_slice()).is_err());
+            let subset = SourceFilesMap::<u32>::load_subset(std::io::Cursor::new(input), ["*"]);
+            assert!(subset.is_err());
+        }
+    }
+
+    #[test]
+    fn version_negotiation() {
+        assert_eq!(negotiate(SUPPORTED_VERSIONS), Some(FORMAT_VERSION));
+        assert_eq!(negotiate(0..=1), Some(1));
+        assert_eq!(negotiate(FORMAT_VERSION + 1..=FORMAT_VERSION + 5), None);
+    }
+
+    #[test]
+    #[ignore = "regenerates the checked-in fixtures"]
+    fn regenerate_fixtures() -> Result<(), String> {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/wire");
+        for version in SUPPORTED_VERSIONS {
+            let mut map = Vec::new();
+            fixture_map()?
+                .write_cache_version(&mut map, version)
+                .map_err(|e| e.to_string())?;
+            std::fs::write(format!("{}/map_v{}_u8.bin", dir, version), map)
+                .map_err(|e| e.to_string())?;
+        }
+        #[cfg(feature = "rt-feedback")]
+        {
+            let mut feedback = Vec::new();
+            fixture_feedback()
+                .write_to(&mut feedback)
+                .map_err(|e| e.to_string())?;
+            std::fs::write(format!("{}/feedback_v1.bin", dir), feedback)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod compact_serde {
+    use crate::*;
+
+    fn bincode_round_trip<T>(value: &T) -> Result<(T, usize), String>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let config = bincode::config::standard();
+        let bytes = bincode::serde::encode_to_vec(value, config).map_err(|e| e.to_string())?;
+        let (decoded, _) =
+            bincode::serde::decode_from_slice(&bytes, config).map_err(|e| e.to_string())?;
+        Ok((decoded, bytes.len()))
+    }
+
+    fn postcard_round_trip<T>(value: &T) -> Result<(T, usize), String>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+    {
+        let bytes = postcard::to_allocvec(value).map_err(|e| e.to_string())?;
+        let decoded = postcard::from_bytes(&bytes).map_err(|e| e.to_string())?;
+        Ok((decoded, bytes.len()))
+    }
+
+    #[test]
+    fn positions_encode_as_bare_integers() -> Result<(), String> {
+        let pos = StandardAbsolutePosition::new(300, 1200, 15, 1210, 200);
+        let legacy = bincode::config::legacy();
+        let fixed = bincode::serde::encode_to_vec(pos, legacy).map_err(|e| e.to_string())?;
+        assert_eq!(fixed, pos.as_raw().to_le_bytes());
+
+        let (decoded, len) = bincode_round_trip(&pos)?;
+        assert_eq!(decoded, pos);
+        assert!(len <= 9);
+        let (decoded, len) = postcard_round_trip(&pos)?;
+        assert_eq!(decoded, pos);
+        assert!(len <= 10);
+
+        let rel = RelativePosition::new(1, 0, 1, 4);
+        let (decoded, len) = postcard_round_trip(&rel)?;
+        assert_eq!(decoded, rel);
+        assert!(len < 8, "small positions stay small under varint: {}", len);
+        assert_eq!(bincode_round_trip(&rel)?.0, rel);
+        Ok(())
+    }
+
+    #[test]
+    fn map_round_trips_with_views() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("b.rs".to_string(), b"fn b() {}\n".to_vec())?;
+        files.add_file("a.rs".to_string(), b"fn a() {\n}\n".to_vec())?;
+        files.finalize()?;
+
+        let (bincode_map, _) = bincode_round_trip(&files)?;
+        let (postcard_map, _) = postcard_round_trip(&files)?;
+        for decoded in [bincode_map, postcard_map] {
+            assert_eq!(
+                decoded.iter().collect::<Vec<_>>(),
+                files.iter().collect::<Vec<_>>()
+            );
+            assert_eq!(decoded.get_id("b.rs"), files.get_id("b.rs"));
+            #[cfg(feature = "view")]
+            {
+                let pos = CompactAbsolutePosition::new(1, 1, 0, 2, 1);
+                assert_eq!(decoded.view(1, &pos), Some(&b"fn a() {\n}"[..]));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_paths_are_rejected() {
+        let encoded = postcard::to_allocvec(&(
+            [("a.rs", b"x".to_vec()), ("a.rs", b"y".to_vec())],
+            0usize,
+            0usize,
+        ))
+        .unwrap();
+        assert!(postcard::from_bytes::<SourceFilesMap<u8>>(&encoded).is_err());
+    }
+
+    #[test]
+    fn maps_serialized_by_older_releases_load() -> Result<(), String> {
+        let files = r#"[{"path": "a.rs", "content": [120]}]"#;
+        let before_graph = format!(
+            r#"{{"files": {files}, "avg_file_size": 2048, "expected_files": 100, "epoch": 1}}"#
+        );
+        let map: SourceFilesMap<u8> =
+            serde_json::from_str(&before_graph).map_err(|e| e.to_string())?;
+        assert_eq!(map.get_content(1), Some(&b"x"[..]));
+        assert_eq!(map.epoch(), 1);
+        assert!(map.graph().is_empty());
+
+        let before_epochs =
+            format!(r#"{{"files": {files}, "avg_file_size": 2048, "expected_files": 100}}"#);
+        let map: SourceFilesMap<u8> =
+            serde_json::from_str(&before_epochs).map_err(|e| e.to_string())?;
+        assert_eq!(map.get_content(1), Some(&b"x"[..]));
+        assert_eq!(map.epoch(), 0);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "export"))]
+mod export {
+    use super::test_utils::add_files;
+    use crate::exp::{EXPORT_SCHEMA_VERSION, Export};
+    use crate::*;
+
+    fn sample_map() -> Result<SourceFilesMap<u8>, String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        add_files!(files => {
+            "src/lib.rs" b"mod a;\nmod b;\n",
+            "src/a.rs" b"fn a() {}",
+        });
+        files.finalize()?;
+        Ok(files)
+    }
+
+    #[test]
+    fn files_only_by_default() -> Result<(), String> {
+        let files = sample_map()?;
+        let mut out = Vec::new();
+        files
+            .export_json(&mut out, ExportOptions::default())
+            .map_err(|e| e.to_string())?;
+        let json: serde_json::Value = serde_json::from_slice(&out).map_err(|e| e.to_string())?;
+        assert_eq!(json["schema"], EXPORT_SCHEMA_VERSION);
+        assert!(json.get("spans").is_none());
+        assert_eq!(json["files"][0]["path"], "src/a.rs");
+        assert_eq!(json["files"][0]["lines"], 1);
+        assert_eq!(json["files"][1]["id"], 2);
+        assert_eq!(json["files"][1]["lines"], 3);
+        assert_eq!(json["files"][1]["bytes"], 14);
+        let hash = json["files"][1]["hash"].as_str().unwrap_or_default();
+        assert_eq!(hash.len(), 16);
+        assert_eq!(u64::from_str_radix(hash, 16).ok(), files.content_hash(2));
+        Ok(())
+    }
+
+    #[test]
+    fn spans_round_trip() -> Result<(), String> {
+        let files = sample_map()?;
+        let options = ExportOptions {
+            pretty: true,
+            spans: Some(vec![
+                LabeledSpan::new(AbsolutePosition::new(2, 2, 1, 2, 5), Some("mod".into())),
+                LabeledSpan::new(AbsolutePosition::new(9, 1, 1, 1, 1), None),
+            ]),
+        };
+        let mut out = Vec::new();
+        files
+            .export_json(&mut out, options)
+            .map_err(|e| e.to_string())?;
+        let export: Export = serde_json::from_slice(&out).map_err(|e| e.to_string())?;
+        let spans = export.spans.ok_or("spans missing")?;
+        assert_eq!(spans[0].path.as_deref(), Some("src/lib.rs"));
+        assert_eq!(
+            (
+                spans[0].start_line,
+                spans[0].end_col,
+                spans[0].label.as_deref()
+            ),
+            (2, 5, Some("mod"))
+        );
+        assert_eq!((spans[1].file, spans[1].path.as_ref()), (9, None));
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "export"))]
+mod span_dump {
+    use super::test_utils::add_files;
+    use crate::exp::ExportedSpan;
+    use crate::*;
+
+    fn sample() -> Result<(SourceFilesMap<u8>, Vec<LabeledSpan<u8>>), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        add_files!(files => {
+            "a, \"quoted\".rs" b"x",
+            "b.rs" b"y",
+        });
+        files.finalize()?;
+        let spans = vec![
+            LabeledSpan::new(
+                AbsolutePosition::new(1, 1, 1, 1, 2),
+                Some("dead code".into()),
+            ),
+            LabeledSpan::new(AbsolutePosition::new(2, 3, 4, 5, 6), None),
+        ];
+        Ok((files, spans))
+    }
+
+    #[test]
+    fn jsonl_one_record_per_line() -> Result<(), String> {
+        let (files, spans) = sample()?;
+        let mut out = Vec::new();
+        let written = files
+            .dump_spans(&mut out, DumpFormat::Jsonl, &spans)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(written, 2);
+        let text = String::from_utf8(out).map_err(|e| e.to_string())?;
+        let records = text
+            .lines()
+            .map(serde_json::from_str::<ExportedSpan>)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].label.as_deref(), Some("dead code"));
+        assert_eq!(records[1].path.as_deref(), Some("b.rs"));
+        assert_eq!((records[1].start_line, records[1].end_col), (3, 6));
+        Ok(())
+    }
+
+    #[test]
+    fn csv_quotes_fields() -> Result<(), String> {
+        let (files, spans) = sample()?;
+        let mut out = Vec::new();
+        files
+            .dump_spans(&mut out, DumpFormat::Csv, &spans)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(
+            String::from_utf8(out).map_err(|e| e.to_string())?,
+            "file,path,start_line,start_col,end_line,end_col,label\n\
+             1,\"a, \"\"quoted\"\".rs\",1,1,1,2,dead code\n\
+             2,b.rs,3,4,5,6,\n"
+        );
+        let empty = SpanWriter::new(Vec::new(), DumpFormat::Csv)
+            .finish()
+            .map_err(|e| e.to_string())?;
+        assert_eq!(empty.iter().filter(|&&b| b == b'\n').count(), 1);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod remap {
+    use super::test_utils::finalized;
+    use crate::*;
+
+    fn map_of(paths: &[&str]) -> Result<SourceFilesMap<u8>, String> {
+        let contents: Vec<_> = paths.iter().map(|path| (*path, "x")).collect();
+        finalized(SourceFilesMap::new(), &contents)
+    }
+
+    #[test]
+    fn id_tables_follow_paths_and_compose() -> Result<(), String> {
+        let first = map_of(&["b.rs", "c.rs", "d.rs"])?;
+        let second = map_of(&["a.rs", "c.rs", "d.rs"])?;
+        let third = map_of(&["d.rs", "e.rs"])?;
+
+        let step = first.remap_to(&second);
+        assert_eq!(
+            (step.get(1), step.get(2), step.get(3)),
+            (None, Some(2), Some(3))
+        );
+        let pos = AbsolutePosition::new(3, 4, 1, 4, 9);
+        assert_eq!(step.apply(&pos), Some(AbsolutePosition::new(3, 4, 1, 4, 9)));
+
+        let both = step.then(&second.remap_to(&third));
+        assert_eq!((both.get(2), both.get(3)), (None, Some(1)));
+        assert_eq!(both.apply(&pos), Some(AbsolutePosition::new(1, 4, 1, 4, 9)));
+        assert_eq!(IdRemapTable::<u8>::identity(3).then(&step), step);
+        Ok(())
+    }
+
+    #[cfg(feature = "view")]
+    #[test]
+    fn full_maps_widen_to_wider_ids() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        for i in 0..=u8::MAX_FILES {
+            let path = format!("f{i:03}.rs");
+            if let Err(error) = files.add_file(path, b"a\nb".to_vec()) {
+                assert!(matches!(error, SourceFilesError::CapacityExceeded { .. }));
+            }
+        }
+        files.finalize()?;
+        assert_eq!(files.dropped_files(), ["f255.rs"]);
+        let pos = AbsolutePosition::new(7, 2, 1, 2, 1);
+        let text = files.view(7, &pos).map(<[u8]>::to_vec);
+
+        let (mut wide, remap) = files.widen::<u16>();
+        let moved = remap.apply(&pos).ok_or("every ID is kept")?;
+        assert_eq!(moved, AbsolutePosition::<u16>::new(7, 2, 1, 2, 1));
+        assert_eq!(wide.view(7, &moved).map(<[u8]>::to_vec), text);
+        for path in wide.dropped_files().to_vec() {
+            wide.add_file(path, b"c".to_vec())?;
+        }
+        wide.finalize()?;
+        assert_eq!(wide.get_id("f255.rs"), Some(256));
+        assert_eq!(wide.get_id("f006.rs"), Some(7));
+        Ok(())
+    }
+
+    #[test]
+    fn edits_shift_later_spans() {
+        let mut edits = EditRemap::<u8>::new();
+        // Line 2, columns 5..10 replaced by two lines ending at line 3 column 4
+        edits.record(1, RelativePosition::new(2, 5, 2, 10), 3, 4);
+
+        let before = AbsolutePosition::new(1, 1, 1, 2, 4);
+        assert_eq!(edits.apply(&before), Some(before));
+        let touching = AbsolutePosition::new(1, 1, 1, 2, 5);
+        assert_eq!(edits.apply(&touching), None);
+        let same_line = AbsolutePosition::new(1, 2, 12, 2, 15);
+        assert_eq!(
+            edits.apply(&same_line),
+            Some(AbsolutePosition::new(1, 3, 6, 3, 9))
+        );
+        let later = AbsolutePosition::new(1, 7, 3, 8, 1);
+        assert_eq!(
+            edits.apply(&later),
+            Some(AbsolutePosition::new(1, 8, 3, 9, 1))
+        );
+        let overlapping = AbsolutePosition::new(1, 2, 8, 2, 12);
+        assert_eq!(edits.apply(&overlapping), None);
+        let other_file = AbsolutePosition::new(2, 2, 8, 2, 12);
+        assert_eq!(edits.apply(&other_file), Some(other_file));
+
+        let mut removal = EditRemap::<u8>::new();
+        removal.record(1, RelativePosition::new(1, 1, 2, 1), 1, 1);
+        assert_eq!(
+            edits.then(&removal).apply(&later),
+            Some(AbsolutePosition::new(1, 7, 3, 8, 1))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn tables_serialize() -> Result<(), String> {
+        let table = map_of(&["a.rs", "b.rs"])?.remap_to(&map_of(&["b.rs"])?);
+        let bytes = postcard::to_allocvec(&table).map_err(|e| e.to_string())?;
+        let decoded: IdRemapTable<u8> = postcard::from_bytes(&bytes).map_err(|e| e.to_string())?;
+        assert_eq!(decoded, table);
+
+        let mut edits = EditRemap::<u8>::new();
+        edits.record(1, RelativePosition::new(1, 1, 1, 2), 1, 5);
+        let bytes = postcard::to_allocvec(&edits).map_err(|e| e.to_string())?;
+        let decoded: EditRemap<u8> = postcard::from_bytes(&bytes).map_err(|e| e.to_string())?;
+        assert_eq!(decoded, edits);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod file_order {
+    use crate::*;
+    use std::sync::Arc;
+
+    fn finalize_with(order: FileOrder) -> Result<Vec<String>, String> {
+        let mut files = SourceFilesMap::<u8>::new().with_order(order);
+        for (path, content) in [
+            ("src/z.rs", "first"),
+            ("README.md", ""),
+            ("src/a.rs", ""),
+            ("src/z.rs", "second"),
+        ] {
+            files.add_file(path.to_string(), content.as_bytes().to_vec())?;
+        }
+        files.finalize()?;
+        assert_eq!(
+            files.get_content(files.get_id("src/z.rs").ok_or("missing")?),
+            Some(&b"first"[..])
+        );
+        Ok(files.iter().map(|(_, path, _)| path.to_string()).collect())
+    }
+
+    #[test]
+    fn path_order_is_default() -> Result<(), String> {
+        assert_eq!(
+            finalize_with(FileOrder::default())?,
+            ["README.md", "src/a.rs", "src/z.rs"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn insertion_order() -> Result<(), String> {
+        assert_eq!(
+            finalize_with(FileOrder::Insertion)?,
+            ["src/z.rs", "README.md", "src/a.rs"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn custom_key_ties_keep_submission_order() -> Result<(), String> {
+        let by_depth = FileOrder::Key(Arc::new(|path: &str| path.matches('/').count().to_string()));
+        assert_eq!(
+            finalize_with(by_depth)?,
+            ["README.md", "src/z.rs", "src/a.rs"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn paths_follow_their_files_across_finalizes() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        for path in ["src/é.rs", "docs/日本.md", "src/a.rs", "src/é.rs"] {
+            files.add_file(path.to_string(), path.as_bytes().to_vec())?;
+        }
+        files.finalize()?;
+        files.add_file("build.rs".to_string(), b"build.rs".to_vec())?;
+        files.finalize()?;
+
+        let (files, _) = files.widen::<u16>();
+        let paths: Vec<_> = files.iter().map(|(_, path, _)| path).collect();
+        assert_eq!(paths, ["build.rs", "docs/日本.md", "src/a.rs", "src/é.rs"]);
+        for (id, path, content) in files.iter() {
+            assert_eq!(files.get_path(id), Some(path));
+            assert_eq!(files.get_id(path), Some(id));
+            assert_eq!(content, path.as_bytes());
+        }
+        assert_eq!(files.get_path(5), None);
+        assert_eq!(files.get_id("src/é"), None);
+        Ok(())
+    }
+
+    #[test]
+    // 5,000 files take too long under Miri
+    #[cfg_attr(miri, ignore)]
+    fn perfect_hash_lookup_finds_every_path() -> Result<(), String> {
+        let mut builder = SourceFilesMap::<u16>::builder().with_phf_lookup(true);
+        for i in 0..5000 {
+            builder.add_file(format!("src/mod{}/file{i}.rs", i % 37), Vec::new())?;
+        }
+        let mut files = builder.finalize()?;
+        assert!(files.has_phf_lookup());
+        for (id, path, _) in files.iter() {
+            assert_eq!(files.get_id(path), Some(id));
+        }
+        assert_eq!(files.get_id("src/mod0/file1.rs"), None);
+        assert_eq!(files.get_id(""), None);
+
+        files.add_file("build.rs".to_string(), Vec::new())?;
+        files.finalize()?;
+        assert!(files.has_phf_lookup());
+        assert_eq!(files.get_id("build.rs"), Some(1));
+        assert_eq!(files.get_id("src/mod0/file0.rs"), Some(2));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod streaming_ids {
+    use crate::*;
+
+    fn streaming(policy: DuplicatePolicy) -> SourceFilesMap<u8> {
+        SourceFilesMap::new()
+            .with_order(FileOrder::Insertion)
+            .with_duplicate_policy(policy)
+    }
+
+    #[test]
+    fn ids_usable_before_finalize() -> Result<(), String> {
+        let mut files = streaming(DuplicatePolicy::KeepFirst);
+        files.add_file("z.rs".to_string(), b"z".to_vec())?;
+        files.add_file("a.rs".to_string(), b"a".to_vec())?;
+        assert_eq!(files.get_id("z.rs"), Some(1));
+        assert_eq!(files.get_id("a.rs"), Some(2));
+        assert_eq!(files.get_content(2), Some(&b"a"[..]));
+
+        files.add_file("z.rs".to_string(), b"again".to_vec())?;
+        files.finalize()?;
+        assert_eq!(files.len(), 2);
+        assert_eq!(files.get_id("a.rs"), Some(2));
+        assert_eq!(files.get_content(1), Some(&b"z"[..]));
+        Ok(())
+    }
+
+    #[test]
+    fn keep_last_replaces_in_place() -> Result<(), String> {
+        let mut files = streaming(DuplicatePolicy::KeepLast);
+        files.add_file("a.rs".to_string(), b"old".to_vec())?;
+        files.add_file("b.rs".to_string(), b"b".to_vec())?;
+        files.add_file("a.rs".to_string(), b"new".to_vec())?;
+        files.finalize()?;
+        assert_eq!(files.get_id("a.rs"), Some(1));
+        assert_eq!(files.get_content(1), Some(&b"new"[..]));
+        Ok(())
+    }
+
+    #[test]
+    fn reject_reports_duplicates() -> Result<(), String> {
+        let mut files = streaming(DuplicatePolicy::Reject);
+        files.add_file("a.rs".to_string(), Vec::new())?;
+        assert_eq!(
+            files.add_file("a.rs".to_string(), Vec::new()),
+            Err(SourceFilesError::DuplicatePath {
+                path: "a.rs".to_string()
+            })
+        );
+
+        let mut sorted = SourceFilesMap::<u8>::new().with_duplicate_policy(DuplicatePolicy::Reject);
+        sorted.add_file("a.rs".to_string(), Vec::new())?;
+        sorted.add_file("a.rs".to_string(), Vec::new())?;
+        assert_eq!(
+            sorted.finalize(),
+            Err(SourceFilesError::DuplicatePath {
+                path: "a.rs".to_string()
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn insert_file_returns_viewable_ids() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("z.rs".to_string(), b"z".to_vec())?;
+        let id = files.insert_file("a.rs".to_string(), b"fn a() {}\nfn b() {}".to_vec())?;
+        assert_eq!(id, 2);
+        assert_eq!(files.get_id("z.rs"), Some(1));
+        assert_eq!(files.insert_file("a.rs".to_string(), Vec::new())?, id);
+        #[cfg(feature = "view")]
+        assert_eq!(
+            files.view(id, &CompactAbsolutePosition::new(id, 2, 1, 2, 4)),
+            Some(&b"fn b"[..])
+        );
+        files.finalize()?;
+        assert_eq!(files.get_id("a.rs"), Some(id));
+        Ok(())
+    }
+
+    #[test]
+    fn sorted_keep_last() -> Result<(), String> {
+        let mut files =
+            SourceFilesMap::<u8>::new().with_duplicate_policy(DuplicatePolicy::KeepLast);
+        files.add_file("b.rs".to_string(), b"old".to_vec())?;
+        files.add_file("a.rs".to_string(), Vec::new())?;
+        files.add_file("b.rs".to_string(), b"new".to_vec())?;
+        files.finalize()?;
+        assert_eq!(files.get_id("b.rs"), Some(2));
+        assert_eq!(files.get_content(2), Some(&b"new"[..]));
+        Ok(())
+    }
+
+    #[test]
+    fn streamed_files_thaw_perfect_hash_lookup() -> Result<(), String> {
+        let mut files = streaming(DuplicatePolicy::KeepFirst).with_phf_lookup(true);
+        files.add_file("z.rs".to_string(), b"z".to_vec())?;
+        files.finalize()?;
+        assert!(files.has_phf_lookup());
+        files.add_file("a.rs".to_string(), b"a".to_vec())?;
+        assert!(!files.has_phf_lookup());
+        assert_eq!(files.get_id("z.rs"), Some(1));
+        assert_eq!(files.get_id("a.rs"), Some(2));
+        files.finalize()?;
+        assert!(files.has_phf_lookup());
+        assert_eq!(files.get_id("a.rs"), Some(2));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod builder {
+    use crate::*;
+
+    #[test]
+    fn builds_a_finalized_map() -> Result<(), String> {
+        let mut builder = SourceFilesMap::<u8>::builder()
+            .with_duplicate_policy(DuplicatePolicy::KeepLast)
+            .file("b.rs".to_string(), b"b".to_vec())?
+            .file("a.rs".to_string(), b"a".to_vec())?
+            .file("gone.rs".to_string(), Vec::new())?;
+        builder.add_file("b.rs".to_string(), b"b2".to_vec())?;
+        assert!(builder.update_file("a.rs", b"a2".to_vec()));
+        assert!(!builder.update_file("missing.rs", Vec::new()));
+        assert!(builder.remove_file("gone.rs"));
+        assert_eq!(builder.len(), 3);
+
+        let files = builder.finalize()?;
+        assert_eq!(files.len(), 2);
+        assert_eq!(files.get_content(1), Some(&b"a2"[..]));
+        assert_eq!(files.get_content(2), Some(&b"b2"[..]));
+        Ok(())
+    }
+
+    #[test]
+    fn removal_shifts_insertion_ids() -> Result<(), String> {
+        let mut builder = SourceFilesMapBuilder::<u8>::new().with_order(FileOrder::Insertion);
+        for path in ["x.rs", "y.rs", "z.rs"] {
+            builder.add_file(path.to_string(), Vec::new())?;
+        }
+        builder.remove_file("x.rs");
+        let files = builder.finalize()?;
+        assert_eq!(files.get_id("z.rs"), Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn frozen_maps_are_shared_across_threads() -> Result<(), String> {
+        fn assert_shareable<T: Send + Sync + Clone>(_: &T) {}
+
+        let frozen = SourceFilesMap::<u8>::builder()
+            .file("a.rs".to_string(), b"fn a() {}".to_vec())?
+            .finalize()?
+            .freeze();
+        assert_shareable(&frozen);
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let frozen = frozen.clone();
+                std::thread::spawn(move || {
+                    frozen.get_content(frozen.get_id("a.rs")?).map(<[u8]>::len)
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().map_err(|_| "reader panicked")?, Some(9));
+        }
+
+        let copy = frozen.clone();
+        assert!(copy.ptr_eq(&frozen));
+        let mut next = copy.thaw();
+        next.add_file("b.rs".to_string(), Vec::new())?;
+        next.finalize()?;
+        assert_eq!((frozen.len(), next.len()), (1, 2));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod epochs {
+    use crate::*;
+
+    #[test]
+    fn refinalizing_invalidates_tagged_positions() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("b.rs".to_string(), b"fn b() {}".to_vec())?;
+        files.finalize()?;
+        let tagged = files.tag(AbsolutePosition::new(1, 1, 1, 1, 4));
+        assert_eq!(files.resolve(&tagged), Ok(tagged.pos));
+        #[cfg(feature = "view")]
+        assert_eq!(files.view_tagged(&tagged), Ok(Some(&b"fn b"[..])));
+
+        // "a.rs" now takes ID 1
+        files.add_file("a.rs".to_string(), b"fn a() {}".to_vec())?;
+        files.finalize()?;
+        assert_eq!(
+            files.resolve(&tagged),
+            Err(SourceFilesError::StaleEpoch {
+                found: 1,
+                current: 2
+            })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn insertion_order_keeps_its_epoch() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new().with_order(FileOrder::Insertion);
+        let id = files.insert_file("b.rs".to_string(), Vec::new())?;
+        let tagged = files.tag(AbsolutePosition::new(id, 1, 1, 1, 1));
+        files.insert_file("a.rs".to_string(), Vec::new())?;
+        files.finalize()?;
+        assert!(files.resolve(&tagged).is_ok());
+
+        let mut builder = SourceFilesMap::<u8>::builder().with_order(FileOrder::Insertion);
+        builder.add_file("x.rs".to_string(), Vec::new())?;
+        builder.add_file("y.rs".to_string(), Vec::new())?;
+        builder.remove_file("x.rs");
+        assert_eq!(builder.finalize()?.epoch(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn handles_resolve_in_their_generation() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("b.rs".to_string(), b"fn b() {}".to_vec())?;
+        files.finalize()?;
+        let handle = files.file(1).ok_or("b")?.handle();
+        assert_eq!(handle, files.handle(1));
+        assert_eq!(files.handle_id(handle), 1);
+        // Release builds carry the bare ID
+        #[cfg(not(debug_assertions))]
+        assert_eq!(size_of::<FileHandle<u8>>(), size_of::<u8>());
+        assert_eq!(
+            files.file_by_handle(handle).map(|file| file.path()),
+            Some("b.rs")
+        );
+        #[cfg(feature = "view")]
+        assert_eq!(
+            files.view_by_handle(handle, &AbsolutePosition::new(1u8, 1, 1, 1, 4)),
+            Some(&b"fn b"[..])
+        );
+        Ok(())
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "file handle from map generation 1 used in generation 2")]
+    fn refinalizing_invalidates_handles_in_debug_builds() {
+        let mut files = SourceFilesMap::<u8>::new();
+        files
+            .add_file("b.rs".to_string(), b"fn b() {}".to_vec())
+            .unwrap();
+        files.finalize().unwrap();
+        let handle = files.file(1).unwrap().handle();
+        // "a.rs" now takes ID 1
+        files
+            .add_file("a.rs".to_string(), b"fn a() {}".to_vec())
+            .unwrap();
+        files.finalize().unwrap();
+        files.file_by_handle(handle);
+    }
+}
+
+#[cfg(test)]
+mod cursor {
+    use crate::*;
+
+    #[test]
+    fn tracks_lines_and_columns() {
+        let mut cursor = Cursor::new(b"let x = 1;\n  foo(bar)\n");
+        assert_eq!(cursor.eat_while(|b| b.is_ascii_alphabetic()), b"let");
+        assert!(cursor.eat(b' '));
+        assert!(!cursor.eat(b' '));
+        assert_eq!((cursor.line(), cursor.column()), (1, 5));
+        assert_eq!(cursor.advance_by(7), b"x = 1;\n");
+        assert_eq!(
+            (cursor.line(), cursor.column(), cursor.offset()),
+            (2, 1, 11)
+        );
+        cursor.eat_while(|b| b == b' ');
+        assert_eq!(cursor.peek(), Some(b'f'));
+        assert_eq!(cursor.peek_nth(3), Some(b'('));
+        assert_eq!(cursor.advance(), Some(b'f'));
+        cursor.advance_by(100);
+        assert!(cursor.is_eof());
+        assert_eq!(cursor.advance(), None);
+        assert_eq!((cursor.line(), cursor.column()), (3, 1));
+    }
+
+    #[cfg(feature = "view")]
+    #[test]
+    fn spans_view_back_consumed_bytes() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file(
+            "a.rs".to_string(),
+            b"fn main() {\n    call();\n}\n".to_vec(),
+        )?;
+        files.finalize()?;
+        let file = files.file(1).ok_or("missing file")?;
+        let mut cursor = file.cursor();
+
+        let mut tokens = Vec::new();
+        while !cursor.is_eof() {
+            cursor.eat_while(|b| b.is_ascii_whitespace());
+            let mark = cursor.mark();
+            if cursor.eat_while(|b| b.is_ascii_alphanumeric()).is_empty() {
+                cursor.advance();
+            }
+            if cursor.offset() > mark.offset() {
+                let span = cursor.span_from(mark).ok_or("span overflow")?;
+                assert_eq!(file.view(&span), Some(cursor.consumed(mark)));
+                tokens.push(cursor.consumed(mark));
+            }
+        }
+        assert_eq!(tokens.len(), 10);
+
+        // Multi-line spans too, including a trailing line break
+        let mut cursor = file.cursor();
+        cursor.advance_by(10);
+        let mark = cursor.mark();
+        cursor.advance_by(14);
+        let span = cursor.span_from(mark).ok_or("span overflow")?;
+        assert_eq!(file.view(&span), Some(&b"{\n    call();\n"[..]));
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "view"))]
+mod byte_positions {
+    use super::test_utils::finalized;
+    use crate::*;
+
+    fn sample() -> Result<SourceFilesMap<u8>, String> {
+        finalized(
+            SourceFilesMap::new(),
+            &[("a.rs", "let a = 1;\nlet bb = 22;\n")],
+        )
+    }
+
+    #[test]
+    fn contiguous_files_are_one_chunk() -> Result<(), String> {
+        let mut files = sample()?;
+        let chunks: Vec<&[u8]> = files.content_chunks(1).collect();
+        assert_eq!(chunks, [files.get_content(1).unwrap()]);
+        assert_eq!(files.content_chunks(2).count(), 0);
+        let view = files.view_cow(1, &RelativePosition::new(2, 5, 2, 6));
+        assert!(matches!(view, Some(std::borrow::Cow::Borrowed(b"bb"))));
+        files.insert_file("empty.rs".to_string(), Vec::new())?;
+        assert_eq!(files.content_chunks(2).count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn failed_views_say_why() -> Result<(), String> {
+        let files = sample()?;
+        assert_eq!(
+            files.try_view(1, &RelativePosition::new(2, 5, 2, 6)),
+            Ok(&b"bb"[..])
+        );
+        assert_eq!(
+            files.try_view(1, &AbsolutePosition::<u8>::new(2, 1, 1, 1, 1)),
+            Err(SourceFilesError::FileMismatch {
+                expected: 1,
+                found: 2
+            })
+        );
+        assert_eq!(
+            files.try_view(2, &RelativePosition::new(1, 1, 1, 1)),
+            Err(SourceFilesError::UnknownFile { id: 2 })
+        );
+        let past_end = RelativePosition::new(9, 1, 9, 2);
+        assert_eq!(
+            files.try_view(1, &past_end),
+            Err(SourceFilesError::InvalidPosition {
+                id: 1,
+                position: past_end
+            })
+        );
+        assert_eq!(files.view(1, &past_end), None);
+        Ok(())
+    }
+
+    #[test]
+    fn exclusive_ends_view_the_same_bytes() -> Result<(), String> {
+        let files = sample()?;
+        let pos = files.position(1, 15..17).ok_or("position")?;
+        assert_eq!(pos, AbsolutePosition::new(1, 2, 5, 2, 6));
+        let exclusive = files
+            .position_as(1, 15..17, RangeSemantics::Exclusive)
+            .ok_or("exclusive")?;
+        assert_eq!(exclusive, AbsolutePosition::new(1, 2, 5, 2, 7));
+        assert_eq!(exclusive.to_inclusive(), Some(pos));
+        assert_eq!(
+            files.view_as(1, &exclusive, RangeSemantics::Exclusive),
+            Some(&b"bb"[..])
+        );
+        assert_eq!(
+            files.view_as(1, &pos, RangeSemantics::Inclusive),
+            Some(&b"bb"[..])
+        );
+        assert_eq!(
+            files.view_as(2, &exclusive, RangeSemantics::Exclusive),
+            None
+        );
+
+        let rel = RelativePosition::new(1, 1, 1, u8::MAX);
+        assert_eq!(rel.to_exclusive(), None);
+        assert_eq!(rel.with_end_column(0).to_inclusive(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn empty_files_and_spans_follow_the_truth_table() -> Result<(), String> {
+        use std::ops::Range;
+        let mut files = SourceFilesMap::<u8>::new().with_order(FileOrder::Insertion);
+        for content in ["", "ab", "ab\n"] {
+            files.add_file(format!("{}.txt", content.len()), content.into())?;
+        }
+        files.finalize()?;
+        let cases = [
+            (1, 0..0, Some((1, 1, 1, 0))),
+            (1, 0..1, None),
+            (2, 1..1, Some((1, 2, 1, 1))),
+            (2, 2..2, Some((1, 3, 1, 2))),
+            (3, 3..3, Some((2, 1, 2, 0))),
+            (2, Range { start: 2, end: 1 }, None),
+        ];
+        for (id, range, expected) in cases {
+            let pos = files.position(id, range.clone());
+            let expected =
+                expected.map(|(sl, sc, el, ec)| AbsolutePosition::new(id, sl, sc, el, ec));
+            assert_eq!(pos, expected, "{range:?} of file {id}");
+            if let Some(pos) = pos {
+                assert_eq!(
+                    files.view(id, &pos),
+                    Some(&b""[..]),
+                    "{range:?} of file {id}"
+                );
+                assert_eq!(files.file(id).ok_or("file")?.byte_range(&pos), Some(range));
+            }
+        }
+        assert_eq!(files.view(2, &RelativePosition::new(0, 1, 1, 1)), None);
+
+        let empty = clo::CompactLineOffsets::compute(b"");
+        assert_eq!(empty.line_count(), 1);
+        assert_eq!(empty.get_line_range(1), Some((0, 0)));
+        assert_eq!(empty.locate(0), Some((1, 0)));
+        assert_eq!(empty.locate(1), None);
+        Ok(())
+    }
+
+    #[test]
+    fn content_handles_outlive_edits_and_the_map() -> Result<(), String> {
+        let mut files = sample()?;
+        let handle = files.content_arc(1).ok_or("missing")?;
+        assert!(std::sync::Arc::ptr_eq(
+            &handle,
+            &files.content_arc(1).ok_or("missing")?
+        ));
+        assert!(files.content_arc(2).is_none());
+        let original = handle.to_vec();
+        let worker = std::thread::spawn(move || handle.len());
+
+        assert!(files.replace_range(1, 0..1, b"x"));
+        let edited = files.content_arc(1).ok_or("missing")?;
+        assert_eq!(edited.as_ref(), files.get_content(1).ok_or("missing")?);
+        drop(files);
+        assert_eq!(
+            worker.join().map_err(|_| "worker panicked")?,
+            original.len()
+        );
+        assert_eq!(edited[0], b'x');
+        Ok(())
+    }
+
+    #[test]
+    fn ranges_round_trip_through_view() -> Result<(), String> {
+        let files = sample()?;
+        let content = files.get_content(1).ok_or("missing")?;
+        for range in [0..3, 4..5, 15..17, 8..15, 0..content.len(), 11..11] {
+            let pos = files.position(1, range.clone()).ok_or("unencodable")?;
+            assert_eq!(pos.file_id(), 1);
+            if range.is_empty() {
+                continue;
+            }
+            assert_eq!(files.view(1, &pos), Some(&content[range]));
+        }
+        assert_eq!(files.position(1, 0..100), None);
+        assert_eq!(files.position(2, 0..1), None);
+        Ok(())
+    }
+
+    #[cfg(feature = "logos")]
+    #[test]
+    fn logos_tokens_carry_positions() -> Result<(), String> {
+        #[derive(logos::Logos, Debug, PartialEq)]
+        #[logos(source = [u8], skip r"[ \t\n]+")]
+        enum Token {
+            #[token(b"let")]
+            Let,
+            #[regex(b"[a-z]+")]
+            Ident,
+            #[regex(b"[0-9]+")]
+            Number,
+            #[token(b"=")]
+            Eq,
+            #[token(b";")]
+            Semi,
+        }
+
+        let files = sample()?;
+        let file = files.file(1).ok_or("missing")?;
+        let tokens: Vec<_> = file.lex::<Token>().collect();
+        assert_eq!(tokens.len(), 10);
+        let (token, pos) = &tokens[8];
+        assert_eq!(token, &Ok(Token::Number));
+        let pos = pos.ok_or("unencodable")?;
+        assert_eq!((pos.start_line(), pos.start_column()), (2, 10));
+        assert_eq!(files.view(1, &pos), Some(&b"22"[..]));
+        Ok(())
+    }
+
+    #[cfg(feature = "nom")]
+    #[test]
+    fn nom_outputs_know_their_position() -> Result<(), String> {
+        use nom::bytes::complete::{tag, take_while1};
+        use nom::character::complete::multispace0;
+        use nom::sequence::{delimited, preceded};
+        use nom::{IResult, Parser};
+
+        type Input<'a> = PositionedInput<'a, u8>;
+        fn binding(input: Input<'_>) -> IResult<Input<'_>, (Input<'_>, Input<'_>)> {
+            let name = preceded(
+                (multispace0, tag("let ")),
+                take_while1(|b: u8| b.is_ascii_lowercase()),
+            );
+            let value = delimited(
+                tag(" = "),
+                take_while1(|b: u8| b.is_ascii_digit()),
+                tag(";"),
+            );
+            (name, value).parse(input)
+        }
+
+        let files = sample()?;
+        let file = files.file(1).ok_or("missing")?;
+        let input = file.nom_input();
+        let (rest, _) = binding(input).map_err(|e| e.to_string())?;
+        let (rest, (name, value)) = binding(rest).map_err(|e| e.to_string())?;
+        assert_eq!(name.fragment(), b"bb");
+        let pos = name.to_position().ok_or("unencodable")?;
+        assert_eq!((pos.start_line(), pos.start_column()), (2, 5));
+        assert_eq!(files.view(1, &pos), Some(&b"bb"[..]));
+        assert_eq!(
+            files.view(1, &value.to_position().ok_or("unencodable")?),
+            Some(&b"22"[..])
+        );
+        assert_eq!(rest.location_offset(), 23);
+        let all = input.position_until(&rest).ok_or("unencodable")?;
+        assert_eq!(files.view(1, &all), Some(&b"let a = 1;\nlet bb = 22;"[..]));
+        Ok(())
+    }
+
+    #[cfg(feature = "chumsky")]
+    #[test]
+    fn chumsky_spans_and_errors() -> Result<(), String> {
+        use chumsky::prelude::*;
+
+        let files = sample()?;
+        let file = files.file(1).ok_or("missing")?;
+        let source = std::str::from_utf8(file.content()).map_err(|e| e.to_string())?;
+
+        let positions: Vec<_> = file
+            .spanned_positions([("let", SimpleSpan::from(11..14))])
+            .collect();
+        let pos = positions[0].1.ok_or("unencodable")?;
+        assert_eq!(files.view(1, &pos), Some(&b"let"[..]));
+
+        let parser = just::<_, _, extra::Err<Rich<char>>>("let a = 1;\nlet bb = 2;");
+        let errors = parser.parse(source).into_errors();
+        let pos = file.error_position(&errors[0]).ok_or("unencodable")?;
+        assert_eq!((pos.start_line(), pos.start_column()), (2, 11));
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "diff"))]
+mod porting {
+    use crate::*;
+
+    const OLD: &[u8] = b"use a;\nfn main() {\n    let x = 1;\n    call(x);\n}\n";
+    const NEW: &[u8] = b"// header\nuse a;\nuse b;\nfn main() {\n    let x = 2;\n    call(x);\n}\n";
+
+    #[test]
+    fn unchanged_spans_follow_inserted_lines() {
+        let call = CompactAbsolutePosition::new(1, 4, 5, 4, 12);
+        assert_eq!(
+            port_position(OLD, NEW, &call),
+            Some(CompactAbsolutePosition::new(1, 6, 5, 6, 12))
+        );
+        let body = CompactAbsolutePosition::new(1, 4, 1, 5, 1);
+        assert_eq!(
+            port_position(OLD, NEW, &body),
+            Some(CompactAbsolutePosition::new(1, 6, 1, 7, 1))
+        );
+    }
+
+    #[test]
+    fn edited_spans_are_dropped() {
+        let porter = LinePorter::new(OLD, NEW);
+        // The `let` line changed
+        assert_eq!(
+            porter.port(&CompactAbsolutePosition::new(1, 3, 5, 3, 14)),
+            None
+        );
+        // Both ends survived, but "use b;" was inserted in between
+        assert_eq!(
+            porter.port(&CompactAbsolutePosition::new(1, 1, 1, 2, 4)),
+            None
+        );
+        assert_eq!(porter.port_line(1), Some(2));
+        assert_eq!(porter.port_line(0), None);
+        assert_eq!(porter.port_line(99), None);
+    }
+
+    #[test]
+    fn spans_over_an_edited_middle_line_are_dropped() {
+        let span = CompactAbsolutePosition::new(1, 1, 1, 3, 1);
+        assert_eq!(port_position(b"a\nb\nc", b"a\nX\nc", &span), None);
+        assert_eq!(port_position(b"a\nb\nc", b"a\nb\nc", &span), Some(span));
+    }
+
+    #[test]
+    fn pending_diff_shows_unflushed_edits() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("src/main.rs".to_string(), OLD.to_vec())?;
+        files.add_file("src/lib.rs".to_string(), b"pub mod a;\n".to_vec())?;
+        files.finalize()?;
+        assert_eq!(files.pending_diff_all(), "");
+        let id = files.get_id("src/main.rs").ok_or("missing file")?;
+        assert!(files.replace_range(id, 31..32, b"2"));
+        assert_eq!(
+            files.pending_diff(id),
+            "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,5 +1,5 @@\n use a;\n fn main() {\n-    let x = 1;\n+    let x = 2;\n     call(x);\n }\n"
+        );
+        assert_eq!(files.pending_diff_all(), files.pending_diff(id));
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "view"))]
+mod baseline {
+    use super::test_utils::finalized;
+    use crate::*;
+
+    fn map_with(content: &str) -> Result<SourceFilesMap<u8>, String> {
+        finalized(SourceFilesMap::new(), &[("lib.rs", content)])
+    }
+
+    #[test]
+    fn fingerprints_survive_line_shifts() -> Result<(), String> {
+        let before = map_with("fn a() {\n    let unused = 1;\n}\n")?;
+        let finding = AbsolutePosition::new(1, 2, 9, 2, 14);
+        let baseline = Baseline::from_positions(&before, [&finding]);
+        assert_eq!(baseline.len(), 1);
+        assert!(baseline.is_suppressed(&before, &finding));
+
+        // Code inserted above and reindented: same finding, new line
+        let after = map_with("use x;\n\nfn a() {\n        let unused = 1;\n}\n")?;
+        let moved = AbsolutePosition::new(1, 4, 13, 4, 18);
+        assert_eq!(after.view(1, &moved), Some(&b"unused"[..]));
+        assert!(baseline.is_suppressed(&after, &moved));
+        assert_eq!(
+            Fingerprint::of(&before, &finding),
+            Fingerprint::of(&after, &moved)
+        );
+
+        // The same text in another context is a new finding
+        let other = map_with("fn b() {\n    let unused = 1;\n}\n")?;
+        assert!(!baseline.is_suppressed(&other, &finding));
+        assert!(!baseline.is_suppressed(&after, &AbsolutePosition::new(9, 1, 1, 1, 1)));
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn baselines_serialize() -> Result<(), String> {
+        let files = map_with("a\nb\nc\n")?;
+        let baseline = Baseline::from_positions(&files, &[AbsolutePosition::new(1, 2, 1, 2, 1)]);
+        let bytes = postcard::to_allocvec(&baseline).map_err(|e| e.to_string())?;
+        let decoded: Baseline = postcard::from_bytes(&bytes).map_err(|e| e.to_string())?;
+        assert_eq!(decoded, baseline);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "view"))]
+mod ignore_comments {
+    use crate::*;
+
+    #[test]
+    fn directives_suppress_rules() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file(
+            "a.rs".to_string(),
+            b"// sourcier-ignore-next-line[unused, shadow]\nlet x = 1;\nlet y = 2; // sourcier-ignore-line\nlet z = 3;\n// sourcier-ignore-linter\n".to_vec(),
+        )?;
+        files.add_file(
+            "b.py".to_string(),
+            b"# sourcier-ignore-file[todo]\nx = 1\n".to_vec(),
+        )?;
+        files.add_file("c.rs".to_string(), b"let x = 1;\n".to_vec())?;
+        files.finalize()?;
+        let index = IgnoreScanner::default().scan(&files);
+        assert_eq!(index.files(), 2);
+
+        let at = |id, line| AbsolutePosition::new(id, line, 1, line, 3);
+        assert!(index.is_ignored(&at(1, 2), "unused"));
+        assert!(index.is_ignored(&at(1, 2), "shadow"));
+        assert!(!index.is_ignored(&at(1, 2), "other"));
+        assert!(index.is_ignored(&at(1, 3), "anything"));
+        assert!(!index.is_ignored(&at(1, 4), "unused"));
+        // A longer word starting with the directive is not a directive
+        assert!(!index.is_ignored(&at(1, 5), "anything"));
+        assert!(index.is_ignored(&at(2, 2), "todo"));
+        assert!(!index.is_ignored(&at(2, 2), "unused"));
+        assert!(!index.is_ignored(&at(3, 1), "unused"));
+        Ok(())
+    }
+
+    #[test]
+    fn custom_syntax() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file(
+            "a.sql".to_string(),
+            b"SELECT 1; -- lint:allow-line[star]\n".to_vec(),
+        )?;
+        files.finalize()?;
+        let index = IgnoreScanner::new(IgnoreConfig {
+            comment_prefixes: vec!["--".to_string()],
+            directive: "lint:allow".to_string(),
+        })
+        .scan(&files);
+        assert!(index.is_ignored(&AbsolutePosition::new(1, 1, 1, 1, 6), "star"));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod diagnostics {
+    use crate::*;
+
+    fn bag() -> DiagnosticBag<u8> {
+        [
+            Diagnostic::warning("unused variable", AbsolutePosition::new(2, 3, 5, 3, 6))
+                .with_code("unused"),
+            Diagnostic::error("mismatched types", AbsolutePosition::new(1, 10, 1, 10, 4))
+                .with_code("E0308")
+                .with_label(AbsolutePosition::new(1, 2, 1, 2, 3), "expected due to this")
+                .with_note("expected `u8`, found `&str`"),
+            Diagnostic::new(
+                Severity::Help,
+                "remove it",
+                AbsolutePosition::new(2, 3, 5, 3, 6),
+            )
+            .with_fix(AbsolutePosition::new(2, 3, 1, 3, 10), ""),
+            Diagnostic::error("cannot borrow", AbsolutePosition::new(2, 3, 5, 3, 6)),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn filters_and_counts() {
+        let bag = bag();
+        assert!(bag.has_errors());
+        assert_eq!(bag.count(Severity::Error), 2);
+        assert_eq!(bag.at_least(Severity::Warning).count(), 3);
+        assert_eq!(bag.in_file(2).count(), 3);
+        assert_eq!(
+            bag.with_code("E0308").next().map(|d| d.labels.len()),
+            Some(1)
+        );
+
+        let mut bag = bag;
+        bag.retain(|d| d.severity != Severity::Error);
+        assert!(!bag.has_errors());
+    }
+
+    #[test]
+    fn sorts_by_location_then_severity() {
+        let mut bag = bag();
+        bag.sort();
+        let order: Vec<_> = bag.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            order,
+            [
+                "mismatched types",
+                "cannot borrow",
+                "unused variable",
+                "remove it"
+            ]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn round_trips_through_serde() -> Result<(), String> {
+        let bag = bag();
+        let bytes = postcard::to_allocvec(&bag).map_err(|e| e.to_string())?;
+        let decoded: DiagnosticBag<u8> = postcard::from_bytes(&bytes).map_err(|e| e.to_string())?;
+        assert_eq!(decoded, bag);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "sarif"))]
+mod sarif {
+    use crate::*;
+
+    #[test]
+    fn log_with_regions_snippets_and_fixes() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file(
+            "src/main.rs".to_string(),
+            "let é = 1;\nlet x = é;\n".as_bytes().to_vec(),
+        )?;
+        files.finalize()?;
+        let bag: DiagnosticBag<u8> = [
+            Diagnostic::warning("unused variable", AbsolutePosition::new(1, 2, 5, 2, 5))
+                .with_code("unused")
+                .with_label(AbsolutePosition::new(1, 1, 5, 1, 6), "declared here")
+                .with_fix(AbsolutePosition::new(1, 2, 5, 2, 5), "_x"),
+            Diagnostic::error("bad", AbsolutePosition::new(7, 1, 1, 1, 1)).with_code("unused"),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut out = Vec::new();
+        bag.write_sarif(
+            &mut out,
+            &files,
+            SarifDriver::new("lint").with_version("1.0"),
+        )
+        .map_err(|e| e.to_string())?;
+        let log: serde_json::Value = serde_json::from_slice(&out).map_err(|e| e.to_string())?;
+        assert_eq!(log["version"], "2.1.0");
+        let run = &log["runs"][0];
+        assert_eq!(
+            run["tool"]["driver"]["rules"].as_array().map(Vec::len),
+            Some(1)
+        );
+
+        let result = &run["results"][0];
+        assert_eq!(result["ruleId"], "unused");
+        assert_eq!(result["level"], "warning");
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/main.rs");
+        assert_eq!(location["region"]["startLine"], 2);
+        assert_eq!(location["region"]["startColumn"], 5);
+        assert_eq!(location["region"]["endColumn"], 6);
+        assert_eq!(location["region"]["snippet"]["text"], "x");
+
+        // `é` is two bytes but one UTF-16 unit
+        let related = &result["relatedLocations"][0];
+        assert_eq!(related["message"]["text"], "declared here");
+        assert_eq!(related["physicalLocation"]["region"]["startColumn"], 5);
+        assert_eq!(related["physicalLocation"]["region"]["endColumn"], 6);
+        assert_eq!(
+            related["physicalLocation"]["region"]["snippet"]["text"],
+            "é"
+        );
+
+        let replacement = &result["fixes"][0]["artifactChanges"][0]["replacements"][0];
+        assert_eq!(replacement["insertedContent"]["text"], "_x");
+
+        assert_eq!(run["results"][1]["level"], "error");
+        assert_eq!(
+            run["results"][1]["locations"].as_array().map(Vec::len),
+            Some(0)
+        );
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "lsp"))]
+mod lsp {
+    use crate::*;
+    use lsp_types::{DiagnosticSeverity, NumberOrString, Position, Range, Uri};
+    use std::str::FromStr;
+
+    fn uri(path: &str) -> Option<Uri> {
+        Uri::from_str(&format!("file:///work/{path}")).ok()
+    }
+
+    #[test]
+    fn converts_with_utf16_ranges_and_related_information() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("a.rs".to_string(), "let 😀 = é;\n".as_bytes().to_vec())?;
+        files.add_file("b.rs".to_string(), b"use a;\n".to_vec())?;
+        files.finalize()?;
+        // `é` spans bytes 11..13 of line 1
+        let bag: DiagnosticBag<u8> = [
+            Diagnostic::error("bad value", AbsolutePosition::new(1, 1, 12, 1, 13))
+                .with_code("E1")
+                .with_note("values must be ascii")
+                .with_label(AbsolutePosition::new(2, 1, 5, 1, 5), "imported here")
+                .with_label(AbsolutePosition::new(9, 1, 1, 1, 1), "unknown file"),
+            Diagnostic::warning("elsewhere", AbsolutePosition::new(2, 1, 1, 1, 3)),
+        ]
+        .into_iter()
+        .collect();
+
+        let diagnostics = bag.to_lsp_diagnostics(&files, 1, uri);
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = &diagnostics[0];
+        // The emoji is 4 bytes but 2 UTF-16 units
+        assert_eq!(
+            diagnostic.range,
+            Range::new(Position::new(0, 9), Position::new(0, 10))
+        );
+        assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(diagnostic.code, Some(NumberOrString::String("E1".into())));
+        assert_eq!(diagnostic.message, "bad value\nvalues must be ascii");
+        let related = diagnostic.related_information.clone().unwrap_or_default();
+        assert_eq!(related.len(), 1);
+        assert_eq!(related[0].location.uri.as_str(), "file:///work/b.rs");
+        assert_eq!(
+            related[0].location.range,
+            Range::new(Position::new(0, 4), Position::new(0, 5))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn converts_watched_file_events() {
+        let event = |uri: &str, typ| lsp_types::FileEvent::new(Uri::from_str(uri).unwrap(), typ);
+        let created = event(
+            "file:///work/src/new%20file.rs",
+            lsp_types::FileChangeType::CREATED,
+        );
+        assert_eq!(
+            FileEvent::from_lsp(&created, "/work"),
+            Some(FileEvent::new("src/new file.rs", FileChange::Created))
+        );
+        let outside = event("file:///other/a.rs", lsp_types::FileChangeType::DELETED);
+        assert_eq!(FileEvent::from_lsp(&outside, "/work"), None);
+        let remote = event(
+            "https://example.com/a.rs",
+            lsp_types::FileChangeType::CHANGED,
+        );
+        assert_eq!(FileEvent::from_lsp(&remote, "/"), None);
+    }
+}
+
+#[cfg(all(test, feature = "view"))]
+mod fixes {
+    use crate::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Default)]
+    struct Edits(AtomicUsize);
+
+    impl MapObserver<u8> for Edits {
+        fn on_edit(&self, _id: u8, _old_size: usize, _new_size: usize) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn applies_in_reverse_and_reports_conflicts() -> Result<(), String> {
+        let edits = Arc::new(Edits::default());
+        let mut files = SourceFilesMap::<u8>::new().with_observer(edits.clone());
+        files.add_file(
+            "a.rs".to_string(),
+            b"let x = 1;\nlet y = x;\nuse(y);\n".to_vec(),
+        )?;
+        files.finalize()?;
+        let bag: DiagnosticBag<u8> = [
+            Diagnostic::warning("rename", AbsolutePosition::new(1, 1, 5, 1, 5))
+                .with_fix(AbsolutePosition::new(1, 1, 5, 1, 5), "value")
+                .with_fix(AbsolutePosition::new(1, 2, 9, 2, 9), "value"),
+            Diagnostic::warning("split", AbsolutePosition::new(1, 1, 9, 1, 9))
+                .with_fix(AbsolutePosition::new(1, 1, 9, 1, 9), "{\n    1\n}"),
+            Diagnostic::warning("clash", AbsolutePosition::new(1, 2, 9, 2, 9))
+                .with_fix(AbsolutePosition::new(1, 2, 5, 2, 9), "z = x"),
+            Diagnostic::warning("gone", AbsolutePosition::new(1, 9, 1, 9, 1))
+                .with_fix(AbsolutePosition::new(1, 9, 1, 9, 1), ""),
+        ]
+        .into_iter()
+        .collect();
+        let fixes: Vec<Fix<u8>> = bag.fixes().cloned().collect();
+        let used = AbsolutePosition::new(1, 3, 5, 3, 5);
+        assert_eq!(files.view(1, &used), Some(&b"y"[..]));
+
+        let report = apply_fixes(&mut files, &fixes);
+        assert_eq!(report.conflicts, [3]);
+        assert_eq!(report.unresolved, [4]);
+        assert!(!report.is_clean());
+        assert_eq!(
+            files.get_content(1),
+            Some(&b"let value = {\n    1\n};\nlet y = value;\nuse(y);\n"[..])
+        );
+        assert_eq!(edits.0.load(Ordering::Relaxed), 1);
+
+        let applied: Vec<_> = report
+            .applied
+            .iter()
+            .map(|(index, pos)| (*index, pos.and_then(|pos| files.view(1, &pos))))
+            .collect();
+        assert_eq!(
+            applied,
+            [
+                (0, Some(&b"value"[..])),
+                (1, Some(&b"value"[..])),
+                (2, Some(&b"{\n    1\n}"[..])),
+            ]
+        );
+        let moved = report.remap.apply(&used);
+        assert_eq!(moved.and_then(|pos| files.view(1, &pos)), Some(&b"y"[..]));
+        Ok(())
+    }
+
+    #[test]
+    fn plans_batches_and_applies_them_in_turn() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("a.rs".to_string(), b"let x = 1;\n".to_vec())?;
+        files.add_file("b.rs".to_string(), b"let x = 2;\n".to_vec())?;
+        files.finalize()?;
+        let fixes = [
+            Fix {
+                span: AbsolutePosition::new(1, 1, 5, 1, 5),
+                replacement: "y".to_string(),
+            },
+            Fix {
+                span: AbsolutePosition::new(1, 1, 1, 1, 5),
+                replacement: "const x".to_string(),
+            },
+            Fix {
+                span: AbsolutePosition::new(2, 1, 5, 1, 5),
+                replacement: "z".to_string(),
+            },
+            Fix {
+                span: AbsolutePosition::new(1, 1, 6, 1, 5),
+                replacement: ": u8".to_string(),
+            },
+            Fix {
+                span: AbsolutePosition::new(1, 1, 6, 1, 5),
+                replacement: ": i32".to_string(),
+            },
+        ];
+        let plan = plan_fixes(&fixes);
+        assert!(!plan.is_conflict_free());
+        assert_eq!(plan.batches, [vec![0, 2, 3], vec![1, 4]]);
+        let pairs: Vec<_> = plan.conflicts.iter().map(|c| (c.first, c.second)).collect();
+        assert_eq!(pairs, [(0, 1), (3, 4)]);
+        assert_eq!(plan.conflicts[0].second_span, fixes[1].span);
+
+        let report = apply_fixes(&mut files, &plan.batch(0, &fixes));
+        assert!(report.is_clean());
+        assert_eq!(files.get_content(1), Some(&b"let y: u8 = 1;\n"[..]));
+        assert_eq!(files.get_content(2), Some(&b"let z = 2;\n"[..]));
+        let rest: Vec<Fix<u8>> = plan
+            .batch(1, &fixes)
+            .into_iter()
+            .filter_map(|fix| {
+                Some(Fix {
+                    span: report.remap.apply(&fix.span)?,
+                    replacement: fix.replacement,
+                })
+            })
+            .collect();
+        // The rewrite of `let x` overlapped the first batch, the insertion moved
+        assert_eq!(rest.len(), 1);
+        assert!(apply_fixes(&mut files, &rest).is_clean());
+        assert_eq!(files.get_content(1), Some(&b"let y: i32: u8 = 1;\n"[..]));
+        Ok(())
+    }
+
+    #[test]
+    fn flushes_edits_keeping_line_endings_and_bom() -> Result<(), String> {
+        let dir = std::env::temp_dir().join(format!("sourcier-flush-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let original = b"\xef\xbb\xbfone\r\ntwo\r\n".to_vec();
+        std::fs::write(dir.join("a.txt"), &original).map_err(|e| e.to_string())?;
+
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("a.txt".to_string(), original.clone())?;
+        files.add_file("b.txt".to_string(), b"untouched".to_vec())?;
+        files.finalize()?;
+        assert!(files.edited_files().is_empty());
+        // Drop the BOM and insert a line with a bare line feed
+        assert!(files.replace_range(1, 0..3, b""));
+        assert!(files.replace_range(1, 5..5, b"1.5\n"));
+        assert_eq!(files.edited_files(), [1]);
+        assert_eq!(files.original_content(1), Some(original.as_slice()));
+
+        let options = WriteOptions {
+            root: Some(dir.clone()),
+            ..WriteOptions::default()
+        };
+        let written = files
+            .flush_to_disk(None, &options)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(written, [1]);
+        assert!(!files.is_edited(1));
+        let on_disk = std::fs::read(dir.join("a.txt")).map_err(|e| e.to_string())?;
+        std::fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+        assert_eq!(on_disk, b"\xef\xbb\xbfone\r\n1.5\r\ntwo\r\n");
+        assert!(!dir.join("b.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn renames_through_the_definition_index() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file(
+            "a.rs".to_string(),
+            b"fn area(w: u32) -> u32 {\n    w * w\n}\nlet s = area(2);\n".to_vec(),
+        )?;
+        files.finalize()?;
+        let mut index = DefinitionIndex::new();
+        index.define("area", AbsolutePosition::new(1, 1, 4, 1, 7));
+        index.reference("area", AbsolutePosition::new(1, 4, 9, 4, 12));
+        index.define("w", AbsolutePosition::new(1, 1, 9, 1, 9));
+        index.reference("w", AbsolutePosition::new(1, 2, 5, 2, 5));
+        index.reference("w", AbsolutePosition::new(1, 2, 9, 2, 9));
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.references_of(&"w").len(), 2);
+
+        // Cursor right after the `w` of `w * w`
+        let cursor = AbsolutePosition::new(1, 2, 6, 2, 5);
+        assert_eq!(
+            index.definition_at(&cursor),
+            Some((&"w", Some(AbsolutePosition::new(1, 1, 9, 1, 9))))
+        );
+        assert_eq!(
+            index.definition_at(&AbsolutePosition::new(1, 3, 1, 3, 0)),
+            None
+        );
+
+        let fixes = index.rename(&"w", "width");
+        assert_eq!(fixes.len(), 3);
+        assert!(apply_fixes(&mut files, &fixes).is_clean());
+        assert_eq!(
+            files.get_content(1),
+            Some(&b"fn area(width: u32) -> u32 {\n    width * width\n}\nlet s = area(2);\n"[..])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn replace_range_reindexes_lines() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("a.txt".to_string(), b"one two".to_vec())?;
+        files.finalize()?;
+        assert!(files.replace_range(1, 3..4, b"\n"));
+        assert!(!files.replace_range(1, 5..99, b""));
+        assert_eq!(files.line_count(1), Some(2));
+        let file = files.file(1).ok_or("missing file")?;
+        assert_eq!(file.line(2), Some(&b"two"[..]));
+        let end = file.position(7..7).ok_or("no position")?;
+        assert_eq!(file.byte_range(&end), Some(7..7));
+        Ok(())
+    }
+
+    #[test]
+    fn fixes_append_at_the_end_of_file() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new().with_order(FileOrder::Insertion);
+        files.add_file("bare.rs".to_string(), b"a\nbc".to_vec())?;
+        files.add_file("newline.rs".to_string(), b"a\nbc\n".to_vec())?;
+        files.add_file("empty.rs".to_string(), Vec::new())?;
+        files.finalize()?;
+
+        let eof = |files: &SourceFilesMap<u8>, id| files.eof_position(id).ok_or("eof");
+        assert_eq!(eof(&files, 1)?, AbsolutePosition::new(1, 2, 3, 2, 2));
+        assert_eq!(eof(&files, 2)?, AbsolutePosition::new(2, 3, 1, 3, 0));
+        assert_eq!(eof(&files, 3)?, AbsolutePosition::new(3, 1, 1, 1, 0));
+        assert_eq!(files.eof_position(4), None);
+        for id in 1..=3 {
+            assert_eq!(files.view(id, &eof(&files, id)?), Some(&b""[..]));
+        }
+        let bare = files.file(1).ok_or("bare")?;
+        assert_eq!(bare.line(2), Some(&b"bc"[..]));
+        assert_eq!(bare.line(3), None);
+        assert_eq!(files.file(2).ok_or("newline")?.line(3), Some(&b""[..]));
+
+        let fixes: Vec<Fix<u8>> = (1..=3)
+            .map(|id| {
+                Ok(Fix {
+                    span: eof(&files, id)?,
+                    replacement: "end\n".to_string(),
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        assert!(apply_fixes(&mut files, &fixes).is_clean());
+        assert_eq!(files.get_content(1), Some(&b"a\nbcend\n"[..]));
+        assert_eq!(files.get_content(2), Some(&b"a\nbc\nend\n"[..]));
+        assert_eq!(files.get_content(3), Some(&b"end\n"[..]));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod named_ranges {
+    use crate::*;
+
+    #[test]
+    fn lookup_prefixes_and_remapping() {
+        let mut names: NamedRanges<u8> = [
+            ("fn:parse_expr", AbsolutePosition::new(1, 3, 1, 9, 1)),
+            ("fn:parse_stmt", AbsolutePosition::new(1, 12, 1, 20, 1)),
+            ("struct:Token", AbsolutePosition::new(2, 1, 1, 4, 1)),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(names.len(), 3);
+        assert_eq!(
+            names.get("struct:Token"),
+            Some(AbsolutePosition::new(2, 1, 1, 4, 1))
+        );
+        let fns: Vec<_> = names.with_prefix("fn:").map(|(key, _)| key).collect();
+        assert_eq!(fns, ["fn:parse_expr", "fn:parse_stmt"]);
+        assert_eq!(names.in_file(2).count(), 1);
+
+        // Two lines inserted inside parse_expr
+        let mut edits = EditRemap::new();
+        edits.record(1, RelativePosition::new(5, 1, 5, 0), 7, 0);
+        assert_eq!(names.remap(&edits), ["fn:parse_expr"]);
+        assert_eq!(
+            names.get("fn:parse_stmt"),
+            Some(AbsolutePosition::new(1, 14, 1, 22, 1))
+        );
+
+        let mut table = IdRemapTable::identity(2);
+        table = table.then(&IdRemapTable::identity(1));
+        assert_eq!(names.remap_ids(&table), ["struct:Token"]);
+        assert!(names.contains("fn:parse_stmt"));
+        assert_eq!(
+            names.remove("fn:parse_stmt").map(|pos| pos.file_id()),
+            Some(1)
+        );
+        assert!(names.is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serializes_as_a_key_map() -> Result<(), String> {
+        let mut names = NamedRanges::<u8>::new();
+        names.insert("fn:main", AbsolutePosition::new(1, 1, 1, 3, 1));
+        let bytes = postcard::to_allocvec(&names).map_err(|e| e.to_string())?;
+        let decoded: NamedRanges<u8> = postcard::from_bytes(&bytes).map_err(|e| e.to_string())?;
+        assert_eq!(decoded, names);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "macros"))]
+mod macros {
+    use crate::*;
+
+    #[test]
+    fn captures_call_site_and_expression_spans() {
+        let here = pos!();
+        assert_eq!(here.start_line() as u32, line!() - 1);
+        assert_eq!(here.start_column(), 20);
+        assert_eq!(here.end_column(), 25);
+
+        let (value, span) = span_of! { 1 + 2 };
+        assert_eq!(value, 3);
+        assert_eq!(span.start_line() as u32, line!() - 2);
+        assert_eq!((span.start_column(), span.end_column()), (40, 44));
+    }
+}
+
+#[cfg(test)]
+mod static_files {
+    use crate::*;
+    use std::sync::LazyLock;
+
+    static EMBEDDED: LazyLock<SourceFilesMap<u16>> = static_files! {
+        "src/lib.rs",
+        "Cargo.toml",
+    };
+
+    #[test]
+    fn embeds_finalized_files() {
+        assert_eq!(EMBEDDED.len(), 2);
+        assert_eq!(EMBEDDED.get_path(1), Some("Cargo.toml"));
+        let manifest = EMBEDDED.get_content(1).unwrap_or_default();
+        assert_eq!(manifest, include_bytes!("../Cargo.toml"));
+        assert!(EMBEDDED.line_count(2) > Some(1));
+    }
+}
+
+#[cfg(test)]
+mod display {
+    use crate::*;
+
+    #[test]
+    fn renders_paths_and_short_form() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("src/lib.rs".to_string(), b"mod a;\n".to_vec())?;
+        files.finalize()?;
+        let pos = AbsolutePosition::new(1, 10, 5, 12, 20);
+        assert_eq!(files.display(&pos).to_string(), "src/lib.rs:10:5-12:20");
+        assert_eq!(files.display(&pos).short().to_string(), "src/lib.rs:10:5");
+        let unknown = AbsolutePosition::new(7, 1, 1, 1, 3);
+        assert_eq!(files.display(&unknown).to_string(), "<file 7>:1:1-1:3");
+        Ok(())
+    }
+
+    #[test]
+    fn wraps_locations_in_osc8_links() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("src/my lib.rs".to_string(), b"mod a;\n".to_vec())?;
+        files.finalize()?;
+        let pos = AbsolutePosition::new(1, 3, 5, 3, 9);
+        let links = Hyperlinks::new("/work");
+        assert_eq!(
+            files.display(&pos).short().hyperlink(&links).to_string(),
+            "\x1b]8;;file:///work/src/my%20lib.rs#L3\x1b\\src/my lib.rs:3:5\x1b]8;;\x1b\\"
+        );
+        let vscode = links.with_template("vscode://file{path}:{line}:{col}");
+        assert_eq!(
+            vscode.url("src/a.rs", 3, 5),
+            "vscode://file/work/src/a.rs:3:5"
+        );
+        let unknown = AbsolutePosition::new(7, 1, 1, 1, 3);
+        assert_eq!(
+            files.display(&unknown).hyperlink(&vscode).to_string(),
+            "<file 7>:1:1-1:3"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn builds_editor_urls() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("src/lib.rs".to_string(), b"mod a;\n".to_vec())?;
+        files.finalize()?;
+        let pos = AbsolutePosition::new(1, 10, 5, 10, 9);
+        let url = |editor| files.editor_url(&pos, editor, "/work");
+        assert_eq!(
+            url(EditorKind::VsCode).as_deref(),
+            Some("vscode://file/work/src/lib.rs:10:5")
+        );
+        assert_eq!(
+            url(EditorKind::Idea).as_deref(),
+            Some("idea://open?file=/work/src/lib.rs&line=10&column=5")
+        );
+        assert_eq!(
+            url(EditorKind::Zed).as_deref(),
+            Some("zed://file/work/src/lib.rs:10:5")
+        );
+        assert_eq!(
+            url(EditorKind::File).as_deref(),
+            Some("file:///work/src/lib.rs#L10")
+        );
+        let unknown = AbsolutePosition::new(2, 1, 1, 1, 1);
+        assert_eq!(
+            files.editor_url(&unknown, EditorKind::VsCode, "/work"),
+            None
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod load_options {
+    use crate::*;
+
+    #[test]
+    fn oversized_and_binary_files_are_skipped() -> Result<(), String> {
+        let options = LoadOptions::new()
+            .with_max_file_size(16)
+            .with_skip_binary(true);
+        let mut files = SourceFilesMap::<u8>::new().with_load_options(options);
+        files.add_file("a.rs".to_string(), b"fn a() {}\n".to_vec())?;
+        assert_eq!(
+            files.add_file("big.json".to_string(), vec![b' '; 17]),
+            Err(SourceFilesError::Skipped {
+                path: "big.json".to_string(),
+                reason: SkipReason::TooLarge { size: 17, max: 16 },
+            })
+        );
+        assert!(
+            files
+                .insert_file("logo.png".to_string(), b"\x89PNG\r\n\x1a\n\0\0".to_vec())
+                .is_err()
+        );
+        files.finalize()?;
+
+        assert_eq!(files.len(), 1);
+        assert_eq!(files.get_id("big.json"), None);
+        let skipped: Vec<_> = files
+            .skipped_files()
+            .iter()
+            .map(|file| (file.path.as_str(), file.reason))
+            .collect();
+        assert_eq!(
+            skipped,
+            [
+                ("big.json", SkipReason::TooLarge { size: 17, max: 16 }),
+                ("logo.png", SkipReason::Binary),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn builder_applies_load_options() -> Result<(), String> {
+        let mut builder = SourceFilesMap::<u8>::builder()
+            .with_load_options(LoadOptions::new().with_skip_binary(true));
+        builder.add_file("a.txt".to_string(), b"text".to_vec())?;
+        assert!(
+            builder
+                .add_file("b.bin".to_string(), b"a\0b".to_vec())
+                .is_err()
+        );
+        let files = builder.finalize()?;
+        assert_eq!(files.len(), 1);
+        assert_eq!(files.skipped_files()[0].path, "b.bin");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod warnings {
+    use crate::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Collect(Mutex<Vec<Warning>>);
+
+    impl WarningSink for Collect {
+        fn warn(&self, warning: Warning) {
+            self.0.lock().unwrap().push(warning);
+        }
+    }
+
+    #[test]
+    fn degradations_are_kept_until_taken() -> Result<(), String> {
+        let options = LoadOptions::new().with_skip_binary(true);
+        let mut files = SourceFilesMap::<u8>::new().with_load_options(options);
+        for i in 0..u8::MAX {
+            files.add_file(format!("{i}.rs"), Vec::new())?;
+        }
+        assert!(files.add_file("full.rs".to_string(), Vec::new()).is_err());
+        assert!(files.add_file("logo.png".to_string(), vec![0; 8]).is_err());
+        assert_eq!(
+            files.take_warnings(),
+            [
+                Warning::Dropped {
+                    path: "full.rs".to_string(),
+                    max_files: 255
+                },
+                Warning::Skipped {
+                    path: "logo.png".to_string(),
+                    reason: SkipReason::Binary
+                },
+            ]
+        );
+        assert_eq!(files.take_warnings(), []);
+        Ok(())
+    }
+
+    #[cfg(feature = "view")]
+    #[test]
+    fn sinks_receive_position_overflows() -> Result<(), String> {
+        let sink = Arc::new(Collect::default());
+        let mut files = SourceFilesMap::<u8>::builder()
+            .with_warning_sink(sink.clone())
+            .file("wide.rs".to_string(), vec![b'x'; 300])?
+            .finalize()?;
+        assert!(files.position(1, 0..10).is_some());
+        assert_eq!(files.position(1, 290..300), None);
+        assert_eq!(
+            *sink.0.lock().unwrap(),
+            [Warning::PositionOverflow {
+                id: 1,
+                line: 1,
+                column: 290
+            }]
+        );
+        assert_eq!(files.take_warnings(), []);
+        Ok(())
+    }
+    #[cfg(all(feature = "view", feature = "rt-feedback"))]
+    #[test]
+    fn truncations_are_counted_per_file() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("a.rs".to_string(), vec![b'x'; 300])?;
+        files.add_file("b.rs".to_string(), vec![b'\n'; 70_000])?;
+        files.add_file("c.rs".to_string(), b"short".to_vec())?;
+        files.finalize()?;
+        assert_eq!(files.position(1, 280..290), None);
+        assert_eq!(files.position(2, 69_990..69_995), None);
+        assert_eq!(files.position(2, 69_995..69_999), None);
+        assert!(files.position(3, 0..5).is_some());
+        assert_eq!(
+            files.truncation_report(),
+            [
+                (
+                    2,
+                    FileTruncations {
+                        lines: 2,
+                        columns: 0
+                    }
+                ),
+                (
+                    1,
+                    FileTruncations {
+                        lines: 0,
+                        columns: 1
+                    }
+                ),
+            ]
+        );
+        assert_eq!(files.take_warnings().len(), 3);
+        files.finalize()?;
+        assert_eq!(files.truncation_report(), []);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod binary_files {
+    use crate::*;
+
+    #[test]
+    fn sniffs_nul_bytes_and_invalid_utf8() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("a.rs".to_string(), "fn é() {}\n".as_bytes().to_vec())?;
+        files.add_file("b.png".to_string(), b"\x89PNG\r\n\x1a\n\0\0".to_vec())?;
+        files.add_file("c.dat".to_string(), b"\xff\xfe\xfa ok".to_vec())?;
+        files.add_file("d.txt".to_string(), b"latin-1 caf\xe9 menu\n".to_vec())?;
+        files.finalize()?;
+        let binary: Vec<_> = ["a.rs", "b.png", "c.dat", "d.txt"]
+            .into_iter()
+            .map(|path| files.is_binary(files.get_id(path).unwrap()))
+            .collect();
+        assert_eq!(binary, [false, true, true, false]);
+        assert!(files.file(2).is_some_and(|file| file.is_binary()));
+        assert!(!files.is_binary(9));
+        Ok(())
+    }
+
+    #[test]
+    fn edits_resniff_content() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        let id = files.insert_file("a.txt".to_string(), b"text".to_vec())?;
+        assert!(files.replace_range(id, 0..0, b"\0"));
+        assert!(files.is_binary(id));
+        assert!(files.replace_range(id, 0..1, b""));
+        assert!(!files.is_binary(id));
+        Ok(())
+    }
+
+    #[cfg(feature = "view")]
+    #[test]
+    fn view_str_degrades_to_placeholder() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("a.bin".to_string(), b"ab\0cd".to_vec())?;
+        files.add_file("b.txt".to_string(), b"caf\xe9 au lait".to_vec())?;
+        files.finalize()?;
+        let pos = RelativePosition::new(1, 1, 1, 4);
+        assert_eq!(files.view_str(1, &pos).as_deref(), Some(BINARY_PLACEHOLDER));
+        assert_eq!(files.view_str(2, &pos).as_deref(), Some("caf\u{fffd}"));
+        Ok(())
+    }
+
+    #[cfg(feature = "diff")]
+    #[test]
+    fn pending_diff_of_binary_file_is_one_line() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        let id = files.insert_file("a.bin".to_string(), b"ab\0cd".to_vec())?;
+        assert!(files.replace_range(id, 0..1, b"x"));
+        assert_eq!(
+            files.pending_diff(id),
+            "Binary files a/a.bin and b/a.bin differ\n"
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod path_filter {
+    use crate::*;
+
+    #[test]
+    fn follows_gitignore_semantics() {
+        let filter = PathFilter::from_gitignore(
+            "# build output\n/target\n*.log\n!keep.log\nnode_modules/\ndocs/**/*.tmp\n\\#notes\n",
+        );
+        assert_eq!(filter.len(), 6);
+        let excluded = |path| filter.is_excluded(path, false);
+        assert!(excluded("target/debug/app"));
+        assert!(!excluded("crates/target/x.rs"));
+        assert!(excluded("a/b/trace.log"));
+        assert!(!excluded("a/keep.log"));
+        assert!(excluded("web/node_modules/react/index.js"));
+        assert!(!excluded("node_modules"));
+        assert!(filter.is_excluded("node_modules", true));
+        assert!(excluded("docs/x.tmp"));
+        assert!(excluded("docs/a/b/x.tmp"));
+        assert!(!excluded("src/x.tmp"));
+        assert!(excluded("#notes"));
+        assert!(!excluded("src/lib.rs"));
+    }
+
+    #[test]
+    fn globs_and_classes() {
+        let filter = PathFilter::new(["src/*.rs", "file?.[ch]", "[!a-m]*.txt", "out/**"]);
+        let excluded = |path| filter.is_excluded(path, false);
+        assert!(excluded("src/lib.rs"));
+        assert!(!excluded("src/bin/main.rs"));
+        assert!(excluded("lib/file1.c"));
+        assert!(!excluded("lib/file12.c"));
+        assert!(excluded("notes.txt"));
+        assert!(!excluded("bar.txt"));
+        assert!(excluded("out/a/b"));
+        assert!(!excluded("out"));
+    }
+
+    #[test]
+    fn add_dir_uses_filter_and_load_options() -> Result<(), String> {
+        let dir = std::env::temp_dir().join(format!("sourcier-add-dir-{}", std::process::id()));
+        let io = |e: std::io::Error| e.to_string();
+        std::fs::create_dir_all(dir.join("src/nested")).map_err(io)?;
+        std::fs::create_dir_all(dir.join("target")).map_err(io)?;
+        std::fs::write(dir.join("src/lib.rs"), "mod nested;\n").map_err(io)?;
+        std::fs::write(dir.join("src/nested/mod.rs"), "").map_err(io)?;
+        std::fs::write(dir.join("src/big.rs"), vec![b'/'; 64]).map_err(io)?;
+        std::fs::write(dir.join("src/logo.png"), b"\x89PNG\0").map_err(io)?;
+        std::fs::write(dir.join("target/out.rs"), "").map_err(io)?;
+        std::fs::write(dir.join("debug.log"), "").map_err(io)?;
+
+        let mut files = SourceFilesMap::<u8>::new().with_load_options(
+            LoadOptions::new()
+                .with_max_file_size(32)
+                .with_skip_binary(true),
+        );
+        let added = files.add_dir(&dir, &PathFilter::new(["target/", "*.log"]));
+        std::fs::remove_dir_all(&dir).map_err(io)?;
+        assert_eq!(added.map_err(io)?, 2);
+        files.finalize()?;
+
+        let paths: Vec<_> = files.iter().map(|(_, path, _)| path).collect();
+        assert_eq!(paths, ["src/lib.rs", "src/nested/mod.rs"]);
+        let skipped: Vec<_> = files
+            .skipped_files()
+            .iter()
+            .map(|file| (file.path.as_str(), file.reason))
+            .collect();
+        assert_eq!(
+            skipped,
+            [
+                ("src/big.rs", SkipReason::TooLarge { size: 64, max: 32 }),
+                ("src/logo.png", SkipReason::Binary),
+            ]
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod file_watching {
+    use crate::*;
+    use std::path::Path;
+
+    fn write(dir: &Path, path: &str, content: &str) -> Result<(), String> {
+        std::fs::write(dir.join(path), content).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn applies_created_changed_and_deleted_files() -> Result<(), String> {
+        let dir = std::env::temp_dir().join(format!("sourcier-watch-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src/old")).map_err(|e| e.to_string())?;
+        write(&dir, "src/b.rs", "fn b() {}\n")?;
+        write(&dir, "src/c.rs", "fn c() {}\n")?;
+        write(&dir, "src/old/d.rs", "")?;
+        let mut files = SourceFilesMap::<u8>::new();
+        files
+            .add_dir(&dir, &PathFilter::default())
+            .map_err(|e| e.to_string())?;
+        files.finalize()?;
+        assert!(files.replace_range(1, 0..0, b"// unsaved\n"));
+
+        write(&dir, "src/a.rs", "fn a() {}\n")?;
+        write(&dir, "src/b.rs", "fn b() {}\nfn bb() {}\n")?;
+        write(&dir, "src/a.log", "")?;
+        std::fs::remove_file(dir.join("src/c.rs")).map_err(|e| e.to_string())?;
+        std::fs::remove_dir_all(dir.join("src/old")).map_err(|e| e.to_string())?;
+        let events = [
+            FileEvent::new("src/a.rs", FileChange::Created),
+            FileEvent::new("src/a.rs", FileChange::Changed),
+            FileEvent::new("src/a.log", FileChange::Created),
+            FileEvent::new("src/b.rs", FileChange::Changed),
+            FileEvent::new("src/c.rs", FileChange::Deleted),
+            FileEvent::new("src/old", FileChange::Deleted),
+        ];
+        let update = files.apply_file_events(&dir, &events, &PathFilter::new(["*.log"]));
+        std::fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+        let update = update.map_err(|e| e.to_string())?;
+
+        assert_eq!(update.changed, ["src/b.rs"]);
+        assert_eq!(update.added, ["src/a.rs"]);
+        assert_eq!(update.removed, ["src/c.rs", "src/old/d.rs"]);
+        let paths: Vec<_> = files.iter().map(|(_, path, _)| path).collect();
+        assert_eq!(paths, ["src/a.rs", "src/b.rs"]);
+        assert!(files.edited_files().is_empty());
+        assert_eq!(files.line_count(2), Some(3));
+        let remap = update.remap.ok_or("structure changed")?;
+        assert_eq!(
+            (remap.get(1), remap.get(2), remap.get(3)),
+            (Some(2), None, None)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn content_only_changes_keep_ids() -> Result<(), String> {
+        let dir = std::env::temp_dir().join(format!("sourcier-watch-ids-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        write(&dir, "a.rs", "one")?;
+        let mut files = SourceFilesMap::<u8>::new();
+        files
+            .add_dir(&dir, &PathFilter::default())
+            .map_err(|e| e.to_string())?;
+        files.finalize()?;
+        let epoch = files.epoch();
+
+        let unchanged = [FileEvent::new("a.rs", FileChange::Changed)];
+        let noop = files.apply_file_events(&dir, &unchanged, &PathFilter::default());
+        write(&dir, "a.rs", "two")?;
+        let update = files.apply_file_events(&dir, &unchanged, &PathFilter::default());
+        std::fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+
+        assert!(noop.map_err(|e| e.to_string())?.is_empty());
+        let update = update.map_err(|e| e.to_string())?;
+        assert_eq!(update.changed, ["a.rs"]);
+        assert!(update.remap.is_none());
+        assert_eq!(files.epoch(), epoch);
+        assert_eq!(files.get_content(1), Some(&b"two"[..]));
+        Ok(())
+    }
+
+    #[test]
+    fn changed_files_are_normalized_again() -> Result<(), String> {
+        let dir = std::env::temp_dir().join(format!("sourcier-watch-nrm-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        write(&dir, "a.rs", "\tx\r\n")?;
+        let normalize = Normalize::new().with_tab_width(4).with_crlf_to_lf(true);
+        let mut files = SourceFilesMap::<u8>::new()
+            .with_load_options(LoadOptions::new().with_normalize(normalize));
+        files
+            .add_dir(&dir, &PathFilter::default())
+            .map_err(|e| e.to_string())?;
+        files.finalize()?;
+
+        let events = [FileEvent::new("a.rs", FileChange::Changed)];
+        let noop = files.apply_file_events(&dir, &events, &PathFilter::default());
+        write(&dir, "a.rs", "y\t\tz\r\n")?;
+        let update = files.apply_file_events(&dir, &events, &PathFilter::default());
+        std::fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+
+        assert!(noop.map_err(|e| e.to_string())?.is_empty());
+        assert_eq!(update.map_err(|e| e.to_string())?.changed, ["a.rs"]);
+        assert_eq!(files.get_content(1), Some(&b"y       z\n"[..]));
+        // `z` and the line feed, past the two tabs and the dropped `\r`
+        assert_eq!(files.original_range(1, 8..10), Some(3..6));
+        Ok(())
+    }
+
+    #[test]
+    fn watchman_files_become_events() {
+        let notification = WatchmanNotification {
+            root: "/repo".to_string(),
+            files: vec![
+                WatchmanFile {
+                    name: "src/new.rs".to_string(),
+                    exists: true,
+                    new: true,
+                },
+                WatchmanFile {
+                    name: "src/lib.rs".to_string(),
+                    exists: true,
+                    new: false,
+                },
+                WatchmanFile {
+                    name: "docs/gone.md".to_string(),
+                    exists: false,
+                    new: false,
+                },
+            ],
+        };
+        assert_eq!(
+            notification.events("/repo/src"),
+            [
+                FileEvent::new("new.rs", FileChange::Created),
+                FileEvent::new("lib.rs", FileChange::Changed),
+            ]
+        );
+        assert_eq!(notification.events("/repo")[2].change, FileChange::Deleted);
+    }
+}
+
+#[cfg(test)]
+mod disk_refresh {
+    use crate::*;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    // Miri cannot set modification times
+    #[cfg_attr(miri, ignore)]
+    fn cached_maps_pick_up_disk_changes() -> Result<(), String> {
+        let dir = std::env::temp_dir().join(format!("sourcier-refresh-{}", std::process::id()));
+        let io = |e: std::io::Error| e.to_string();
+        std::fs::create_dir_all(&dir).map_err(io)?;
+        for path in ["a.rs", "b.rs", "c.rs", "d.rs", "e.rs"] {
+            std::fs::write(dir.join(path), format!("// {path}\n")).map_err(io)?;
+        }
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_dir(&dir, &PathFilter::default()).map_err(io)?;
+        files.finalize()?;
+        let mut cache = Vec::new();
+        files.write_cache(&mut cache).map_err(|e| e.to_string())?;
+        let mut files =
+            SourceFilesMap::<u8>::read_cache(&mut cache.as_slice()).map_err(|e| e.to_string())?;
+        let fresh = files.refresh_from_disk(&dir);
+
+        // a: new content; b: gone; c: touched only; d: edited in both places
+        std::fs::write(dir.join("a.rs"), "// a.rs\nfn a() {}\n").map_err(io)?;
+        std::fs::remove_file(dir.join("b.rs")).map_err(io)?;
+        std::fs::File::options()
+            .write(true)
+            .open(dir.join("c.rs"))
+            .and_then(|file| file.set_modified(SystemTime::now() + Duration::from_secs(60)))
+            .map_err(io)?;
+        std::fs::write(dir.join("d.rs"), "// d.rs on disk\n").map_err(io)?;
+        assert!(files.replace_range(4, 0..0, b"// unsaved\n"));
+        let report = files.refresh_from_disk(&dir);
+        let again = files.refresh_from_disk(&dir);
+        std::fs::remove_dir_all(&dir).map_err(io)?;
+
+        assert!(fresh.map_err(io)?.is_clean());
+        let report = report.map_err(io)?;
+        assert_eq!(report.changed, [1]);
+        assert_eq!(report.missing, [2]);
+        assert_eq!(report.conflicts, [4]);
+        assert_eq!(report.unchanged, 2);
+        assert_eq!(report.stale().collect::<Vec<_>>(), [1, 2, 4]);
+        assert_eq!(files.line_count(1), Some(3));
+        assert_eq!(files.get_content(1), Some(&b"// a.rs\nfn a() {}\n"[..]));
+        assert!(files.edited_files().contains(&4));
+        let again = again.map_err(io)?;
+        assert_eq!((again.changed.len(), again.unchanged), (0, 3));
+        assert_eq!(again.conflicts, [4]);
+        Ok(())
+    }
+
+    #[test]
+    // Miri cannot set modification times
+    #[cfg_attr(miri, ignore)]
+    fn verification_hashes_the_disk() -> Result<(), String> {
+        let dir = std::env::temp_dir().join(format!("sourcier-verify-{}", std::process::id()));
+        let io = |e: std::io::Error| e.to_string();
+        std::fs::create_dir_all(&dir).map_err(io)?;
+        for path in ["a.rs", "b.rs", "c.rs"] {
+            std::fs::write(dir.join(path), format!("// {path}\n")).map_err(io)?;
+        }
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_dir(&dir, &PathFilter::default()).map_err(io)?;
+        files.finalize()?;
+        // Same length and modification time, different bytes
+        let modified = std::fs::metadata(dir.join("a.rs"))
+            .and_then(|metadata| metadata.modified())
+            .map_err(io)?;
+        std::fs::write(dir.join("a.rs"), "// A.rs\n").map_err(io)?;
+        std::fs::File::options()
+            .write(true)
+            .open(dir.join("a.rs"))
+            .and_then(|file| file.set_modified(modified))
+            .map_err(io)?;
+        std::fs::remove_file(dir.join("c.rs")).map_err(io)?;
+        // Edits in the map are not modifications on disk
+        assert!(files.replace_range(2, 0..0, b"// unsaved\n"));
+
+        let one = files.verify(1, &dir);
+        let all = files.verify_all(&dir);
+        let unknown = files.verify(9, &dir);
+        std::fs::remove_dir_all(&dir).map_err(io)?;
+
+        assert_eq!(one.map_err(io)?, Verification::Modified);
+        assert_eq!(
+            all.map_err(io)?,
+            [
+                (1, Verification::Modified),
+                (2, Verification::Fresh),
+                (3, Verification::Missing)
+            ]
+        );
+        assert_eq!(
+            unknown.map_err(|e| e.kind()),
+            Err(std::io::ErrorKind::NotFound)
+        );
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "edit"))]
+mod rope_storage {
+    use crate::*;
+
+    #[test]
+    fn rope_matches_contiguous_edits() {
+        let mut expected: Vec<u8> = (0..5000u32)
+            .flat_map(|i| format!("line {i}\n").into_bytes())
+            .collect();
+        let mut rope = Rope::from(expected.clone());
+        // Deterministic pseudo-random keystrokes all over the buffer
+        let mut seed = 0x2545_f491_u64;
+        for step in 0..2000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let at = seed as usize % (expected.len() + 1);
+            let end = (at + (seed >> 32) as usize % 4).min(expected.len());
+            let text: &[u8] = if step % 3 == 0 { b"x\n" } else { b"y" };
+            expected.splice(at..end, text.iter().copied());
+            assert!(rope.replace(at..end, text));
+        }
+        assert_eq!(rope.len(), expected.len());
+        assert_eq!(rope.to_vec(), expected);
+        let newlines: Vec<usize> = memchr::memchr_iter(b'\n', &expected).collect();
+        assert_eq!(rope.line_count(), newlines.len() + 1);
+        assert_eq!(rope.line_start(1), Some(0));
+        assert_eq!(rope.line_start(700), Some(newlines[698] + 1));
+        assert_eq!(rope.line_start(newlines.len() + 2), None);
+        assert_eq!(rope.slice(100..4000).as_deref(), Some(&expected[100..4000]));
+        assert!(!rope.replace(0..expected.len() + 1, b""));
+    }
+
+    #[test]
+    fn rope_backed_files_edit_and_view() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("a.rs".to_string(), b"fn a() {}\nfn b() {}\n".to_vec())?;
+        files.finalize()?;
+        assert_eq!(files.storage(1), Some(Storage::Contiguous));
+        assert!(files.set_storage(1, Storage::Rope));
+        assert!(!files.set_storage(2, Storage::Rope));
+
+        assert!(files.replace_range(1, 3..4, b"_"));
+        assert!(files.replace_range(1, 10..10, b"\n"));
+        assert_eq!(files.get_content(1), Some(&b"fn _() {}\n\nfn b() {}\n"[..]));
+        assert_eq!(files.line_count(1), Some(4));
+        assert_eq!(
+            files.view(1, &RelativePosition::new(3, 4, 3, 4)),
+            Some(&b"b"[..])
+        );
+        assert_eq!(
+            files.file(1).and_then(|file| file.line(3)),
+            Some(&b"fn b() {}"[..])
+        );
+        assert_eq!(
+            files.original_content(1),
+            Some(&b"fn a() {}\nfn b() {}\n"[..])
+        );
+
+        assert!(files.set_storage(1, Storage::Contiguous));
+        assert_eq!(
+            files.view(1, &RelativePosition::new(1, 4, 1, 4)),
+            Some(&b"_"[..])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rope_backed_files_read_through_chunks() -> Result<(), String> {
+        let content: Vec<u8> = (0..400u32)
+            .flat_map(|i| format!("line {i:03}\n").into_bytes())
+            .collect();
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("a.txt".to_string(), content.clone())?;
+        files.finalize()?;
+        let hash = files.content_hash(1);
+        files.set_storage(1, Storage::Rope);
+
+        let chunks: Vec<&[u8]> = files.content_chunks(1).collect();
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.concat(), content);
+        assert_eq!(files.content_hash(1), hash);
+        // Lines 114 and 115 straddle the first chunk boundary at byte 1024
+        let across = RelativePosition::new(114, 1, 115, 8);
+        let view = files.view_cow(1, &across).ok_or("span resolves")?;
+        assert!(matches!(view, std::borrow::Cow::Owned(_)));
+        assert_eq!(view.as_ref(), b"line 113\nline 114");
+        assert_eq!(
+            files.view_str(1, &across).as_deref(),
+            Some("line 113\nline 114")
+        );
+        let within = files.view_cow(1, &RelativePosition::new(2, 1, 2, 8));
+        assert!(matches!(
+            within,
+            Some(std::borrow::Cow::Borrowed(b"line 001"))
+        ));
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn rope_backed_files_serialize_as_bytes() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("a.txt".to_string(), b"one\ntwo\n".to_vec())?;
+        files.finalize()?;
+        let contiguous = postcard::to_allocvec(&files).map_err(|e| e.to_string())?;
+        files.set_storage(1, Storage::Rope);
+        let rope = postcard::to_allocvec(&files).map_err(|e| e.to_string())?;
+        assert_eq!(rope, contiguous);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "bytes"))]
+mod bytes_storage {
+    use crate::*;
+    use bytes::Bytes;
+
+    #[test]
+    fn bytes_backed_files_share_their_buffer() -> Result<(), String> {
+        let buffer = Bytes::from_static(b"fn a() {}\nfn b() {}\n");
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file_bytes("a.rs".to_string(), buffer.clone())?;
+        files.finalize()?;
+        assert_eq!(files.storage(1), Some(Storage::Bytes));
+        assert_eq!(files.line_count(1), Some(3));
+
+        let content = files.content_bytes(1).ok_or("file exists")?;
+        assert_eq!(content.as_ptr(), buffer.as_ptr());
+        #[cfg(feature = "view")]
+        {
+            let view = files
+                .view_bytes(1, &RelativePosition::new(2, 4, 2, 4))
+                .ok_or("span resolves")?;
+            assert_eq!(view, &b"b"[..]);
+            assert_eq!(view.as_ptr(), buffer[13..].as_ptr());
+        }
+        drop(files);
+        assert_eq!(content, buffer);
+        Ok(())
+    }
+
+    #[test]
+    fn bytes_backed_files_edit_and_convert() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("a.rs".to_string(), b"fn a() {}\n".to_vec())?;
+        files.finalize()?;
+        assert!(files.set_storage(1, Storage::Bytes));
+        assert!(files.replace_range(1, 3..4, b"main"));
+        assert_eq!(files.storage(1), Some(Storage::Bytes));
+        assert_eq!(files.get_content(1), Some(&b"fn main() {}\n"[..]));
+        #[cfg(feature = "view")]
+        assert_eq!(
+            files.view(1, &RelativePosition::new(1, 4, 1, 7)),
+            Some(&b"main"[..])
+        );
+        assert_eq!(files.original_content(1), Some(&b"fn a() {}\n"[..]));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod dyn_map {
+    use crate::*;
+
+    #[test]
+    fn dynamic_maps_widen_on_demand() -> Result<(), String> {
+        let mut files = DynSourceFilesMap::new();
+        assert_eq!(files.width(), IdWidth::U8);
+        for i in 0..300 {
+            files.add_file(format!("f{i:03}.rs"), format!("// {i}\n").into_bytes())?;
+        }
+        assert_eq!(files.width(), IdWidth::U16);
+        files.finalize()?;
+        assert_eq!(files.len(), 300);
+
+        let id = files.get_id("f299.rs").ok_or("missing")?;
+        assert_eq!(id, 300);
+        assert_eq!(files.get_path(id), Some("f299.rs"));
+        assert_eq!(files.get_content(id), Some(&b"// 299\n"[..]));
+        assert_eq!(files.line_count(id), Some(2));
+        assert_eq!(files.get_path(70_000), None);
+        assert_eq!(files.iter().last().map(|(id, _, _)| id), Some(300));
+        #[cfg(feature = "view")]
+        {
+            let pos = files.position(id, 3..6).ok_or("unencodable")?;
+            assert_eq!(files.view(id, &pos), Some(&b"299"[..]));
+        }
+        assert_eq!(
+            DynSourceFilesMap::with_width(IdWidth::U32).map(|files| files.width()),
+            Some(IdWidth::U32)
+        );
+        Ok(())
+    }
+
+    #[test]
+    // 65,536 files take hours under Miri
+    #[cfg_attr(miri, ignore)]
+    fn dynamic_maps_widen_past_u16() -> Result<(), String> {
+        let mut files = DynSourceFilesMap::with_width(IdWidth::U16).ok_or("u16 maps")?;
+        for i in 0..=u16::MAX_FILES {
+            files.add_file(format!("f{i:05}.rs"), format!("// {i}\n").into_bytes())?;
+        }
+        assert_eq!(files.width(), IdWidth::U32);
+        files.finalize()?;
+
+        let id = files.get_id("f65535.rs").ok_or("missing")?;
+        assert_eq!(id, 65_536);
+        assert_eq!(files.get_content(id), Some(&b"// 65535\n"[..]));
+        #[cfg(feature = "view")]
+        {
+            let pos = files.position(id, 3..8).ok_or("unencodable")?;
+            assert_eq!(files.view(id, &pos), Some(&b"65535"[..]));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn u32_positions_keep_every_field() {
+        let pos = AbsolutePosition::new(70_000u32, 65_535, 255, 12, 7);
+        assert_eq!(pos.file_id(), 70_000);
+        assert_eq!(pos.source_file_id(), Some(70_000));
+        assert_eq!(
+            (
+                pos.start_line(),
+                pos.start_column(),
+                pos.end_line(),
+                pos.end_column()
+            ),
+            (65_535, 255, 12, 7)
+        );
+        assert_eq!(pos.with_file_id(u32::MAX).file_id(), u32::MAX);
+        assert_eq!(AbsolutePosition::from_raw(pos.as_raw()), Some(pos));
+    }
+}
+
+#[cfg(test)]
+mod position_fields {
+    use crate::*;
+
+    #[test]
+    fn setters_patch_one_field() {
+        let pos = AbsolutePosition::<u16>::new(300, 10, 5, 15, 20);
+        assert_eq!(
+            pos.with_start_line(11),
+            AbsolutePosition::new(300, 11, 5, 15, 20)
+        );
+        assert_eq!(
+            pos.with_start_column(255),
+            AbsolutePosition::new(300, 10, 255, 15, 20)
+        );
+        assert_eq!(
+            pos.with_end_line(u16::MAX).with_end_column(0),
+            AbsolutePosition::new(300, 10, 5, u16::MAX, 0)
+        );
+        assert_eq!(pos.with_file_id(1), AbsolutePosition::new(1, 10, 5, 15, 20));
+
+        let rel = RelativePosition::new(1, 2, 3, 4);
+        assert_eq!(
+            rel.with_start_line(9).with_start_column(8),
+            RelativePosition::new(9, 8, 3, 4)
+        );
+        assert_eq!(
+            rel.with_end_line(7).with_end_column(6),
+            RelativePosition::new(1, 2, 7, 6)
+        );
+    }
+
+    #[test]
+    fn children_fold_into_the_enclosing_span() -> Result<(), String> {
+        let children = [
+            AbsolutePosition::<u8>::new(2, 3, 5, 3, 9),
+            AbsolutePosition::new(2, 1, 8, 1, 12),
+            AbsolutePosition::new(2, 1, 4, 1, 6),
+            AbsolutePosition::new(2, 3, 1, 3, 2),
+        ];
+        assert_eq!(
+            SpanAccumulator::enclose(&children)?,
+            Some(AbsolutePosition::new(2, 1, 4, 3, 9))
+        );
+        assert_eq!(SpanAccumulator::<u8>::enclose(&[])?, None);
+
+        let mut span = SpanAccumulator::new().with(&children[0])?;
+        let error = span.push(&AbsolutePosition::new(5, 1, 1, 9, 9));
+        assert_eq!(
+            error,
+            Err(SourceFilesError::FileMismatch {
+                expected: 2,
+                found: 5
+            })
+        );
+        assert_eq!(span.span(), Some(children[0]));
+        assert_eq!(span.file_id(), Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn raw_values_decode_back() {
+        let pos = AbsolutePosition::<u16>::new(300, 10, 5, 15, 20);
+        assert_eq!(AbsolutePosition::from_raw(pos.as_raw()), Some(pos));
+        assert!(AbsolutePosition::<u8>::from_raw(u64::MAX << 8).is_some());
+        // The low byte of `u8` positions and the top of `u32` ones are unused
+        assert_eq!(AbsolutePosition::<u8>::from_raw(u64::MAX), None);
+        assert!(AbsolutePosition::<u32>::from_raw(u128::MAX >> 48).is_some());
+        assert_eq!(AbsolutePosition::<u32>::from_raw(1 << 80), None);
+
+        let rel = RelativePosition::new(u16::MAX, 2, 3, 4);
+        assert_eq!(RelativePosition::from_raw(rel.as_raw()), Some(rel));
+        assert_eq!(RelativePosition::from_raw(1 << 48), None);
+    }
+
+    #[test]
+    fn spans_grow_to_later_tokens() {
+        let open = AbsolutePosition::<u8>::new(3, 1, 1, 1, 4);
+        let close = RelativePosition::new(4, 1, 4, 1);
+        assert_eq!(open.widen_to(&close), AbsolutePosition::new(3, 1, 1, 4, 1));
+        assert_eq!(
+            open.to_relative().widen_to(&close),
+            RelativePosition::new(1, 1, 4, 1)
+        );
+    }
+}
+
+#[cfg(test)]
+mod position_column {
+    use crate::*;
+
+    /// Token spans of a made-up file: short tokens with the odd multi-line one
+    fn tokens() -> Vec<AbsolutePosition<u16>> {
+        let mut tokens = Vec::new();
+        for line in 1..=2000u16 {
+            let file = 1 + line / 700;
+            let mut col = 1 + (line % 8) as u8 * 4;
+            for token in 0..6u8 {
+                let len = 1 + (line as u8).wrapping_mul(token) % 9;
+                if token == 5 && line % 50 == 0 {
+                    tokens.push(AbsolutePosition::new(file, line, col, line + 3, 2));
+                    break;
+                }
+                tokens.push(AbsolutePosition::new(file, line, col, line, col + len - 1));
+                col += len + 1;
+            }
+        }
+        tokens
+    }
+
+    #[test]
+    fn columns_round_trip_in_a_fraction_of_the_space() {
+        let tokens = tokens();
+        let mut column: PositionColumn<u16> = tokens.iter().copied().collect();
+        column.shrink_to_fit();
+        assert_eq!(column.len(), tokens.len());
+        assert!(column.iter().eq(tokens.iter().copied()));
+        for index in [0, 1, 63, 64, 65, 5000, tokens.len() - 1] {
+            assert_eq!(column.get(index), Some(tokens[index]));
+        }
+        assert_eq!(column.get(tokens.len()), None);
+        assert_eq!(column.get(tokens.len() + 64), None);
+        let plain = tokens.len() * size_of::<AbsolutePosition<u16>>();
+        assert!(
+            column.heap_size() * 3 < plain,
+            "{} bytes packed, {plain} plain",
+            column.heap_size()
+        );
+    }
+
+    #[test]
+    fn arbitrary_positions_round_trip() {
+        let positions: Vec<AbsolutePosition<u8>> = (0..300u32)
+            .map(|i| {
+                let x = i.wrapping_mul(2_654_435_761);
+                AbsolutePosition::new(
+                    (x % 255) as u8 + 1,
+                    (x >> 8) as u16,
+                    (x >> 3) as u8,
+                    (x >> 12) as u16,
+                    (x >> 20) as u8,
+                )
+            })
+            .collect();
+        let column: PositionColumn<u8> = positions.iter().copied().collect();
+        assert!(column.iter().eq(positions.iter().copied()));
+        assert_eq!(column.get(130), Some(positions[130]));
+    }
+}
+
+#[cfg(test)]
+mod position_set {
+    use crate::*;
+
+    #[test]
+    fn sets_answer_ordered_queries() {
+        let mut set: PositionSet<u8> = [
+            AbsolutePosition::new(2, 1, 1, 1, 3),
+            AbsolutePosition::new(1, 3, 5, 3, 9),
+            AbsolutePosition::new(1, 1, 1, 1, 2),
+            AbsolutePosition::new(1, 1, 4, 1, 8),
+            AbsolutePosition::new(1, 1, 1, 1, 2),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(set.len(), 4);
+        assert!(!set.insert(AbsolutePosition::new(1, 1, 4, 1, 8)));
+        assert!(set.insert(AbsolutePosition::new(1, 2, 1, 2, 4)));
+        assert!(set.contains(&AbsolutePosition::new(1, 2, 1, 2, 4)));
+
+        let after = |line, col| set.first_at_or_after(&AbsolutePosition::new(1, line, col, 0, 0));
+        assert_eq!(after(1, 2), Some(AbsolutePosition::new(1, 1, 4, 1, 8)));
+        assert_eq!(after(1, 4), Some(AbsolutePosition::new(1, 1, 4, 1, 8)));
+        assert_eq!(after(2, 2), Some(AbsolutePosition::new(1, 3, 5, 3, 9)));
+        assert_eq!(after(4, 1), None);
+
+        let span = AbsolutePosition::new(1, 1, 3, 3, 4);
+        let starting: Vec<_> = set.starting_in(&span).collect();
+        assert_eq!(
+            starting,
+            [
+                AbsolutePosition::new(1, 1, 4, 1, 8),
+                AbsolutePosition::new(1, 2, 1, 2, 4),
+            ]
+        );
+        assert_eq!(set.in_file(2).count(), 1);
+        assert!(set.remove(&AbsolutePosition::new(2, 1, 1, 1, 3)));
+        assert_eq!(set.in_file(2).count(), 0);
+    }
+
+    #[test]
+    fn overlapping_entries_merge() {
+        let mut set: PositionSet<u8> = [
+            AbsolutePosition::new(1, 1, 1, 1, 5),
+            AbsolutePosition::new(1, 1, 3, 2, 2),
+            AbsolutePosition::new(1, 2, 1, 2, 1),
+            // Touches the merged span without overlapping it
+            AbsolutePosition::new(1, 2, 3, 2, 6),
+            AbsolutePosition::new(2, 1, 2, 1, 3),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(set.merge_overlapping(), 2);
+        assert_eq!(
+            set.as_slice(),
+            [
+                AbsolutePosition::new(1, 1, 1, 2, 2),
+                AbsolutePosition::new(1, 2, 3, 2, 6),
+                AbsolutePosition::new(2, 1, 2, 1, 3),
+            ]
+        );
+    }
+}
+
+#[cfg(all(test, feature = "view"))]
+mod token_spans {
+    use crate::*;
+
+    #[test]
+    fn tokens_map_back_to_their_text() -> Result<(), String> {
+        let source = b"let x = 1;\nlet y = x + 2;\n";
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("a.rs".to_string(), source.to_vec())?;
+        files.finalize()?;
+        let file = files.file(1).ok_or("missing")?;
+
+        let mut spans = TokenSpans::new();
+        let mut start = None;
+        for (at, byte) in source.iter().enumerate().chain([(source.len(), &b' ')]) {
+            match (start, byte.is_ascii_whitespace()) {
+                (None, false) => start = Some(at),
+                (Some(from), true) => {
+                    let index = spans.push(file.position(from..at).ok_or("unencodable")?);
+                    assert_eq!(index + 1, spans.len());
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(spans.len(), 10);
+        let text = |pos: AbsolutePosition<u8>| files.view(1, &pos).map(<[u8]>::to_vec);
+        assert_eq!(text(spans.get(5).ok_or("missing")?), Some(b"y".to_vec()));
+        assert_eq!(
+            text(spans.span(4..8).ok_or("missing")?),
+            Some(b"let y = x".to_vec())
+        );
+        assert_eq!(spans.span(3..3), None);
+        assert_eq!(spans.span(8..11), None);
+        assert!(
+            spans
+                .iter()
+                .eq((0..10).filter_map(|index| spans.get(index)))
+        );
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "git"))]
+mod git_blame {
+    use crate::*;
+    use std::process::Command;
+
+    fn git(dir: &std::path::Path, args: &[&str]) -> Result<(), String> {
+        let status = Command::new("git")
+            .current_dir(dir)
+            .args(args)
+            .env("GIT_AUTHOR_NAME", "Ada")
+            .env("GIT_AUTHOR_EMAIL", "ada@example.com")
+            .env("GIT_AUTHOR_DATE", "1700000000 +0000")
+            .env("GIT_COMMITTER_NAME", "Ada")
+            .env("GIT_COMMITTER_EMAIL", "ada@example.com")
+            .status()
+            .map_err(|e| e.to_string())?;
+        status
+            .success()
+            .then_some(())
+            .ok_or(format!("git {args:?} failed"))
+    }
+
+    #[test]
+    fn lines_are_attributed_to_commits() -> Result<(), String> {
+        let dir = std::env::temp_dir().join(format!("sourcier-blame-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src")).map_err(|e| e.to_string())?;
+        git(&dir, &["init", "-q"])?;
+        std::fs::write(dir.join("src/a.rs"), "fn a() {}\nfn b() {}\n")
+            .map_err(|e| e.to_string())?;
+        git(&dir, &["add", "."])?;
+        git(&dir, &["commit", "-qm", "Add a and b"])?;
+
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("src/a.rs".to_string(), b"fn a() {}\nfn b() {}\n".to_vec())?;
+        files.add_file("src/untracked.rs".to_string(), b"x\n".to_vec())?;
+        files.finalize()?;
+        let id = files.get_id("src/a.rs").ok_or("missing")?;
+        // Unsaved edit of the second line
+        files.replace_range(id, 13..14, b"c");
+
+        let blame = files.blame(id, &dir).map_err(|e| e.to_string())?;
+        assert_eq!(blame.len(), 2);
+        assert_eq!(blame[0].line, 1);
+        assert_eq!(blame[0].author, "Ada");
+        assert_eq!(blame[0].author_email, "ada@example.com");
+        assert_eq!(blame[0].author_time, 1_700_000_000);
+        assert_eq!(blame[0].summary, "Add a and b");
+        assert!(!blame[0].is_uncommitted());
+        assert!(blame[1].is_uncommitted());
+
+        let at = files
+            .blame_at(id, &RelativePosition::new(1, 4, 1, 4), &dir)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(at.as_ref(), Some(&blame[0]));
+        let past_end = files.blame_at(id, &RelativePosition::new(3, 1, 3, 0), &dir);
+        assert!(matches!(past_end, Ok(None)));
+        let untracked = files.get_id("src/untracked.rs").ok_or("missing")?;
+        assert!(files.blame(untracked, &dir).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod fixtures {
+    use crate::*;
+
+    #[test]
+    fn fixtures_are_deterministic_and_sized() -> Result<(), String> {
+        let small = Fixture::small_files(200);
+        assert_eq!(small.files, Fixture::small_files(200).files);
+        assert!(
+            small
+                .files
+                .iter()
+                .all(|(_, content)| (256..4096).contains(&content.len()))
+        );
+
+        let large = Fixture::large_file(1 << 20);
+        assert_eq!(large.total_bytes(), 1 << 20);
+        let needles = memchr::memmem::find_iter(&large.files[0].1, Fixture::NEEDLE).count();
+        assert!(needles > 100);
+
+        let deep = Fixture::deep_paths(300, 12);
+        assert_eq!(deep.files[0].0.matches('/').count(), 12);
+        let files = deep.load::<u16>()?;
+        assert_eq!(files.len(), 300);
+        Ok(())
+    }
+}
+
+// RUSTFLAGS="--cfg sourcier_loom" cargo test --release --lib loom_models
+#[cfg(all(test, sourcier_loom))]
+mod loom_models {
+    use crate::*;
+    use std::io::Cursor;
+
+    const MAP_V2_U8: &[u8] = include_bytes!("../fixtures/wire/map_v2_u8.bin");
+
+    /// Check `f` under every interleaving with at most two preemptions
+    ///
+    /// Primitives of the code under test must come from `crate::snc`, and
+    /// threads be spawned with `loom::thread`.
+    fn model(f: impl Fn() + Sync + Send + 'static) {
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(2);
+        builder.check(f);
+    }
+
+    fn partial() -> PartialSourceFilesMap<u8, Cursor<&'static [u8]>> {
+        SourceFilesMap::load_subset(Cursor::new(MAP_V2_U8), ["*.txt"]).expect("fixture loads")
+    }
+
+    #[test]
+    fn prefetch_hands_every_file_over_once() {
+        model(|| {
+            let mut partial = partial();
+            let ids = 1..=partial.len() as u8;
+            let prefetch = partial.prefetch(ids, || Ok(Cursor::new(MAP_V2_U8)));
+            assert_eq!(prefetch.wait().expect("fixture reads"), 2);
+            assert_eq!(partial.apply_prefetched(), 2);
+            assert_eq!(partial.apply_prefetched(), 0);
+            assert_eq!(partial.loaded_ids().count(), 3);
+        });
+    }
+
+    #[test]
+    fn every_waiter_wakes_up() {
+        model(|| {
+            let partial = partial();
+            let ids = 1..=partial.len() as u8;
+            let prefetch = partial.prefetch(ids, || Ok(Cursor::new(MAP_V2_U8)));
+            let other = prefetch.clone();
+            let waiter = loom::thread::spawn(move || other.wait().is_ok());
+            assert!(prefetch.wait().is_ok());
+            assert!(waiter.join().expect("waiter finishes"));
+            assert!(prefetch.is_finished());
+        });
+    }
+}
+
+#[cfg(test)]
+mod workspace {
+    use super::test_utils::finalized;
+    use crate::*;
+
+    fn package(files: &[(&str, &str)]) -> Result<SourceFilesMap<u8>, String> {
+        finalized(SourceFilesMap::new(), files)
+    }
+
+    fn workspace() -> Result<SourceWorkspace<u8>, String> {
+        let mut workspace = SourceWorkspace::new();
+        workspace.insert(
+            "crates/core",
+            package(&[
+                ("src/lib.rs", "// TODO: docs\n"),
+                ("src/a.rs", "fn a() {}\n"),
+            ])?,
+        );
+        workspace.insert(
+            "crates/cli",
+            package(&[("src/lib.rs", "fn main() {} // TODO\n")])?,
+        );
+        Ok(workspace)
+    }
+
+    #[test]
+    fn files_are_addressed_by_namespace_and_id() -> Result<(), String> {
+        let mut workspace = workspace()?;
+        assert_eq!(workspace.len(), 2);
+        assert_eq!(workspace.file_count(), 3);
+
+        let cli = workspace.namespace("crates/cli").ok_or("cli namespace")?;
+        assert_eq!(workspace.name(cli), Some("crates/cli"));
+        let found: Vec<_> = workspace.find("src/lib.rs").collect();
+        assert_eq!(found.len(), 2);
+        assert_eq!(
+            workspace.get_content(found[1]),
+            Some(&b"fn main() {} // TODO\n"[..])
+        );
+        let a = workspace.get_id("crates/core", "src/a.rs").ok_or("a.rs")?;
+        assert_eq!(workspace.get_path(a), Some("src/a.rs"));
+        assert_eq!(workspace.get_id("crates/cli", "src/a.rs"), None);
+
+        // Same name, same namespace, new map
+        let again = workspace.insert("crates/cli", package(&[("src/b.rs", "")])?);
+        assert_eq!(again, cli);
+        assert_eq!(workspace.len(), 2);
+        assert_eq!(workspace.find("src/lib.rs").count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn search_spans_every_map() -> Result<(), String> {
+        let workspace = workspace()?;
+        let found = workspace.search(b"TODO");
+        let names: Vec<_> = found.iter().map(|m| workspace.name(m.namespace)).collect();
+        assert_eq!(names, [Some("crates/core"), Some("crates/cli")]);
+        assert_eq!(found[1].range, 16..20);
+        #[cfg(feature = "view")]
+        {
+            let position = workspace.position(&found[1]).ok_or("position")?;
+            assert_eq!(position.start_column(), 17);
+        }
+        assert!(workspace.search(b"").is_empty());
+        Ok(())
+    }
+
+    #[cfg(feature = "sarif")]
+    #[test]
+    fn diagnostics_export_to_one_run() -> Result<(), String> {
+        let workspace = workspace()?;
+        let core = workspace.namespace("crates/core").ok_or("core")?;
+        let cli = workspace.namespace("crates/cli").ok_or("cli")?;
+        let (_, core_lib) = workspace.get_id("crates/core", "src/lib.rs").ok_or("lib")?;
+        let (_, cli_lib) = workspace.get_id("crates/cli", "src/lib.rs").ok_or("lib")?;
+        let core_bag: DiagnosticBag<u8> =
+            [
+                Diagnostic::warning("missing docs", AbsolutePosition::new(core_lib, 1, 4, 1, 7))
+                    .with_code("docs"),
+            ]
+            .into_iter()
+            .collect();
+        let cli_bag: DiagnosticBag<u8> =
+            [
+                Diagnostic::error("todo left", AbsolutePosition::new(cli_lib, 1, 17, 1, 20))
+                    .with_code("todo"),
+            ]
+            .into_iter()
+            .collect();
+
+        let log = workspace.to_sarif(
+            [(core, &core_bag), (cli, &cli_bag)],
+            SarifDriver::new("lint"),
+        );
+        assert_eq!(log.runs.len(), 1);
+        let run = &log.runs[0];
+        assert_eq!(run.tool.driver.rules.len(), 2);
+        let uris: Vec<_> = run
+            .results
+            .iter()
+            .map(|r| {
+                r.locations[0]
+                    .physical_location
+                    .artifact_location
+                    .uri
+                    .as_str()
+            })
+            .collect();
+        assert_eq!(uris, ["crates/core/src/lib.rs", "crates/cli/src/lib.rs"]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod file_graph {
+    use crate::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn queries_follow_edges_both_ways() {
+        // 1 -> 2 -> 3 -> 2, 4 -> 3
+        let mut graph: FileGraph<u8> = [(1, 2), (2, 3), (3, 2), (4, 3)].into_iter().collect();
+        assert!(!graph.add_edge(1, 2));
+        assert_eq!(graph.edge_count(), 4);
+        assert_eq!(graph.dependencies(2).collect::<Vec<_>>(), [3]);
+        assert_eq!(graph.dependents(3).collect::<Vec<_>>(), [2, 4]);
+        assert_eq!(graph.reachable(1), BTreeSet::from([2, 3]));
+        assert_eq!(graph.reachable(2), BTreeSet::from([2, 3]));
+        assert_eq!(graph.reachable(4), BTreeSet::from([2, 3]));
+        assert_eq!(graph.affected_by([3]), BTreeSet::from([1, 2, 3, 4]));
+        assert_eq!(graph.affected_by([1]), BTreeSet::from([1]));
+
+        graph.remove_file(3);
+        assert_eq!(graph.edges().collect::<Vec<_>>(), [(1, 2)]);
+        assert!(graph.remove_edge(1, 2));
+        assert!(!graph.remove_edge(1, 2));
+        assert!(graph.is_empty());
+    }
+
+    #[test]
+    fn edges_follow_reassigned_ids() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("src/main.rs".to_string(), b"mod util;".to_vec())?;
+        files.add_file("src/util.rs".to_string(), Vec::new())?;
+        files.finalize()?;
+        let main = files.get_id("src/main.rs").ok_or("main")?;
+        let util = files.get_id("src/util.rs").ok_or("util")?;
+        files.graph_mut().add_edge(main, util);
+
+        // Sorted first, the new file shifts every ID
+        files.add_file("build.rs".to_string(), Vec::new())?;
+        files.finalize()?;
+        let main = files.get_id("src/main.rs").ok_or("main")?;
+        let util = files.get_id("src/util.rs").ok_or("util")?;
+        assert_eq!(files.graph().edges().collect::<Vec<_>>(), [(main, util)]);
+        Ok(())
+    }
+
+    #[test]
+    fn edges_of_removed_files_are_dropped() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new().with_order(FileOrder::Insertion);
+        let a = files.insert_file("a.rs".to_string(), Vec::new())?;
+        let b = files.insert_file("b.rs".to_string(), Vec::new())?;
+        let c = files.insert_file("c.rs".to_string(), Vec::new())?;
+        files.graph_mut().add_edge(a, b);
+        files.graph_mut().add_edge(c, b);
+
+        assert!(files.remove_pending("a.rs"));
+        let b = files.get_id("b.rs").ok_or("b")?;
+        let c = files.get_id("c.rs").ok_or("c")?;
+        assert_eq!(files.graph().edges().collect::<Vec<_>>(), [(c, b)]);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_ids_are_rejected() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("a.rs".to_string(), Vec::new())?;
+        files.finalize()?;
+        let graph: FileGraph<u8> = [(1, 9)].into_iter().collect();
+        assert_eq!(
+            files.set_graph(graph),
+            Err(SourceFilesError::UnknownFile { id: 9 })
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn graph_serializes_with_the_map() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u16>::new();
+        files.add_file("a.rs".to_string(), Vec::new())?;
+        files.add_file("b.rs".to_string(), Vec::new())?;
+        files.finalize()?;
+        files.graph_mut().add_edge(2, 1);
+
+        let config = bincode::config::standard();
+        let bytes = bincode::serde::encode_to_vec(&files, config).map_err(|e| e.to_string())?;
+        let (loaded, _): (SourceFilesMap<u16>, _) =
+            bincode::serde::decode_from_slice(&bytes, config).map_err(|e| e.to_string())?;
+        assert_eq!(loaded.graph(), files.graph());
+
+        let mut cache = Vec::new();
+        files.write_cache(&mut cache).map_err(|e| e.to_string())?;
+        let loaded =
+            SourceFilesMap::<u16>::read_cache(&mut &cache[..]).map_err(|e| e.to_string())?;
+        assert_eq!(loaded.graph().edges().collect::<Vec<_>>(), [(2, 1)]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod extensions {
+    use crate::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Lints(Vec<&'static str>);
+
+    #[test]
+    fn values_are_kept_per_type_and_file() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("a.rs".to_string(), Vec::new())?;
+        files.add_file("b.rs".to_string(), Vec::new())?;
+        files.finalize()?;
+
+        assert_eq!(files.set_ext(1, Lints(vec!["unused"]))?, None);
+        assert_eq!(files.set_ext(1, 42u32)?, None);
+        assert_eq!(files.set_ext(1, 7u32)?, Some(42));
+        assert_eq!(files.get_ext::<Lints>(1), Some(&Lints(vec!["unused"])));
+        assert_eq!(files.get_ext::<u32>(1), Some(&7));
+        assert_eq!(files.get_ext::<u32>(2), None);
+        assert_eq!(files.get_ext::<String>(1), None);
+
+        files.get_ext_mut::<Lints>(1).ok_or("lints")?.0.push("dead");
+        let copy = files.clone();
+        assert_eq!(
+            files.remove_ext::<Lints>(1),
+            Some(Lints(vec!["unused", "dead"]))
+        );
+        assert_eq!(files.get_ext::<Lints>(1), None);
+        assert!(copy.get_ext::<Lints>(1).is_some());
+        assert_eq!(
+            files.set_ext(9, 1u32),
+            Err(SourceFilesError::UnknownFile { id: 9 })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn values_follow_their_file() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("b.rs".to_string(), Vec::new())?;
+        files.add_file("c.rs".to_string(), Vec::new())?;
+        files.finalize()?;
+        files.set_ext(1, "b")?;
+        files.set_ext(2, "c")?;
+
+        // Sorted first, the new file shifts both IDs
+        files.add_file("a.rs".to_string(), Vec::new())?;
+        files.finalize()?;
+        let ext = |files: &SourceFilesMap<u8>, path| {
+            files
+                .get_id(path)
+                .and_then(|id| files.get_ext::<&str>(id).copied())
+        };
+        assert_eq!(ext(&files, "a.rs"), None);
+        assert_eq!(ext(&files, "b.rs"), Some("b"));
+        assert_eq!(ext(&files, "c.rs"), Some("c"));
+
+        let mut files = SourceFilesMap::<u8>::new().with_order(FileOrder::Insertion);
+        for path in ["a.rs", "b.rs", "c.rs"] {
+            let id = files.insert_file(path.to_string(), Vec::new())?;
+            files.set_ext(id, path)?;
+        }
+        assert!(files.remove_pending("a.rs"));
+        assert_eq!(files.get_ext::<&str>(1), Some(&"b.rs"));
+        assert_eq!(files.get_ext::<&str>(2), Some(&"c.rs"));
+        assert_eq!(files.get_ext::<&str>(3), None);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod normalization {
+    use crate::*;
+
+    #[test]
+    fn passes_rewrite_and_map_offsets_back() {
+        let all = Normalize::new()
+            .with_tab_width(4)
+            .with_crlf_to_lf(true)
+            .with_trim_trailing_whitespace(true);
+        let (out, offsets) = all.apply(b"\tx = 1;  \r\nab\tc\n");
+        assert_eq!(out, b"    x = 1;\nab  c\n");
+        // `x`, the first newline, `c` and the end
+        assert_eq!(offsets.original_offset(4), 1);
+        assert_eq!(offsets.original_offset(10), 10);
+        assert_eq!(offsets.original_offset(15), 14);
+        assert_eq!(offsets.original_offset(17), 16);
+        // Inside an expanded tab
+        assert_eq!(offsets.original_offset(2), 0);
+        assert_eq!(offsets.original_range(0..4), 0..1);
+
+        let (out, offsets) = all.apply(b"clean\n");
+        assert_eq!(out, b"clean\n");
+        assert!(offsets.is_identity());
+        let (out, offsets) = all.apply(b"");
+        assert!(out.is_empty() && offsets.is_identity());
+        // CR kept, whitespace before it trimmed
+        let (out, _) = Normalize::new()
+            .with_trim_trailing_whitespace(true)
+            .apply(b"a \r\n");
+        assert_eq!(out, b"a\r\n");
+    }
+
+    #[test]
+    fn maps_normalize_at_add_time() -> Result<(), String> {
+        let options = LoadOptions::new()
+            .with_normalize(Normalize::new().with_tab_width(4).with_crlf_to_lf(true));
+        let mut files = SourceFilesMap::<u8>::new().with_load_options(options);
+        files.add_file(
+            "a.rs".to_string(),
+            b"fn a() {\r\n\tlet x;\r\n}\r\n".to_vec(),
+        )?;
+        files.add_file("b.rs".to_string(), b"fn b() {}\n".to_vec())?;
+        files.finalize()?;
+        assert_eq!(
+            files.get_content(1),
+            Some(&b"fn a() {\n    let x;\n}\n"[..])
+        );
+        assert!(files.offset_map(2).is_none());
+        assert_eq!(files.original_range(2, 3..4), Some(3..4));
+
+        // `x` is at 17..18 normalized, 15..16 as added
+        assert_eq!(files.original_range(1, 17..18), Some(15..16));
+        #[cfg(feature = "view")]
+        {
+            let pos = files.position(1, 17..18).ok_or("position")?;
+            assert_eq!((pos.start_line(), pos.start_column()), (2, 9));
+            let original = files.original_position(1, &pos).ok_or("original")?;
+            assert_eq!(
+                (
+                    original.start_line(),
+                    original.start_column(),
+                    original.end_column()
+                ),
+                (2, 6, 6)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn edits_drop_the_offsets_of_added_content() -> Result<(), String> {
+        let options = LoadOptions::new().with_normalize(Normalize::new().with_tab_width(4));
+        let mut files = SourceFilesMap::<u8>::new().with_load_options(options);
+        files.add_file("a.rs".to_string(), b"\tx\n".to_vec())?;
+        files.finalize()?;
+        assert!(files.offset_map(1).is_some());
+
+        // Removing the expanded tab leaves content the offsets do not describe
+        assert!(files.replace_range(1, 0..4, b""));
+        assert_eq!(files.get_content(1), Some(&b"x\n"[..]));
+        assert!(files.offset_map(1).is_none());
+        assert_eq!(files.original_range(1, 0..1), Some(0..1));
+        #[cfg(feature = "view")]
+        {
+            let pos = files.position(1, 0..1).ok_or("position")?;
+            assert_eq!(files.original_position(1, &pos), Some(pos));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn discarded_duplicates_keep_the_offsets_of_the_kept_file() -> Result<(), String> {
+        let options = LoadOptions::new().with_normalize(Normalize::new().with_tab_width(4));
+        let mut files = SourceFilesMap::<u8>::new().with_load_options(options.clone());
+        files.add_file("a.rs".to_string(), b"\tx".to_vec())?;
+        files.add_file("a.rs".to_string(), b"y".to_vec())?;
+        files.finalize()?;
+        assert_eq!(files.get_content(1), Some(&b"    x"[..]));
+        assert_eq!(files.original_range(1, 4..5), Some(1..2));
+
+        let mut files = SourceFilesMap::<u8>::new()
+            .with_load_options(options)
+            .with_order(FileOrder::Insertion)
+            .with_duplicate_policy(DuplicatePolicy::KeepLast);
+        files.add_file("a.rs".to_string(), b"\tx".to_vec())?;
+        files.add_file("a.rs".to_string(), b"y".to_vec())?;
+        assert_eq!(files.get_content(1), Some(&b"y"[..]));
+        assert!(files.offset_map(1).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn edited_files_flush_back_in_their_disk_format() -> Result<(), String> {
+        let dir = std::env::temp_dir().join(format!("sourcier-normalized-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let raw = b"x\r\ny  \r\n\tw\r\n".to_vec();
+        std::fs::write(dir.join("a.rs"), &raw).map_err(|e| e.to_string())?;
+        let normalize = Normalize::new()
+            .with_tab_width(4)
+            .with_crlf_to_lf(true)
+            .with_trim_trailing_whitespace(true);
+        let options = LoadOptions::new().with_normalize(normalize);
+        let mut files = SourceFilesMap::<u8>::new().with_load_options(options);
+        files.add_file("a.rs".to_string(), raw)?;
+        files.finalize()?;
+        assert_eq!(files.get_content(1), Some(&b"x\ny\n    w\n"[..]));
+        assert!(files.replace_range(1, 0..1, b"z"));
+
+        // The disk still holds the content as added
+        let report = files.refresh_from_disk(&dir).map_err(|e| e.to_string())?;
+        assert!(report.conflicts.is_empty());
+        assert_eq!(report.unchanged, 1);
+        let verified = files.verify(1, &dir).map_err(|e| e.to_string())?;
+        assert_eq!(verified, Verification::Fresh);
+
+        let write = WriteOptions {
+            root: Some(dir.clone()),
+            ..WriteOptions::default()
+        };
+        let written = files.flush_to_disk(None, &write);
+        let on_disk = std::fs::read(dir.join("a.rs"));
+        let refreshed = files.refresh_from_disk(&dir);
+        std::fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+        assert_eq!(written.map_err(|e| e.to_string())?, [1]);
+        // Only the edited line is rewritten, with the file's line ending
+        assert_eq!(on_disk.map_err(|e| e.to_string())?, b"z\r\ny  \r\n\tw\r\n");
+        assert_eq!(refreshed.map_err(|e| e.to_string())?.unchanged, 1);
+        // The written file normalizes to the content, so offsets are back
+        let offsets = files.offset_map(1).ok_or("offsets")?;
+        assert_eq!(offsets.original_offset(8), 9);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "view"))]
+mod snippets {
+    use crate::*;
+
+    #[test]
+    fn gutters_fit_the_last_line_number() {
+        assert_eq!(gutter_width(0), 1);
+        assert_eq!(gutter_width(9), 1);
+        assert_eq!(gutter_width(10), 2);
+        assert_eq!(gutter_width(120), 3);
+    }
+
+    #[test]
+    fn underlines_count_display_columns() -> Result<(), String> {
+        assert_eq!(display_width("\té".as_bytes(), 4), 5);
+        assert_eq!(display_width(b"ab\tc", 4), 5);
+
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file(
+            "a.rs".to_string(),
+            "\tlet é = f(\r\n  x);\n".as_bytes().to_vec(),
+        )?;
+        files.finalize()?;
+        // `é = f(` through `x)`
+        let pos = files.position(1, 5..18).ok_or("position")?;
+        assert_eq!(
+            files.underline_segments(&pos, 4),
+            [
+                UnderlineSegment {
+                    line: 1,
+                    start: 8,
+                    width: 6
+                },
+                UnderlineSegment {
+                    line: 2,
+                    start: 0,
+                    width: 4
+                },
+            ]
+        );
+        let empty = files.position(1, 1..1).ok_or("empty")?;
+        let segments = files.underline_segments(&empty, snp::DEFAULT_TAB_WIDTH);
+        assert_eq!((segments[0].start, segments[0].width), (4, 0));
+        assert_eq!(segments[0].carets(), 1);
+        assert_eq!(underline_segment(b"abc", 3, &pos, 4), None);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "tui"))]
+mod tui_widget {
+    use crate::*;
+    use ratatui::buffer::Buffer;
+    use ratatui::layout::Rect;
+    use ratatui::style::{Modifier, Style};
+    use ratatui::widgets::Widget;
+
+    fn rows(buf: &Buffer) -> Vec<String> {
+        (0..buf.area.height)
+            .map(|y| {
+                (0..buf.area.width)
+                    .map(|x| buf[(x, y)].symbol())
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn snippet_view_renders_lines_and_highlights() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        let content: String = (1..=12).map(|n| format!("line {n}\n")).collect();
+        files.add_file("a.txt".to_string(), content.into_bytes())?;
+        files.add_file("b.txt".to_string(), b"other\n".to_vec())?;
+        files.finalize()?;
+        let id = files.get_id("a.txt").ok_or("id")?;
+        // `10` on line 10
+        let pos = files.position(id, 68..70).ok_or("position")?;
+        let elsewhere = files.position(2, 0..5).ok_or("elsewhere")?;
+        let view = SnippetView::new(&files, id)
+            .ok_or("view")?
+            .with_highlights([pos, elsewhere]);
+        let scroll = view.scroll_to(&pos, 3);
+        assert_eq!(scroll, 9);
+        let view = view.with_scroll(scroll);
+
+        let mut buf = Buffer::empty(Rect::new(0, 0, 12, 3));
+        (&view).render(buf.area, &mut buf);
+        assert_eq!(rows(&buf), [" 9 │ line 9", "10 │ line 10", "11 │ line 11"]);
+        let highlighted: Vec<u16> = (0..12)
+            .filter(|&x| buf[(x, 1)].modifier.contains(Modifier::REVERSED))
+            .collect();
+        assert_eq!(highlighted, [10, 11]);
+        assert!(!buf[(0, 1)].modifier.contains(Modifier::REVERSED));
+
+        // Near the end only the remaining lines show, the empty one after the
+        // trailing newline included, cut to the area
+        let mut buf = Buffer::empty(Rect::new(0, 0, 9, 3));
+        view.with_scroll(12)
+            .with_text_style(Style::new().add_modifier(Modifier::BOLD))
+            .render(buf.area, &mut buf);
+        assert_eq!(rows(&buf), ["12 │ line", "13 │", ""]);
+        assert!(buf[(5, 0)].modifier.contains(Modifier::BOLD));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod map_fingerprint {
+    use super::test_utils::finalized;
+    use crate::*;
+
+    fn map(files: &[(&str, &[u8])], options: LoadOptions) -> Result<SourceFilesMap<u8>, String> {
+        finalized(SourceFilesMap::new().with_load_options(options), files)
+    }
+
+    #[test]
+    fn fingerprints_change_with_paths_content_and_options() -> Result<(), String> {
+        let files: &[(&str, &[u8])] = &[("b.rs", b"fn b() {}\n"), ("a.rs", b"fn a() {}\n")];
+        let base = map(files, LoadOptions::new())?;
+        let fingerprint = base.fingerprint().ok_or("finalized")?;
+        // Submission order does not matter once IDs are assigned by path
+        let reversed: Vec<_> = files.iter().rev().copied().collect();
+        assert_eq!(
+            map(&reversed, LoadOptions::new())?.fingerprint(),
+            Some(fingerprint)
+        );
+        assert_eq!(base.clone().freeze().fingerprint(), Some(fingerprint));
+
+        let edited = map(
+            &[("b.rs", b"fn b() {}\n"), ("a.rs", b"fn a() { }\n")],
+            LoadOptions::new(),
+        )?;
+        assert_ne!(edited.fingerprint(), Some(fingerprint));
+        let renamed = map(
+            &[("b.rs", b"fn b() {}\n"), ("c.rs", b"fn a() {}\n")],
+            LoadOptions::new(),
+        )?;
+        assert_ne!(renamed.fingerprint(), Some(fingerprint));
+        let options = LoadOptions::new().with_max_file_size(1 << 20);
+        assert_ne!(map(files, options)?.fingerprint(), Some(fingerprint));
+
+        let mut pending = base;
+        pending.add_file("c.rs".to_string(), Vec::new())?;
+        assert_eq!(pending.fingerprint(), None);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "cdc"))]
+mod chunked_cache {
+    use crate::*;
+    use std::collections::HashSet;
+
+    fn snapshot(edited: &str) -> Result<SourceFilesMap<u16>, String> {
+        let mut files = SourceFilesMap::new();
+        for n in 0..300 {
+            let content: String = (0..20)
+                .map(|line| format!("fn item_{n}_{line}() -> u32 {{ {} }}\n", n * line))
+                .collect();
+            files.add_file(format!("src/m{n:03}.rs"), content.into_bytes())?;
+        }
+        files.add_file("src/m150b.rs".to_string(), edited.as_bytes().to_vec())?;
+        files.finalize()?;
+        Ok(files)
+    }
+
+    #[test]
+    fn snapshots_share_most_chunks() -> Result<(), String> {
+        let io = |e: std::io::Error| e.to_string();
+        let root = std::env::temp_dir().join(format!("sourcier-chunks-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let store = ChunkStore::new(&root).with_chunking(Chunking::new(256, 1024, 4096));
+
+        let first = snapshot("fn old() {}\n")?;
+        let before = first.write_chunked(&store).map_err(|e| e.to_string())?;
+        let second = snapshot("fn new() { let edited = true; }\n")?;
+        let after = second.write_chunked(&store).map_err(|e| e.to_string())?;
+        assert!(after.chunks.len() > 10);
+        let known: HashSet<_> = before.chunks.iter().collect();
+        let shared = after.chunks.iter().filter(|c| known.contains(c)).count();
+        assert!(
+            shared * 10 >= after.chunks.len() * 9,
+            "{shared} of {} chunks shared",
+            after.chunks.len()
+        );
+
+        let mut raw = Vec::new();
+        after.write_to(&mut raw).map_err(io)?;
+        let manifest = ChunkManifest::read_from(&mut raw.as_slice()).map_err(|e| e.to_string())?;
+        assert_eq!(manifest, after);
+        let loaded =
+            SourceFilesMap::<u16>::read_chunked(&store, &manifest).map_err(|e| e.to_string())?;
+        assert_eq!(loaded.fingerprint(), second.fingerprint());
+
+        // Dropping the first snapshot only deletes the chunks it alone used
+        let deleted = store.retain([&after]).map_err(io)?;
+        assert_eq!(
+            deleted,
+            before.chunks.len()
+                - before
+                    .chunks
+                    .iter()
+                    .filter(|c| after.chunks.contains(c))
+                    .count()
+        );
+        assert!(SourceFilesMap::<u16>::read_chunked(&store, &after).is_ok());
+        assert!(SourceFilesMap::<u16>::read_chunked(&store, &before).is_err());
+
+        std::fs::write(store.path(&after.chunks[0]), b"tampered").map_err(io)?;
+        assert!(matches!(store.read(&after), Err(WireError::Corrupt(_))));
+        std::fs::remove_dir_all(&root).map_err(io)?;
+        Ok(())
+    }
+
+    #[test]
+    fn oversized_manifests_fail_without_allocating() -> Result<(), String> {
+        let io = |e: std::io::Error| e.to_string();
+        let root = std::env::temp_dir().join(format!("sourcier-big-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let store = ChunkStore::new(&root);
+        let small = store.write(b"fn a() {}\n").map_err(io)?;
+        let mut chunks = vec![small.chunks[0]; 1000];
+        for chunk in &mut chunks[1..] {
+            chunk.len = u32::MAX;
+        }
+        let result = store.read(&ChunkManifest { chunks });
+        std::fs::remove_dir_all(&root).map_err(io)?;
+        // The second chunk is shorter than claimed
+        assert!(matches!(result, Err(WireError::Corrupt(_))));
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "rustc"))]
+mod rustc_import {
+    use crate::*;
+    use serde_json::json;
+
+    const MAIN: &str = "fn main() {\n    let x: u32 = \"a\";\n    return;\n}\n";
+
+    fn span(file: &str, text: &str, primary: bool, label: Option<&str>) -> serde_json::Value {
+        let start = MAIN.find(text).unwrap_or(0);
+        json!({
+            "file_name": file, "byte_start": start, "byte_end": start + text.len(),
+            "line_start": 2, "line_end": 2, "column_start": 1, "column_end": 1,
+            "is_primary": primary, "label": label,
+            "suggested_replacement": null, "suggestion_applicability": null,
+        })
+    }
+
+    fn output() -> String {
+        let mismatch = json!({
+            "message": "mismatched types", "code": {"code": "E0308", "explanation": null},
+            "level": "error",
+            "spans": [
+                span("src/main.rs", "\"a\"", true, Some("expected `u32`, found `&str`")),
+                span("./src/main.rs", "u32", false, Some("expected due to this")),
+            ],
+            "children": [], "rendered": "error[E0308]: mismatched types\n",
+        });
+        let mut fix = span("src/main.rs", "return;", true, None);
+        fix["suggested_replacement"] = json!("");
+        fix["suggestion_applicability"] = json!("MachineApplicable");
+        let lint = json!({
+            "message": "unneeded `return` statement",
+            "code": {"code": "clippy::needless_return"}, "level": "warning",
+            "spans": [span("src/main.rs", "return;", true, None)],
+            "children": [
+                {"message": "`#[warn(clippy::needless_return)]` on by default",
+                 "level": "note", "spans": [], "children": []},
+                {"message": "remove `return`", "level": "help", "spans": [fix], "children": []},
+            ],
+        });
+        let outside = json!({
+            "message": "unused import", "level": "warning",
+            "spans": [span("/registry/dep/src/lib.rs", "fn", true, None)], "children": [],
+        });
+        let summary = json!({
+            "message": "aborting due to 1 previous error", "level": "error",
+            "spans": [], "children": [],
+        });
+        [
+            json!({"reason": "compiler-artifact", "package_id": "demo"}),
+            json!({"reason": "compiler-message", "package_id": "demo", "message": mismatch}),
+            json!({"reason": "compiler-message", "package_id": "demo", "message": lint}),
+            outside,
+            summary,
+            json!({"reason": "build-finished", "success": false}),
+        ]
+        .iter()
+        .map(|line| format!("{line}\n\n"))
+        .collect()
+    }
+
+    #[test]
+    fn compiler_messages_resolve_against_the_map() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("src/main.rs".to_string(), MAIN.as_bytes().to_vec())?;
+        files.finalize()?;
+        let main = files.get_id("src/main.rs").ok_or("main")?;
+        let import = files
+            .import_rustc_json(&output())
+            .map_err(|e| e.to_string())?;
+        assert_eq!(import.diagnostics.len(), 2);
+        assert_eq!(import.unresolved.len(), 2);
+        assert_eq!(
+            import.unresolved[1].message,
+            "aborting due to 1 previous error"
+        );
+
+        let mut diagnostics = import.diagnostics.iter();
+        let mismatch = diagnostics.next().ok_or("mismatch")?;
+        assert_eq!(mismatch.severity, Severity::Error);
+        assert_eq!(mismatch.code.as_deref(), Some("E0308"));
+        assert_eq!(files.view(main, &mismatch.primary), Some(&b"\"a\""[..]));
+        assert_eq!(mismatch.primary.start_line(), 2);
+        let labels: Vec<_> = mismatch
+            .labels
+            .iter()
+            .map(|label| (files.view(main, &label.pos), label.message.as_str()))
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                (Some(&b"\"a\""[..]), "expected `u32`, found `&str`"),
+                (Some(&b"u32"[..]), "expected due to this"),
+            ]
+        );
+
+        let lint = diagnostics.next().ok_or("lint")?;
+        assert_eq!(lint.severity, Severity::Warning);
+        assert_eq!(lint.code.as_deref(), Some("clippy::needless_return"));
+        assert_eq!(
+            lint.notes,
+            [
+                "note: `#[warn(clippy::needless_return)]` on by default",
+                "help: remove `return`",
+            ]
+        );
+        assert_eq!(lint.fixes.len(), 1);
+        assert_eq!(files.view(main, &lint.fixes[0].span), Some(&b"return;"[..]));
+        assert_eq!(lint.fixes[0].replacement, "");
+
+        assert!(files.import_rustc_json("{not json").is_err());
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "js"))]
+mod js_import {
+    use super::test_utils::finalized;
+    use crate::*;
+
+    // "é😀" takes 3 UTF-16 units and 6 bytes, so columns after it differ
+    const APP: &str = "const s = \"é😀\"; var unused = 1;\nlet n: number = s;\n";
+
+    fn map() -> Result<(SourceFilesMap<u8>, u8), String> {
+        let files = finalized(SourceFilesMap::new(), &[("src/app.ts", APP)])?;
+        let id = files.get_id("src/app.ts").ok_or("app")?;
+        Ok((files, id))
+    }
+
+    #[test]
+    fn eslint_columns_count_utf16_units() -> Result<(), String> {
+        let (files, app) = map()?;
+        // `unused` starts at UTF-16 index 21 of line 1, `var` at 17
+        let report = r#"[
+            {"filePath": "/repo/src/app.ts", "errorCount": 1, "messages": [
+                {"ruleId": "no-unused-vars", "severity": 2, "message": "'unused' is unused",
+                 "line": 1, "column": 22, "endLine": 1, "endColumn": 28},
+                {"ruleId": "no-var", "severity": 1, "message": "Unexpected var",
+                 "line": 1, "column": 18, "endLine": 1, "endColumn": 33,
+                 "fix": {"range": [17, 20], "text": "let"}},
+                {"ruleId": null, "severity": 1, "message": "File ignored"}
+            ]},
+            {"filePath": "/elsewhere/lib.js", "messages": [
+                {"ruleId": "semi", "severity": 2, "message": "Missing semicolon",
+                 "line": 1, "column": 1}
+            ]}
+        ]"#;
+        let import = files
+            .import_eslint_json("/repo", report)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(
+            import.unresolved,
+            [
+                "/repo/src/app.ts: File ignored",
+                "/elsewhere/lib.js: Missing semicolon"
+            ]
+        );
+        let diagnostics: Vec<_> = import.diagnostics.iter().collect();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].code.as_deref(), Some("no-unused-vars"));
+        assert_eq!(
+            files.view(app, &diagnostics[0].primary),
+            Some(&b"unused"[..])
+        );
+        assert_eq!(diagnostics[1].severity, Severity::Warning);
+        assert_eq!(
+            files.view(app, &diagnostics[1].primary),
+            Some(&b"var unused = 1;"[..])
+        );
+        let fix = &diagnostics[1].fixes[0];
+        assert_eq!(files.view(app, &fix.span), Some(&b"var"[..]));
+        assert_eq!(fix.replacement, "let");
+
+        assert!(files.import_eslint_json("/repo", "{}").is_err());
+        let file = files.file(app).ok_or("file")?;
+        assert_eq!(file.byte_column(1, 12), Some(13));
+        assert_eq!(file.byte_column(1, 13), Some(17));
+        assert_eq!(file.byte_column(1, 40), None);
+        assert_eq!(file.utf16_offset(17), Some(20));
+        Ok(())
+    }
+
+    #[test]
+    fn tsc_lines_become_diagnostics() -> Result<(), String> {
+        let (files, app) = map()?;
+        let output = "\
+src/app.ts(2,5): error TS2322: Type 'string' is not assignable to type 'number'.
+  The types are incompatible.
+error TS5083: Cannot read file '/repo/tsconfig.base.json'.
+/repo/src/app.ts(1,18): warning TS6133: 'unused' is declared but never read.
+src/missing.ts(1,1): error TS1005: ';' expected.
+";
+        let import = files.import_tsc_output("/repo", output);
+        assert_eq!(
+            import.unresolved,
+            [
+                "error TS5083: Cannot read file '/repo/tsconfig.base.json'.",
+                "src/missing.ts(1,1): error TS1005: ';' expected.",
+            ]
+        );
+        let diagnostics: Vec<_> = import.diagnostics.iter().collect();
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].code.as_deref(), Some("TS2322"));
+        assert_eq!(diagnostics[0].notes, ["The types are incompatible."]);
+        assert_eq!(
+            (
+                diagnostics[0].primary.start_line(),
+                diagnostics[0].primary.start_column()
+            ),
+            (2, 5)
+        );
+        assert_eq!(diagnostics[1].severity, Severity::Warning);
+        let var = files.position(app, 20..23).ok_or("var")?;
+        assert_eq!(
+            (
+                diagnostics[1].primary.start_line(),
+                diagnostics[1].primary.start_column()
+            ),
+            (1, var.start_column())
+        );
+        assert_eq!(files.utf16_position(app, (1, 18), (1, 21)), Some(var));
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "view"))]
+mod gnu_messages {
+    use crate::*;
+
+    const MAIN: &str = "int main(void) {\n\tint x = y;\n\treturn 0;\n}\n";
+
+    #[test]
+    fn locations_parse_leniently() -> Result<(), String> {
+        let parser = GnuParser::new();
+        let message = parser
+            .parse_line("C:\\src\\main.c:2:10: Fatal Error: 'y' undeclared")
+            .ok_or("drive")?;
+        assert_eq!(
+            (message.path, message.line, message.column, message.end),
+            ("C:\\src\\main.c", 2, Some(10), None)
+        );
+        assert_eq!(
+            (message.severity, message.message),
+            (Severity::Error, "'y' undeclared")
+        );
+        let message = parser.parse_line("main.c:2.6-2.10: oops").ok_or("range")?;
+        assert_eq!((message.column, message.end), (Some(6), Some((2, 10))));
+        assert_eq!(
+            (message.severity, message.message),
+            (Severity::Error, "oops")
+        );
+        let message = parser.parse_line("main.c:3: x").ok_or("no column")?;
+        assert_eq!((message.line, message.column, message.end), (3, None, None));
+        assert!(parser.parse_line("main.c: In function 'main':").is_none());
+        assert!(parser.parse_line("    2 |  int x = y;").is_none());
+
+        let parser = GnuParser::new()
+            .with_keyword("E", Severity::Error)
+            .with_keyword("warning", Severity::Note)
+            .with_untagged_severity(Severity::Warning);
+        let message = parser.parse_line("a.f90:1:1: e: bad").ok_or("e")?;
+        assert_eq!(
+            (message.severity, message.message),
+            (Severity::Error, "bad")
+        );
+        let message = parser.parse_line("a.f90:1: WARNING: w").ok_or("w")?;
+        assert_eq!(message.severity, Severity::Note);
+        let message = parser.parse_line("a.f90:1: plain").ok_or("plain")?;
+        assert_eq!(
+            (message.severity, message.message),
+            (Severity::Warning, "plain")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn output_imports_with_notes_as_labels() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("src/main.c".to_string(), MAIN.as_bytes().to_vec())?;
+        files.finalize()?;
+        let main = files.get_id("src/main.c").ok_or("main")?;
+        let output = "\
+src/main.c: In function 'main':
+src/main.c:2:10: error: 'y' undeclared
+    2 |  int x = y;
+      |          ^
+/work/src/main.c:1:5: note: 'main' declared here
+/work/src/main.c:2:6-10: warning: unused variable 'x'
+src/main.c:3: warning: whole line
+src/main.c:2:40: error: past the line
+lib/other.c:1:1: error: not mapped
+";
+        let import = GnuParser::new().import(&files, "/work", output);
+        assert_eq!(
+            import.unresolved,
+            [
+                "src/main.c: In function 'main':",
+                "    2 |  int x = y;",
+                "      |          ^",
+                "src/main.c:2:40: error: past the line",
+                "lib/other.c:1:1: error: not mapped",
+            ]
+        );
+        let diagnostics: Vec<_> = import.diagnostics.iter().collect();
+        assert_eq!(diagnostics.len(), 3);
+        let undeclared = diagnostics[0];
+        assert_eq!(undeclared.severity, Severity::Error);
+        assert_eq!(
+            (
+                undeclared.primary.start_line(),
+                undeclared.primary.start_column()
+            ),
+            (2, 10)
+        );
+        assert_eq!(files.view(main, &undeclared.primary), Some(&b""[..]));
+        assert_eq!(undeclared.labels.len(), 1);
+        assert_eq!(files.view(main, &undeclared.labels[0].pos), Some(&b""[..]));
+        assert_eq!(undeclared.labels[0].message, "'main' declared here");
+        assert_eq!(
+            files.view(main, &diagnostics[1].primary),
+            Some(&b"x = y"[..])
+        );
+        assert_eq!(
+            files.view(main, &diagnostics[2].primary),
+            Some(&b"\treturn 0;"[..])
+        );
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "lsif"))]
+mod lsif_dump {
+    use super::test_utils::add_files;
+    use crate::*;
+    use serde_json::Value;
+
+    fn dump() -> Result<(SourceFilesMap<u8>, Vec<Value>), String> {
+        let mut files = SourceFilesMap::new();
+        add_files!(files => {
+            "src/lib.rs" b"fn add() {}\nfn main() { add(); }\n",
+            "src/my mod.rs" "/* é */ add();\n".as_bytes(),
+        });
+        files.finalize()?;
+        let lib = files.get_id("src/lib.rs").ok_or("lib")?;
+        let other = files.get_id("src/my mod.rs").ok_or("other")?;
+        let mut index = DefinitionIndex::new();
+        let span = |id, range| files.position(id, range).ok_or("span");
+        index.define("add", span(lib, 3..6)?);
+        index.reference("add", span(lib, 24..27)?);
+        index.reference("add", span(other, 9..12)?);
+        index.reference("add", span(other, 9..12)?);
+        index.reference("gone", span(lib, 0..2)?);
+        index.define("main", span(lib, 15..19)?);
+
+        let mut out = Vec::new();
+        index
+            .write_lsif(&files, &LsifOptions::new("file:///work/", "rust"), &mut out)
+            .map_err(|e| e.to_string())?;
+        let lines = String::from_utf8(out).map_err(|e| e.to_string())?;
+        let elements = lines
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.to_string())?;
+        Ok((files, elements))
+    }
+
+    /// `inV`s of the edges labelled `label` leaving `from`
+    fn follow(elements: &[Value], from: &Value, label: &str) -> Vec<u64> {
+        elements
+            .iter()
+            .filter(|e| e["label"] == label && e["outV"] == *from)
+            .flat_map(|e| match &e["inVs"] {
+                Value::Array(ids) => ids.iter().filter_map(Value::as_u64).collect(),
+                _ => e["inV"].as_u64().into_iter().collect::<Vec<_>>(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn references_lead_to_their_definition() -> Result<(), String> {
+        let (_, elements) = dump()?;
+        assert_eq!(elements[0]["label"], "metaData");
+        assert_eq!(elements[0]["positionEncoding"], "utf-16");
+        let ids: Vec<_> = elements.iter().map(|e| e["id"].as_u64()).collect();
+        assert_eq!(
+            ids,
+            (1..=elements.len() as u64).map(Some).collect::<Vec<_>>()
+        );
+        let uris: Vec<_> = elements
+            .iter()
+            .filter(|e| e["label"] == "document")
+            .map(|e| e["uri"].as_str().unwrap_or_default())
+            .collect();
+        assert_eq!(
+            uris,
+            ["file:///work/src/lib.rs", "file:///work/src/my%20mod.rs"]
+        );
+
+        let by_id = |id: u64| &elements[id as usize - 1];
+        // The reference after `é` starts at UTF-16 character 8, byte 9
+        let reference = elements
+            .iter()
+            .find(|e| e["label"] == "range" && e["start"]["character"] == 8)
+            .ok_or("reference range")?;
+        assert_eq!(reference["start"]["line"], 0);
+        assert_eq!(reference["end"]["character"], 11);
+        let result_set = follow(&elements, &reference["id"], "next");
+        assert_eq!(result_set.len(), 1);
+        let definition_result = follow(&elements, &result_set[0].into(), "textDocument/definition");
+        let definitions = follow(&elements, &definition_result[0].into(), "item");
+        assert_eq!(definitions.len(), 1);
+        let definition = by_id(definitions[0]);
+        assert_eq!(
+            definition["start"],
+            serde_json::json!({"line": 0, "character": 3})
+        );
+
+        let reference_result = follow(&elements, &result_set[0].into(), "textDocument/references");
+        assert_eq!(
+            follow(&elements, &reference_result[0].into(), "item").len(),
+            3
+        );
+        assert_eq!(
+            elements
+                .iter()
+                .filter(|e| e["label"] == "resultSet")
+                .count(),
+            3
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn dumps_are_deterministic() -> Result<(), String> {
+        assert_eq!(dump()?.1, dump()?.1);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "view"))]
+mod tags_files {
+    use super::test_utils::add_files;
+    use crate::*;
+
+    fn tagged() -> Result<(SourceFilesMap<u8>, NamedRanges<u8>), String> {
+        let mut files = SourceFilesMap::new();
+        add_files!(files => {
+            "src/lib.rs" b"pub mod parse;\r\nfn eval(x: u32) -> u32 { x / 2 }\n",
+            "src/parse.rs" b"// parser\npub fn parse(s: &str) {}\n",
+        });
+        files.finalize()?;
+        let lib = files.get_id("src/lib.rs").ok_or("lib")?;
+        let parse = files.get_id("src/parse.rs").ok_or("parse")?;
+        let mut ranges = NamedRanges::new();
+        ranges.insert("parse", files.position(parse, 17..22).ok_or("parse")?);
+        ranges.insert("eval", files.position(lib, 19..23).ok_or("eval")?);
+        ranges.insert("mod_parse", files.position(lib, 8..13).ok_or("mod")?);
+        ranges.insert("bad\tname", files.position(lib, 0..3).ok_or("bad")?);
+        Ok((files, ranges))
+    }
+
+    #[test]
+    fn ctags_are_sorted_with_escaped_patterns() -> Result<(), String> {
+        let (files, ranges) = tagged()?;
+        let mut out = Vec::new();
+        let count = files
+            .write_tags(ranges.iter(), TagsFormat::Ctags, &mut out)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(count, 3);
+        assert_eq!(
+            String::from_utf8(out).map_err(|e| e.to_string())?,
+            "!_TAG_FILE_FORMAT\t2\t/extended format/\n\
+             !_TAG_FILE_SORTED\t1\t/0=unsorted, 1=sorted, 2=foldcase/\n\
+             !_TAG_PROGRAM_NAME\tsourcier\t//\n\
+             eval\tsrc/lib.rs\t/^fn eval(x: u32) -> u32 { x \\/ 2 }$/;\"\tline:2\n\
+             mod_parse\tsrc/lib.rs\t/^pub mod parse;$/;\"\tline:1\n\
+             parse\tsrc/parse.rs\t/^pub fn parse(s: &str) {}$/;\"\tline:2\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn paths_with_tabs_or_line_breaks_are_left_out() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        add_files!(files => {
+            "src/a\tb.rs" b"fn a() {}\n",
+            "src/c\nd.rs" b"fn c() {}\n",
+        });
+        files.finalize()?;
+        let mut ranges = NamedRanges::new();
+        for (name, path) in [("a", "src/a\tb.rs"), ("c", "src/c\nd.rs")] {
+            let id = files.get_id(path).ok_or("path")?;
+            ranges.insert(name, files.position(id, 3..4).ok_or("span")?);
+        }
+        for format in [TagsFormat::Ctags, TagsFormat::Etags] {
+            let count = files
+                .write_tags(ranges.iter(), format, std::io::sink())
+                .map_err(|e| e.to_string())?;
+            assert_eq!(count, 0);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn etags_sections_count_their_bytes() -> Result<(), String> {
+        let (files, ranges) = tagged()?;
+        let mut index = DefinitionIndex::new();
+        for (name, pos) in ranges.iter() {
+            index.define(name.to_string(), pos);
+        }
+        let definitions = index
+            .symbols()
+            .filter_map(|(key, definition, _)| Some((key.clone(), definition?)));
+        let mut out = Vec::new();
+        files
+            .write_tags(definitions, TagsFormat::Etags, &mut out)
+            .map_err(|e| e.to_string())?;
+        let lib = "pub mod parse\x7fmod_parse\x011,0\nfn eval\x7feval\x012,16\n";
+        let parse = "pub fn parse\x7fparse\x012,10\n";
+        assert_eq!(
+            String::from_utf8(out).map_err(|e| e.to_string())?,
+            format!(
+                "\x0c\nsrc/lib.rs,{}\n{lib}\x0c\nsrc/parse.rs,{}\n{parse}",
+                lib.len(),
+                parse.len()
+            )
+        );
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "dwarf"))]
+mod dwarf_lines {
+    use crate::*;
+    use gimli::write::{Address, DebugLine, EndianVec, LineStringTable, StringTable};
+    use gimli::{DebugLineOffset, Encoding, Format, LittleEndian};
+
+    #[test]
+    fn line_programs_read_back_with_gimli() -> Result<(), String> {
+        let err = |e: gimli::write::Error| e.to_string();
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file("main.toy".to_string(), b"let x = 1\nprint x\n".to_vec())?;
+        files.add_file("lib.toy".to_string(), b"fn print(v) {}\n".to_vec())?;
+        files.finalize()?;
+        let main = files.get_id("main.toy").ok_or("main")?;
+        let lib = files.get_id("lib.toy").ok_or("lib")?;
+        let span = |id, range| files.position(id, range).ok_or("span");
+
+        let mut table = LineTable::new();
+        table.push(0x8, span(main, 16..17)?);
+        table.push(0x0, span(main, 4..5)?);
+        table.push(0xc, span(lib, 3..8)?);
+        assert_eq!(table.lookup(0xa), Some(span(main, 16..17)?));
+        assert_eq!(table.lookup(0x100), Some(span(lib, 3..8)?));
+        let offsets: Vec<u64> = table.rows().iter().map(|(offset, _)| *offset).collect();
+        assert_eq!(offsets, [0x0, 0x8, 0xc]);
+        let mut late = LineTable::new();
+        late.push(0x4, span(main, 4..5)?);
+        assert_eq!(late.lookup(0x3), None);
+
+        let encoding = Encoding {
+            format: Format::Dwarf32,
+            version: 4,
+            address_size: 8,
+        };
+        let program = table
+            .line_program(&files, encoding, "/src", Address::Constant(0x1000), 0x10)
+            .ok_or("program")?;
+        let mut debug_line = DebugLine::from(EndianVec::new(LittleEndian));
+        program
+            .write(
+                &mut debug_line,
+                encoding,
+                &mut LineStringTable::default(),
+                &mut StringTable::default(),
+            )
+            .map_err(err)?;
+
+        let bytes = debug_line.slice().to_vec();
+        let read = gimli::DebugLine::new(&bytes, LittleEndian);
+        let mut rows = read
+            .program(DebugLineOffset(0), 8, None, None)
+            .map_err(|e| e.to_string())?
+            .rows();
+        let mut seen = Vec::new();
+        while let Some((header, row)) = rows.next_row().map_err(|e| e.to_string())? {
+            let name = match header.file(row.file_index()).map(|file| file.path_name()) {
+                Some(gimli::AttributeValue::String(name)) => {
+                    String::from_utf8_lossy(name.slice()).into_owned()
+                }
+                _ => String::new(),
+            };
+            let column = match row.column() {
+                gimli::ColumnType::Column(column) => column.get(),
+                gimli::ColumnType::LeftEdge => 0,
+            };
+            let line = row.line().map_or(0, |line| line.get());
+            seen.push((row.address(), name, line, column, row.end_sequence()));
+        }
+        assert_eq!(
+            seen,
+            [
+                (0x1000, "main.toy".to_string(), 1, 5, false),
+                (0x1008, "main.toy".to_string(), 2, 7, false),
+                (0x100c, "lib.toy".to_string(), 1, 4, false),
+                (0x1010, "lib.toy".to_string(), 1, 4, true),
+            ]
+        );
+        assert!(
+            LineTable::<u8>::new()
+                .line_program(&files, encoding, "/src", Address::Constant(0), 0)
+                .is_none()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "view"))]
+mod span_coverage {
+    use super::test_utils::add_files;
+    use crate::*;
+
+    const LIB: &str = "fn add(a: u32) -> u32 {\n    if a > 1 {\n\n        a\n    } else { 0 }\n}\n";
+
+    fn coverage() -> Result<(SourceFilesMap<u8>, SpanCoverage<u8>), String> {
+        let mut files = SourceFilesMap::new();
+        add_files!(files => {
+            "src/lib.rs" LIB.as_bytes(),
+            "src/ops/mul.rs" b"fn mul() {}\n",
+            "build.rs" b"fn main() {}\n",
+        });
+        files.finalize()?;
+        let lib = files.get_id("src/lib.rs").ok_or("lib")?;
+        let mul = files.get_id("src/ops/mul.rs").ok_or("mul")?;
+        let span = |id, range| files.position(id, range).ok_or("span");
+        let body = LIB.find("if").ok_or("if")?;
+        let then = LIB.find("a\n").ok_or("then")?;
+        let other = LIB.find("0 }").ok_or("else")?;
+
+        let mut coverage = SpanCoverage::new();
+        // The whole function ran twice, up to the line break ending it
+        coverage.mark(span(lib, 0..LIB.len())?, 2);
+        coverage.uncovered(span(lib, then..then + 2)?);
+        coverage.covered(span(lib, body..other)?);
+        coverage.uncovered(span(lib, other..other + 1)?);
+        let mut second = SpanCoverage::new();
+        second.covered(span(lib, body..other)?);
+        second.uncovered(span(mul, 0..11)?);
+        coverage.merge(&second);
+        assert_eq!(coverage.spans(lib).len(), 4);
+        assert_eq!(coverage.spans(lib)[2].1, 2);
+        Ok((files, coverage))
+    }
+
+    #[test]
+    fn lines_take_the_most_hit_span() -> Result<(), String> {
+        let (files, coverage) = coverage()?;
+        let lib = files.get_id("src/lib.rs").ok_or("lib")?;
+        let lines: Vec<_> = coverage.line_hits(&files, lib).into_iter().collect();
+        // Line 3 is blank and line 7 only the empty end of the function
+        assert_eq!(lines, [(1, 2), (2, 2), (4, 2), (5, 2), (6, 2)]);
+        let mut only_else = SpanCoverage::new();
+        let other = LIB.find("0 }").ok_or("else")?;
+        only_else.uncovered(files.position(lib, other..other + 1).ok_or("span")?);
+        assert_eq!(
+            only_else.summary(&files, lib),
+            CoverageSummary {
+                lines_found: 1,
+                lines_hit: 0
+            }
+        );
+        let total = coverage.total(&files);
+        assert_eq!((total.lines_found, total.lines_hit), (6, 5));
+        assert_eq!(CoverageSummary::default().line_rate(), 1.0);
+        Ok(())
+    }
+
+    #[test]
+    fn lcov_and_cobertura_reports() -> Result<(), String> {
+        let (files, coverage) = coverage()?;
+        let mut lcov = Vec::new();
+        coverage
+            .write_lcov(&files, Some("unit"), &mut lcov)
+            .map_err(|e| e.to_string())?;
+        assert_eq!(
+            String::from_utf8(lcov).map_err(|e| e.to_string())?,
+            "TN:unit\nSF:src/lib.rs\nDA:1,2\nDA:2,2\nDA:4,2\nDA:5,2\nDA:6,2\nLF:5\nLH:5\nend_of_record\n\
+             TN:unit\nSF:src/ops/mul.rs\nDA:1,0\nLF:1\nLH:0\nend_of_record\n"
+        );
+
+        let mut xml = Vec::new();
+        coverage
+            .write_cobertura(&files, 1_700_000_000_000, &mut xml)
+            .map_err(|e| e.to_string())?;
+        let xml = String::from_utf8(xml).map_err(|e| e.to_string())?;
+        assert!(
+            xml.contains(r#"line-rate="0.8333" branch-rate="0" lines-covered="5" lines-valid="6""#)
+        );
+        assert!(xml.contains(r#"timestamp="1700000000000">"#));
+        assert!(xml.contains(r#"<package name="src" line-rate="1.0000""#));
+        assert!(xml.contains(r#"<package name="src/ops" line-rate="0.0000""#));
+        assert!(xml.contains(
+            r#"<class name="src/ops/mul.rs" filename="src/ops/mul.rs" line-rate="0.0000""#
+        ));
+        assert!(xml.contains(r#"<line number="4" hits="2" branch="false"/>"#));
+        assert!(!xml.contains("build.rs"));
+        assert!(xml.trim_end().ends_with("</coverage>"));
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "view"))]
+mod span_heatmap {
+    use super::test_utils::add_files;
+    use crate::*;
+
+    const LIB: &str = "fn main() {\n    hot();\n    cold();\n}\n";
+
+    fn heatmap() -> Result<(SourceFilesMap<u8>, SpanHeatmap<u8>), String> {
+        let mut files = SourceFilesMap::new();
+        add_files!(files => {
+            "src/lib.rs" LIB.as_bytes(),
+            "build.rs" b"fn main() {}\n",
+        });
+        files.finalize()?;
+        let lib = files.get_id("src/lib.rs").ok_or("lib")?;
+        let build = files.get_id("build.rs").ok_or("build")?;
+        let span = |id, range| files.position(id, range).ok_or("span");
+        let hot = LIB.find("hot").ok_or("hot")?;
+
+        let mut heatmap = SpanHeatmap::new();
+        // The whole function, ending with its line break
+        heatmap.add(span(lib, 0..LIB.len())?);
+        heatmap.add_weighted(span(lib, hot..hot + 6)?, 7);
+        heatmap.add(span(build, 0..12)?);
+        Ok((files, heatmap))
+    }
+
+    #[test]
+    fn counts_each_line_of_a_span() -> Result<(), String> {
+        let (files, heatmap) = heatmap()?;
+        let lib = files.get_id("src/lib.rs").ok_or("lib")?;
+        let build = files.get_id("build.rs").ok_or("build")?;
+        let counts: Vec<_> = heatmap.lines(lib).collect();
+        assert_eq!(counts, vec![(1, 1), (2, 8), (3, 1), (4, 1)]);
+        assert_eq!(heatmap.count(lib, 5), 0);
+        assert_eq!(heatmap.max(), 8);
+        assert_eq!(
+            heatmap.hottest(2),
+            vec![(lib, 2, 8), (lib.min(build), 1, 1)]
+        );
+        assert_eq!(heatmap.files().count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn renders_lines_shaded_by_heat() -> Result<(), String> {
+        let (files, heatmap) = heatmap()?;
+        let lib = files.get_id("src/lib.rs").ok_or("lib")?;
+        let rendered = heatmap.render_ansi(&files, lib).ok_or("render")?;
+        let lines: Vec<_> = rendered.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[1], "8 2 │ \x1b[48;5;196m    hot();\x1b[0m");
+        assert_eq!(lines[2], "1 3 │ \x1b[48;5;28m    cold();\x1b[0m");
+        assert!(heatmap.render_ansi(&files, 9).is_none());
+
+        let plain = SpanHeatmap::<u8>::new();
+        let rendered = plain.render_ansi(&files, lib).ok_or("render")?;
+        assert!(!rendered.contains('\x1b'));
+        assert!(rendered.starts_with("  1 │ fn main() {\n"));
+        Ok(())
+    }
+
+    #[cfg(feature = "export")]
+    #[test]
+    fn exports_counts_as_json() -> Result<(), String> {
+        let (files, heatmap) = heatmap()?;
+        let mut out = Vec::new();
+        heatmap
+            .write_json(&files, &mut out)
+            .map_err(|e| e.to_string())?;
+        let export: HeatmapExport = serde_json::from_slice(&out).map_err(|e| e.to_string())?;
+        assert_eq!(export, heatmap.export(&files));
+        assert_eq!(export.max, 8);
+        let lib = export
+            .files
+            .iter()
+            .find(|file| file.path.as_deref() == Some("src/lib.rs"))
+            .ok_or("lib")?;
+        assert_eq!(lib.lines, vec![(1, 1), (2, 8), (3, 1), (4, 1)]);
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "test-support", feature = "view"))]
+mod span_assertions {
+    use super::test_utils::finalized;
+    use crate::*;
+
+    fn files() -> Result<SourceFilesMap<u8>, String> {
+        let content = "fn main() {\n\tlet unused = 1;\n}\n";
+        finalized(SourceFilesMap::new(), &[("src/lib.rs", content)])
+    }
+
+    #[test]
+    fn equal_spans_pass() -> Result<(), String> {
+        let files = files()?;
+        let id = files.get_id("src/lib.rs").ok_or("lib")?;
+        let name = files.position(id, 3..7).ok_or("span")?;
+        assert_span_eq!(files, name, "src/lib.rs", 1:4..1:7);
+        assert_span_eq!(files, name, "src/lib.rs", 1:4..1:7, "name of {}", "main");
+        check_span(&files, &name, "src/lib.rs", (1, 4, 1, 7))
+    }
+
+    #[test]
+    fn mismatch_renders_both_spans() -> Result<(), String> {
+        let files = files()?;
+        let id = files.get_id("src/lib.rs").ok_or("lib")?;
+        let unused = files.position(id, 17..23).ok_or("span")?;
+        let message = check_span(&files, &unused, "src/lib.rs", (2, 5, 2, 9))
+            .err()
+            .ok_or("spans are equal")?;
+        assert_eq!(
+            message,
+            "spans differ\n\
+             expected: src/lib.rs:2:5-2:9\n  \
+             2 │     let unused = 1;\n    \
+             │        ^^^^^\n  \
+             actual: src/lib.rs:2:6-2:11\n  \
+             2 │     let unused = 1;\n    \
+             │         ^^^^^^\n"
+        );
+
+        let message = check_span(&files, &unused, "src/main.rs", (1, 1, 1, 2))
+            .err()
+            .ok_or("spans are equal")?;
+        assert!(message.contains("expected: src/main.rs:1:1-1:2\n  (file not in the map)\n"));
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "actual: src/lib.rs:1:4-1:7")]
+    fn macro_panics_on_mismatch() {
+        let files = files().unwrap();
+        let id = files.get_id("src/lib.rs").unwrap();
+        let name = files.position(id, 3..7).unwrap();
+        assert_span_eq!(files, name, "src/lib.rs", 1:1..1:2);
+    }
+}
+
+#[cfg(all(test, feature = "test-support", feature = "view"))]
+mod golden_annotations {
+    use super::test_utils::add_files;
+    use crate::*;
+
+    const FIXTURE: &str = "fn main() {\n    let unused = 1;\n    //  ^^^^^^ expected: unused-variable\n    let x: u8 = \"1\";\n    //          ^^^ error: mismatched types\n    //  ^ warning\n}\n";
+
+    fn fixture() -> Result<(SourceFilesMap<u8>, u8), String> {
+        let mut files = SourceFilesMap::new();
+        add_files!(files => { "src/main.rs" FIXTURE.as_bytes() });
+        files.finalize()?;
+        let id = files.get_id("src/main.rs").ok_or("main")?;
+        Ok((files, id))
+    }
+
+    fn span(
+        files: &SourceFilesMap<u8>,
+        id: u8,
+        text: &str,
+    ) -> Result<AbsolutePosition<u8>, String> {
+        let start = FIXTURE.find(text).ok_or("text")?;
+        files
+            .position(id, start..start + text.len())
+            .ok_or_else(|| "span".to_string())
+    }
+
+    #[test]
+    fn parses_carets_under_code_lines() -> Result<(), String> {
+        let (files, id) = fixture()?;
+        let annotations = AnnotationParser::new().parse(&files, id);
+        assert_eq!(annotations.len(), 3);
+        assert_eq!(annotations[0].pos, span(&files, id, "unused")?);
+        assert_eq!(annotations[0].severity, None);
+        assert_eq!(annotations[0].text, "unused-variable");
+        assert_eq!(annotations[1].pos, span(&files, id, "\"1\"")?);
+        assert_eq!(annotations[1].severity, Some(Severity::Error));
+        assert_eq!(annotations[1].text, "mismatched types");
+        // Stacked annotations share the code line above them
+        assert_eq!(
+            annotations[2].pos,
+            span(&files, id, "x: u8")?.with_end_column(9)
+        );
+        assert_eq!(annotations[2].severity, None);
+        assert_eq!(annotations[2].text, "warning");
+        Ok(())
+    }
+
+    #[test]
+    fn matches_diagnostics_by_code_or_message() -> Result<(), String> {
+        let (files, id) = fixture()?;
+        let parser = AnnotationParser::new();
+        let mut diagnostics = vec![
+            Diagnostic::error("mismatched types", span(&files, id, "\"1\"")?),
+            Diagnostic::warning("unused variable `unused`", span(&files, id, "unused")?)
+                .with_code("unused-variable"),
+            Diagnostic::new(
+                Severity::Note,
+                "warning",
+                span(&files, id, "x: u8")?.with_end_column(9),
+            ),
+        ];
+        check_annotations(&files, id, &parser, &diagnostics)?;
+
+        diagnostics[0].severity = Severity::Warning;
+        diagnostics.push(Diagnostic::error("stray", span(&files, id, "main")?));
+        let message = check_annotations(&files, id, &parser, &diagnostics)
+            .err()
+            .ok_or("annotations match")?;
+        assert_eq!(
+            message,
+            "annotations of src/main.rs differ from the diagnostics\n\
+             missing src/main.rs:4:17-4:19: error: mismatched types\n  \
+             4 │     let x: u8 = \"1\";\n    \
+             │                 ^^^\n\
+             unexpected src/main.rs:4:17-4:19: warning: mismatched types\n  \
+             4 │     let x: u8 = \"1\";\n    \
+             │                 ^^^\n\
+             unexpected src/main.rs:1:4-1:7: error: stray\n  \
+             1 │ fn main() {\n    \
+             │    ^^^^\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn bare_carets_leave_specific_diagnostics_alone() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file(
+            "a.rs".to_string(),
+            b"let x = 1;\n//  ^\n//  ^ error: bad\n".to_vec(),
+        )?;
+        files.finalize()?;
+        let id = files.get_id("a.rs").ok_or("a")?;
+        let x = AbsolutePosition::new(id, 1, 5, 1, 5);
+        let diagnostics = [Diagnostic::error("bad", x), Diagnostic::warning("other", x)];
+        check_annotations(&files, id, &AnnotationParser::new(), &diagnostics)
+    }
+
+    #[test]
+    fn custom_comment_markers() -> Result<(), String> {
+        let mut files = SourceFilesMap::<u8>::new();
+        files.add_file(
+            "q.lisp".to_string(),
+            b"(car nil)\n;    ^^^ error: nil\n".to_vec(),
+        )?;
+        files.finalize()?;
+        let id = files.get_id("q.lisp").ok_or("q")?;
+        assert!(AnnotationParser::new().parse(&files, id).is_empty());
+        let annotations = AnnotationParser::new().with_comment(";").parse(&files, id);
+        assert_eq!(annotations.len(), 1);
+        assert_eq!(files.view(id, &annotations[0].pos), Some(&b"nil"[..]));
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod metrics_observer {
+    use crate::*;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    /// Values recorded so far, by name with their labels
+    fn values(snapshotter: &Snapshotter) -> BTreeMap<String, DebugValue> {
+        snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| {
+                let key = key.key();
+                let labels: Vec<_> = key
+                    .labels()
+                    .map(|label| format!("{}={}", label.key(), label.value()))
+                    .collect();
+                match labels.is_empty() {
+                    true => (key.name().to_string(), value),
+                    false => (format!("{}{{{}}}", key.name(), labels.join(",")), value),
+                }
+            })
+            .collect()
+    }
+
+    fn gauge(value: f64) -> DebugValue {
+        DebugValue::Gauge(value.into())
+    }
+
+    #[test]
+    fn observer_records_map_activity() -> Result<(), String> {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || -> Result<(), String> {
+            let mut files = SourceFilesMap::<u8>::new().with_observer(Arc::new(MetricsObserver));
+            files.add_file("a.rs".to_string(), b"fn a() {}\n".to_vec())?;
+            files.add_file("b.rs".to_string(), b"fn b() {}\n".to_vec())?;
+            files.finalize()?;
+            let values = values(&snapshotter);
+            assert_eq!(
+                values.get(MetricsObserver::FILES_SUBMITTED),
+                Some(&DebugValue::Counter(2))
+            );
+            assert_eq!(
+                values.get(MetricsObserver::FINALIZATIONS),
+                Some(&DebugValue::Counter(1))
+            );
+            assert_eq!(
+                values.get(MetricsObserver::FILES_INDEXED),
+                Some(&gauge(2.0))
+            );
+            assert_eq!(
+                values.get(MetricsObserver::BYTES_STORED),
+                Some(&gauge(20.0))
+            );
+            assert!(matches!(
+                values.get(MetricsObserver::FINALIZE_SECONDS),
+                Some(DebugValue::Histogram(samples)) if samples.len() == 1
+            ));
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "rt-feedback")]
+    #[test]
+    fn feedback_publishes_gauges() -> Result<(), String> {
+        let feedback = Arc::new(RuntimeFeedback::default());
+        let mut files = SourceFilesMap::<u8>::with_feedback(Some(feedback.clone()));
+        files.add_file("a.rs".to_string(), vec![b'x'; 100])?;
+        files.add_file("b.rs".to_string(), vec![b'x'; 10])?;
+        files.finalize()?;
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || feedback.publish_metrics());
+        let values = values(&snapshotter);
+        assert_eq!(
+            values.get("sourcier_feedback_finalizations"),
+            Some(&gauge(1.0))
+        );
+        assert_eq!(values.get("sourcier_feedback_files"), Some(&gauge(2.0)));
+        assert_eq!(values.get("sourcier_feedback_bytes"), Some(&gauge(110.0)));
+        assert_eq!(
+            values.get("sourcier_feedback_peak_files"),
+            Some(&gauge(2.0))
+        );
+        assert_eq!(
+            values.get("sourcier_feedback_max_file_size"),
+            Some(&gauge(100.0))
+        );
+        assert_eq!(
+            values.get("sourcier_feedback_p95_file_size"),
+            Some(&gauge(100.0))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use crate::fid::{AbsolutePosition, FileId};
+use crate::pcl::PositionColumn;
+use std::ops::Range;
+
+/// Side table from token index to span, filled while lexing
+///
+/// Parsers address tokens by index, not by byte: push each token's span as
+/// it is lexed, keep the index in the token stream, and look the span up
+/// when a node or a diagnostic needs it. Spans live in a
+/// [`PositionColumn`], a few bytes per token.
+#[derive(Debug, Clone)]
+pub struct TokenSpans<Id: FileId> {
+    spans: PositionColumn<Id>,
+}
+
+impl<Id: FileId> Default for TokenSpans<Id> {
+    fn default() -> Self {
+        Self {
+            spans: PositionColumn::new(),
+        }
+    }
+}
+
+impl<Id: FileId> TokenSpans<Id> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the span of the next token, returning its index
+    pub fn push(&mut self, span: AbsolutePosition<Id>) -> usize {
+        self.spans.push(span);
+        self.spans.len() - 1
+    }
+
+    /// Span of the token at `index`
+    pub fn get(&self, index: usize) -> Option<AbsolutePosition<Id>> {
+        self.spans.get(index)
+    }
+
+    /// Span from the start of the first token of `tokens` to the end of the
+    /// last, None when the range is empty, out of bounds or spans two files
+    pub fn span(&self, tokens: Range<usize>) -> Option<AbsolutePosition<Id>> {
+        let last = tokens
+            .end
+            .checked_sub(1)
+            .filter(|&last| last >= tokens.start)?;
+        let (first, last) = (self.get(tokens.start)?, self.get(last)?);
+        (first.file_id() == last.file_id()).then(|| first.widen_to(&last))
+    }
+
+    /// Spans in token order
+    pub fn iter(&self) -> impl Iterator<Item = AbsolutePosition<Id>> + '_ {
+        self.spans.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// Release spare capacity once lexing is done
+    pub fn shrink_to_fit(&mut self) {
+        self.spans.shrink_to_fit();
+    }
+
+    /// Bytes allocated on the heap
+    pub fn heap_size(&self) -> usize {
+        self.spans.heap_size()
+    }
+}
+
+impl<Id: FileId> Extend<AbsolutePosition<Id>> for TokenSpans<Id> {
+    fn extend<I: IntoIterator<Item = AbsolutePosition<Id>>>(&mut self, spans: I) {
+        self.spans.extend(spans);
+    }
+}
+
+impl<Id: FileId> FromIterator<AbsolutePosition<Id>> for TokenSpans<Id> {
+    fn from_iter<I: IntoIterator<Item = AbsolutePosition<Id>>>(spans: I) -> Self {
+        Self {
+            spans: spans.into_iter().collect(),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Glue between byte-span tokenizers and sourcier positions
+//!
+//! Lexers and parser combinators report byte ranges; these adapters resolve
+//! them against a [`FileRef`]'s line offsets so every token or error carries
+//! an [`AbsolutePosition`] that views back exactly the spanned bytes.
+
+use crate::fid::{AbsolutePosition, FileId};
+use crate::fvw::FileRef;
+
+/// `logos` lexer yielding each token with its position
+///
+/// The position is None when the span cannot be encoded (line or column past
+/// the position limits). The lexer must run over the pinned file's content.
+#[cfg(feature = "logos")]
+pub struct PositionedLexer<'s, 'a, T: logos::Logos<'s>, Id: FileId> {
+    lexer: logos::Lexer<'s, T>,
+    file: FileRef<'a, Id>,
+}
+
+#[cfg(feature = "logos")]
+impl<'s, 'a, T: logos::Logos<'s>, Id: FileId> PositionedLexer<'s, 'a, T, Id> {
+    pub fn new(lexer: logos::Lexer<'s, T>, file: FileRef<'a, Id>) -> Self {
+        Self { lexer, file }
+    }
+
+    /// The wrapped lexer, e.g. to read `slice()` or `extras`
+    pub fn lexer(&self) -> &logos::Lexer<'s, T> {
+        &self.lexer
+    }
+}
+
+#[cfg(feature = "logos")]
+impl<'s, T: logos::Logos<'s>, Id: FileId> Iterator for PositionedLexer<'s, '_, T, Id> {
+    type Item = (Result<T, T::Error>, Option<AbsolutePosition<Id>>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.lexer.next()?;
+        Some((token, self.file.position(self.lexer.span())))
+    }
+}
+
+#[cfg(feature = "logos")]
+impl<'a, Id: FileId> FileRef<'a, Id> {
+    /// Lex the pinned file with `T`, attaching positions to tokens
+    pub fn lex<T>(&self) -> PositionedLexer<'a, 'a, T, Id>
+    where
+        T: logos::Logos<'a, Source = [u8]>,
+        T::Extras: Default,
+    {
+        PositionedLexer::new(T::lexer(self.content()), *self)
+    }
+}
+
+#[cfg(feature = "chumsky")]
+impl<'a, Id: FileId> FileRef<'a, Id> {
+    /// Position of a chumsky span over the pinned file
+    pub fn span_position(&self, span: chumsky::span::SimpleSpan) -> Option<AbsolutePosition<Id>> {
+        self.position(span.into_range())
+    }
+
+    /// Attach positions to `(token, span)` pairs, e.g. a chumsky lexer's output
+    pub fn spanned_positions<T>(
+        &self,
+        tokens: impl IntoIterator<Item = (T, chumsky::span::SimpleSpan)>,
+    ) -> impl Iterator<Item = (T, Option<AbsolutePosition<Id>>)> {
+        let file = *self;
+        tokens
+            .into_iter()
+            .map(move |(token, span)| (token, file.span_position(span)))
+    }
+
+    /// Position of the span a chumsky error points at
+    pub fn error_position<T>(
+        &self,
+        error: &chumsky::error::Rich<'_, T, chumsky::span::SimpleSpan>,
+    ) -> Option<AbsolutePosition<Id>> {
+        self.span_position(*error.span())
+    }
+}
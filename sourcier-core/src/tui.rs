@@ -0,0 +1,196 @@
+//! ratatui widget drawing a region of a file
+//!
+//! [`SnippetView`] reads lines straight from the map and lays out its
+//! gutter and highlights with [`snp`](crate::snp), so what it shows lines
+//! up with the text snippets of the same positions.
+
+use crate::fid::{AbsolutePosition, FileId, SourceFilePosition};
+use crate::fvw::FileRef;
+use crate::sfm::SourceFilesMap;
+use crate::snp::{DEFAULT_TAB_WIDTH, gutter_width, underline_segment};
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::Widget;
+
+/// Separator between the line numbers and the text
+const SEPARATOR: &str = " │ ";
+
+/// Lines of a file with line numbers and highlighted spans
+///
+/// Rows start at the scroll line and go on until the area or the file ends.
+/// Tabs expand to spaces; every other character takes one cell, bytes that
+/// are not valid UTF-8 showing as `U+FFFD`. Text wider than the area is cut.
+#[derive(Debug, Clone)]
+pub struct SnippetView<'a, Id: FileId> {
+    file: FileRef<'a, Id>,
+    highlights: Vec<AbsolutePosition<Id>>,
+    scroll: usize,
+    tab_width: usize,
+    text_style: Style,
+    gutter_style: Style,
+    highlight_style: Style,
+}
+
+impl<'a, Id: FileId> SnippetView<'a, Id> {
+    /// View of a file from its first line (None for unknown files)
+    pub fn new(map: &'a SourceFilesMap<Id>, id: Id) -> Option<Self> {
+        Some(Self {
+            file: map.file(id)?,
+            highlights: Vec::new(),
+            scroll: 1,
+            tab_width: DEFAULT_TAB_WIDTH,
+            text_style: Style::default(),
+            gutter_style: Style::default().add_modifier(Modifier::DIM),
+            highlight_style: Style::default().add_modifier(Modifier::REVERSED),
+        })
+    }
+
+    /// Highlight a span; spans of other files are ignored
+    pub fn with_highlight(mut self, pos: AbsolutePosition<Id>) -> Self {
+        if pos.file_id() == self.file.id() {
+            self.highlights.push(pos);
+        }
+        self
+    }
+
+    pub fn with_highlights(
+        mut self,
+        positions: impl IntoIterator<Item = AbsolutePosition<Id>>,
+    ) -> Self {
+        for pos in positions {
+            self = self.with_highlight(pos);
+        }
+        self
+    }
+
+    /// Show the file from a 1-based line
+    pub fn with_scroll(mut self, line: usize) -> Self {
+        self.scroll = line.max(1);
+        self
+    }
+
+    pub fn with_tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = tab_width.max(1);
+        self
+    }
+
+    pub fn with_text_style(mut self, style: Style) -> Self {
+        self.text_style = style;
+        self
+    }
+
+    pub fn with_gutter_style(mut self, style: Style) -> Self {
+        self.gutter_style = style;
+        self
+    }
+
+    /// Style patched over the highlighted cells
+    pub fn with_highlight_style(mut self, style: Style) -> Self {
+        self.highlight_style = style;
+        self
+    }
+
+    /// First line shown
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    /// Number of lines in the file
+    pub fn line_count(&self) -> usize {
+        self.file
+            .line_offsets()
+            .map_or(0, |lines| lines.line_count())
+    }
+
+    /// Scroll line keeping `pos` on screen in `height` rows
+    ///
+    /// The current scroll when the start of `pos` is already shown, else the
+    /// one centering it.
+    pub fn scroll_to(&self, pos: &AbsolutePosition<Id>, height: usize) -> usize {
+        let line = pos.start_line() as usize;
+        if (self.scroll..self.scroll + height.max(1)).contains(&line) {
+            return self.scroll;
+        }
+        line.saturating_sub(height / 2).max(1)
+    }
+
+    fn render_line(&self, text: &[u8], line: usize, text_area: Rect, buf: &mut Buffer) {
+        let text = text.strip_suffix(b"\r").unwrap_or(text);
+        let width = text_area.width as usize;
+        let mut column = 0;
+        let mut put = |c: char, column: &mut usize| {
+            if *column < width {
+                let x = text_area.x + *column as u16;
+                if let Some(cell) = buf.cell_mut((x, text_area.y)) {
+                    cell.set_char(c).set_style(self.text_style);
+                }
+            }
+            *column += 1;
+        };
+        for chunk in text.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                match c {
+                    '\t' => {
+                        for _ in 0..self.tab_width - column % self.tab_width {
+                            put(' ', &mut column);
+                        }
+                    }
+                    c if c.is_control() => put(char::REPLACEMENT_CHARACTER, &mut column),
+                    c => put(c, &mut column),
+                }
+            }
+            for _ in chunk.invalid() {
+                put(char::REPLACEMENT_CHARACTER, &mut column);
+            }
+        }
+        for pos in &self.highlights {
+            let Some(segment) = underline_segment(text, line, pos, self.tab_width) else {
+                continue;
+            };
+            if segment.start >= width {
+                continue;
+            }
+            let cells = segment.carets().min(width - segment.start);
+            let area = Rect::new(
+                text_area.x + segment.start as u16,
+                text_area.y,
+                cells as u16,
+                1,
+            );
+            buf.set_style(area, self.highlight_style);
+        }
+    }
+}
+
+impl<Id: FileId> Widget for SnippetView<'_, Id> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Widget::render(&self, area, buf);
+    }
+}
+
+impl<Id: FileId> Widget for &SnippetView<'_, Id> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let area = area.intersection(buf.area);
+        if area.is_empty() {
+            return;
+        }
+        let last = self
+            .line_count()
+            .min(self.scroll + area.height as usize - 1);
+        if last < self.scroll {
+            return;
+        }
+        let digits = gutter_width(last);
+        let gutter = (digits + SEPARATOR.chars().count()).min(area.width as usize) as u16;
+        for (row, line) in (self.scroll..=last).enumerate() {
+            let y = area.y + row as u16;
+            let number = format!("{line:>digits$}{SEPARATOR}");
+            buf.set_stringn(area.x, y, number, gutter as usize, self.gutter_style);
+            let text_area = Rect::new(area.x + gutter, y, area.width - gutter, 1);
+            if let Some(text) = self.file.line(line) {
+                self.render_line(text, line, text_area, buf);
+            }
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use crate::err::SourceFilesError;
+use crate::fid::FileId;
+use crate::pfl::PathFilter;
+use crate::rmp::IdRemapTable;
+use crate::sfm::SourceFilesMap;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Component, Path};
+
+/// What happened to a file on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileChange {
+    Created,
+    Changed,
+    Deleted,
+}
+
+/// A change reported by a file watcher, for a path relative to the watched root
+///
+/// Paths use `/` separators, like the paths [`SourceFilesMap::add_dir`] stores.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileEvent {
+    pub path: String,
+    pub change: FileChange,
+}
+
+impl FileEvent {
+    pub fn new(path: impl Into<String>, change: FileChange) -> Self {
+        Self {
+            path: path.into(),
+            change,
+        }
+    }
+}
+
+/// `path` relative to `root` with `/` separators, None when outside of it
+pub(crate) fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let mut parts = Vec::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// ID of a path given absolute or relative to `root`
+    #[cfg(feature = "view")]
+    pub(crate) fn resolve_under(&self, root: &Path, path: &str) -> Option<Id> {
+        self.get_id(&relative_path(root, &root.join(path))?)
+    }
+}
+
+/// Subscription notification pushed by watchman
+///
+/// Deserialize it from the JSON watchman sends (with the `serde` feature), or
+/// fill it by hand from another client library. Only the fields needed to
+/// update a map are kept.
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchmanNotification {
+    /// Watched root, which file names are relative to
+    pub root: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub files: Vec<WatchmanFile>,
+}
+
+/// One file of a [`WatchmanNotification`], with the `name`, `exists` and `new` fields
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchmanFile {
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(default = "exists_by_default"))]
+    pub exists: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub new: bool,
+}
+
+#[cfg(feature = "serde")]
+fn exists_by_default() -> bool {
+    true
+}
+
+impl WatchmanNotification {
+    /// Events for the files under `root`, with paths relative to it
+    pub fn events(&self, root: impl AsRef<Path>) -> Vec<FileEvent> {
+        let root = root.as_ref();
+        self.files
+            .iter()
+            .filter_map(|file| {
+                let path = relative_path(root, &Path::new(&self.root).join(&file.name))?;
+                let change = match (file.exists, file.new) {
+                    (false, _) => FileChange::Deleted,
+                    (true, true) => FileChange::Created,
+                    (true, false) => FileChange::Changed,
+                };
+                Some(FileEvent::new(path, change))
+            })
+            .collect()
+    }
+}
+
+/// What [`SourceFilesMap::apply_file_events`] did to a map
+#[derive(Debug, Clone)]
+pub struct WatchUpdate<Id: FileId> {
+    /// Paths whose content was reloaded
+    pub changed: Vec<String>,
+    /// Paths added to the map
+    pub added: Vec<String>,
+    /// Paths dropped from the map, rejected reloads included
+    pub removed: Vec<String>,
+    /// Translation of the IDs before the update, when files were added or
+    /// removed; None means every ID is unchanged
+    pub remap: Option<IdRemapTable<Id>>,
+}
+
+impl<Id: FileId> WatchUpdate<Id> {
+    /// Whether the events left the map untouched
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Bring a finalized map in line with file watcher events
+    ///
+    /// Paths are relative to `root`. Only the last event of each path counts,
+    /// and it is checked against the disk: changed files are reloaded (their
+    /// line offsets refreshed and pending edits discarded), new files not
+    /// excluded by `filter` are added, and deleted files, or files under a
+    /// deleted directory, are dropped. Files the
+    /// [`LoadOptions`](crate::LoadOptions) reject are skipped, or dropped
+    /// when already mapped. Adding or dropping files finalizes the map again,
+    /// and the returned update carries the [`IdRemapTable`] from the previous
+    /// IDs.
+    pub fn apply_file_events(
+        &mut self,
+        root: impl AsRef<Path>,
+        events: &[FileEvent],
+        filter: &PathFilter,
+    ) -> io::Result<WatchUpdate<Id>> {
+        let root = root.as_ref();
+        let mut last: HashMap<&str, FileChange> = HashMap::new();
+        let mut order = Vec::new();
+        for event in events {
+            if last.insert(&event.path, event.change).is_none() {
+                order.push(event.path.as_str());
+            }
+        }
+
+        let mut update = WatchUpdate {
+            changed: Vec::new(),
+            added: Vec::new(),
+            removed: Vec::new(),
+            remap: None,
+        };
+        let mut new_files = Vec::new();
+        for path in order {
+            let content = match last[path] {
+                FileChange::Deleted => None,
+                FileChange::Created | FileChange::Changed => read_file(&root.join(path))?,
+            };
+            match (self.get_id(path), content) {
+                (Some(id), Some(content)) => {
+                    if let Some(reason) = self.load_options().check(&content) {
+                        self.record_skip(path.to_string(), reason);
+                        update.removed.push(path.to_string());
+                    } else {
+                        let (content, normalized) = self.normalization(content);
+                        let changed = self.edit_content(id, |current| {
+                            if *current == content {
+                                return false;
+                            }
+                            *current = content;
+                            true
+                        });
+                        if changed {
+                            update.changed.push(path.to_string());
+                        }
+                        self.set_normalized(id, normalized);
+                        self.mark_clean(id);
+                    }
+                }
+                (Some(_), None) => update.removed.push(path.to_string()),
+                (None, Some(content)) => {
+                    if !filter.is_excluded(path, false) {
+                        new_files.push((path.to_string(), content));
+                    }
+                }
+                (None, None) => {
+                    // Deleting a directory reports only the directory
+                    let prefix = format!("{path}/");
+                    update.removed.extend(
+                        self.iter()
+                            .filter(|(_, file, _)| file.starts_with(&prefix))
+                            .map(|(_, file, _)| file.to_string()),
+                    );
+                }
+            }
+        }
+
+        let old_paths: Vec<String> = self.iter().map(|(_, path, _)| path.to_string()).collect();
+        for path in &update.removed {
+            if let Some(id) = self.get_id(path) {
+                self.mark_clean(id);
+            }
+            self.remove_pending(path);
+        }
+        for (path, content) in new_files {
+            match self.add_file(path.clone(), content) {
+                Ok(()) => update.added.push(path),
+                Err(SourceFilesError::Skipped { .. }) => {}
+                Err(error) => return Err(io::Error::other(error)),
+            }
+        }
+        if !update.added.is_empty() || !update.removed.is_empty() {
+            self.finalize().map_err(io::Error::other)?;
+            let ids = old_paths
+                .iter()
+                .map(|path| self.get_id(path).map(Into::into))
+                .collect();
+            update.remap = Some(IdRemapTable::from_raw(ids));
+        }
+        Ok(update)
+    }
+}
+
+/// Content of a regular file, None when it is gone or not a file
+fn read_file(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => {}
+        Ok(_) => return Ok(None),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error),
+    }
+    match fs::read(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
@@ -0,0 +1,511 @@
+use crate::dsk::DiskStamp;
+use crate::fid::FileId;
+use crate::grf::FileGraph;
+use crate::sfm::SourceFilesMap;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::ops::RangeInclusive;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Magic number opening every persisted map
+pub const MAP_MAGIC: [u8; 8] = *b"SOURCIER";
+
+/// Version written by default
+///
+/// Version 2 adds the modification time and content hash each file was read
+/// from disk with, checked by [`SourceFilesMap::refresh_from_disk`].
+/// Version 3 adds the edges of the [`SourceFilesMap::graph`] after the files.
+/// Version 4 moves the contents after everything else, so the paths, sizes,
+/// line counts, hashes and edges are read without them, see
+/// [`SourceFilesMap::open_metadata_only`].
+pub const FORMAT_VERSION: u16 = 4;
+
+/// Versions this build can read and write
+pub const SUPPORTED_VERSIONS: RangeInclusive<u16> = 1..=FORMAT_VERSION;
+
+/// Errors reading or writing the persisted format
+#[derive(Debug)]
+pub enum WireError {
+    /// The input does not start with the expected magic number
+    BadMagic,
+    /// The input was written in a version outside `supported`
+    FormatVersion {
+        found: u16,
+        supported: RangeInclusive<u16>,
+    },
+    /// The input was written for a different file ID type
+    IdWidth {
+        expected: u32,
+        found: u32,
+    },
+    /// The input is structurally invalid
+    Corrupt(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for WireError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "Not a sourcier file (bad magic number)"),
+            Self::FormatVersion { found, supported } => write!(
+                f,
+                "Unsupported format version {} (supported: {}..={})",
+                found,
+                supported.start(),
+                supported.end()
+            ),
+            Self::IdWidth { expected, found } => write!(
+                f,
+                "File written with {}-bit IDs, expected {}-bit IDs",
+                found, expected
+            ),
+            Self::Corrupt(reason) => write!(f, "Corrupt input: {}", reason),
+            Self::Io(error) => write!(f, "I/O error: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for WireError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WireError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// Pick the highest version both this build and a peer supporting `peer` can use
+pub fn negotiate(peer: RangeInclusive<u16>) -> Option<u16> {
+    let highest = (*peer.end()).min(*SUPPORTED_VERSIONS.end());
+    let lowest = (*peer.start()).max(*SUPPORTED_VERSIONS.start());
+    (lowest <= highest).then_some(highest)
+}
+
+/// Fixed-size header preceding every persisted map
+///
+/// Layout (little endian): 8-byte magic, `u16` version, `u8` file ID bits,
+/// one reserved zero byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WireHeader {
+    pub version: u16,
+    pub id_bits: u8,
+}
+
+impl WireHeader {
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(&MAP_MAGIC)?;
+        out.write_all(&self.version.to_le_bytes())?;
+        out.write_all(&[self.id_bits, 0])
+    }
+
+    /// Read and validate a header, rejecting unknown magic and versions
+    pub fn read_from(input: &mut impl Read) -> Result<Self, WireError> {
+        let mut raw = [0u8; 12];
+        input.read_exact(&mut raw)?;
+        if raw[..8] != MAP_MAGIC {
+            return Err(WireError::BadMagic);
+        }
+        let version = u16::from_le_bytes([raw[8], raw[9]]);
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(WireError::FormatVersion {
+                found: version,
+                supported: SUPPORTED_VERSIONS,
+            });
+        }
+        Ok(Self {
+            version,
+            id_bits: raw[10],
+        })
+    }
+}
+
+pub(crate) fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut raw = [0u8; 4];
+    input.read_exact(&mut raw)?;
+    Ok(u32::from_le_bytes(raw))
+}
+
+pub(crate) fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut raw = [0u8; 8];
+    input.read_exact(&mut raw)?;
+    Ok(u64::from_le_bytes(raw))
+}
+
+/// Read exactly `len` bytes without trusting `len` for the allocation size
+pub(crate) fn read_bytes(input: &mut impl Read, len: u64) -> Result<Vec<u8>, WireError> {
+    let mut bytes = Vec::new();
+    input.take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(WireError::Corrupt("truncated input".to_string()));
+    }
+    Ok(bytes)
+}
+
+impl<Id: FileId> SourceFilesMap<Id> {
+    /// Persist the finalized map in the current [`FORMAT_VERSION`]
+    pub fn write_cache(&self, out: &mut impl Write) -> Result<(), WireError> {
+        self.write_cache_version(out, FORMAT_VERSION)
+    }
+
+    /// Persist the finalized map in an older `version`, e.g. one picked by [`negotiate`]
+    ///
+    /// File IDs are stored implicitly by order, so a loaded map resolves
+    /// previously created positions to the same files. From version 2 each
+    /// file is followed by its disk stamp, a zero time for files that were
+    /// not read from disk. From version 3 the files are followed by the
+    /// number of graph edges and their raw ID pairs.
+    ///
+    /// Version 4 splits the rest in a metadata segment, its byte length
+    /// first, then per file the path, content length, line count and disk
+    /// stamp, then the edges; and a blob segment of the contents, back to
+    /// back in ID order.
+    pub fn write_cache_version(&self, out: &mut impl Write, version: u16) -> Result<(), WireError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("cache_write", version, files = self.len()).entered();
+        let written = self.write_cache_files(out, version);
+        #[cfg(feature = "tracing")]
+        match &written {
+            Ok(()) => tracing::debug!("wrote source files cache"),
+            Err(error) => tracing::warn!(%error, "failed to write source files cache"),
+        }
+        written
+    }
+
+    fn write_cache_files(&self, out: &mut impl Write, version: u16) -> Result<(), WireError> {
+        if !SUPPORTED_VERSIONS.contains(&version) {
+            return Err(WireError::FormatVersion {
+                found: version,
+                supported: SUPPORTED_VERSIONS,
+            });
+        }
+        WireHeader {
+            version,
+            id_bits: Id::FILE_ID_BITS as u8,
+        }
+        .write_to(out)?;
+        out.write_all(&(self.len() as u64).to_le_bytes())?;
+        if version >= 4 {
+            let mut metadata = Vec::new();
+            for (id, path, content) in self.iter() {
+                write_record_head(&mut metadata, path, content.len())?;
+                let lines = self.line_count(id).unwrap_or_default() as u64;
+                metadata.write_all(&lines.to_le_bytes())?;
+                self.write_stamp(&mut metadata, path, content)?;
+            }
+            self.write_edges(&mut metadata)?;
+            out.write_all(&(metadata.len() as u64).to_le_bytes())?;
+            out.write_all(&metadata)?;
+            for (_, _, content) in self.iter() {
+                out.write_all(content)?;
+            }
+            return Ok(());
+        }
+        for (_, path, content) in self.iter() {
+            write_record_head(out, path, content.len())?;
+            out.write_all(content)?;
+            if version >= 2 {
+                self.write_stamp(out, path, content)?;
+            }
+        }
+        if version >= 3 {
+            self.write_edges(out)?;
+        }
+        Ok(())
+    }
+
+    fn write_stamp(&self, out: &mut impl Write, path: &str, content: &[u8]) -> io::Result<()> {
+        let stamp = self.disk_stamp(path).unwrap_or(DiskStamp {
+            mtime_ns: 0,
+            hash: xxh3_64(content),
+        });
+        out.write_all(&stamp.mtime_ns.to_le_bytes())?;
+        out.write_all(&stamp.hash.to_le_bytes())
+    }
+
+    fn write_edges(&self, out: &mut impl Write) -> io::Result<()> {
+        // Edges naming unknown IDs are not persisted
+        let edges: Vec<(u64, u64)> = self
+            .graph()
+            .edges()
+            .filter(|&(from, to)| self.get_path(from).is_some() && self.get_path(to).is_some())
+            .map(|(from, to)| (from.into(), to.into()))
+            .collect();
+        out.write_all(&(edges.len() as u64).to_le_bytes())?;
+        for (from, to) in edges {
+            out.write_all(&from.to_le_bytes())?;
+            out.write_all(&to.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Load a map written by [`SourceFilesMap::write_cache`]
+    pub fn read_cache(input: &mut impl Read) -> Result<Self, WireError> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("cache_read").entered();
+        let map = Self::read_cache_files(input);
+        #[cfg(feature = "tracing")]
+        match &map {
+            Ok(map) => tracing::debug!(files = map.len(), "read source files cache"),
+            Err(error) => tracing::warn!(%error, "failed to read source files cache"),
+        }
+        map
+    }
+
+    fn read_cache_files(input: &mut impl Read) -> Result<Self, WireError> {
+        let (header, count) = read_preamble::<Id>(input)?;
+        let mut files = Vec::with_capacity(count.min(1 << 16) as usize);
+        let mut stamps = Vec::new();
+        let graph = if header.version >= 4 {
+            let metadata = read_metadata::<Id>(input, header.version, count)?;
+            for file in metadata.files {
+                let content = read_bytes(input, file.len)?;
+                stamps.push((file.path.clone(), file.stamp()));
+                files.push((file.path, content));
+            }
+            metadata.graph
+        } else {
+            for _ in 0..count {
+                let (path, content_len) = read_record_head(input)?;
+                let content = read_bytes(input, content_len)?;
+                if let Some(stamp) = read_stamp(input, header.version)? {
+                    stamps.push((path.clone(), stamp));
+                }
+                files.push((path, content));
+            }
+            read_graph(input, header.version)?
+        };
+        let mut map = Self::from_finalized(files).map_err(WireError::Corrupt)?;
+        for (path, stamp) in stamps {
+            map.set_disk_stamp(path, stamp);
+        }
+        map.set_graph(graph)
+            .map_err(|error| WireError::Corrupt(error.to_string()))?;
+        Ok(map)
+    }
+
+    /// Read what a cache says about its files, without their contents
+    ///
+    /// From version 4 the contents are past the end of what is read, so this
+    /// costs the size of the metadata however large the files are. Older
+    /// versions interleave them and are read through.
+    pub fn open_metadata_only(input: &mut impl Read) -> Result<CacheMetadata<Id>, WireError> {
+        let (header, count) = read_preamble::<Id>(input)?;
+        read_metadata(input, header.version, count)
+    }
+}
+
+/// A file as described by a cache, see [`SourceFilesMap::open_metadata_only`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub path: String,
+    /// Content length in bytes
+    pub len: u64,
+    pub line_count: u64,
+    /// XXH3 of the content as last read from disk, or as written
+    pub hash: u64,
+    /// Modification time it was read from disk with, in nanoseconds since the
+    /// Unix epoch, 0 when unknown
+    pub mtime_ns: u64,
+}
+
+impl FileMetadata {
+    pub(crate) fn stamp(&self) -> DiskStamp {
+        DiskStamp {
+            mtime_ns: self.mtime_ns,
+            hash: self.hash,
+        }
+    }
+}
+
+/// Files and graph of a cache, without contents
+///
+/// IDs are the ones the loaded map assigns, so they resolve positions
+/// created against it.
+#[derive(Debug, Clone)]
+pub struct CacheMetadata<Id: FileId> {
+    /// Format version the cache was written in
+    pub version: u16,
+    files: Vec<FileMetadata>,
+    ids: HashMap<String, usize>,
+    graph: FileGraph<Id>,
+}
+
+impl<Id: FileId> CacheMetadata<Id> {
+    fn new(version: u16, files: Vec<FileMetadata>, graph: FileGraph<Id>) -> Self {
+        let ids = files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| (file.path.clone(), index))
+            .collect();
+        Self {
+            version,
+            files,
+            ids,
+            graph,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn get(&self, id: Id) -> Option<&FileMetadata> {
+        let raw: u64 = id.into();
+        self.files.get(raw.checked_sub(1)? as usize)
+    }
+
+    pub fn get_id(&self, path: &str) -> Option<Id> {
+        Id::try_from(*self.ids.get(path)? as u64 + 1).ok()
+    }
+
+    pub fn get_path(&self, id: Id) -> Option<&str> {
+        Some(&self.get(id)?.path)
+    }
+
+    pub fn line_count(&self, id: Id) -> Option<usize> {
+        Some(self.get(id)?.line_count as usize)
+    }
+
+    /// Every file with its ID, in ID order
+    pub fn iter(&self) -> impl Iterator<Item = (Id, &FileMetadata)> {
+        self.files
+            .iter()
+            .enumerate()
+            .filter_map(|(index, file)| Some((Id::try_from(index as u64 + 1).ok()?, file)))
+    }
+
+    /// Bytes of content the cache holds
+    pub fn content_len(&self) -> u64 {
+        self.files.iter().map(|file| file.len).sum()
+    }
+
+    pub fn graph(&self) -> &FileGraph<Id> {
+        &self.graph
+    }
+}
+
+/// Metadata of the `count` files after the preamble, reading the contents
+/// through before version 4
+pub(crate) fn read_metadata<Id: FileId>(
+    input: &mut impl Read,
+    version: u16,
+    count: u64,
+) -> Result<CacheMetadata<Id>, WireError> {
+    let mut files = Vec::with_capacity(count.min(1 << 16) as usize);
+    if version < 4 {
+        for _ in 0..count {
+            let (path, len) = read_record_head(input)?;
+            let content = read_bytes(input, len)?;
+            let stamp = read_stamp(input, version)?.unwrap_or(DiskStamp {
+                mtime_ns: 0,
+                hash: xxh3_64(&content),
+            });
+            files.push(FileMetadata {
+                path,
+                len,
+                line_count: memchr::memchr_iter(b'\n', &content).count() as u64 + 1,
+                hash: stamp.hash,
+                mtime_ns: stamp.mtime_ns,
+            });
+        }
+        let graph = read_graph(input, version)?;
+        return Ok(CacheMetadata::new(version, files, graph));
+    }
+    let segment_len = read_u64(input)?;
+    let mut segment = input.take(segment_len);
+    for _ in 0..count {
+        let (path, len) = read_record_head(&mut segment)?;
+        let line_count = read_u64(&mut segment)?;
+        let mtime_ns = read_u64(&mut segment)?;
+        files.push(FileMetadata {
+            path,
+            len,
+            line_count,
+            hash: read_u64(&mut segment)?,
+            mtime_ns,
+        });
+    }
+    let graph = read_graph(&mut segment, version)?;
+    if segment.limit() != 0 {
+        return Err(WireError::Corrupt(
+            "metadata segment length does not match its records".to_string(),
+        ));
+    }
+    Ok(CacheMetadata::new(version, files, graph))
+}
+
+/// Header checked against `Id`, and the number of file records following it
+pub(crate) fn read_preamble<Id: FileId>(
+    input: &mut impl Read,
+) -> Result<(WireHeader, u64), WireError> {
+    let header = WireHeader::read_from(input)?;
+    if header.id_bits as u32 != Id::FILE_ID_BITS {
+        return Err(WireError::IdWidth {
+            expected: Id::FILE_ID_BITS,
+            found: header.id_bits as u32,
+        });
+    }
+    let count = read_u64(input)?;
+    if count > Id::MAX_FILES as u64 {
+        return Err(WireError::Corrupt(format!(
+            "{} files exceed the ID type capacity",
+            count
+        )));
+    }
+    Ok((header, count))
+}
+
+fn write_record_head(out: &mut impl Write, path: &str, content_len: usize) -> io::Result<()> {
+    out.write_all(&(path.len() as u32).to_le_bytes())?;
+    out.write_all(path.as_bytes())?;
+    out.write_all(&(content_len as u64).to_le_bytes())
+}
+
+/// Path and content length opening a file record
+pub(crate) fn read_record_head(input: &mut impl Read) -> Result<(String, u64), WireError> {
+    let path_len = read_u32(input)?;
+    let path = String::from_utf8(read_bytes(input, path_len as u64)?)
+        .map_err(|_| WireError::Corrupt("path is not UTF-8".to_string()))?;
+    Ok((path, read_u64(input)?))
+}
+
+/// Disk stamp closing a file record, present from version 2
+pub(crate) fn read_stamp(input: &mut impl Read, version: u16) -> io::Result<Option<DiskStamp>> {
+    if version < 2 {
+        return Ok(None);
+    }
+    let mtime_ns = read_u64(input)?;
+    let hash = read_u64(input)?;
+    Ok(Some(DiskStamp { mtime_ns, hash }))
+}
+
+/// Graph edges following the file records, present from version 3
+pub(crate) fn read_graph<Id: FileId>(
+    input: &mut impl Read,
+    version: u16,
+) -> Result<FileGraph<Id>, WireError> {
+    if version < 3 {
+        return Ok(FileGraph::new());
+    }
+    let count = read_u64(input)?;
+    let id = |raw: u64| {
+        Id::try_from(raw).map_err(|_| WireError::Corrupt(format!("file ID {} out of range", raw)))
+    };
+    let mut graph = FileGraph::new();
+    for _ in 0..count {
+        let from = id(read_u64(input)?)?;
+        graph.add_edge(from, id(read_u64(input)?)?);
+    }
+    Ok(graph)
+}
@@ -0,0 +1,84 @@
+use crate::lod::SkipReason;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Operation that went through in a degraded way instead of failing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// `path` was not added because the map already held `max_files` files
+    Dropped { path: String, max_files: usize },
+    /// `path` was rejected by the [`LoadOptions`](crate::LoadOptions) of the map
+    Skipped { path: String, reason: SkipReason },
+    /// A span of file `id` reaches a 1-based `line` or 0-based byte `column`
+    /// past what positions encode, so no position was made for it
+    PositionOverflow { id: u64, line: usize, column: usize },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dropped { path, max_files } => write!(
+                f,
+                "Dropped {}: exceeded maximum of {} files for ID type",
+                path, max_files
+            ),
+            Self::Skipped { path, reason } => write!(f, "Skipped {}: {}", path, reason),
+            Self::PositionOverflow { id, line, column } => write!(
+                f,
+                "No position for {}:{} of file {}: line or column too large",
+                line, column, id
+            ),
+        }
+    }
+}
+
+/// Receiver of the [`Warning`]s of a map
+///
+/// Called through `&self`, including from read-only queries like `position`,
+/// so sinks must be thread safe. Without a sink, a map keeps its warnings
+/// until [`SourceFilesMap::take_warnings`](crate::SourceFilesMap::take_warnings).
+pub trait WarningSink: Send + Sync {
+    fn warn(&self, warning: Warning);
+}
+
+/// Warnings of a map, kept or forwarded to its sink
+#[derive(Default)]
+pub(crate) struct Warnings {
+    kept: Mutex<Vec<Warning>>,
+    sink: Option<Arc<dyn WarningSink>>,
+}
+
+impl Warnings {
+    pub(crate) fn push(&self, warning: Warning) {
+        match &self.sink {
+            Some(sink) => sink.warn(warning),
+            None => self.kept.lock().expect("warnings lock").push(warning),
+        }
+    }
+
+    pub(crate) fn set_sink(&mut self, sink: Arc<dyn WarningSink>) {
+        self.sink = Some(sink);
+    }
+
+    pub(crate) fn take(&mut self) -> Vec<Warning> {
+        std::mem::take(self.kept.get_mut().expect("warnings lock"))
+    }
+}
+
+impl Clone for Warnings {
+    fn clone(&self) -> Self {
+        Self {
+            kept: Mutex::new(self.kept.lock().expect("warnings lock").clone()),
+            sink: self.sink.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for Warnings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Warnings")
+            .field("kept", &self.kept)
+            .field("sink", &self.sink.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
@@ -0,0 +1,161 @@
+#[cfg(feature = "view")]
+use crate::fid::AbsolutePosition;
+use crate::fid::FileId;
+use crate::sfm::SourceFilesMap;
+use std::ops::Range;
+
+/// Handle on one map of a [`SourceWorkspace`], valid for its lifetime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Namespace(u32);
+
+impl Namespace {
+    /// Insertion index of the map in its workspace
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Occurrence of a needle found by [`SourceWorkspace::search`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkspaceMatch<Id: FileId> {
+    pub namespace: Namespace,
+    pub id: Id,
+    /// Bytes of the occurrence in the content of the file
+    pub range: Range<usize>,
+}
+
+/// Several maps, e.g. one per package, addressed together
+///
+/// Each map keeps its own IDs and is reached through the [`Namespace`]
+/// returned when it was inserted, so a file is addressed globally as
+/// `(Namespace, Id)`. Namespaces are named, and a name inserted again
+/// replaces its map under the same namespace.
+#[derive(Debug, Clone)]
+pub struct SourceWorkspace<Id: FileId> {
+    names: Vec<String>,
+    maps: Vec<SourceFilesMap<Id>>,
+}
+
+impl<Id: FileId> Default for SourceWorkspace<Id> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Id: FileId> SourceWorkspace<Id> {
+    pub fn new() -> Self {
+        Self {
+            names: Vec::new(),
+            maps: Vec::new(),
+        }
+    }
+
+    /// Add `map` under `name`, replacing the map already there
+    pub fn insert(&mut self, name: impl Into<String>, map: SourceFilesMap<Id>) -> Namespace {
+        let name = name.into();
+        match self.namespace(&name) {
+            Some(namespace) => {
+                self.maps[namespace.index()] = map;
+                namespace
+            }
+            None => {
+                self.names.push(name);
+                self.maps.push(map);
+                Namespace(self.maps.len() as u32 - 1)
+            }
+        }
+    }
+
+    /// Namespace of the map inserted under `name`
+    pub fn namespace(&self, name: &str) -> Option<Namespace> {
+        let index = self.names.iter().position(|known| known == name)?;
+        Some(Namespace(index as u32))
+    }
+
+    pub fn name(&self, namespace: Namespace) -> Option<&str> {
+        self.names.get(namespace.index()).map(String::as_str)
+    }
+
+    pub fn map(&self, namespace: Namespace) -> Option<&SourceFilesMap<Id>> {
+        self.maps.get(namespace.index())
+    }
+
+    pub fn map_mut(&mut self, namespace: Namespace) -> Option<&mut SourceFilesMap<Id>> {
+        self.maps.get_mut(namespace.index())
+    }
+
+    /// Every namespace with its name and map, in insertion order
+    pub fn namespaces(&self) -> impl Iterator<Item = (Namespace, &str, &SourceFilesMap<Id>)> {
+        self.names
+            .iter()
+            .zip(&self.maps)
+            .enumerate()
+            .map(|(index, (name, map))| (Namespace(index as u32), name.as_str(), map))
+    }
+
+    /// Number of maps
+    pub fn len(&self) -> usize {
+        self.maps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.maps.is_empty()
+    }
+
+    /// Total number of files across maps
+    pub fn file_count(&self) -> usize {
+        self.maps.iter().map(SourceFilesMap::len).sum()
+    }
+
+    pub fn get_path(&self, (namespace, id): (Namespace, Id)) -> Option<&str> {
+        self.map(namespace)?.get_path(id)
+    }
+
+    pub fn get_content(&self, (namespace, id): (Namespace, Id)) -> Option<&[u8]> {
+        self.map(namespace)?.get_content(id)
+    }
+
+    /// Every file, namespace by namespace in ID order
+    pub fn iter(&self) -> impl Iterator<Item = ((Namespace, Id), &str, &[u8])> {
+        self.namespaces().flat_map(|(namespace, _, map)| {
+            map.iter()
+                .map(move |(id, path, content)| ((namespace, id), path, content))
+        })
+    }
+
+    /// Every map holding `path`, with the ID it has there
+    pub fn find(&self, path: &str) -> impl Iterator<Item = (Namespace, Id)> {
+        self.namespaces()
+            .filter_map(move |(namespace, _, map)| Some((namespace, map.get_id(path)?)))
+    }
+
+    /// File at `path` in the map named `name`
+    pub fn get_id(&self, name: &str, path: &str) -> Option<(Namespace, Id)> {
+        let namespace = self.namespace(name)?;
+        Some((namespace, self.map(namespace)?.get_id(path)?))
+    }
+
+    /// Non-overlapping occurrences of `needle` in every file
+    pub fn search(&self, needle: &[u8]) -> Vec<WorkspaceMatch<Id>> {
+        if needle.is_empty() {
+            return Vec::new();
+        }
+        let finder = memchr::memmem::Finder::new(needle);
+        self.iter()
+            .flat_map(|((namespace, id), _, content)| {
+                finder.find_iter(content).map(move |at| WorkspaceMatch {
+                    namespace,
+                    id,
+                    range: at..at + needle.len(),
+                })
+            })
+            .collect()
+    }
+
+    /// Position of a match, see [`SourceFilesMap::position`]
+    #[cfg(feature = "view")]
+    pub fn position(&self, found: &WorkspaceMatch<Id>) -> Option<AbsolutePosition<Id>> {
+        self.map(found.namespace)?
+            .position(found.id, found.range.clone())
+    }
+}
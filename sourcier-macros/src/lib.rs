@@ -0,0 +1,67 @@
+//! Macros capturing positions of the Rust source they are written in
+//!
+//! Re-exported by `sourcier-core` under its `macros` feature; the expansions
+//! refer to `::sourcier_core`, so use them through that crate.
+
+use proc_macro::{Delimiter, Group, Span, TokenStream, TokenTree};
+
+/// `RelativePosition` literal from the start of `first` to the end of `last`
+fn position(first: Span, last: Span) -> Result<TokenStream, String> {
+    let (start, end) = (first.start(), last.end());
+    let start_col = u8::try_from(start.column())
+        .map_err(|_| format!("column {} does not fit a position", start.column()))?;
+    // Span columns are 1-based; position end columns are 0-based exclusive
+    let end_col = u8::try_from(end.column() - 1)
+        .map_err(|_| format!("column {} does not fit a position", end.column()))?;
+    let line = |line: usize| {
+        u16::try_from(line).map_err(|_| format!("line {line} does not fit a position"))
+    };
+    Ok(format!(
+        "::sourcier_core::RelativePosition::new({}, {}, {}, {})",
+        line(start.line())?,
+        start_col,
+        line(end.line())?,
+        end_col
+    )
+    .parse()
+    .expect("position literal is valid Rust"))
+}
+
+fn compile_error(message: &str) -> TokenStream {
+    format!("::core::compile_error!({message:?})")
+        .parse()
+        .expect("compile_error! call is valid Rust")
+}
+
+/// Position of the `pos!()` invocation, as a `RelativePosition`
+///
+/// Pair it with `file!()` to know which file the position is in.
+#[proc_macro]
+pub fn pos(input: TokenStream) -> TokenStream {
+    if !input.is_empty() {
+        return compile_error("pos!() takes no arguments");
+    }
+    let call = Span::call_site();
+    position(call, call).unwrap_or_else(|message| compile_error(&message))
+}
+
+/// Evaluate an expression along with the position of its source
+///
+/// `span_of! { a + b }` yields `(a + b, RelativePosition)`, the position
+/// covering the expression tokens.
+#[proc_macro]
+pub fn span_of(input: TokenStream) -> TokenStream {
+    let tokens: Vec<TokenTree> = input.clone().into_iter().collect();
+    let (Some(first), Some(last)) = (tokens.first(), tokens.last()) else {
+        return compile_error("span_of! expects an expression");
+    };
+    let position = match position(first.span(), last.span()) {
+        Ok(position) => position,
+        Err(message) => return compile_error(&message),
+    };
+    let mut pair = TokenStream::new();
+    pair.extend([TokenTree::Group(Group::new(Delimiter::Parenthesis, input))]);
+    pair.extend(",".parse::<TokenStream>().expect("comma is a token"));
+    pair.extend(position);
+    TokenStream::from(TokenTree::Group(Group::new(Delimiter::Parenthesis, pair)))
+}
@@ -0,0 +1,19 @@
+//! Serve a map read from a cache file
+//!
+//! `cargo run -p sourcier-serve --example serve -- map.bin 127.0.0.1:3000`
+
+use sourcier_core::SourceFilesMap;
+use std::fs::File;
+use std::io::BufReader;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut args = std::env::args().skip(1);
+    let cache = args.next().ok_or("usage: serve <cache> [address]")?;
+    let address = args.next().unwrap_or_else(|| "127.0.0.1:3000".to_string());
+    let map = SourceFilesMap::<u16>::read_cache(&mut BufReader::new(File::open(cache)?))?;
+    let listener = tokio::net::TcpListener::bind(&address).await?;
+    println!("serving {} files on http://{address}", map.len());
+    axum::serve(listener, sourcier_serve::router(map.freeze())).await?;
+    Ok(())
+}
@@ -0,0 +1,284 @@
+//! gRPC service resolving positions over a shared map
+//!
+//! Serves `sourcier.v1.Positions` from `proto/sourcier.proto`, which clients
+//! in other languages generate their stubs from; the messages below mirror
+//! it, which the tests check field by field. A daemon keeping the index up to
+//! date swaps in each new generation with [`PositionsService::replace`] while
+//! requests keep being served.
+//!
+//! ```ignore
+//! tonic::transport::Server::builder()
+//!     .add_service(PositionsService::new(map.freeze()))
+//!     .serve(address)
+//!     .await?;
+//! ```
+
+use crate::{DEFAULT_SEARCH_LIMIT, occurrences};
+use sourcier_core::{FileId, FrozenSourceFilesMap, RelativePosition, SourceFilePosition};
+use std::convert::Infallible;
+use std::future::{Ready, ready};
+use std::sync::{Arc, RwLock};
+use std::task::{Context, Poll};
+use tonic::codegen::{Body, BoxFuture, Service, StdError, http};
+use tonic::{Request, Response, Status};
+use tonic_prost::ProstCodec;
+
+/// Name the service is routed under
+pub const SERVICE_NAME: &str = "sourcier.v1.Positions";
+
+const RESOLVE_PATH: &str = "/sourcier.v1.Positions/ResolvePath";
+const GET_VIEW: &str = "/sourcier.v1.Positions/GetView";
+const SEARCH: &str = "/sourcier.v1.Positions/Search";
+
+/// 1-based lines and start column, end column inclusive, as positions display
+#[derive(Clone, Copy, PartialEq, Eq, ::prost::Message)]
+pub struct Span {
+    #[prost(uint32, tag = "1")]
+    pub start_line: u32,
+    #[prost(uint32, tag = "2")]
+    pub start_column: u32,
+    #[prost(uint32, tag = "3")]
+    pub end_line: u32,
+    #[prost(uint32, tag = "4")]
+    pub end_column: u32,
+}
+
+impl Span {
+    fn of(pos: &impl SourceFilePosition) -> Self {
+        Self {
+            start_line: pos.start_line().into(),
+            start_column: pos.start_column().into(),
+            end_line: pos.end_line().into(),
+            end_column: pos.end_column().into(),
+        }
+    }
+
+    fn to_position(self) -> Option<RelativePosition> {
+        Some(RelativePosition::new(
+            self.start_line.try_into().ok()?,
+            self.start_column.try_into().ok()?,
+            self.end_line.try_into().ok()?,
+            self.end_column.try_into().ok()?,
+        ))
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, ::prost::Message)]
+pub struct ResolvePathRequest {
+    #[prost(string, tag = "1")]
+    pub path: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ::prost::Message)]
+pub struct ResolvePathResponse {
+    #[prost(uint64, tag = "1")]
+    pub file: u64,
+    #[prost(uint64, tag = "2")]
+    pub line_count: u64,
+    #[prost(fixed64, tag = "3")]
+    pub content_hash: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ::prost::Message)]
+pub struct GetViewRequest {
+    #[prost(uint64, tag = "1")]
+    pub file: u64,
+    #[prost(message, optional, tag = "2")]
+    pub span: Option<Span>,
+}
+
+#[derive(Clone, PartialEq, Eq, ::prost::Message)]
+pub struct GetViewResponse {
+    #[prost(bytes = "vec", tag = "1")]
+    pub text: Vec<u8>,
+    #[prost(fixed64, tag = "2")]
+    pub content_hash: u64,
+}
+
+#[derive(Clone, PartialEq, Eq, ::prost::Message)]
+pub struct SearchRequest {
+    #[prost(string, tag = "1")]
+    pub query: String,
+    /// Most matches to return, [`DEFAULT_SEARCH_LIMIT`] when 0
+    #[prost(uint32, tag = "2")]
+    pub limit: u32,
+}
+
+#[derive(Clone, PartialEq, Eq, ::prost::Message)]
+pub struct SearchMatch {
+    #[prost(uint64, tag = "1")]
+    pub file: u64,
+    #[prost(string, tag = "2")]
+    pub path: String,
+    /// None past the range positions encode
+    #[prost(message, optional, tag = "3")]
+    pub span: Option<Span>,
+    #[prost(uint64, tag = "4")]
+    pub start: u64,
+    #[prost(uint64, tag = "5")]
+    pub end: u64,
+}
+
+#[derive(Clone, PartialEq, Eq, ::prost::Message)]
+pub struct SearchResponse {
+    #[prost(message, repeated, tag = "1")]
+    pub matches: Vec<SearchMatch>,
+}
+
+/// `sourcier.v1.Positions` over a map that can be replaced while serving
+///
+/// Clones share the map, so keep one to [`replace`](Self::replace) it after
+/// handing another to the server. Each request works on the generation
+/// current when it arrived.
+#[derive(Debug)]
+pub struct PositionsService<Id: FileId> {
+    map: Arc<RwLock<FrozenSourceFilesMap<Id>>>,
+}
+
+impl<Id: FileId> Clone for PositionsService<Id> {
+    fn clone(&self) -> Self {
+        Self {
+            map: Arc::clone(&self.map),
+        }
+    }
+}
+
+impl<Id: FileId> PositionsService<Id> {
+    pub fn new(map: FrozenSourceFilesMap<Id>) -> Self {
+        Self {
+            map: Arc::new(RwLock::new(map)),
+        }
+    }
+
+    /// Serve `map` from now on, returning the generation it replaces
+    pub fn replace(&self, map: FrozenSourceFilesMap<Id>) -> FrozenSourceFilesMap<Id> {
+        let mut current = self.map.write().unwrap_or_else(|e| e.into_inner());
+        std::mem::replace(&mut current, map)
+    }
+
+    /// Generation currently served
+    pub fn map(&self) -> FrozenSourceFilesMap<Id> {
+        self.map.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// `ResolvePath`: ID, line count and content hash of a path
+    pub fn resolve_path(&self, request: ResolvePathRequest) -> Result<ResolvePathResponse, Status> {
+        let map = self.map();
+        let unknown = || Status::not_found(format!("unknown file {}", request.path));
+        let id = map.get_id(&request.path).ok_or_else(unknown)?;
+        Ok(ResolvePathResponse {
+            file: id.into(),
+            line_count: map.line_count(id).ok_or_else(unknown)? as u64,
+            content_hash: map.content_hash(id).ok_or_else(unknown)?,
+        })
+    }
+
+    /// `GetView`: text under a span of a file
+    pub fn get_view(&self, request: GetViewRequest) -> Result<GetViewResponse, Status> {
+        let map = self.map();
+        let unknown = || Status::not_found(format!("unknown file {}", request.file));
+        let id = Id::try_from(request.file).map_err(|_| unknown())?;
+        let content_hash = map.content_hash(id).ok_or_else(unknown)?;
+        let span = request
+            .span
+            .ok_or_else(|| Status::invalid_argument("missing span"))?
+            .to_position()
+            .ok_or_else(|| Status::invalid_argument("span exceeds what positions encode"))?;
+        let text = map
+            .view(id, &span)
+            .ok_or_else(|| Status::out_of_range("span is out of bounds"))?;
+        Ok(GetViewResponse {
+            text: text.to_vec(),
+            content_hash,
+        })
+    }
+
+    /// `Search`: occurrences of a text, see [`find`](crate::find)
+    pub fn search(&self, request: SearchRequest) -> SearchResponse {
+        let map = self.map();
+        let limit = match request.limit {
+            0 => DEFAULT_SEARCH_LIMIT,
+            limit => limit as usize,
+        };
+        let matches = occurrences(&map, request.query.as_bytes())
+            .take(limit)
+            .map(|(id, path, range)| SearchMatch {
+                file: id.into(),
+                path: path.to_string(),
+                span: map.position(id, range.clone()).map(|pos| Span::of(&pos)),
+                start: range.start as u64,
+                end: range.end as u64,
+            })
+            .collect();
+        SearchResponse { matches }
+    }
+}
+
+/// One RPC, answered synchronously by a closure
+struct Unary<F>(F);
+
+impl<F, Req, Res> Service<Request<Req>> for Unary<F>
+where
+    F: FnMut(Req) -> Result<Res, Status>,
+{
+    type Response = Response<Res>;
+    type Error = Status;
+    type Future = Ready<Result<Response<Res>, Status>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Status>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<Req>) -> Self::Future {
+        ready((self.0)(request.into_inner()).map(Response::new))
+    }
+}
+
+/// Decode a request, answer it with `f` and encode the reply
+fn unary<B, Req, Res>(
+    request: http::Request<B>,
+    f: impl FnMut(Req) -> Result<Res, Status> + Send + 'static,
+) -> BoxFuture<http::Response<tonic::body::Body>, Infallible>
+where
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+    Req: prost::Message + Default + Send + 'static,
+    Res: prost::Message + Send + 'static,
+{
+    Box::pin(async move {
+        let mut grpc = tonic::server::Grpc::new(ProstCodec::<Res, Req>::default());
+        Ok(grpc.unary(Unary(f), request).await)
+    })
+}
+
+impl<Id, B> Service<http::Request<B>> for PositionsService<Id>
+where
+    Id: FileId + Send + Sync,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<tonic::body::Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let service = self.clone();
+        match request.uri().path() {
+            RESOLVE_PATH => unary(request, move |r| service.resolve_path(r)),
+            GET_VIEW => unary(request, move |r| service.get_view(r)),
+            SEARCH => unary(request, move |r| Ok(service.search(r))),
+            path => {
+                let status = Status::unimplemented(format!("no method {path}"));
+                Box::pin(ready(Ok(status.into_http())))
+            }
+        }
+    }
+}
+
+impl<Id: FileId> tonic::server::NamedService for PositionsService<Id> {
+    const NAME: &'static str = SERVICE_NAME;
+}
@@ -0,0 +1,251 @@
+//! HTTP endpoints browsing a frozen sourcier map
+//!
+//! [`router`] mounts them all over a [`FrozenSourceFilesMap`], e.g. one read
+//! from a cache produced in CI. The handlers and the helpers they are made of
+//! are public too, to mount them under other routes or middleware:
+//!
+//! - `GET /file/{id}`: content of a file
+//! - `GET /view?file={path}&span={line:col-line:col}`: text under a span
+//! - `GET /search?q={text}&limit={n}`: occurrences of a text, as JSON
+//!
+//! File and view responses carry an `ETag` made from the content hash of the
+//! file and answer `304 Not Modified` to a matching `If-None-Match`.
+
+#[cfg(test)]
+mod tests;
+
+#[cfg(feature = "grpc")]
+pub mod grpc;
+
+use axum::Router;
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::get;
+use serde::{Deserialize, Serialize};
+use sourcier_core::{FileId, FrozenSourceFilesMap, RelativePosition, SourceFilePosition};
+use std::fmt;
+use std::ops::Range;
+
+/// Matches returned by `/search` when no limit is given
+pub const DEFAULT_SEARCH_LIMIT: usize = 100;
+
+/// Router serving every endpoint over `map`
+pub fn router<Id: FileId + Send + Sync>(map: FrozenSourceFilesMap<Id>) -> Router {
+    Router::new()
+        .route("/file/{id}", get(file::<Id>))
+        .route("/view", get(view::<Id>))
+        .route("/search", get(search::<Id>))
+        .with_state(map)
+}
+
+/// Failed request, answered with its status and message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServeError {
+    /// ID or path not in the map
+    UnknownFile(String),
+    /// Span not in `line:col-line:col` form
+    MalformedSpan(String),
+    /// Span past the end of its file
+    SpanOutOfBounds(String),
+}
+
+impl ServeError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::UnknownFile(_) => StatusCode::NOT_FOUND,
+            Self::MalformedSpan(_) => StatusCode::BAD_REQUEST,
+            Self::SpanOutOfBounds(_) => StatusCode::RANGE_NOT_SATISFIABLE,
+        }
+    }
+}
+
+impl fmt::Display for ServeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownFile(file) => write!(f, "unknown file {file}"),
+            Self::MalformedSpan(span) => write!(f, "malformed span {span:?}"),
+            Self::SpanOutOfBounds(span) => write!(f, "span {span} is out of bounds"),
+        }
+    }
+}
+
+impl std::error::Error for ServeError {}
+
+impl IntoResponse for ServeError {
+    fn into_response(self) -> Response {
+        (self.status(), self.to_string()).into_response()
+    }
+}
+
+/// Strong `ETag` of a content hash
+pub fn etag(hash: u64) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{hash:016x}\"")).expect("hex digits are a valid header")
+}
+
+/// Whether `If-None-Match` in `headers` holds `etag`
+///
+/// Weak tags match their strong form, as the header compares weakly.
+pub fn not_modified(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let Ok(etag) = etag.to_str() else {
+        return false;
+    };
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+/// Span in the `line:col-line:col` form positions display in
+pub fn parse_span(span: &str) -> Option<RelativePosition> {
+    let (start, end) = span.split_once('-')?;
+    let point = |point: &str| -> Option<(u16, u8)> {
+        let (line, col) = point.split_once(':')?;
+        Some((line.trim().parse().ok()?, col.trim().parse().ok()?))
+    };
+    let ((start_line, start_col), (end_line, end_col)) = (point(start)?, point(end)?);
+    Some(RelativePosition::new(
+        start_line, start_col, end_line, end_col,
+    ))
+}
+
+/// `body` with its `ETag`, or an empty `304` if the client has it
+fn tagged(headers: &HeaderMap, hash: u64, content_type: &'static str, body: Vec<u8>) -> Response {
+    let etag = etag(hash);
+    if not_modified(headers, &etag) {
+        return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+    }
+    (
+        [
+            (header::ETAG, etag),
+            (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+const TEXT: &str = "text/plain; charset=utf-8";
+const BINARY: &str = "application/octet-stream";
+
+/// `GET /file/{id}`: the content of a file, as text unless it looks binary
+pub async fn file<Id: FileId>(
+    State(map): State<FrozenSourceFilesMap<Id>>,
+    Path(raw): Path<u64>,
+    headers: HeaderMap,
+) -> Result<Response, ServeError> {
+    let unknown = || ServeError::UnknownFile(raw.to_string());
+    let id = Id::try_from(raw).map_err(|_| unknown())?;
+    let content = map.get_content(id).ok_or_else(unknown)?;
+    let hash = map.content_hash(id).ok_or_else(unknown)?;
+    let content_type = if map.is_binary(id) { BINARY } else { TEXT };
+    Ok(tagged(&headers, hash, content_type, content.to_vec()))
+}
+
+/// Query of `GET /view`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ViewQuery {
+    /// Path of the file in the map
+    pub file: String,
+    /// `line:col-line:col`, see [`parse_span`]
+    pub span: String,
+}
+
+/// `GET /view`: the text under a span of a file
+pub async fn view<Id: FileId>(
+    State(map): State<FrozenSourceFilesMap<Id>>,
+    Query(query): Query<ViewQuery>,
+    headers: HeaderMap,
+) -> Result<Response, ServeError> {
+    let id = map
+        .get_id(&query.file)
+        .ok_or_else(|| ServeError::UnknownFile(query.file.clone()))?;
+    let span =
+        parse_span(&query.span).ok_or_else(|| ServeError::MalformedSpan(query.span.clone()))?;
+    let text = map
+        .view(id, &span)
+        .ok_or_else(|| ServeError::SpanOutOfBounds(query.span.clone()))?;
+    let hash = map
+        .content_hash(id)
+        .ok_or_else(|| ServeError::UnknownFile(query.file.clone()))?;
+    Ok(tagged(&headers, hash, TEXT, text.to_vec()))
+}
+
+/// Query of `GET /search`
+#[derive(Debug, Clone, Deserialize)]
+pub struct SearchQuery {
+    /// Text to find, matched byte for byte
+    pub q: String,
+    /// Most matches to return, [`DEFAULT_SEARCH_LIMIT`] when missing
+    pub limit: Option<usize>,
+}
+
+/// Occurrence of a searched text, as `GET /search` lists them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub file: u64,
+    pub path: String,
+    /// `line:col-line:col`, None past the range positions encode
+    pub span: Option<String>,
+    /// Byte offsets of the occurrence
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Non-overlapping occurrences of `needle` in `map`, the first `limit` in ID
+/// order
+pub fn find<Id: FileId>(
+    map: &FrozenSourceFilesMap<Id>,
+    needle: &[u8],
+    limit: usize,
+) -> Vec<SearchHit> {
+    occurrences(map, needle)
+        .take(limit)
+        .map(|(id, path, range)| {
+            let span = map.position(id, range.clone()).map(|pos| {
+                format!(
+                    "{}:{}-{}:{}",
+                    pos.start_line(),
+                    pos.start_column(),
+                    pos.end_line(),
+                    pos.end_column()
+                )
+            });
+            SearchHit {
+                file: id.into(),
+                path: path.to_string(),
+                span,
+                start: range.start,
+                end: range.end,
+            }
+        })
+        .collect()
+}
+
+/// Byte ranges of the non-overlapping occurrences of `needle`, in ID order
+pub(crate) fn occurrences<'a, Id: FileId>(
+    map: &'a FrozenSourceFilesMap<Id>,
+    needle: &'a [u8],
+) -> impl Iterator<Item = (Id, &'a str, Range<usize>)> + 'a {
+    let finder = memchr::memmem::Finder::new(needle);
+    map.iter()
+        .filter(move |_| !needle.is_empty())
+        .flat_map(move |(id, path, content)| {
+            finder
+                .find_iter(content)
+                .map(|at| (id, path, at..at + needle.len()))
+                .collect::<Vec<_>>()
+        })
+}
+
+/// `GET /search`: occurrences of a text, see [`find`]
+pub async fn search<Id: FileId>(
+    State(map): State<FrozenSourceFilesMap<Id>>,
+    Query(query): Query<SearchQuery>,
+) -> Json<Vec<SearchHit>> {
+    let limit = query.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+    Json(find(&map, query.q.as_bytes(), limit))
+}
@@ -0,0 +1,421 @@
+use crate::*;
+use axum::body::{Body, to_bytes};
+use axum::http::Request;
+use sourcier_core::SourceFilesMap;
+use tower::ServiceExt;
+
+fn map() -> Result<FrozenSourceFilesMap<u8>, String> {
+    let mut files = SourceFilesMap::new();
+    files.add_file(
+        "src/lib.rs".to_string(),
+        b"mod parse;\nfn main() {}\n".to_vec(),
+    )?;
+    files.add_file("src/parse.rs".to_string(), b"fn parse() {}\n".to_vec())?;
+    files.finalize()?;
+    Ok(files.freeze())
+}
+
+async fn get(
+    map: &FrozenSourceFilesMap<u8>,
+    uri: &str,
+    if_none_match: Option<&HeaderValue>,
+) -> Result<(StatusCode, HeaderMap, Vec<u8>), String> {
+    let mut request = Request::get(uri);
+    if let Some(etag) = if_none_match {
+        request = request.header(header::IF_NONE_MATCH, etag);
+    }
+    let request = request.body(Body::empty()).map_err(|e| e.to_string())?;
+    let response = router(map.clone())
+        .oneshot(request)
+        .await
+        .map_err(|e| e.to_string())?;
+    let (parts, body) = response.into_parts();
+    let body = to_bytes(body, usize::MAX)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok((parts.status, parts.headers, body.to_vec()))
+}
+
+#[tokio::test]
+async fn files_are_served_with_etags() -> Result<(), String> {
+    let map = map()?;
+    let (status, headers, body) = get(&map, "/file/2", None).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, b"fn parse() {}\n");
+    let tag = headers.get(header::ETAG).ok_or("etag")?.clone();
+    assert_eq!(tag, etag(map.content_hash(2).ok_or("hash")?));
+
+    let (status, _, body) = get(&map, "/file/2", Some(&tag)).await?;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert!(body.is_empty());
+    let weak = HeaderValue::from_str(&format!(
+        "\"x\", W/{}",
+        tag.to_str().map_err(|e| e.to_string())?
+    ))
+    .map_err(|e| e.to_string())?;
+    assert_eq!(
+        get(&map, "/file/2", Some(&weak)).await?.0,
+        StatusCode::NOT_MODIFIED
+    );
+    assert_eq!(get(&map, "/file/9", None).await?.0, StatusCode::NOT_FOUND);
+    Ok(())
+}
+
+#[tokio::test]
+async fn views_resolve_spans_by_path() -> Result<(), String> {
+    let map = map()?;
+    let (status, headers, body) = get(&map, "/view?file=src/lib.rs&span=2:4-2:7", None).await?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, b"main");
+    assert_eq!(
+        headers.get(header::ETAG),
+        Some(&etag(map.content_hash(1).ok_or("hash")?))
+    );
+    let status = |uri: &'static str| {
+        let map = map.clone();
+        async move { get(&map, uri, None).await.map(|(status, _, _)| status) }
+    };
+    assert_eq!(
+        status("/view?file=src/lib.rs&span=2:4").await?,
+        StatusCode::BAD_REQUEST
+    );
+    assert_eq!(
+        status("/view?file=src/lib.rs&span=9:1-9:2").await?,
+        StatusCode::RANGE_NOT_SATISFIABLE
+    );
+    assert_eq!(
+        status("/view?file=nope.rs&span=1:1-1:1").await?,
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        parse_span("1:2-3:4"),
+        Some(RelativePosition::new(1, 2, 3, 4))
+    );
+    assert_eq!(parse_span("1:2"), None);
+    Ok(())
+}
+
+#[tokio::test]
+async fn search_lists_matches_as_json() -> Result<(), String> {
+    let map = map()?;
+    let (status, _, body) = get(&map, "/search?q=parse", None).await?;
+    assert_eq!(status, StatusCode::OK);
+    let hits: Vec<SearchHit> = serde_json::from_slice(&body).map_err(|e| e.to_string())?;
+    let found: Vec<(&str, Option<&str>)> = hits
+        .iter()
+        .map(|hit| (hit.path.as_str(), hit.span.as_deref()))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            ("src/lib.rs", Some("1:5-1:9")),
+            ("src/parse.rs", Some("1:4-1:8")),
+        ]
+    );
+    let (_, _, body) = get(&map, "/search?q=parse&limit=1", None).await?;
+    let hits: Vec<SearchHit> = serde_json::from_slice(&body).map_err(|e| e.to_string())?;
+    assert_eq!(hits.len(), 1);
+    assert_eq!((hits[0].file, hits[0].start, hits[0].end), (1, 4, 9));
+    Ok(())
+}
+
+#[cfg(feature = "grpc")]
+mod positions {
+    use super::map;
+    use crate::grpc::*;
+    use prost::Message;
+    use sourcier_core::SourceFilesMap;
+    use std::collections::{BTreeMap, BTreeSet};
+    use tonic::codegen::http;
+    use tonic::codegen::http::uri::PathAndQuery;
+    use tonic::{Code, Request, Status};
+    use tonic_prost::ProstCodec;
+    use tower::ServiceExt;
+
+    const PROTO: &str = include_str!("../proto/sourcier.proto");
+
+    /// `(tag, wire type)` of every field, by message
+    type Fields = BTreeMap<String, BTreeSet<(u64, u64)>>;
+
+    /// Package, RPC names and message fields declared in the proto file
+    fn parse_proto() -> (String, Vec<String>, Fields) {
+        let (mut package, mut rpcs, mut messages) = (String::new(), Vec::new(), Fields::new());
+        let mut message = None;
+        for line in PROTO.lines() {
+            let line = line.split("//").next().unwrap_or_default().trim();
+            let words: Vec<&str> = line
+                .split(|c: char| c.is_whitespace() || "();={}".contains(c))
+                .filter(|word| !word.is_empty())
+                .collect();
+            match words.as_slice() {
+                ["package", name] => package = name.to_string(),
+                ["rpc", name, ..] => rpcs.push(name.to_string()),
+                ["message", name] => {
+                    messages.insert(name.to_string(), BTreeSet::new());
+                    message = Some(name.to_string());
+                }
+                [.., kind, _, tag] if line.ends_with(';') => {
+                    let wire_type = match *kind {
+                        "uint32" | "uint64" | "int32" | "int64" | "bool" => 0,
+                        "fixed64" | "sfixed64" | "double" => 1,
+                        "fixed32" | "sfixed32" | "float" => 5,
+                        _ => 2,
+                    };
+                    let fields = messages.get_mut(message.as_deref().unwrap_or_default());
+                    fields
+                        .expect("fields belong to a message")
+                        .insert((tag.parse().expect("numeric tag"), wire_type));
+                }
+                _ => {}
+            }
+        }
+        (package, rpcs, messages)
+    }
+
+    fn varint(bytes: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = bytes.split_first().expect("complete varint");
+            *bytes = rest;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                break;
+            }
+        }
+        value
+    }
+
+    /// `(tag, wire type)` of the fields present in an encoded message
+    fn encoded_fields(message: &impl Message) -> BTreeSet<(u64, u64)> {
+        let buf = message.encode_to_vec();
+        let mut bytes = buf.as_slice();
+        let mut fields = BTreeSet::new();
+        while !bytes.is_empty() {
+            let key = varint(&mut bytes);
+            let skip = match key & 7 {
+                0 => {
+                    varint(&mut bytes);
+                    0
+                }
+                1 => 8,
+                2 => varint(&mut bytes) as usize,
+                5 => 4,
+                other => panic!("unexpected wire type {other}"),
+            };
+            bytes = &bytes[skip..];
+            fields.insert((key >> 3, key & 7));
+        }
+        fields
+    }
+
+    async fn call<Req, Res>(
+        service: &PositionsService<u8>,
+        method: &'static str,
+        request: Req,
+    ) -> Result<Res, Status>
+    where
+        Req: prost::Message + Send + Sync + 'static,
+        Res: prost::Message + Default + Send + Sync + 'static,
+    {
+        let mut client = tonic::client::Grpc::new(service.clone());
+        client
+            .ready()
+            .await
+            .map_err(|e| Status::unknown(e.to_string()))?;
+        let path = PathAndQuery::from_static(method);
+        let response = client
+            .unary(Request::new(request), path, ProstCodec::default())
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    #[tokio::test]
+    async fn rpcs_resolve_against_the_current_map() -> Result<(), String> {
+        let service = PositionsService::new(map()?);
+        let resolved: ResolvePathResponse = call(
+            &service,
+            "/sourcier.v1.Positions/ResolvePath",
+            ResolvePathRequest {
+                path: "src/parse.rs".to_string(),
+            },
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+        assert_eq!((resolved.file, resolved.line_count), (2, 2));
+
+        let span = Span {
+            start_line: 2,
+            start_column: 4,
+            end_line: 2,
+            end_column: 7,
+        };
+        let view: GetViewResponse = call(
+            &service,
+            "/sourcier.v1.Positions/GetView",
+            GetViewRequest {
+                file: 1,
+                span: Some(span),
+            },
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+        assert_eq!(view.text, b"main");
+
+        let found: SearchResponse = call(
+            &service,
+            "/sourcier.v1.Positions/Search",
+            SearchRequest {
+                query: "parse".to_string(),
+                limit: 0,
+            },
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+        let spans: Vec<_> = found.matches.iter().map(|m| (m.file, m.span)).collect();
+        assert_eq!(
+            spans,
+            [
+                (
+                    1,
+                    Some(Span {
+                        start_line: 1,
+                        start_column: 5,
+                        end_line: 1,
+                        end_column: 9
+                    })
+                ),
+                (
+                    2,
+                    Some(Span {
+                        start_line: 1,
+                        start_column: 4,
+                        end_line: 1,
+                        end_column: 8
+                    })
+                ),
+            ]
+        );
+
+        // A new generation is served to the next requests
+        let mut next = SourceFilesMap::new();
+        next.add_file("src/new.rs".to_string(), b"fn new() {}\n".to_vec())?;
+        next.finalize()?;
+        service.replace(next.freeze());
+        let missing = call::<_, ResolvePathResponse>(
+            &service,
+            "/sourcier.v1.Positions/ResolvePath",
+            ResolvePathRequest {
+                path: "src/parse.rs".to_string(),
+            },
+        )
+        .await
+        .err()
+        .ok_or("stale map")?;
+        assert_eq!(missing.code(), Code::NotFound);
+        let beyond = service
+            .get_view(GetViewRequest {
+                file: 1,
+                span: Some(span),
+            })
+            .err()
+            .ok_or("view past the end")?;
+        assert_eq!(beyond.code(), Code::OutOfRange);
+        let unknown = call::<_, SearchResponse>(
+            &service,
+            "/sourcier.v1.Positions/Nope",
+            SearchRequest::default(),
+        )
+        .await
+        .err()
+        .ok_or("unknown method")?;
+        assert_eq!(unknown.code(), Code::Unimplemented);
+        Ok(())
+    }
+
+    #[test]
+    fn messages_match_the_proto() {
+        let span = Span {
+            start_line: 1,
+            start_column: 2,
+            end_line: 3,
+            end_column: 4,
+        };
+        let found = SearchMatch {
+            file: 1,
+            path: "a".to_string(),
+            span: Some(span),
+            start: 1,
+            end: 2,
+        };
+        let encoded: Fields = [
+            ("Span", encoded_fields(&span)),
+            (
+                "ResolvePathRequest",
+                encoded_fields(&ResolvePathRequest {
+                    path: "a".to_string(),
+                }),
+            ),
+            (
+                "ResolvePathResponse",
+                encoded_fields(&ResolvePathResponse {
+                    file: 1,
+                    line_count: 1,
+                    content_hash: 1,
+                }),
+            ),
+            (
+                "GetViewRequest",
+                encoded_fields(&GetViewRequest {
+                    file: 1,
+                    span: Some(span),
+                }),
+            ),
+            (
+                "GetViewResponse",
+                encoded_fields(&GetViewResponse {
+                    text: b"a".to_vec(),
+                    content_hash: 1,
+                }),
+            ),
+            (
+                "SearchRequest",
+                encoded_fields(&SearchRequest {
+                    query: "a".to_string(),
+                    limit: 1,
+                }),
+            ),
+            ("SearchMatch", encoded_fields(&found)),
+            (
+                "SearchResponse",
+                encoded_fields(&SearchResponse {
+                    matches: vec![found.clone()],
+                }),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, fields)| (name.to_string(), fields))
+        .collect();
+        assert_eq!(encoded, parse_proto().2);
+    }
+
+    #[tokio::test]
+    async fn methods_match_the_proto() -> Result<(), String> {
+        let (package, rpcs, _) = parse_proto();
+        assert_eq!(SERVICE_NAME, format!("{package}.Positions"));
+        assert_eq!(rpcs, ["ResolvePath", "GetView", "Search"]);
+        let service = PositionsService::new(map()?);
+        for rpc in rpcs {
+            let request = http::Request::post(format!("/{SERVICE_NAME}/{rpc}"))
+                .header("content-type", "application/grpc")
+                .body(tonic::body::Body::empty())
+                .map_err(|e| e.to_string())?;
+            let Ok(response) = service.clone().oneshot(request).await;
+            let status = Status::from_header_map(response.headers());
+            assert!(
+                status.is_none_or(|status| status.code() != Code::Unimplemented),
+                "{rpc} is not routed"
+            );
+        }
+        Ok(())
+    }
+}